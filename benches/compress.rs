@@ -0,0 +1,73 @@
+use futures::{executor::block_on_stream, stream};
+use std::{convert::Infallible, time::Instant};
+use zstd_seekable_s3::StreamCompress;
+
+// Compresses `data` as a single item, which is where the size of the output
+// buffer matters most.
+fn compress_single_item(name: &str, data: &[u8]) {
+    let start = Instant::now();
+    let compress = stream::iter(std::iter::once(Ok::<_, Infallible>(data)))
+        .compress(1, 1 << 20)
+        .unwrap();
+    let compressed: usize = block_on_stream(compress)
+        .map(|bytes| bytes.unwrap().len())
+        .sum();
+    println!(
+        "{}: compressed a single {} byte item to {} bytes in {:?}",
+        name,
+        data.len(),
+        compressed,
+        start.elapsed()
+    );
+}
+
+// Compresses `data` in frames of `frame_size` with the given size of output
+// buffer, to see what the buffer costs and saves.
+fn compress_with_out_buffer(data: &[u8], frame_size: usize, out_buffer_len: usize) {
+    let start = Instant::now();
+    let compress = stream::iter(data.chunks(64 << 10).map(Ok::<_, Infallible>))
+        .compress(1, frame_size)
+        .unwrap()
+        .with_out_buffer_len(out_buffer_len)
+        .unwrap();
+    let compressed: usize = block_on_stream(compress)
+        .map(|bytes| bytes.unwrap().len())
+        .sum();
+    println!(
+        "frames of {} bytes, out buffer of {} bytes: compressed to {} bytes in {:?}",
+        frame_size,
+        out_buffer_len,
+        compressed,
+        start.elapsed()
+    );
+}
+
+fn main() {
+    let len = 256 << 20;
+    let mut text = Vec::with_capacity(len);
+    let mut line = 0u64;
+    while text.len() < len {
+        text.extend_from_slice(format!("line {} of the benchmark input\n", line).as_bytes());
+        line = line.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+    }
+    compress_single_item("text", &text);
+
+    // Barely compresses, so the output is about as large as the input.
+    let mut state = 0x5DEE_CE66u64;
+    let noise: Vec<u8> = (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect();
+    compress_single_item("noise", &noise);
+
+    // The default is CStream::out_size(), a little over 128 KiB.
+    for &frame_size in &[4 << 10, 1 << 20] {
+        for &out_buffer_len in &[1 << 10, 16 << 10, 128 << 10, 1 << 20] {
+            compress_with_out_buffer(&text[..64 << 20], frame_size, out_buffer_len);
+        }
+    }
+}
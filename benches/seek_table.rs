@@ -0,0 +1,52 @@
+use std::time::Instant;
+use zstd_seekable_s3::SeekTable;
+
+// Builds the bytes of a seek table with the given number of equally sized
+// frames.
+fn seek_table_bytes(num_frames: u32, frame_size: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0x184D_2A5Eu32.to_le_bytes());
+    bytes.extend_from_slice(&(num_frames * 8 + 9).to_le_bytes());
+    for _ in 0..num_frames {
+        bytes.extend_from_slice(&(frame_size / 4).to_le_bytes());
+        bytes.extend_from_slice(&frame_size.to_le_bytes());
+    }
+    bytes.extend_from_slice(&num_frames.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+    bytes
+}
+
+// Compares looking up frames in a million frame table with a binary search and
+// with a naive linear scan.
+fn main() {
+    let num_frames = 1_000_000;
+    let table = SeekTable::parse(&seek_table_bytes(num_frames, 1024)).unwrap();
+    let lookups = 1000u64;
+    let step = table.decompressed_len() / lookups;
+
+    let start = Instant::now();
+    let mut found = 0;
+    for offset in (0..table.decompressed_len()).step_by(step as usize) {
+        found += table.frame_for_offset(offset).unwrap();
+    }
+    let binary = start.elapsed();
+
+    let start = Instant::now();
+    let mut found_linear = 0;
+    for offset in (0..table.decompressed_len()).step_by(step as usize) {
+        found_linear += (0..table.num_frames())
+            .find(|&frame| {
+                offset
+                    < table.frame_decompressed_offset(frame) + table.frame_decompressed_size(frame)
+            })
+            .unwrap();
+    }
+    let linear = start.elapsed();
+
+    assert_eq!(found, found_linear);
+    println!(
+        "{} lookups in {} frames: binary search {:?}, linear scan {:?}",
+        lookups, num_frames, binary, linear
+    );
+}
@@ -20,6 +20,11 @@ struct Opt {
     key: String,
     #[structopt(long, help = "Region the bucket is in.")]
     region: Region,
+    #[structopt(
+        long,
+        help = "S3 endpoint to use instead of AWS's, such as http://localhost:9000 for MinIO."
+    )]
+    endpoint: Option<String>,
     #[structopt(long, help = "Role to assume, if any.")]
     role_arn: Option<String>,
     #[structopt(long, help = "File to write decompressed output to.")]
@@ -45,6 +50,16 @@ fn main() {
         config.pool_idle_timeout(core::time::Duration::from_secs(20));
         config
     };
+    // S3 compatible servers go by a custom region. rusoto always addresses
+    // buckets by path, which is what they want.
+    let region = match opt.endpoint.to_owned() {
+        Some(endpoint) => Region::Custom {
+            name: opt.region.name().to_owned(),
+            endpoint,
+        },
+        None => opt.region.to_owned(),
+    };
+
     let http_client = HttpClient::new_with_config(http_config).unwrap();
     // Make the S3 client. If the user specified a role, make sure to assume it
     // and refresh it as needed. Otherwise, just use the default credentials
@@ -63,12 +78,12 @@ fn main() {
             S3Client::new_with(
                 http_client,
                 rusoto_credential::AutoRefreshingProvider::new(provider).unwrap(),
-                opt.region.to_owned(),
+                region,
             )
         }
         None => {
             let provider = DefaultCredentialsProvider::new().unwrap();
-            S3Client::new_with(http_client, provider, opt.region.to_owned())
+            S3Client::new_with(http_client, provider, region)
         }
     };
 
@@ -85,7 +100,10 @@ fn main() {
         let rt = tokio::runtime::Runtime::new().unwrap();
 
         // We get a wrapper over S3 object that does knows how to do seeking of a file. Note however that this is just the raw data!
-        let seekable_raw_object = s3.get_seekable_object(&rt, None, req).unwrap().unwrap();
+        let seekable_raw_object = s3
+            .get_seekable_object(rt.handle().clone(), None, req)
+            .unwrap()
+            .unwrap();
 
         // We wrap the seekable S3 object with a shim that actually knows about the
         // compression.
@@ -22,7 +22,7 @@ struct Opt {
     #[structopt(default_value = "1024")]
     frame_size: usize,
     #[structopt(default_value = "1")]
-    compression_level: usize,
+    compression_level: i32,
 }
 
 // We compress a bunch of lines into a given location. We track where the
@@ -30,13 +30,18 @@ struct Opt {
     #[structopt(long, default_value = "1024")]
     frame_size: usize,
     #[structopt(long, default_value = "1")]
-    compression_level: usize,
+    compression_level: i32,
     #[structopt(long, help = "Bucket to upload object to.")]
     bucket: String,
     #[structopt(long, help = "Object key to upload at.")]
     key: String,
     #[structopt(long, help = "Region the bucket is in.")]
     region: Region,
+    #[structopt(
+        long,
+        help = "S3 endpoint to use instead of AWS's, such as http://localhost:9000 for MinIO."
+    )]
+    endpoint: Option<String>,
     #[structopt(long, help = "Role to assume, if any.")]
     role_arn: Option<String>,
 }
@@ -54,6 +59,16 @@ fn main() {
 
     let sts = StsClient::new(opt.region.to_owned());
 
+    // S3 compatible servers go by a custom region. rusoto always addresses
+    // buckets by path, which is what they want.
+    let region = match opt.endpoint.to_owned() {
+        Some(endpoint) => Region::Custom {
+            name: opt.region.name().to_owned(),
+            endpoint,
+        },
+        None => opt.region.to_owned(),
+    };
+
     let http_client = HttpClient::new().unwrap();
     // Make the S3 client. If the user specified a role, make sure to assume it
     // and refresh it as needed. Otherwise, just use the default credentials
@@ -72,12 +87,12 @@ fn main() {
             S3Client::new_with(
                 http_client,
                 rusoto_credential::AutoRefreshingProvider::new(provider).unwrap(),
-                opt.region.to_owned(),
+                region,
             )
         }
         None => {
             let provider = DefaultCredentialsProvider::new().unwrap();
-            S3Client::new_with(http_client, provider, opt.region.to_owned())
+            S3Client::new_with(http_client, provider, region)
         }
     };
 
@@ -158,6 +173,7 @@ fn main() {
         // whichever stream they come from. This will allow us to cancel the
         // multi-part upload if something went wrong.
         #[derive(Debug)]
+        #[allow(dead_code)]
         enum Error {
             CompressionError(zstd_seekable::Error),
             PartUploadError(RusotoError<UploadPartError>),
@@ -243,7 +259,7 @@ fn main() {
         };
         // We get a wrapper over S3 object that does knows how to do seeking of a file. Note however that this is just the raw data!
         let seekable_raw_object = s3
-            .get_seekable_object(&runtime, None, req)
+            .get_seekable_object(runtime.handle().clone(), None, req)
             .unwrap()
             .unwrap();
         // We wrap the seekable S3 object with a shim that actually knows about the
@@ -0,0 +1,42 @@
+#![no_main]
+
+// Random access reads of untrusted objects. The first 12 bytes are the range
+// to read, the rest is the object. We only read objects that pass
+// verify_all with frames of a sensible size, as the docs ask for untrusted
+// data: zstd's seekable decoder spins on frames shorter than the seek table
+// says, which isn't something we can fix from here.
+
+use libfuzzer_sys::fuzz_target;
+use std::{convert::TryInto, io::Cursor};
+use zstd_seekable_s3::{FrameCache, FrameCacheKey, SeekableDecompress};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 12 {
+        return;
+    }
+    let (range, object) = data.split_at(12);
+    let offset = u64::from_le_bytes(range[..8].try_into().unwrap());
+    let len = u32::from_le_bytes(range[8..].try_into().unwrap()) as usize % (1 << 20);
+
+    let decompress = match SeekableDecompress::new(Cursor::new(object.to_vec()))
+        .and_then(|decompress| decompress.max_frame_decompressed_size(1 << 20))
+    {
+        Ok(decompress) => decompress,
+        Err(_) => return,
+    };
+    let expected = decompress.decompressed_len();
+    let mut decompress = decompress.with_length_check(expected);
+    match decompress.verify_all(1) {
+        Ok(report) if report.is_ok() => {}
+        _ => return,
+    }
+    let plain = decompress.read_range(offset, len);
+    decompress.set_frame_cache(Some(FrameCache::new(
+        1 << 20,
+        FrameCacheKey::CompressedHash,
+    )));
+    let cached = decompress.read_range(offset, len);
+    if let (Ok(plain), Ok(cached)) = (plain, cached) {
+        assert_eq!(plain, cached);
+    }
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+// Seek tables straight out of untrusted objects: parsing them, and anything
+// we do with one that parsed, should fail gracefully rather than panic.
+
+use libfuzzer_sys::fuzz_target;
+use zstd_seekable_s3::SeekTable;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = SeekTable::len_from_footer(data);
+    let table = match SeekTable::parse(data) {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    assert_eq!(
+        SeekTable::parse(&table.to_bytes()).as_ref().ok(),
+        Some(&table)
+    );
+    // Offsets and lengths come from the data too, so they can be anything.
+    let offset = table.decompressed_len() / 3;
+    let len = u64::from(data[0]) << (data.len() % 64);
+    let _ = table.frame_for_offset(offset);
+    let _ = table.frame_for_compressed_offset(offset);
+    let _ = table.split_range(offset, len);
+    let _ = table.split_range(u64::MAX, len);
+    // Part sizes below S3's minimum aren't worth listing parts for: a
+    // corrupt table can claim gigabytes and there'd be that many parts.
+    let _ = table.parts_for_range(offset, len, 5 * 1024 * 1024);
+    let _ = table.aligned_parts(5 * 1024 * 1024);
+    let _ = table.describe();
+});
@@ -0,0 +1,54 @@
+use crate::CompressError;
+use bytes::Bytes;
+use futures::stream::FusedStream;
+use pin_project_lite::pin_project;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    /// Compressed output as an [`http_body::Body`], see
+    /// [`Compress::into_body`](crate::Compress::into_body).
+    #[derive(Debug)]
+    pub struct CompressBody<S> {
+        #[pin]
+        stream: S,
+    }
+}
+
+impl<S> CompressBody<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        CompressBody { stream }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, E> http_body::Body for CompressBody<S>
+where
+    S: FusedStream<Item = Result<Bytes, CompressError<E>>>,
+{
+    type Data = Bytes;
+    type Error = CompressError<E>;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        self.project().stream.poll_next(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
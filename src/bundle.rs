@@ -0,0 +1,187 @@
+use crate::{SeekTable, SeekTableError, SEEK_TABLE_FOOTER_LEN};
+use std::{
+    fmt::Display,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+};
+
+// Splits bundles, seekable objects written one after the other into a
+// single file, back into the objects.
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BundleError {
+    // Whatever ends at this offset isn't a seekable object with a seek table.
+    NoSeekTable { end: u64, error: SeekTableError },
+    // The seek table ending at `end` describes an object of `len` bytes,
+    // more than there is in front of it.
+    TooLong { end: u64, len: u64 },
+    Io(std::io::Error),
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BundleError::NoSeekTable { end, .. } => write!(
+                f,
+                "No seekable object ends at offset {} of the bundle.",
+                end
+            ),
+            BundleError::TooLong { end, len } => write!(
+                f,
+                "Object ending at offset {} of the bundle is {} bytes long, more than there is.",
+                end, len
+            ),
+            BundleError::Io(_) => write!(f, "Reading the bundle failed."),
+        }
+    }
+}
+
+impl std::error::Error for BundleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BundleError::NoSeekTable { error, .. } => Some(error),
+            BundleError::TooLong { .. } => None,
+            BundleError::Io(e) => Some(e),
+        }
+    }
+}
+
+/// One of the objects in a bundle, see [`bundle_entries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    /// Where the object starts in the bundle.
+    pub offset: u64,
+    /// Length of the whole object, seek table included.
+    pub len: u64,
+    pub seek_table: SeekTable,
+}
+
+impl BundleEntry {
+    /// Reads just this object, as if it was on its own, out of `bundle`. The
+    /// reader can go to
+    /// [`SeekableDecompress`](crate::SeekableDecompress) like any other.
+    pub fn reader<R>(&self, bundle: R) -> BundleObject<R> {
+        BundleObject {
+            inner: bundle,
+            offset: self.offset,
+            len: self.len,
+            position: 0,
+        }
+    }
+}
+
+/// Finds the objects in a bundle: seekable objects concatenated into one
+/// file, each with its own seek table. Gives them in the order they come in
+/// the bundle. A file holding a single object gives just that.
+///
+/// We go backwards from the end: the seek table at the end of the bundle
+/// says how long the last object is, which tells us where the one before
+/// it ends, and so on back to the start. That only works if the objects
+/// are back to back and every one of them has a seek table covering all of
+/// it: anything else in the bundle, such as padding between objects or an
+/// object written without a seek table, or one whose table leaves out some
+/// of its frames, stops the walk with an error somewhere in front of it.
+/// Nothing about the objects themselves is checked, only that the seek
+/// tables line up with the bundle.
+pub fn bundle_entries<R: Read + Seek>(bundle: &mut R) -> Result<Vec<BundleEntry>, BundleError> {
+    let mut end = bundle.seek(SeekFrom::End(0)).map_err(BundleError::Io)?;
+    let mut entries = Vec::new();
+    while end > 0 {
+        let no_seek_table = |error| BundleError::NoSeekTable { end, error };
+        if end < SEEK_TABLE_FOOTER_LEN as u64 {
+            return Err(no_seek_table(SeekTableError::TooShort {
+                needed: SEEK_TABLE_FOOTER_LEN,
+                got: end as usize,
+            }));
+        }
+        let mut footer = [0; SEEK_TABLE_FOOTER_LEN];
+        read_at(bundle, end - SEEK_TABLE_FOOTER_LEN as u64, &mut footer)?;
+        let table_len = SeekTable::len_from_footer(&footer).map_err(no_seek_table)? as u64;
+        if table_len > end {
+            return Err(BundleError::TooLong {
+                end,
+                len: table_len,
+            });
+        }
+        let mut table = vec![0; table_len as usize];
+        read_at(bundle, end - table_len, &mut table)?;
+        let seek_table = SeekTable::parse(&table).map_err(no_seek_table)?;
+        let len = seek_table.compressed_len() + table_len;
+        if len > end {
+            return Err(BundleError::TooLong { end, len });
+        }
+        end -= len;
+        entries.push(BundleEntry {
+            offset: end,
+            len,
+            seek_table,
+        });
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+fn read_at<R: Read + Seek>(bundle: &mut R, offset: u64, buf: &mut [u8]) -> Result<(), BundleError> {
+    bundle
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| bundle.read_exact(buf))
+        .map_err(BundleError::Io)
+}
+
+/// One object of a bundle, see [`BundleEntry::reader`].
+#[derive(Debug)]
+pub struct BundleObject<R> {
+    inner: R,
+    offset: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<R> BundleObject<R> {
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for BundleObject<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.len.saturating_sub(self.position);
+        if left == 0 {
+            return Ok(0);
+        }
+        let len = buf.len().min(left as usize);
+        self.inner
+            .seek(SeekFrom::Start(self.offset + self.position))?;
+        let n = self.inner.read(&mut buf[..len])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Seek for BundleObject<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.position = pos;
+                return Ok(pos);
+            }
+            SeekFrom::End(pos) => (self.len, pos),
+            SeekFrom::Current(pos) => (self.position, pos),
+        };
+        let new_pos = if offset >= 0 {
+            base_pos.checked_add(offset as u64)
+        } else {
+            base_pos.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match new_pos {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
@@ -1,18 +1,190 @@
-use bytes::Bytes;
-use futures::{ready, stream::FusedStream, Stream};
+use crate::{
+    cstream::{
+        config_error, zstd_error, FrameCStream, MAX_FRAME_SIZE,
+        ZSTD_ERROR_FRAME_PARAMETER_UNSUPPORTED,
+    },
+    encryption::{encrypt_frame, CipherError, Encryptor},
+    frame_boundary::{Chunker, FrameBoundary},
+    frame_plan::FramePlan,
+    instrument,
+    manifest::{ContentHasher, HashFuture, HashStream, ManifestBuilder, ManifestFuture},
+    metadata::metadata_frame,
+    totals::totals_frame,
+    trailing_index::trailing_index_frame,
+    FrameCompressor, SeekTable,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    ready,
+    stream::{self, FusedStream},
+    Stream, StreamExt, TryStreamExt,
+};
 use parking_lot::Mutex;
 use pin_project_lite::pin_project;
-use std::{convert::Infallible, marker::PhantomData, pin::Pin};
-use zstd_seekable::{self, CStream, SeekableCStream};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use zstd_seekable::{self, CStream};
 
 pin_project! {
+    /// Seekable compression of a stream, see [`StreamCompress::compress`].
+    ///
+    /// Only the upstream stream is pinned so this is [`Unpin`] whenever that
+    /// is, and can be polled with [`StreamExt::next`] and friends as is.
+    /// Otherwise put it in a box first, for example with
+    /// [`StreamExt::boxed`].
+    ///
+    /// Nothing is pulled from upstream ahead of the consumer: an item is
+    /// only taken once the output of the one before went out, so a slow
+    /// consumer slows down the upstream rather than having output pile up.
+    /// Each item is compressed in one go, so there's up to one item's worth
+    /// of output buffered at a time, about as much as the item itself for
+    /// data that doesn't compress. For large items, a
+    /// [`poll_budget`](Self::poll_budget) bounds that by the budget instead.
+    /// On top of that, [`align_to_parts`](Self::align_to_parts) holds up to
+    /// a part and a frame of output and [`min_frames`](Self::min_frames)
+    /// holds input until it knows the frame size.
+    ///
+    /// Extras such as [`metadata`](Self::metadata),
+    /// [`trailing_index`](Self::trailing_index) and
+    /// [`totals_frame`](Self::totals_frame) each go in a skippable frame,
+    /// which takes up a frame with no data in the seek table. Decompressing
+    /// skips right over them, with us or any other zstd decoder.
     pub struct Compress<S, E> {
         #[pin]
         stream: S,
-        cstream: Mutex<SeekableCStream>,
+        cstream: Mutex<FrameCStream>,
+        // Where frames should end.
+        chunker: Chunker,
         buf_out: Box<[u8]>,
         wrote_seek_table: bool,
-        error_type: PhantomData<E>,
+        // Write out the seek table when upstream errors rather than just
+        // passing the error through.
+        finalize_on_error: bool,
+        // Upstream error we are holding on to until we have yielded the seek
+        // table.
+        pending_error: Option<E>,
+        // Only yield output at frame boundaries once we have at least this
+        // much of it.
+        part_alignment: Option<usize>,
+        // Output held back until we get to a frame boundary.
+        held: BytesMut,
+        // Where frames end in the held output.
+        held_frame_ends: Vec<usize>,
+        // Parts cut from the held output, waiting to be yielded.
+        ready_parts: VecDeque<Bytes>,
+        // Encrypts whole frames before they go out.
+        encryptor: Option<Mutex<Box<dyn Encryptor + Send>>>,
+        // Output of the frame in progress, when encrypting.
+        frame_buf: Vec<u8>,
+        // How many frames we encrypted so far.
+        frames_encrypted: usize,
+        // End a frame after every upstream item.
+        frame_per_item: bool,
+        // Leave the seek table off the end.
+        omit_seek_table: bool,
+        // Yield every frame on its own, then the seek table.
+        by_frame: bool,
+        // Key/value pairs to write at the start, until we do.
+        metadata: Vec<(String, String)>,
+        // Total length of the input for the frame plan, and whether the plan
+        // still has to go out.
+        expected_len: Option<u64>,
+        plan_pending: bool,
+        // Builds the manifest as we go, if anyone asked for it.
+        manifest: Option<ManifestBuilder>,
+        // Most input to compress in one poll, and what's left of the item
+        // that went over it.
+        poll_budget: Option<usize>,
+        leftover: Bytes,
+        // Fewest frames to make, until we know enough about the input to
+        // pick a frame size for them, along with the input held back until
+        // then. After that, whether the upstream ended while we were at it,
+        // and the error it ended with.
+        min_frames: Option<usize>,
+        held_input: BytesMut,
+        input_ended: bool,
+        held_error: Option<E>,
+        // Longest a frame stays open, with what to wait on for it.
+        max_frame_age: Option<(Duration, NewDeadline)>,
+        // When the frame with the given index gets too old.
+        frame_deadline: Mutex<Option<(usize, FrameDeadline)>>,
+        // Most compressed output to yield, and whether we ran into it, after
+        // which the stream is over.
+        max_output_bytes: Option<u64>,
+        over_output_limit: bool,
+        // Makes the index to put after the data, until we do.
+        trailing_index: Option<Mutex<IndexProducer>>,
+        // Write the totals frame in front of the seek table.
+        totals_frame: bool,
+        // Bytes of the caller's to put in front of everything, until we do.
+        header: Bytes,
+        progress: CompressProgress,
+    }
+}
+
+// Resolves once a frame has been open for too long.
+type FrameDeadline = Pin<Box<dyn Future<Output = ()> + Send>>;
+type NewDeadline = fn(Duration) -> FrameDeadline;
+type IndexProducer = Box<dyn FnOnce(&SeekTable) -> Vec<u8> + Send>;
+
+/// Handle for seeing how much a [`Compress`] stream did so far, from
+/// wherever the stream ended up.
+#[derive(Debug, Clone, Default)]
+pub struct CompressProgress {
+    inner: Arc<CompressCounters>,
+}
+
+#[derive(Debug, Default)]
+struct CompressCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    frames: AtomicU64,
+    #[cfg(feature = "testutil")]
+    peak_buffered_bytes: AtomicU64,
+}
+
+impl CompressProgress {
+    /// Bytes taken from upstream.
+    pub fn bytes_in(&self) -> u64 {
+        self.inner.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Compressed bytes yielded, seek table included.
+    pub fn bytes_out(&self) -> u64 {
+        self.inner.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Frames finished.
+    pub fn frames(&self) -> u64 {
+        self.inner.frames.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes the stream held on to in its own buffers at once so
+    /// far, for tests checking memory stays within bounds: output
+    /// held back for [`Compress::align_to_parts`] or encryption, and parts
+    /// not yet yielded, input held back for [`Compress::min_frames`] and the
+    /// rest of an item split up by [`Compress::poll_budget`]. What zstd
+    /// keeps inside its context doesn't count. Only with the `testutil`
+    /// feature, so nothing else pays for keeping track.
+    #[cfg(feature = "testutil")]
+    pub fn peak_buffered_bytes(&self) -> u64 {
+        self.inner.peak_buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "testutil")]
+    fn note_buffered(&self, bytes: usize) {
+        self.inner
+            .peak_buffered_bytes
+            .fetch_max(bytes as u64, Ordering::Relaxed);
     }
 }
 
@@ -20,32 +192,94 @@ impl<S, E> std::fmt::Debug for Compress<S, E>
 where
     S: Stream + std::fmt::Debug,
     S::Item: std::fmt::Debug,
+    E: std::fmt::Debug,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Compress")
             .field("stream", &self.stream)
             // .field("cstream", &self.cstream)
+            .field("chunker", &self.chunker)
             .field("buf_out", &self.buf_out)
             .field("wrote_seek_table", &self.wrote_seek_table)
+            .field("finalize_on_error", &self.finalize_on_error)
+            .field("pending_error", &self.pending_error)
+            .field("part_alignment", &self.part_alignment)
+            .field("held", &self.held)
+            .field("held_frame_ends", &self.held_frame_ends)
+            .field("ready_parts", &self.ready_parts)
+            .field("encryptor", &self.encryptor.is_some())
+            .field("frame_buf", &self.frame_buf)
+            .field("frames_encrypted", &self.frames_encrypted)
+            .field("frame_per_item", &self.frame_per_item)
+            .field("omit_seek_table", &self.omit_seek_table)
+            .field("by_frame", &self.by_frame)
+            .field("metadata", &self.metadata)
+            .field("expected_len", &self.expected_len)
+            .field("plan_pending", &self.plan_pending)
+            .field("manifest", &self.manifest.is_some())
+            .field("poll_budget", &self.poll_budget)
+            .field("leftover", &self.leftover.len())
+            .field("min_frames", &self.min_frames)
+            .field("held_input", &self.held_input.len())
+            .field("input_ended", &self.input_ended)
+            .field("held_error", &self.held_error)
+            .field("max_frame_age", &self.max_frame_age.map(|(age, _)| age))
+            .field("max_output_bytes", &self.max_output_bytes)
+            .field("over_output_limit", &self.over_output_limit)
+            .field("trailing_index", &self.trailing_index.is_some())
+            .field("totals_frame", &self.totals_frame)
+            .field("header", &self.header.len())
+            .field("progress", &self.progress)
             .finish()
     }
 }
 
 pub trait StreamCompress {
+    /// Compresses the stream into a seekable object with frames of
+    /// `frame_size` bytes, 0 for the largest ones. Fails, naming the
+    /// setting, if the level is out of zstd's range or the frame size past
+    /// the largest the seekable format allows.
+    ///
+    /// Levels go from 1 up to 22, and 0 picks zstd's default, 3. Negative
+    /// levels, down to -131072, trade ratio for speed, faster and worse the
+    /// further down they go; -1 to -5 or so are the useful ones. As with
+    /// zstd itself, levels past 19 take a lot of memory to decompress.
+    ///
+    /// Frames end every `frame_size` bytes of input however the input is
+    /// split into items, and the output is the same byte for byte whatever
+    /// the split: only [`Compress::frame_per_item`] and
+    /// [`Compress::max_frame_age`] make the layout depend on how and when the
+    /// input comes. Empty items, such as heartbeats, are skipped as if they
+    /// weren't there at all, with those two as well.
     fn compress<I, E>(
         self,
-        compression_level: usize,
+        compression_level: i32,
         frame_size: usize,
     ) -> ZstdError<Compress<Self, E>>
     where
         Self: Stream<Item = Result<I, E>> + Sized,
         I: std::borrow::Borrow<[u8]>;
+
+    /// Like [`compress`](Self::compress) but for streams that can't fail,
+    /// saving you from wrapping every item in `Ok`.
+    fn compress_infallible<I>(
+        self,
+        compression_level: i32,
+        frame_size: usize,
+    ) -> ZstdError<CompressInfallible<Self, I>>
+    where
+        Self: Stream<Item = I> + Sized,
+        I: std::borrow::Borrow<[u8]>;
 }
 
+/// What [`StreamCompress::compress_infallible`] returns.
+pub type CompressInfallible<S, I> =
+    Compress<stream::Map<S, fn(I) -> Result<I, Infallible>>, Infallible>;
+
 impl<S> StreamCompress for S {
     fn compress<I, E>(
         self,
-        compression_level: usize,
+        compression_level: i32,
         frame_size: usize,
     ) -> ZstdError<Compress<Self, E>>
     where
@@ -60,26 +294,489 @@ impl<S> StreamCompress for S {
     {
         Compress::new(self, compression_level, frame_size)
     }
+
+    fn compress_infallible<I>(
+        self,
+        compression_level: i32,
+        frame_size: usize,
+    ) -> ZstdError<CompressInfallible<Self, I>>
+    where
+        Self: Stream<Item = I> + Sized,
+        I: std::borrow::Borrow<[u8]>,
+    {
+        let ok: fn(I) -> Result<I, Infallible> = Ok;
+        Compress::new(self.map(ok), compression_level, frame_size)
+    }
 }
 
+/// What [`Compress::arc_chunks`] returns.
+pub type CompressArc<S, E> = stream::MapOk<Compress<S, E>, fn(Bytes) -> Arc<[u8]>>;
+
+/// A reasonable budget for [`Compress::poll_budget`].
+pub const DEFAULT_POLL_BUDGET: usize = 256 * 1024;
+
 impl<S, E> Compress<S, E> {
-    fn new<I>(stream: S, compression_level: usize, frame_size: usize) -> ZstdError<Self>
+    fn new<I>(stream: S, compression_level: i32, frame_size: usize) -> ZstdError<Self>
     where
         S: Stream<Item = Result<I, E>>,
         I: std::borrow::Borrow<[u8]>,
     {
-        let cstream =
-            parking_lot::const_mutex(SeekableCStream::new(compression_level, frame_size)?);
+        let cstream = parking_lot::const_mutex(FrameCStream::new(compression_level, frame_size)?);
         let buf_out = vec![0; CStream::out_size()].into_boxed_slice();
         Ok(Self {
             stream,
             cstream,
+            chunker: Chunker::new(FrameBoundary::Fixed)?,
             buf_out,
             wrote_seek_table: false,
-            error_type: PhantomData,
+            finalize_on_error: false,
+            pending_error: None,
+            part_alignment: None,
+            held: BytesMut::new(),
+            held_frame_ends: Vec::new(),
+            ready_parts: VecDeque::new(),
+            encryptor: None,
+            frame_buf: Vec::new(),
+            frames_encrypted: 0,
+            frame_per_item: false,
+            omit_seek_table: false,
+            by_frame: false,
+            metadata: Vec::new(),
+            expected_len: None,
+            plan_pending: false,
+            manifest: None,
+            poll_budget: None,
+            leftover: Bytes::new(),
+            min_frames: None,
+            held_input: BytesMut::new(),
+            input_ended: false,
+            held_error: None,
+            max_frame_age: None,
+            frame_deadline: parking_lot::const_mutex(None),
+            max_output_bytes: None,
+            over_output_limit: false,
+            trailing_index: None,
+            totals_frame: false,
+            header: Bytes::new(),
+            progress: CompressProgress::default(),
         })
     }
 
+    /// What to do when the upstream yields an error.
+    ///
+    /// By default the error is passed through as
+    /// [`CompressError::Underlying`] and no seek table is written for it: if
+    /// the consumer stops there, whatever was written out so far is a
+    /// truncated object that can't be seeked in.
+    ///
+    /// With `finalize_on_error` set, the frame in progress is closed and the
+    /// seek table for everything compressed so far is yielded first, followed
+    /// by the upstream error after which the stream ends. The output up to
+    /// the error is then a valid seekable object covering all the data
+    /// consumed before the failure.
+    pub fn finalize_on_error(mut self, finalize_on_error: bool) -> Self {
+        self.finalize_on_error = finalize_on_error;
+        self
+    }
+
+    /// Where to end frames. By default every frame holds the `frame_size`
+    /// bytes given to [`compress`](crate::StreamCompress::compress), see
+    /// [`FrameBoundary`] for the alternatives.
+    ///
+    /// Fails if the boundary settings are out of range. This has to be set
+    /// before any data is compressed.
+    pub fn frame_boundary(mut self, frame_boundary: FrameBoundary) -> ZstdError<Self> {
+        self.chunker = Chunker::new(frame_boundary)?;
+        if let Some(max_frame_size) = self.chunker.max_frame_size() {
+            self.cstream.get_mut().set_max_frame_size(max_frame_size);
+        }
+        Ok(self)
+    }
+
+    /// Align output to multipart upload parts of (at least) `part_size`
+    /// bytes, so that every part holds a whole number of frames and can be
+    /// fetched and decompressed on its own.
+    ///
+    /// Output is held back until a frame ends with at least `part_size` bytes
+    /// buffered, so every yielded chunk but the last one ends on a frame
+    /// boundary and is at least `part_size` long. Feed the stream into
+    /// [`upload_parts`](crate::StreamUploadParts::upload_parts) with the same
+    /// `part_size` as the minimum part size: as that only ever cuts parts
+    /// after a whole chunk, every part then starts and ends on a frame
+    /// boundary. [`SeekTable::aligned_parts`](crate::SeekTable::aligned_parts)
+    /// tells which frames ended up in which part.
+    ///
+    /// We never end frames early to do this, so there is no cost in
+    /// compression ratio. Instead, parts grow past `part_size` up to the next
+    /// frame boundary: up to one compressed frame to each part. The cost is
+    /// memory, as up to one part and one frame of output is buffered.
+    pub fn align_to_parts(mut self, part_size: usize) -> Self {
+        self.part_alignment = Some(part_size);
+        self
+    }
+
+    /// Size of the buffer compressed data is written to before being copied
+    /// into the output, [`CStream::out_size`] unless set with
+    /// [`with_out_buffer_len`](Self::with_out_buffer_len).
+    pub fn out_buffer_len(&self) -> usize {
+        self.buf_out.len()
+    }
+
+    /// Size the buffer compressed data is written to before being copied
+    /// into the output. Compressing goes round the loop once per buffer of
+    /// output, so a smaller buffer saves memory at the cost of more
+    /// iterations and a larger one the other way round. The default,
+    /// [`CStream::out_size`], is what zstd recommends and is plenty for most
+    /// workloads: see the `compress` benchmark before changing it.
+    ///
+    /// Any size works, down to a single byte. The compressed bytes may come
+    /// out slightly different with different sizes, as zstd flushes blocks
+    /// differently when the buffer fills up. Fails if `len` is 0 or over
+    /// 2 GiB.
+    pub fn with_out_buffer_len(mut self, len: usize) -> ZstdError<Self> {
+        if len == 0 || len > MAX_FRAME_SIZE {
+            return Err(config_error(
+                "out_buffer_len",
+                len,
+                &format!("from 1 to {}", MAX_FRAME_SIZE),
+            ));
+        }
+        self.buf_out = vec![0; len].into_boxed_slice();
+        Ok(self)
+    }
+
+    /// Compress at most `budget` bytes of input every time the stream is
+    /// polled, so that a large item doesn't hold up the executor for as long
+    /// as it takes to compress all of it. Whatever compressed output there is
+    /// goes out when the budget runs out, or if there isn't any yet, the
+    /// stream wakes itself up and returns `Pending` to let other tasks run.
+    ///
+    /// Items bigger than the budget are copied to hold on to the rest of
+    /// them, and output comes out in more, smaller pieces. The compressed
+    /// data is the same either way. By default there's no budget;
+    /// [`DEFAULT_POLL_BUDGET`] is a reasonable one, taking on the order of a
+    /// millisecond at fast compression levels.
+    pub fn poll_budget(mut self, budget: usize) -> Self {
+        self.poll_budget = Some(budget.max(1));
+        self
+    }
+
+    /// End frames that have been open for `max_frame_age`, counting from
+    /// when the first byte went in, even if they're short of the frame size.
+    /// Data from a producer that goes quiet then goes out within about that
+    /// long rather than waiting in the compressor for the rest of the frame.
+    ///
+    /// Whichever comes first ends the frame: reaching the frame size, or any
+    /// other boundary from [`frame_boundary`](Self::frame_boundary) or
+    /// [`frame_per_item`](Self::frame_per_item), or getting too old. Either
+    /// way the next frame starts its clock afresh with its first byte.
+    /// Frames are only ended between upstream items, so an item that takes
+    /// longer than this to arrive still waits for it. Every early frame
+    /// compresses less well and costs another entry in the seek table, so
+    /// keep the age well above how often data normally comes in. A
+    /// [`frame_plan`](Self::frame_plan) can't be used with this.
+    ///
+    /// The timer is tokio's: the stream has to be polled on a tokio runtime
+    /// with the time driver enabled.
+    #[cfg(feature = "tokio")]
+    pub fn max_frame_age(mut self, max_frame_age: Duration) -> Self {
+        self.max_frame_age = Some((max_frame_age, |age| Box::pin(tokio::time::sleep(age))));
+        self
+    }
+
+    /// Fail with [`CompressError::OutputTooLarge`] rather than yield more
+    /// than `limit` bytes of output in all, seek table included, for example
+    /// to keep incompressible data from running past a storage quota. The
+    /// stream ends after the error.
+    ///
+    /// The output yielded before the error is never more than `limit` bytes,
+    /// but it's not a complete object either: it's up to you to throw it
+    /// away. [`compress_to_s3`](crate::compress_to_s3()) aborts the multipart
+    /// upload on this error as on any other. When feeding
+    /// [`upload_parts`](crate::StreamUploadParts::upload_parts) yourself,
+    /// abort the multipart upload once this comes out, or the parts uploaded
+    /// so far stay around, and get billed, until something does.
+    pub fn max_output_bytes(mut self, limit: u64) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// A handle to observe how far along we are. Grab it before handing the
+    /// stream off.
+    pub fn progress(&self) -> CompressProgress {
+        self.progress.clone()
+    }
+
+    /// End a frame after every upstream item, so each item can be
+    /// decompressed on its own as soon as it's written out. Items larger
+    /// than the frame size still get split over several frames and empty
+    /// items don't get a frame at all.
+    ///
+    /// Compressing every item on its own does a lot worse than compressing
+    /// them together when items are small, and every frame costs another
+    /// 12 bytes in the seek table, so this is best kept to items of at least
+    /// a few kilobytes.
+    pub fn frame_per_item(mut self, frame_per_item: bool) -> Self {
+        self.frame_per_item = frame_per_item;
+        self
+    }
+
+    /// Whether to write the seek table at the end, which is the default.
+    ///
+    /// Without it, the output is a plain stream of zstd frames: any zstd
+    /// decoder reads it, but it can't be seeked in, not by us anyway. With
+    /// it, the seek table sits in a skippable frame which the `zstd` command
+    /// line tool, libzstd and decoders following the format spec skip over,
+    /// so they read seekable objects just fine too. Turn the seek table off
+    /// for tools that reject skippable frames.
+    pub fn seek_table(mut self, seek_table: bool) -> Self {
+        self.omit_seek_table = !seek_table;
+        self
+    }
+
+    /// Whether to store a checksum of every frame's data, the low 32 bits of
+    /// its XXH64 hash, in the seek table, which is the default. Readers check
+    /// frames against it,
+    /// see [`SeekTable::frame_checksum`](crate::SeekTable::frame_checksum),
+    /// catching corruption zstd itself doesn't notice. Without checksums,
+    /// the seek table takes 8 bytes per frame rather than 12 and compressing
+    /// skips hashing the input.
+    pub fn checksum_frames(mut self, checksum_frames: bool) -> Self {
+        self.cstream.get_mut().set_checksums(checksum_frames);
+        self
+    }
+
+    /// Whether to skip compressing data that doesn't compress, such as JPEGs
+    /// or data that's already compressed, and store it as is instead. Off
+    /// by default. zstd already falls back to storing blocks it can't
+    /// shrink, so the output barely grows either way: what this saves is the
+    /// CPU time spent finding that out.
+    ///
+    /// The heuristic goes frame by frame: after a frame that compressed to
+    /// more than 97% of its size, the next one is stored without trying, as
+    /// a regular zstd frame of raw blocks. One frame in every 8 is still
+    /// compressed to see if the data started compressing again. Stored
+    /// frames are a few bytes bigger than their data, which is a little
+    /// worse than what zstd would have made of them, and data that does
+    /// compress but only after some incompressible frames loses out on up to
+    /// 7 frames' worth of compression. This has to be set before any data is
+    /// compressed.
+    pub fn store_incompressible(mut self, store_incompressible: bool) -> Self {
+        self.cstream
+            .get_mut()
+            .set_store_incompressible(store_incompressible);
+        self
+    }
+
+    /// Yield exactly one item per frame, holding that frame's compressed
+    /// bytes and nothing else, rather than swathes of output as the
+    /// compressor produces it. The seek table comes last as an item of its
+    /// own. A [header](Self::header) is the exception: it goes in front of
+    /// the first frame, in the same item.
+    ///
+    /// Output is held back until the frame it belongs to ends, so up to one
+    /// compressed frame is buffered. This takes precedence over
+    /// [`align_to_parts`](Self::align_to_parts).
+    pub fn by_frame(mut self) -> Self {
+        self.by_frame = true;
+        self
+    }
+
+    /// Yield every frame on its own, as with [`by_frame`](Self::by_frame),
+    /// along with which frame it is and where its data sits in the
+    /// decompressed object, for storage that indexes frames as they come in
+    /// without parsing anything.
+    ///
+    /// Chunks come in the order they make up the object: the
+    /// [metadata](Self::metadata) frame and the [frame plan](Self::frame_plan)
+    /// first if there are any, as frames without any data, then every frame
+    /// of data, then the seek table as the footer unless it's
+    /// [turned off](Self::seek_table). Frame indices match the seek table's
+    /// and go up by one with every frame. Errors pass through as they come.
+    pub fn annotated(mut self) -> Annotated<S, E> {
+        self.by_frame = true;
+        Annotated {
+            compress: self,
+            chunks: 0,
+        }
+    }
+
+    /// Stores `key` and `value` in the object, for example the name and
+    /// modification time of the file it came from, to read back with
+    /// [`SeekableDecompress::metadata`](crate::SeekableDecompress::metadata).
+    /// Pairs are kept in the order they're added and keys can repeat.
+    ///
+    /// The pairs go in front of the data and cost 12 bytes plus 8 for every
+    /// pair on top of the keys and values themselves: keep it to small
+    /// things, it's not meant for anything larger than a few kilobytes.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Writes an index of the application's own after the data, such as
+    /// which keys are in which frame, making the object queryable without
+    /// anything stored alongside it. `index` is called once all the data is
+    /// compressed, with the seek table of every frame so far, and gives the
+    /// bytes to store. Read them back with
+    /// [`SeekableDecompress::trailing_index`](crate::SeekableDecompress::trailing_index).
+    ///
+    /// The index goes right in front of the seek table. Compression fails at
+    /// the end if it's 4GiB or more.
+    pub fn trailing_index(
+        mut self,
+        index: impl FnOnce(&SeekTable) -> Vec<u8> + Send + 'static,
+    ) -> Self {
+        self.trailing_index = Some(Mutex::new(Box::new(index)));
+        self
+    }
+
+    /// Writes the decompressed length and the number of frames in a small
+    /// frame of its own right in front of the seek table, for readers that
+    /// only want the size: it's at a fixed distance from the end given the
+    /// footer, so that's two small reads rather than reading the whole seek
+    /// table. Read it back with
+    /// [`SeekableDecompress::quick_totals`](crate::SeekableDecompress::quick_totals),
+    /// or find it from the footer with
+    /// [`quick_totals_range`](crate::quick_totals_range). Off by default.
+    ///
+    /// This says nothing the seek table doesn't, it's only cheaper to get
+    /// at. The frame is 24 bytes and the frame count includes it. There's no
+    /// frame without a seek table, and with
+    /// [`encrypt_frames`](Self::encrypt_frames) it's encrypted like every
+    /// other frame, so only readers decrypting it can make sense of it.
+    pub fn totals_frame(mut self, totals_frame: bool) -> Self {
+        self.totals_frame = totals_frame;
+        self
+    }
+
+    /// Puts `header` in front of the object, outside of zstd altogether,
+    /// such as the magic number and version of an envelope of the
+    /// application's own. It goes out as the first item on its own, or with
+    /// [`align_to_parts`](Self::align_to_parts) and
+    /// [`by_frame`](Self::by_frame), at the start of the first part or frame
+    /// so parts still end on frame boundaries. It's counted in
+    /// [`bytes_out`](CompressProgress::bytes_out) and
+    /// [`max_output_bytes`](Self::max_output_bytes).
+    ///
+    /// Nothing else knows about it: the seek table, and with it the
+    /// [manifest](Self::manifest), describe the object after the header as
+    /// though it weren't there, and zstd decoders won't take it. Read the
+    /// object with [`SkipHeader`](crate::SkipHeader), or from S3 with
+    /// [`SeekableS3Object::set_header_len`](crate::SeekableS3Object::set_header_len),
+    /// to get past it.
+    pub fn header(mut self, header: impl Into<Bytes>) -> Self {
+        self.header = header.into();
+        self
+    }
+
+    /// Writes a [`FramePlan`] at the start of the object, for readers that
+    /// go through it as it arrives and want to know up front where every
+    /// frame starts in the data. The seek table still goes at the end as
+    /// usual: compressed sizes aren't known until the frames are compressed,
+    /// so the plan can only give decompressed offsets, which it works out
+    /// from `total_len` and the `frame_size` given to
+    /// [`compress`](crate::StreamCompress::compress).
+    ///
+    /// The plan goes in a skippable frame after any
+    /// [metadata](Self::metadata), taking up a frame with no data in the seek
+    /// table. For it to hold, the input has to be exactly `total_len` bytes
+    /// long and frames can only end every `frame_size` bytes: compression
+    /// fails if the input turns out any longer or shorter, and when it
+    /// starts if frames are to end anywhere else, with
+    /// [`frame_boundary`](Self::frame_boundary) or
+    /// [`frame_per_item`](Self::frame_per_item).
+    pub fn frame_plan(mut self, total_len: u64) -> Self {
+        self.expected_len = Some(total_len);
+        self.plan_pending = true;
+        self
+    }
+
+    /// Makes frames smaller than the `frame_size` given to
+    /// [`compress`](crate::StreamCompress::compress) if that's what it takes
+    /// for there to be at least `min_frames` of them, so that even small
+    /// objects can be read in parallel. Frames all get the same size, as
+    /// large as it can be for that.
+    ///
+    /// The object's length has to be known to pick the size. With a
+    /// [frame plan](Self::frame_plan) it is, and nothing changes except the
+    /// size. Otherwise this is best effort going by what input comes: up to
+    /// `min_frames` frames' worth of input is held back, uncompressed, until
+    /// either that much comes and the frame size stands, or the input ends
+    /// short of it and the frame size is cut down to fit. So count on that
+    /// much memory and on nothing coming out until then. Should the upstream
+    /// fail in the meantime, it's taken to have ended there: what came before
+    /// the error is compressed first.
+    ///
+    /// There can only be as many frames as there are bytes, and the extra
+    /// empty frame at the end of input that fills its frames exactly isn't
+    /// a frame anyone reads in parallel, so it doesn't count. Compression
+    /// fails when it starts if frames are to end anywhere but every
+    /// `frame_size` bytes, with [`frame_boundary`](Self::frame_boundary),
+    /// [`frame_per_item`](Self::frame_per_item) or
+    /// [`max_frame_age`](Self::max_frame_age).
+    pub fn min_frames(mut self, min_frames: usize) -> Self {
+        // One frame there always is.
+        self.min_frames = (min_frames > 1).then_some(min_frames);
+        self
+    }
+
+    /// Builds a [`Manifest`](crate::Manifest) of the object as it's
+    /// compressed, hashing the content with `hasher`, for storing alongside
+    /// it without a second pass over the data. The future resolves as soon as
+    /// the seek table is yielded.
+    pub fn manifest(
+        mut self,
+        hasher: impl ContentHasher + Send + 'static,
+    ) -> (Self, ManifestFuture) {
+        let (builder, future) = ManifestBuilder::new(hasher);
+        self.manifest = Some(builder);
+        (self, future)
+    }
+
+    /// Hashes the output with `hasher` as it goes out, passing it on as is,
+    /// for naming the object by its hash when uploading it. The hash covers
+    /// every byte in the order it's yielded, seek table included, and the
+    /// future resolves once the stream ends. See [`manifest`](Self::manifest)
+    /// for hashing the decompressed content instead.
+    pub fn hash_stream(
+        self,
+        hasher: impl ContentHasher + Send + 'static,
+    ) -> (HashStream<Self>, HashFuture) {
+        HashStream::new(self, hasher)
+    }
+
+    /// Encrypt every frame with `encryptor` after compressing it, see
+    /// [`Encryptor`] for the format and, more importantly, what this does and
+    /// does not protect.
+    ///
+    /// Frames are only encrypted whole, so up to one compressed frame of
+    /// output is held back.
+    pub fn encrypt_frames(mut self, encryptor: impl Encryptor + Send + 'static) -> Self {
+        self.encryptor = Some(Mutex::new(Box::new(encryptor)));
+        self
+    }
+
+    /// Compresses the data of every frame with `compressor` rather than
+    /// zstd at the level given to
+    /// [`compress`](crate::StreamCompress::compress), for testing code built
+    /// on this with a compressor that's cheap and predictable. Frames, the
+    /// seek table and everything else come out as they would otherwise,
+    /// going by what the compressor gives, but unless it writes zstd frames
+    /// the output isn't readable. See [`FrameCompressor`].
+    pub fn frame_compressor(mut self, compressor: impl FrameCompressor + Send + 'static) -> Self {
+        self.cstream.get_mut().set_compressor(Box::new(compressor));
+        self
+    }
+
+    // Writes the object as the rest of one whose frames so far are in
+    // `seek_table`, for picking up where a spill left off.
+    #[cfg(feature = "s3")]
+    pub(crate) fn continue_after(mut self, seek_table: crate::SeekTable) -> Self {
+        self.cstream.get_mut().continue_after(seek_table);
+        self
+    }
+
     fn next_input<I>(
         self: &mut Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -88,83 +785,680 @@ impl<S, E> Compress<S, E> {
         S: Stream<Item = Result<I, E>>,
         I: std::borrow::Borrow<[u8]>,
     {
-        self.as_mut().project().stream.poll_next(cx)
+        let this = self.as_mut().project();
+        if *this.input_ended {
+            return std::task::Poll::Ready(this.held_error.take().map(Err));
+        }
+        this.stream.poll_next(cx)
+    }
+
+    // Picks the frame size for Compress::min_frames once we know enough about
+    // how long the input is, `ended` if that's all of it. The input held back
+    // until then goes in next as if it was a single item.
+    fn settle_frame_size(self: &mut Pin<&mut Self>, ended: bool) -> Result<(), CompressError<E>> {
+        let this = self.as_mut().project();
+        let min_frames = match *this.min_frames {
+            Some(min_frames) => min_frames as u64,
+            None => return Ok(()),
+        };
+        let cstream = this.cstream.get_mut();
+        let frame_size = cstream.max_frame_size() as u64;
+        let held = this.held_input.len() as u64;
+        let len = match *this.expected_len {
+            Some(total_len) => total_len,
+            None if ended || held >= min_frames.saturating_mul(frame_size) => held,
+            None => return Ok(()),
+        };
+        if this.chunker.max_frame_size().is_some()
+            || *this.frame_per_item
+            || this.max_frame_age.is_some()
+        {
+            return Err(CompressError::ZstdError(zstd_seekable::Error::Io(
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "min_frames needs frames of a fixed size, without frame_boundary, frame_per_item or max_frame_age.",
+                ),
+            )));
+        }
+        let fitting = ((len + min_frames - 1) / min_frames).max(1);
+        if fitting < frame_size {
+            cstream.set_max_frame_size(fitting as usize);
+        }
+        *this.min_frames = None;
+        *this.input_ended = ended;
+        *this.leftover = this.held_input.split().freeze();
+        Ok(())
     }
 
-    fn compress_input(self: &mut Pin<&mut Self>, mut input: &[u8]) -> ZstdError<bytes::Bytes> {
+    // Compresses some input, `item_end` if it's the end of an upstream
+    // item.
+    fn compress_input(
+        self: &mut Pin<&mut Self>,
+        mut input: &[u8],
+        item_end: bool,
+    ) -> Result<bytes::Bytes, CompressError<E>> {
         // Don't bother doing anything at all if we didn't get any input in.
         if input.is_empty() {
             return Ok(Bytes::new());
         }
+        instrument::bytes_in(input.len());
+        self.progress
+            .inner
+            .bytes_in
+            .fetch_add(input.len() as u64, Ordering::Relaxed);
+        let _timer = instrument::CompressTimer::start();
+        let (leading, leading_ends) = self.take_leading_frames()?;
+        self.check_len(false)?;
 
         let this = self.as_mut().project();
-        let cstream: &mut SeekableCStream = this.cstream.get_mut();
+        if let Some(manifest) = this.manifest {
+            manifest.update(input);
+        }
+        let cstream: &mut FrameCStream = this.cstream.get_mut();
+        let chunker: &mut Chunker = this.chunker;
         let buf_out: &mut [u8] = this.buf_out;
+        let frame_per_item = *this.frame_per_item;
         // It might seem wasteful to make a vector even if we end up only
         // decompressing once. However, Bytes::copy_from_slice just makes a
-        // vector anyway and converts from there.
-        let mut compressed_bytes = Vec::new();
+        // vector anyway and converts from there. Sizing it for the worst case
+        // up front saves growing it over and over for large items.
+        let mut compressed_bytes =
+            Vec::with_capacity(leading.len() + cstream.compress_bound(input.len()));
+        compressed_bytes.extend_from_slice(&leading);
+        // Where the frames we finished end in the output.
+        let mut frame_ends = leading_ends;
         while !input.is_empty() {
-            let (out_pos, in_pos) = cstream.compress(buf_out, input)?;
+            // Work out how much of the input goes in the current frame and
+            // whether the frame ends there.
+            let boundary = chunker.find_boundary(input);
+            let (mut frame_input, rest) = input.split_at(boundary.unwrap_or(input.len()));
+            while !frame_input.is_empty() {
+                let frames = cstream.num_frames();
+                let (out_pos, in_pos) = cstream.compress(buf_out, frame_input)?;
+                compressed_bytes.extend_from_slice(&buf_out[..out_pos]);
+                frame_input = &frame_input[in_pos..];
+                if cstream.num_frames() > frames {
+                    frame_ends.push(compressed_bytes.len());
+                }
+            }
+            if boundary.is_some() || (frame_per_item && item_end && rest.is_empty()) {
+                let frames = cstream.num_frames();
+                loop {
+                    let (out_pos, done) = cstream.flush_frame(buf_out)?;
+                    compressed_bytes.extend_from_slice(&buf_out[..out_pos]);
+                    if done {
+                        break;
+                    }
+                }
+                if cstream.num_frames() > frames {
+                    frame_ends.push(compressed_bytes.len());
+                }
+                chunker.reset();
+            }
+            input = rest;
+        }
+        // Start the clock on a frame that just got its first data.
+        if let Some((max_frame_age, new_deadline)) = *this.max_frame_age {
+            let frame = cstream.num_frames();
+            let deadline = this.frame_deadline.get_mut();
+            if cstream.frame_in_progress()
+                && !matches!(deadline, Some((deadline_frame, _)) if *deadline_frame == frame)
+            {
+                *deadline = Some((frame, new_deadline(max_frame_age)));
+            }
+        }
+        // The output holds on to the whole buffer once it goes out, so don't
+        // let data that compressed well keep the worst case allocated.
+        if compressed_bytes.len() < compressed_bytes.capacity() / 2 {
+            compressed_bytes.shrink_to_fit();
+        }
+        let (compressed_bytes, frame_ends) = self
+            .encrypt(compressed_bytes, frame_ends)
+            .map_err(CompressError::Encrypt)?;
+        Ok(self.release(compressed_bytes, frame_ends))
+    }
+
+    // Gives the metadata frame and the frame plan the first time there's
+    // something to put them in front of, adding them to the seek table,
+    // along with where they end. Empty when there's neither or they're
+    // already out.
+    fn take_leading_frames(
+        self: &mut Pin<&mut Self>,
+    ) -> Result<(Vec<u8>, Vec<usize>), CompressError<E>> {
+        let this = self.as_mut().project();
+        let cstream = this.cstream.get_mut();
+        let mut frames = Vec::new();
+        let mut ends = Vec::new();
+        if !this.metadata.is_empty() {
+            let frame = metadata_frame(&std::mem::take(this.metadata))
+                .ok_or_else(|| zstd_error(ZSTD_ERROR_FRAME_PARAMETER_UNSUPPORTED))?;
+            cstream.push_skippable_frame(frame.len() as u32);
+            frames.extend_from_slice(&frame);
+            ends.push(frames.len());
+        }
+        if let (true, Some(total_len)) = (*this.plan_pending, *this.expected_len) {
+            *this.plan_pending = false;
+            if this.chunker.max_frame_size().is_some()
+                || *this.frame_per_item
+                || this.max_frame_age.is_some()
+            {
+                return Err(CompressError::ZstdError(zstd_seekable::Error::Io(
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "A frame plan needs frames of a fixed size, without frame_boundary, frame_per_item or max_frame_age.",
+                    ),
+                )));
+            }
+            let plan = FramePlan {
+                frame_size: cstream.max_frame_size() as u64,
+                total_len,
+            };
+            let frame = plan.to_frame();
+            cstream.push_skippable_frame(frame.len() as u32);
+            frames.extend_from_slice(&frame);
+            ends.push(frames.len());
+        }
+        Ok((frames, ends))
+    }
+
+    // Ends the frame in progress if it's been open for too long, giving its
+    // output. None if there's no such frame, in which case we'll be woken up
+    // when it does get too old.
+    fn end_old_frame(
+        self: &mut Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Option<Result<Bytes, CompressError<E>>> {
+        let this = self.as_mut().project();
+        let cstream = this.cstream.get_mut();
+        let deadline = this.frame_deadline.get_mut();
+        match deadline {
+            Some((frame, expired)) if *frame == cstream.num_frames() => {
+                expired.as_mut().poll(cx).is_ready().then_some(())?;
+            }
+            _ => return None,
+        }
+        *deadline = None;
+        this.chunker.reset();
+        let buf_out: &mut [u8] = this.buf_out;
+        let mut compressed_bytes = Vec::new();
+        let result = (|| loop {
+            let (out_pos, done) = cstream.flush_frame(buf_out)?;
             compressed_bytes.extend_from_slice(&buf_out[..out_pos]);
-            input = &input[in_pos..];
+            if done {
+                return Ok(());
+            }
+        })();
+        if let Err(e) = result {
+            return Some(Err(CompressError::ZstdError(e)));
+        }
+        let frame_ends = vec![compressed_bytes.len()];
+        Some(
+            self.encrypt(compressed_bytes, frame_ends)
+                .map_err(CompressError::Encrypt)
+                .map(|(compressed_bytes, frame_ends)| self.release(compressed_bytes, frame_ends)),
+        )
+    }
+
+    // Makes sure the input isn't longer than the frame plan says, or any
+    // shorter once it's all in.
+    fn check_len(self: &mut Pin<&mut Self>, ended: bool) -> Result<(), CompressError<E>> {
+        let expected_len = match self.expected_len {
+            Some(expected_len) => expected_len,
+            None => return Ok(()),
+        };
+        let len = self.progress.bytes_in();
+        if len > expected_len || (ended && len < expected_len) {
+            return Err(CompressError::ZstdError(zstd_seekable::Error::Io(
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Input is {}{} bytes long, the frame plan said {}.",
+                        if ended { "" } else { "at least " },
+                        len,
+                        expected_len
+                    ),
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    // When encrypting, swaps the frames that ended in the output for their
+    // encrypted versions and holds on to the output of the frame in
+    // progress. Gives the new output and where the frames end in it.
+    fn encrypt(
+        self: &mut Pin<&mut Self>,
+        compressed_bytes: Vec<u8>,
+        frame_ends: Vec<usize>,
+    ) -> Result<(Vec<u8>, Vec<usize>), CipherError> {
+        let this = self.as_mut().project();
+        let encryptor = match this.encryptor {
+            None => return Ok((compressed_bytes, frame_ends)),
+            Some(encryptor) => encryptor.get_mut(),
+        };
+        let cstream = this.cstream.get_mut();
+        let frame_buf: &mut Vec<u8> = this.frame_buf;
+        let base = frame_buf.len();
+        frame_buf.extend_from_slice(&compressed_bytes);
+
+        let mut encrypted = Vec::with_capacity(frame_buf.len());
+        let mut encrypted_ends = Vec::with_capacity(frame_ends.len());
+        let mut frame_start = 0;
+        for frame_end in frame_ends {
+            let frame_end = base + frame_end;
+            let plaintext = &frame_buf[frame_start..frame_end];
+            let frame = encrypt_frame(&mut **encryptor, *this.frames_encrypted, plaintext)?;
+            cstream.grow_frame(
+                *this.frames_encrypted,
+                (frame.len() - plaintext.len()) as u64,
+            );
+            encrypted.extend_from_slice(&frame);
+            encrypted_ends.push(encrypted.len());
+            *this.frames_encrypted += 1;
+            frame_start = frame_end;
+        }
+        frame_buf.drain(..frame_start);
+        Ok((encrypted, encrypted_ends))
+    }
+
+    // Decides how much of the output we can yield now. Unless we're aligning
+    // to parts, that's all of it. Otherwise we cut as many parts as we can at
+    // the first frame boundary past the part size, yield the first one and
+    // queue up the rest.
+    fn release(
+        self: &mut Pin<&mut Self>,
+        compressed_bytes: Vec<u8>,
+        frame_ends: Vec<usize>,
+    ) -> Bytes {
+        let this = self.as_mut().project();
+        // Every frame on its own is the same as parts of a single byte.
+        let part_size = match (*this.by_frame, this.part_alignment) {
+            (true, _) => 1,
+            (false, None) => return Bytes::from(compressed_bytes),
+            (false, Some(part_size)) => *part_size,
+        };
+        let held: &mut BytesMut = this.held;
+        let held_frame_ends: &mut Vec<usize> = this.held_frame_ends;
+        held_frame_ends.extend(frame_ends.into_iter().map(|end| held.len() + end));
+        held.extend_from_slice(&compressed_bytes);
+        // The most there is at once, before whole parts are cut off.
+        #[cfg(feature = "testutil")]
+        this.progress
+            .note_buffered(held.len() + this.ready_parts.iter().map(Bytes::len).sum::<usize>());
+
+        let mut cut = 0;
+        for &frame_end in held_frame_ends.iter() {
+            if frame_end - cut >= part_size {
+                this.ready_parts
+                    .push_back(held.split_to(frame_end - cut).freeze());
+                cut = frame_end;
+            }
         }
-        Ok(bytes::Bytes::from(compressed_bytes))
+        held_frame_ends.retain(|&frame_end| frame_end > cut);
+        for frame_end in held_frame_ends.iter_mut() {
+            *frame_end -= cut;
+        }
+        this.ready_parts.pop_front().unwrap_or_default()
+    }
+
+    fn next_ready_part(self: &mut Pin<&mut Self>) -> Option<Bytes> {
+        self.as_mut().project().ready_parts.pop_front()
     }
 
-    fn end_stream(self: &mut Pin<&mut Self>) -> ZstdError<Bytes> {
+    fn end_stream(self: &mut Pin<&mut Self>) -> Result<Bytes, CompressError<E>> {
+        let _timer = instrument::CompressTimer::start();
+        // Whatever we held back goes out now. If nothing was compressed,
+        // the metadata and frame plan still have to go in front of the empty
+        // frame.
+        let mut compressed_bytes = {
+            let this = self.as_mut().project();
+            this.chunker.reset();
+            this.held_frame_ends.clear();
+            this.held.split().to_vec()
+        };
+        self.check_len(true)?;
+        let (leading, ends) = self.take_leading_frames()?;
+        // With a frame per item, each of these is an item of its own.
+        let mut leading_frames = Vec::new();
+        if !leading.is_empty() {
+            let (leading, ends) = self
+                .encrypt(leading, ends)
+                .map_err(CompressError::Encrypt)?;
+            if self.by_frame {
+                let mut start = 0;
+                for end in ends {
+                    leading_frames.push(leading[start..end].to_vec());
+                    start = end;
+                }
+            } else {
+                compressed_bytes.extend_from_slice(&leading);
+            }
+        }
+        // The last frame has to be encrypted before the seek table gets
+        // written, and with a frame per item the last item already ended it,
+        // so end it separately in those cases. Same if there's to be no seek
+        // table at all, or it's to go out on its own.
+        let end_separately = self.encryptor.is_some()
+            || self.frame_per_item
+            || self.omit_seek_table
+            || self.by_frame
+            || self.trailing_index.is_some()
+            || self.totals_frame;
+        if end_separately {
+            let mut last_frame = Vec::new();
+            let mut frame_ends = Vec::new();
+            {
+                let this = self.as_mut().project();
+                let cstream = this.cstream.get_mut();
+                let frames = cstream.num_frames();
+                loop {
+                    // We always want at least one frame.
+                    let (out_pos, done) = if *this.frame_per_item && frames > 0 {
+                        cstream.flush_frame(this.buf_out)?
+                    } else {
+                        cstream.end_frame(this.buf_out)?
+                    };
+                    last_frame.extend_from_slice(&this.buf_out[..out_pos]);
+                    if done {
+                        break;
+                    }
+                }
+                if cstream.num_frames() > frames {
+                    frame_ends.push(last_frame.len());
+                }
+            }
+            let (last_frame, _) = self
+                .encrypt(last_frame, frame_ends)
+                .map_err(CompressError::Encrypt)?;
+            compressed_bytes.extend_from_slice(&last_frame);
+        }
+        let mut index = self.take_trailing_index()?;
+        let mut totals = self.take_totals_frame()?;
+        if !self.by_frame {
+            compressed_bytes.append(&mut index);
+            compressed_bytes.append(&mut totals);
+        }
+
         let this = self.as_mut().project();
-        let wrote_seek_table = this.wrote_seek_table;
-        let cstream: &mut Mutex<SeekableCStream> = this.cstream;
+        let cstream = this.cstream.get_mut();
         let buf_out: &mut [u8] = this.buf_out;
+        let mut seek_table = Vec::new();
+        let out = if *this.by_frame {
+            &mut seek_table
+        } else {
+            &mut compressed_bytes
+        };
+        if !*this.omit_seek_table {
+            loop {
+                let out_pos = if end_separately {
+                    cstream.write_seek_table(buf_out)
+                } else {
+                    cstream.end_stream(buf_out)?
+                };
+                if out_pos == 0 {
+                    break;
+                }
+                out.extend_from_slice(&buf_out[..out_pos]);
+            }
+        }
+        *this.wrote_seek_table = true;
+        // The last frame goes out now, and when they're to go out on their
+        // own, the frames in front of it and the index, the totals and the
+        // seek table after it.
+        leading_frames.extend([compressed_bytes, index, totals, seek_table]);
+        let mut pieces = leading_frames
+            .into_iter()
+            .filter(|piece| !piece.is_empty())
+            .map(Bytes::from);
+        let first = pieces.next().unwrap_or_default();
+        this.ready_parts.extend(pieces);
+        Ok(first)
+    }
 
-        let mut cstream = cstream.lock();
-        let mut out_pos = cstream.end_stream(buf_out)?;
-        let mut compressed_bytes = buf_out[..out_pos].to_vec();
-        while out_pos > 0 {
-            out_pos = cstream.end_stream(buf_out)?;
-            compressed_bytes.extend_from_slice(&buf_out[..out_pos])
+    // Gives the trailing index frame, adding it to the seek table. Empty if
+    // there's none. Must be called between frames.
+    fn take_trailing_index(self: &mut Pin<&mut Self>) -> Result<Vec<u8>, CompressError<E>> {
+        let this = self.as_mut().project();
+        let index = match this.trailing_index.take() {
+            Some(index) => index.into_inner(),
+            None => return Ok(Vec::new()),
+        };
+        let cstream = this.cstream.get_mut();
+        let frame = trailing_index_frame(&index(cstream.seek_table()))
+            .ok_or_else(|| zstd_error(ZSTD_ERROR_FRAME_PARAMETER_UNSUPPORTED))?;
+        cstream.push_skippable_frame(frame.len() as u32);
+        let ends = vec![frame.len()];
+        let (frame, _) = self.encrypt(frame, ends).map_err(CompressError::Encrypt)?;
+        Ok(frame)
+    }
+
+    // Gives the totals frame, adding it to the seek table. Empty if there's
+    // to be none. Must be called between frames, after every other one.
+    fn take_totals_frame(self: &mut Pin<&mut Self>) -> Result<Vec<u8>, CompressError<E>> {
+        let this = self.as_mut().project();
+        if !*this.totals_frame || *this.omit_seek_table {
+            return Ok(Vec::new());
         }
-        *wrote_seek_table = true;
-        Ok(Bytes::from(compressed_bytes))
+        let cstream = this.cstream.get_mut();
+        let seek_table = cstream.seek_table();
+        let frame = totals_frame(
+            seek_table.decompressed_len(),
+            seek_table.num_frames() as u64 + 1,
+        );
+        cstream.push_skippable_frame(frame.len() as u32);
+        let ends = vec![frame.len()];
+        let (frame, _) = self.encrypt(frame, ends).map_err(CompressError::Encrypt)?;
+        Ok(frame)
     }
 
     fn finished(self: &mut Pin<&mut Self>) -> bool {
         *self.as_mut().project().wrote_seek_table
     }
+
+    fn finish_manifest(self: &mut Pin<&mut Self>) {
+        let this = self.as_mut().project();
+        if let Some(manifest) = this.manifest {
+            let cstream = this.cstream.get_mut();
+            let seek_table = cstream.seek_table();
+            let mut compressed_len = seek_table.compressed_len();
+            if !*this.omit_seek_table {
+                compressed_len += seek_table.seek_table_len() as u64;
+            }
+            manifest.finish(seek_table, compressed_len);
+        }
+    }
+
+    fn take_pending_error(self: &mut Pin<&mut Self>) -> Option<E> {
+        self.as_mut().project().pending_error.take()
+    }
+
+    fn set_pending_error(self: &mut Pin<&mut Self>, e: E) {
+        *self.as_mut().project().pending_error = Some(e);
+    }
+}
+
+/// Compresses `data` in one go, appending the whole seekable object, seek
+/// table and all, to `out`. Gives how many bytes were appended.
+///
+/// This is for small objects you want in one piece anyway: the output goes
+/// straight into `out`, reusing whatever capacity it has, rather than through
+/// a stream of [`Bytes`].
+pub fn compress_blocking_into(
+    data: &[u8],
+    out: &mut Vec<u8>,
+    compression_level: i32,
+    frame_size: usize,
+) -> ZstdError<usize> {
+    let mut cstream = FrameCStream::new(compression_level, frame_size)?;
+    let start = out.len();
+    let mut written = start;
+    // The compressor writes into spare room at the end of the vector, which
+    // we then trim down to what it actually wrote.
+    let mut compress = || -> ZstdError<usize> {
+        let mut input = data;
+        while !input.is_empty() {
+            out.resize(written + CStream::out_size(), 0);
+            let (out_pos, in_pos) =
+                FrameCStream::compress(&mut cstream, &mut out[written..], input)?;
+            written += out_pos;
+            input = &input[in_pos..];
+        }
+        loop {
+            out.resize(written + CStream::out_size(), 0);
+            match cstream.end_stream(&mut out[written..])? {
+                0 => break Ok(written - start),
+                out_pos => written += out_pos,
+            }
+        }
+    };
+    let result = compress();
+    out.truncate(written);
+    let appended = result?;
+    instrument::bytes_in(data.len());
+    instrument::bytes_out(appended);
+    Ok(appended)
+}
+
+/// Compresses data handed over piece by piece without a stream or a runtime,
+/// with the caller deciding where frames end on top of `frame_size`, for
+/// building custom layouts from blocking code.
+///
+/// Output is held until [`close_frame`](Self::close_frame) or
+/// [`finish`](Self::finish) hand it over, so every piece of output ends at a
+/// frame boundary.
+pub struct BlockingCompress {
+    cstream: FrameCStream,
+    buf_out: Box<[u8]>,
+    // Output since the last close_frame.
+    out: Vec<u8>,
+}
+
+impl std::fmt::Debug for BlockingCompress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockingCompress")
+            .field("frames", &self.frames())
+            .field("pending_output", &self.out.len())
+            .finish()
+    }
+}
+
+impl BlockingCompress {
+    /// Fails if the compression settings are out of range.
+    pub fn new(compression_level: i32, frame_size: usize) -> ZstdError<Self> {
+        Ok(BlockingCompress {
+            cstream: FrameCStream::new(compression_level, frame_size)?,
+            buf_out: vec![0; CStream::out_size()].into_boxed_slice(),
+            out: Vec::new(),
+        })
+    }
+
+    /// Compresses `data`, ending frames every `frame_size` bytes as usual.
+    pub fn write(&mut self, mut data: &[u8]) -> ZstdError<()> {
+        instrument::bytes_in(data.len());
+        while !data.is_empty() {
+            let (out_pos, in_pos) =
+                FrameCStream::compress(&mut self.cstream, &mut self.buf_out, data)?;
+            self.out.extend_from_slice(&self.buf_out[..out_pos]);
+            data = &data[in_pos..];
+        }
+        Ok(())
+    }
+
+    /// Ends the current frame, leaving the object open for more data, and
+    /// gives all the output since the last call: the frame just closed along
+    /// with any that ended on their own before it. Nothing is closed if
+    /// nothing went into the frame, so this never makes empty frames.
+    pub fn close_frame(&mut self) -> ZstdError<Bytes> {
+        loop {
+            let (out_pos, done) = self.cstream.flush_frame(&mut self.buf_out)?;
+            self.out.extend_from_slice(&self.buf_out[..out_pos]);
+            if done {
+                break;
+            }
+        }
+        let out = Bytes::from(std::mem::take(&mut self.out));
+        instrument::bytes_out(out.len());
+        Ok(out)
+    }
+
+    /// Frames finished so far, whether closed by hand or not.
+    pub fn frames(&self) -> u64 {
+        self.cstream.num_frames() as u64
+    }
+
+    /// Ends the last frame and gives the rest of the object, seek table
+    /// included. The seek table covers every frame, closed by hand or not.
+    /// As with input ending on a frame boundary, the last frame is empty if
+    /// nothing was written since a frame was closed.
+    pub fn finish(mut self) -> ZstdError<Bytes> {
+        loop {
+            match self.cstream.end_stream(&mut self.buf_out)? {
+                0 => break,
+                out_pos => self.out.extend_from_slice(&self.buf_out[..out_pos]),
+            }
+        }
+        instrument::bytes_out(self.out.len());
+        Ok(Bytes::from(self.out))
+    }
 }
 
 type ZstdError<A> = std::result::Result<A, zstd_seekable::Error>;
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CompressError<E> {
     ZstdError(zstd_seekable::Error),
     Underlying(E),
+    // The encryptor given to Compress::encrypt_frames failed.
+    Encrypt(CipherError),
+    // The output would have gone past Compress::max_output_bytes.
+    OutputTooLarge { limit: u64 },
+}
+
+impl<E> From<zstd_seekable::Error> for CompressError<E> {
+    fn from(e: zstd_seekable::Error) -> Self {
+        CompressError::ZstdError(e)
+    }
 }
 
+// zstd's generic error code, for errors that have no better one.
+const ZSTD_ERROR_GENERIC: usize = 1;
+const ZSTD_ERROR_DST_SIZE_TOO_SMALL: usize = 70;
+
 impl From<CompressError<Infallible>> for zstd_seekable::Error {
     fn from(e: CompressError<Infallible>) -> Self {
         match e {
             CompressError::ZstdError(e) => e,
             CompressError::Underlying(inf) => panic!("The impossible happened: {}", inf),
+            // There's nothing more specific in zstd's errors.
+            CompressError::Encrypt(_) => zstd_error(ZSTD_ERROR_GENERIC),
+            CompressError::OutputTooLarge { .. } => zstd_error(ZSTD_ERROR_DST_SIZE_TOO_SMALL),
         }
     }
 }
 
-impl<E: std::fmt::Display> std::fmt::Display for CompressError<E> {
+// Errors we wrap are left to source(), except zstd's, which we only pass
+// along.
+impl<E> std::fmt::Display for CompressError<E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CompressError::ZstdError(e) => write!(f, "Compression error: {}", e),
-            CompressError::Underlying(e) => write!(f, "Underlying error: {}", e),
+            CompressError::ZstdError(e) => write!(f, "{}", e),
+            CompressError::Underlying(_) => write!(f, "The input stream failed."),
+            CompressError::Encrypt(_) => write!(f, "Encrypting a frame failed."),
+            CompressError::OutputTooLarge { limit } => {
+                write!(f, "Compressed output would be over {} bytes.", limit)
+            }
         }
     }
 }
 
-impl<E: std::error::Error + std::fmt::Display + 'static> std::error::Error for CompressError<E> {
+impl<E: std::error::Error + 'static> std::error::Error for CompressError<E> {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            CompressError::ZstdError(_) => None,
+            CompressError::ZstdError(e) => e.source(),
             CompressError::Underlying(e) => Some(e),
+            CompressError::Encrypt(e) => Some(&**e),
+            CompressError::OutputTooLarge { .. } => None,
         }
     }
 }
@@ -180,6 +1474,96 @@ where
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
+        if self.over_output_limit {
+            return std::task::Poll::Ready(None);
+        }
+        let mut header = std::mem::take(self.as_mut().project().header);
+        if !header.is_empty() && (self.by_frame || self.part_alignment.is_some()) {
+            // Held output only goes out at frame boundaries.
+            self.as_mut().project().held.extend_from_slice(&header);
+            header.clear();
+        }
+        let mut poll = if header.is_empty() {
+            self.poll_compressed(cx)
+        } else {
+            std::task::Poll::Ready(Some(Ok(header)))
+        };
+        if let (std::task::Poll::Ready(Some(Ok(bytes))), Some(limit)) =
+            (&poll, self.max_output_bytes)
+        {
+            if self.progress.bytes_out() + bytes.len() as u64 > limit {
+                *self.as_mut().project().over_output_limit = true;
+                poll = std::task::Poll::Ready(Some(Err(CompressError::OutputTooLarge { limit })));
+            }
+        }
+        if let std::task::Poll::Ready(Some(Ok(bytes))) = &poll {
+            instrument::bytes_out(bytes.len());
+            let counters = &self.progress.inner;
+            counters
+                .bytes_out
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            let frames = self.cstream.lock().num_frames();
+            counters.frames.store(frames as u64, Ordering::Relaxed);
+        }
+        // Everything's out once the seek table is, so the manifest is done.
+        if poll.is_ready() && self.finished() && self.ready_parts.is_empty() {
+            self.finish_manifest();
+        }
+        #[cfg(feature = "testutil")]
+        self.progress.note_buffered(
+            self.held.len()
+                + self.ready_parts.iter().map(Bytes::len).sum::<usize>()
+                + self.frame_buf.len()
+                + self.leftover.len()
+                + self.held_input.len(),
+        );
+        poll
+    }
+}
+
+impl<S, I, E> Compress<S, E>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    /// Yields the output as `Arc<[u8]>` rather than [`Bytes`], for APIs that
+    /// want it that way. Every chunk gets copied once on the way: an `Arc`
+    /// keeps its counts in the same allocation as the data, so there's no
+    /// turning a buffer into one without a copy. [`Bytes`] clone just as
+    /// cheaply, stick with them unless something needs an `Arc`.
+    pub fn arc_chunks(self) -> CompressArc<S, E> {
+        let to_arc: fn(Bytes) -> Arc<[u8]> = |bytes| Arc::from(&bytes[..]);
+        self.map_ok(to_arc)
+    }
+
+    /// Makes the output the [`http_body::Body`] of a request or response,
+    /// for hyper and anything else built on `http-body`. The error is
+    /// [`CompressError`], which turns into the
+    /// `Box<dyn Error + Send + Sync>` hyper wants when the upstream error is
+    /// `Error + Send + Sync + 'static` itself. reqwest takes a stream for
+    /// its bodies instead, which this already is: use
+    /// `reqwest::Body::wrap_stream`.
+    #[cfg(feature = "http-body")]
+    pub fn into_body(self) -> crate::CompressBody<Self> {
+        crate::CompressBody::new(self)
+    }
+
+    fn poll_compressed(
+        self: &mut Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<std::result::Result<Bytes, CompressError<E>>>> {
+        // Parts we cut but haven't yielded yet go out before we touch the
+        // upstream again.
+        if let Some(part) = self.next_ready_part() {
+            return std::task::Poll::Ready(Some(Ok(part)));
+        }
+
+        // We held on to an upstream error to write out the seek table first.
+        // Now that it's out, pass the error on.
+        if let Some(e) = self.take_pending_error() {
+            return std::task::Poll::Ready(Some(Err(CompressError::Underlying(e))));
+        }
+
         // We've already consumed everything and finalised our compression
         // stream. Yield nothing. Notably, we don't want to poke the upstream
         // again.
@@ -187,10 +1571,65 @@ where
             return std::task::Poll::Ready(None);
         }
 
+        let budget = self.poll_budget.unwrap_or(usize::MAX);
+        let mut spent = 0;
         std::task::Poll::Ready(loop {
+            if spent >= budget {
+                // Out of budget with nothing to show for it: let others run
+                // and pick up where we left off next time.
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            let allowance = budget - spent;
+            // Finish the item that went over the budget before taking on
+            // another one.
+            if !self.leftover.is_empty() {
+                let this = self.as_mut().project();
+                let chunk = this.leftover.split_to(allowance.min(this.leftover.len()));
+                let item_end = this.leftover.is_empty();
+                spent += chunk.len();
+                match self.compress_input(&chunk, item_end) {
+                    Err(e) => break Some(Err(e)),
+                    Ok(compressed_data) if !compressed_data.is_empty() => {
+                        break Some(Ok(compressed_data))
+                    }
+                    Ok(_) => continue,
+                }
+            }
+            match self.end_old_frame(cx) {
+                Some(Err(e)) => break Some(Err(e)),
+                Some(Ok(compressed_data)) if !compressed_data.is_empty() => {
+                    break Some(Ok(compressed_data))
+                }
+                _ => {}
+            }
             match ready!(self.next_input(cx)) {
+                // Empty items make no difference to anything, so there's no
+                // need to even look at them.
+                Some(Ok(bytes)) if bytes.borrow().is_empty() => {}
+                // Until we know the frame size, input is held back.
+                None if self.min_frames.is_some() => {
+                    if let Err(e) = self.settle_frame_size(true) {
+                        break Some(Err(e));
+                    }
+                }
+                Some(Err(e)) if self.min_frames.is_some() => {
+                    *self.as_mut().project().held_error = Some(e);
+                    if let Err(e) = self.settle_frame_size(true) {
+                        break Some(Err(e));
+                    }
+                }
+                Some(Ok(bytes)) if self.min_frames.is_some() => {
+                    self.as_mut()
+                        .project()
+                        .held_input
+                        .extend_from_slice(bytes.borrow());
+                    if let Err(e) = self.settle_frame_size(false) {
+                        break Some(Err(e));
+                    }
+                }
                 None => match self.end_stream() {
-                    Err(e) => break Some(Err(CompressError::ZstdError(e))),
+                    Err(e) => break Some(Err(e)),
                     Ok(compressed_data) => {
                         if compressed_data.is_empty() {
                             break None;
@@ -199,17 +1638,34 @@ where
                         }
                     }
                 },
-                Some(Err(e)) => break Some(Err(CompressError::Underlying(e))),
-                Some(Ok(bytes)) => match self.compress_input(bytes.borrow()) {
-                    Err(e) => break Some(Err(CompressError::ZstdError(e))),
-                    Ok(compressed_data) => {
-                        // Maybe we want to return 0 length Bytes unconditionally?
-                        // Who knows.
-                        if !compressed_data.is_empty() {
+                Some(Err(e)) if *self.as_mut().project().finalize_on_error => {
+                    match self.end_stream() {
+                        Err(compress_e) => break Some(Err(compress_e)),
+                        Ok(compressed_data) => {
+                            self.set_pending_error(e);
                             break Some(Ok(compressed_data));
                         }
                     }
-                },
+                }
+                Some(Err(e)) => break Some(Err(CompressError::Underlying(e))),
+                Some(Ok(bytes)) => {
+                    let input = bytes.borrow();
+                    let taken = allowance.min(input.len());
+                    spent += taken;
+                    if taken < input.len() {
+                        *self.as_mut().project().leftover = Bytes::copy_from_slice(&input[taken..]);
+                    }
+                    match self.compress_input(&input[..taken], taken == input.len()) {
+                        Err(e) => break Some(Err(e)),
+                        Ok(compressed_data) => {
+                            // Maybe we want to return 0 length Bytes
+                            // unconditionally? Who knows.
+                            if !compressed_data.is_empty() {
+                                break Some(Ok(compressed_data));
+                            }
+                        }
+                    }
+                }
             }
         })
     }
@@ -221,6 +1677,89 @@ where
     I: std::borrow::Borrow<[u8]>,
 {
     fn is_terminated(&self) -> bool {
-        self.wrote_seek_table
+        self.over_output_limit
+            || (self.wrote_seek_table
+                && self.pending_error.is_none()
+                && self.ready_parts.is_empty())
+    }
+}
+
+pin_project! {
+    /// Output of [`Compress::annotated`].
+    pub struct Annotated<S, E> {
+        #[pin]
+        compress: Compress<S, E>,
+        // How many chunks we yielded, which is the index of the next frame
+        // until we get to the footer.
+        chunks: usize,
+    }
+}
+
+/// A chunk of output from [`Compress::annotated`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedChunk {
+    /// Which frame of the seek table this is, None for the footer.
+    pub frame_index: Option<usize>,
+    /// Where the frame's data starts and ends in the decompressed object,
+    /// None for the footer.
+    pub decompressed_range: Option<(u64, u64)>,
+    pub bytes: Bytes,
+    /// Whether this is the seek table at the end, rather than a frame.
+    pub is_footer: bool,
+}
+
+impl<S, I, E> Stream for Annotated<S, E>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    type Item = std::result::Result<AnnotatedChunk, CompressError<E>>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        let bytes = match ready!(this.compress.as_mut().poll_next(cx)) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) => return std::task::Poll::Ready(Some(Err(e))),
+            None => return std::task::Poll::Ready(None),
+        };
+        let frame = *this.chunks;
+        *this.chunks += 1;
+        // Frames are in the seek table by the time they go out, so anything
+        // past the end of it is the seek table itself.
+        let cstream = this.compress.cstream.lock();
+        let seek_table = cstream.seek_table();
+        let chunk = if frame < seek_table.num_frames() {
+            let start = seek_table.frame_decompressed_offset(frame);
+            AnnotatedChunk {
+                frame_index: Some(frame),
+                decompressed_range: Some((
+                    start,
+                    start + seek_table.frame_decompressed_size(frame),
+                )),
+                bytes,
+                is_footer: false,
+            }
+        } else {
+            AnnotatedChunk {
+                frame_index: None,
+                decompressed_range: None,
+                bytes,
+                is_footer: true,
+            }
+        };
+        std::task::Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+impl<S, I, E> FusedStream for Annotated<S, E>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    fn is_terminated(&self) -> bool {
+        self.compress.is_terminated()
     }
 }
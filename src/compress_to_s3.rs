@@ -0,0 +1,339 @@
+use crate::{instrument, CompressError, StreamCompress, StreamUploadParts, UploadReport};
+use bytes::Bytes;
+use futures::{Stream, TryFutureExt, TryStreamExt};
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadError, CompleteMultipartUploadRequest,
+    CompletedMultipartUpload, CompletedPart, CreateMultipartUploadError,
+    CreateMultipartUploadRequest, ListMultipartUploadsError, ListPartsError, UploadPartError,
+    UploadPartRequest, S3,
+};
+use std::{fmt::Display, path::PathBuf, sync::Arc};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+/// Settings for [`compress_to_s3`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressToS3Config {
+    /// As with [`StreamCompress::compress`], negative for the fast levels.
+    pub compression_level: i32,
+    pub frame_size: usize,
+    /// Smallest part to upload. S3 wants at least 5MiB for all parts but the
+    /// last one.
+    pub part_size: usize,
+    /// How many parts to upload at once.
+    pub concurrency: usize,
+    /// Have S3 check every part against its MD5, see
+    /// [`UploadParts::verify_part_md5`](crate::UploadParts::verify_part_md5).
+    pub verify_part_md5: bool,
+    /// Local file to append the compressed data to as it goes, before it's
+    /// uploaded, so that a crashed upload can be picked up where it left off
+    /// with [`resume_compress_to_s3`](crate::resume_compress_to_s3). The file
+    /// is created if need be, truncated at the start and truncated again
+    /// once the upload is complete. None by default.
+    ///
+    /// With a spill, an upload that fails is left in place rather than
+    /// aborted, so it can be resumed too.
+    pub spill: Option<PathBuf>,
+}
+
+impl Default for CompressToS3Config {
+    fn default() -> Self {
+        CompressToS3Config {
+            compression_level: 3,
+            frame_size: 1024 * 1024,
+            part_size: 8 * 1024 * 1024,
+            concurrency: 4,
+            verify_part_md5: false,
+            spill: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CompressToS3Error<E> {
+    // Compression failed, or the source stream did.
+    Compress(CompressError<E>),
+    CreateUpload(RusotoError<CreateMultipartUploadError>),
+    UploadPart(RusotoError<UploadPartError>),
+    CompleteUpload(RusotoError<CompleteMultipartUploadError>),
+    // Finding the upload or its parts to resume failed.
+    ListUploads(RusotoError<ListMultipartUploadsError>),
+    ListParts(RusotoError<ListPartsError>),
+    // Reading or writing the spill failed.
+    Spill(std::io::Error),
+}
+
+impl<E> Display for CompressToS3Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressToS3Error::Compress(e) => write!(f, "{}", e),
+            CompressToS3Error::CreateUpload(_) => {
+                write!(f, "Creating the multipart upload failed.")
+            }
+            CompressToS3Error::UploadPart(_) => write!(f, "Uploading a part failed."),
+            CompressToS3Error::CompleteUpload(_) => {
+                write!(f, "Completing the multipart upload failed.")
+            }
+            CompressToS3Error::ListUploads(_) => {
+                write!(f, "Listing the multipart uploads failed.")
+            }
+            CompressToS3Error::ListParts(_) => write!(f, "Listing the uploaded parts failed."),
+            CompressToS3Error::Spill(_) => write!(f, "Spilling to disk failed."),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for CompressToS3Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompressToS3Error::Compress(e) => e.source(),
+            CompressToS3Error::CreateUpload(e) => Some(e),
+            CompressToS3Error::UploadPart(e) => Some(e),
+            CompressToS3Error::CompleteUpload(e) => Some(e),
+            CompressToS3Error::ListUploads(e) => Some(e),
+            CompressToS3Error::ListParts(e) => Some(e),
+            CompressToS3Error::Spill(e) => Some(e),
+        }
+    }
+}
+
+/// A part [`compress_to_s3_with_progress`] got through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartInfo {
+    /// Numbered from 1, as S3 does.
+    pub part_number: i64,
+    /// What S3 gave back for the part, which completing the upload needs.
+    pub e_tag: Option<String>,
+    /// Bytes uploaded in this part and all the ones before it.
+    pub cumulative_bytes: u64,
+}
+
+/// Compresses `source` into a seekable object at `key` in `bucket` with a
+/// multipart upload, aborting the upload if anything fails.
+///
+/// Data is only pulled from `source` as fast as the parts get uploaded: at
+/// most [`concurrency`](CompressToS3Config::concurrency) parts are in flight
+/// with one more being filled, so that's about how much compressed data is
+/// held in memory at worst.
+pub async fn compress_to_s3<A, S, I, E>(
+    source: S,
+    client: &A,
+    bucket: String,
+    key: String,
+    config: CompressToS3Config,
+) -> Result<UploadReport, CompressToS3Error<E>>
+where
+    A: S3,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    compress_to_s3_with_progress(source, client, bucket, key, config, |_| {}).await
+}
+
+/// Like [`compress_to_s3`], calling `on_part_complete` for every part once S3
+/// has it, for progress bars or for keeping track of the upload elsewhere.
+/// Parts are reported in order, even though up to
+/// [`concurrency`](CompressToS3Config::concurrency) of them are uploaded at
+/// once, so a part that finishes early is only reported once the ones
+/// before it are done too.
+pub async fn compress_to_s3_with_progress<A, S, I, E>(
+    source: S,
+    client: &A,
+    bucket: String,
+    key: String,
+    config: CompressToS3Config,
+    mut on_part_complete: impl FnMut(PartInfo),
+) -> Result<UploadReport, CompressToS3Error<E>>
+where
+    A: S3,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    let compress = source
+        .compress(config.compression_level, config.frame_size)
+        .map_err(|e| CompressToS3Error::Compress(e.into()))?;
+    let compress_progress = compress.progress();
+
+    let upload_id = create_upload(client, &bucket, &key).await?;
+
+    let spill = match &config.spill {
+        Some(path) => Some(Arc::new(Mutex::new(
+            File::create(path).await.map_err(CompressToS3Error::Spill)?,
+        ))),
+        None => None,
+    };
+    let part_template = UploadPartRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        upload_id: upload_id.to_owned(),
+        ..Default::default()
+    };
+    let parts = spill_to(compress.map_err(CompressToS3Error::Compress), spill.clone())
+        .upload_parts(part_template, config.part_size)
+        .verify_part_md5(config.verify_part_md5);
+    let progress = parts.progress();
+    let completed = upload_and_complete(
+        parts,
+        client,
+        &bucket,
+        &key,
+        &upload_id,
+        config.concurrency,
+        Vec::new(),
+        0,
+        &mut on_part_complete,
+    )
+    .await;
+
+    match completed {
+        Ok(e_tag) => {
+            if let Some(spill) = spill {
+                truncate_spill(&spill).await?;
+            }
+            Ok(progress.report(&compress_progress, e_tag))
+        }
+        Err(e) if spill.is_some() => Err(e),
+        Err(e) => {
+            let abort = client.abort_multipart_upload(AbortMultipartUploadRequest {
+                bucket,
+                key,
+                upload_id,
+                ..Default::default()
+            });
+            // What went wrong in the first place is the more useful error:
+            // a lifecycle rule can clean up after the upload if this fails.
+            let _abort = abort.await;
+            #[cfg(feature = "tracing")]
+            if let Err(abort_e) = _abort {
+                tracing::warn!(error = %abort_e, "aborting the multipart upload failed");
+            }
+            Err(e)
+        }
+    }
+}
+
+// Starts a multipart upload, giving its ID.
+pub(crate) async fn create_upload<A: S3, E>(
+    client: &A,
+    bucket: &str,
+    key: &str,
+) -> Result<String, CompressToS3Error<E>> {
+    client
+        .create_multipart_upload(CreateMultipartUploadRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        })
+        .await
+        .map_err(CompressToS3Error::CreateUpload)?
+        .upload_id
+        .ok_or_else(|| {
+            CompressToS3Error::CreateUpload(RusotoError::Validation(
+                "Upload ID not set in response.".to_owned(),
+            ))
+        })
+}
+
+// Appends all the data going by to `spill`, if there's one, before passing
+// it on.
+pub(crate) fn spill_to<St, E>(
+    stream: St,
+    spill: Option<Arc<Mutex<File>>>,
+) -> impl Stream<Item = Result<Bytes, CompressToS3Error<E>>>
+where
+    St: Stream<Item = Result<Bytes, CompressToS3Error<E>>>,
+{
+    stream.and_then(move |bytes| {
+        let spill = spill.clone();
+        async move {
+            if let Some(spill) = spill {
+                let mut spill = spill.lock().await;
+                spill
+                    .write_all(&bytes)
+                    .await
+                    .map_err(CompressToS3Error::Spill)?;
+                // tokio holds on to writes until flushed, and whatever it
+                // holds is lost if the process dies.
+                spill.flush().await.map_err(CompressToS3Error::Spill)?;
+            }
+            Ok(bytes)
+        }
+    })
+}
+
+pub(crate) async fn truncate_spill<E>(spill: &Mutex<File>) -> Result<(), CompressToS3Error<E>> {
+    spill
+        .lock()
+        .await
+        .set_len(0)
+        .await
+        .map_err(CompressToS3Error::Spill)
+}
+
+// Uploads `parts`, reporting them as they're done, and completes the upload
+// with them after the parts in `completed`, which S3 has already along with
+// the `cumulative_bytes` in them. Gives the ETag completing the upload gave
+// back.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upload_and_complete<A, P, E>(
+    parts: P,
+    client: &A,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    concurrency: usize,
+    mut completed: Vec<CompletedPart>,
+    mut cumulative_bytes: u64,
+    on_part_complete: &mut impl FnMut(PartInfo),
+) -> Result<Option<String>, CompressToS3Error<E>>
+where
+    A: S3,
+    P: Stream<Item = Result<UploadPartRequest, CompressToS3Error<E>>>,
+{
+    // try_buffered only polls for the next part when there's room for it,
+    // which is what holds compression back.
+    let uploaded: Vec<CompletedPart> = parts
+        .map_ok(|part| {
+            let part_number = part.part_number;
+            let part_len = part.content_length.unwrap_or_default() as u64;
+            client
+                .upload_part(part)
+                .map_ok(move |out| {
+                    instrument::part_uploaded();
+                    let completed = CompletedPart {
+                        e_tag: out.e_tag,
+                        part_number: Some(part_number),
+                    };
+                    (completed, part_len)
+                })
+                .map_err(CompressToS3Error::UploadPart)
+        })
+        .try_buffered(concurrency.max(1))
+        .map_ok(|(completed, part_len)| {
+            cumulative_bytes += part_len;
+            on_part_complete(PartInfo {
+                part_number: completed.part_number.unwrap_or_default(),
+                e_tag: completed.e_tag.clone(),
+                cumulative_bytes,
+            });
+            completed
+        })
+        .try_collect()
+        .await?;
+    completed.extend(uploaded);
+
+    client
+        .complete_multipart_upload(CompleteMultipartUploadRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            upload_id: upload_id.to_owned(),
+            multipart_upload: Some(CompletedMultipartUpload {
+                parts: Some(completed),
+            }),
+            ..Default::default()
+        })
+        .map_err(CompressToS3Error::CompleteUpload)
+        .await
+        .map(|out| out.e_tag)
+}
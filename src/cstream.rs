@@ -0,0 +1,404 @@
+use crate::{instrument, FrameCompressor, SeekTable, ZstdFrameCompressor};
+use std::{convert::TryFrom, fmt::Display};
+use xxhash_rust::xxh64::Xxh64;
+use zstd_seekable::Error;
+
+// Largest decompressed size a single frame can have in the seekable format.
+pub(crate) const MAX_FRAME_SIZE: usize = 0x8000_0000;
+// Most frames a seek table can describe.
+pub(crate) const MAX_FRAMES: usize = 0x800_0000;
+
+// zstd signals errors by returning the negated error code. These are the ones
+// we produce ourselves.
+pub(crate) const ZSTD_ERROR_FRAME_PARAMETER_UNSUPPORTED: usize = 14;
+const ZSTD_ERROR_FRAME_INDEX_TOO_LARGE: usize = 100;
+pub(crate) const ZSTD_ERROR_CORRUPTION_DETECTED: usize = 20;
+// Anything past this is an error code rather than a size, see ZSTD_isError.
+const ZSTD_ERROR_MAX_CODE: usize = 120;
+
+pub(crate) fn zstd_error(code: usize) -> Error {
+    Error::ZSTD(code.wrapping_neg())
+}
+
+// Most compression level zstd has, ZSTD_maxCLevel, and the fastest of the
+// negative ones, ZSTD_minCLevel.
+pub(crate) const MAX_COMPRESSION_LEVEL: i32 = 22;
+pub(crate) const MIN_COMPRESSION_LEVEL: i32 = -(1 << 17);
+
+// An out of range setting. zstd's own error for this doesn't say which
+// setting is wrong, so we go through io::Error for a message that does.
+pub(crate) fn config_error(parameter: &str, value: impl Display, valid: &str) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!(
+            "{} of {} is out of range, must be {}",
+            parameter, value, valid
+        ),
+    ))
+}
+
+// Most output `len` bytes of input split over `frames` frames can compress
+// to, going by ZSTD_compressBound plus the header, checksum and block header
+// of every frame. The bound is the same whatever the compression level.
+pub(crate) fn compress_bound(len: u64, frames: u64) -> u64 {
+    const FRAME_OVERHEAD: u64 = 18 + 4 + 3;
+    const SMALL_SRC: u64 = 128 << 10;
+    let margin = if len < SMALL_SRC {
+        (SMALL_SRC - len) >> 11
+    } else {
+        0
+    };
+    len + (len >> 8) + margin + frames * FRAME_OVERHEAD
+}
+
+// Frames of incompressible data are stored as they are, see
+// FrameCStream::set_store_incompressible. They're regular zstd frames with
+// nothing but raw blocks in them: the magic, a frame header with no content
+// size and a 128KiB window, which is as big as blocks get, then the blocks.
+const STORED_FRAME_HEADER: [u8; 6] = [0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x38];
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+// Store the frame after one that compressed to more than this share of its
+// size.
+const STORE_ABOVE_PERCENT: u64 = 97;
+// While storing, compress every so many frames anyway to see if the data
+// compresses again.
+const STORE_PROBE_INTERVAL: usize = 8;
+
+// Header of a raw block of `len` bytes.
+fn raw_block_header(len: usize, last: bool) -> [u8; 3] {
+    let header = (len as u32) << 3 | u32::from(last);
+    let bytes = header.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+// CStream::compress2 hands back zstd's return code without checking it so we
+// have to do it ourselves.
+pub(crate) fn check(code: usize) -> Result<usize, Error> {
+    if code > ZSTD_ERROR_MAX_CODE.wrapping_neg() {
+        Err(Error::ZSTD(code))
+    } else {
+        Ok(code)
+    }
+}
+
+// A seekable compression stream. This produces the same output as
+// zstd_seekable::SeekableCStream but, as we drive the frames ourselves, we can
+// end frames whenever we like and see where in the output they end. The data
+// of the frames is compressed by a FrameCompressor, zstd unless told
+// otherwise.
+//
+// zstd_seekable's CStream, like its SeekableCStream, only takes a compression
+// level and keeps the underlying context to itself, so advanced parameters
+// such as the strategy, window log or long distance matching can't be set
+// through it. Supporting them would need zstd_seekable to expose
+// ZSTD_CCtx_setParameter. A FrameCompressor of the caller's own can use
+// whatever zstd bindings do. The same goes
+// for dictionaries, per frame or otherwise: there's no way to load one into
+// the context, and the seekable decoder has no way to take one either, so
+// frames compressed with one couldn't be read back through it.
+pub(crate) struct FrameCStream {
+    compressor: Box<dyn FrameCompressor + Send>,
+    max_frame_size: usize,
+    // Sizes of the frame currently being written.
+    frame_compressed_size: usize,
+    frame_decompressed_size: usize,
+    // We told zstd to end the current frame but it hasn't flushed all of it
+    // out yet.
+    ending_frame: bool,
+    hasher: Xxh64,
+    // Every frame finished so far.
+    seek_table: SeekTable,
+    // Encoded seek table and how much of it we wrote out already, once we
+    // started writing it.
+    seek_table_out: Option<(Vec<u8>, usize)>,
+    // Store frames that don't compress rather than compressing them, and
+    // whether the current frame is stored, along with how many frames were
+    // stored since the last one we compressed.
+    store_incompressible: bool,
+    storing: bool,
+    stored_in_a_row: usize,
+    // Output of the stored frame waiting to go out, the data of the block
+    // in progress and whether the frame header went in already.
+    pending: Vec<u8>,
+    block: Vec<u8>,
+    header_queued: bool,
+}
+
+impl FrameCStream {
+    pub(crate) fn new(compression_level: i32, frame_size: usize) -> Result<Self, Error> {
+        if frame_size > MAX_FRAME_SIZE {
+            return Err(config_error(
+                "frame_size",
+                frame_size,
+                &format!("at most {} or 0 for the largest frames", MAX_FRAME_SIZE),
+            ));
+        }
+        Ok(FrameCStream {
+            compressor: Box::new(ZstdFrameCompressor::new(compression_level)?),
+            max_frame_size: if frame_size == 0 {
+                MAX_FRAME_SIZE
+            } else {
+                frame_size
+            },
+            frame_compressed_size: 0,
+            frame_decompressed_size: 0,
+            ending_frame: false,
+            hasher: Xxh64::new(0),
+            seek_table: SeekTable::new(true),
+            seek_table_out: None,
+            store_incompressible: false,
+            storing: false,
+            stored_in_a_row: 0,
+            pending: Vec::new(),
+            block: Vec::new(),
+            header_queued: false,
+        })
+    }
+
+    // Has `compressor` compress the frames from here on. Only to be called
+    // before any data goes in.
+    pub(crate) fn set_compressor(&mut self, compressor: Box<dyn FrameCompressor + Send>) {
+        self.compressor = compressor;
+    }
+
+    // Carries on after the frames in `seek_table`, which come ahead of
+    // everything written from here in the seek table we write. Only to be
+    // called before any data goes in.
+    #[cfg(feature = "s3")]
+    pub(crate) fn continue_after(&mut self, seek_table: SeekTable) {
+        debug_assert_eq!(self.seek_table.num_frames(), 0);
+        self.seek_table = seek_table;
+    }
+
+    // Changes the size at which frames are ended automatically, from the
+    // next frame on. Must be called between frames.
+    pub(crate) fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    pub(crate) fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    // Whether the seek table gets a checksum for every frame. Must be called
+    // before any frames are recorded, skippable ones included.
+    pub(crate) fn set_checksums(&mut self, checksums: bool) {
+        debug_assert_eq!(self.seek_table.num_frames(), 0);
+        self.seek_table = SeekTable::new(checksums);
+    }
+
+    // Whether to store frames as they are when the data doesn't compress,
+    // going by the frame before. Must be called between frames.
+    pub(crate) fn set_store_incompressible(&mut self, store_incompressible: bool) {
+        self.store_incompressible = store_incompressible;
+        self.storing = false;
+    }
+
+    // Whether the current frame has any data in it yet.
+    pub(crate) fn frame_in_progress(&self) -> bool {
+        self.frame_decompressed_size > 0
+    }
+
+    // How many frames were completed so far.
+    pub(crate) fn num_frames(&self) -> usize {
+        self.seek_table.num_frames()
+    }
+
+    // Most output compressing `len` more bytes of input can give, see
+    // compress_bound. Not a hard limit as zstd may hold on to earlier input
+    // and flush it along with this, but close enough to size buffers with.
+    pub(crate) fn compress_bound(&self, len: usize) -> usize {
+        let frames = (self.frame_decompressed_size + len) / self.max_frame_size + 1;
+        compress_bound(len as u64, frames as u64) as usize
+    }
+
+    // Every frame finished so far.
+    pub(crate) fn seek_table(&self) -> &SeekTable {
+        &self.seek_table
+    }
+
+    // Compresses some of the input, returning `(out_pos, in_pos)` like
+    // SeekableCStream::compress. A frame is ended as soon as it holds
+    // `frame_size` bytes. When a frame is completed, we return straight away
+    // so that the end of the frame coincides with the end of the output.
+    pub(crate) fn compress(
+        &mut self,
+        output: &mut [u8],
+        input: &[u8],
+    ) -> Result<(usize, usize), Error> {
+        if self.ending_frame {
+            return self.end_frame(output).map(|(out_pos, _)| (out_pos, 0));
+        }
+
+        let room_in_frame = self.max_frame_size - self.frame_decompressed_size;
+        let input = &input[..input.len().min(room_in_frame)];
+        let (mut out_pos, in_pos) = if input.is_empty() {
+            (0, 0)
+        } else if self.storing {
+            self.store(output, input)
+        } else {
+            let (out_pos, in_pos) = self.compressor.compress(output, input)?;
+            if self.seek_table.has_checksums() {
+                self.hasher.update(&input[..in_pos]);
+            }
+            self.frame_compressed_size += out_pos;
+            self.frame_decompressed_size += in_pos;
+            (out_pos, in_pos)
+        };
+
+        if self.frame_decompressed_size == self.max_frame_size {
+            out_pos += self.end_frame(&mut output[out_pos..])?.0;
+        }
+        Ok((out_pos, in_pos))
+    }
+
+    // Takes as much of the input as fits in the block in progress of the
+    // stored frame, queueing the block up once it's full, and writes out
+    // what's queued. Gives `(out_pos, in_pos)`. Blocks are always as big as
+    // they get, whatever the input comes in, save for the last one.
+    fn store(&mut self, output: &mut [u8], input: &[u8]) -> (usize, usize) {
+        let mut out_pos = self.write_pending(output);
+        if !self.pending.is_empty() {
+            return (out_pos, 0);
+        }
+        let in_pos = input.len().min(MAX_BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&input[..in_pos]);
+        if self.seek_table.has_checksums() {
+            self.hasher.update(&input[..in_pos]);
+        }
+        self.frame_decompressed_size += in_pos;
+        if self.block.len() == MAX_BLOCK_SIZE {
+            self.queue_block(false);
+            out_pos += self.write_pending(&mut output[out_pos..]);
+        }
+        (out_pos, in_pos)
+    }
+
+    // Queues up the block in progress, along with the frame header if it's
+    // the first.
+    fn queue_block(&mut self, last: bool) {
+        if !self.header_queued {
+            self.pending.extend_from_slice(&STORED_FRAME_HEADER);
+            self.header_queued = true;
+        }
+        self.pending
+            .extend_from_slice(&raw_block_header(self.block.len(), last));
+        self.pending.append(&mut self.block);
+    }
+
+    // Writes out as much of what's queued as fits, giving how many bytes
+    // that was.
+    fn write_pending(&mut self, output: &mut [u8]) -> usize {
+        let n = output.len().min(self.pending.len());
+        output[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        self.frame_compressed_size += n;
+        n
+    }
+
+    // Ends the current frame, returning how much output we wrote and whether
+    // the frame is done. If it isn't, this has to be called again with more
+    // room in the output.
+    pub(crate) fn end_frame(&mut self, output: &mut [u8]) -> Result<(usize, bool), Error> {
+        let out_pos = if self.storing {
+            if !self.ending_frame {
+                self.queue_block(true);
+            }
+            self.ending_frame = true;
+            let out_pos = self.write_pending(output);
+            if !self.pending.is_empty() {
+                return Ok((out_pos, false));
+            }
+            out_pos
+        } else {
+            self.ending_frame = true;
+            let (out_pos, remaining) = self.compressor.end_frame(output)?;
+            self.frame_compressed_size += out_pos;
+            if remaining > 0 {
+                return Ok((out_pos, false));
+            }
+            out_pos
+        };
+
+        if self.seek_table.num_frames() == MAX_FRAMES {
+            return Err(zstd_error(ZSTD_ERROR_FRAME_INDEX_TOO_LARGE));
+        }
+        // Neither can overflow: the decompressed size is capped at
+        // MAX_FRAME_SIZE and the compressed size of that is within u32 too.
+        let compressed_size = u32::try_from(self.frame_compressed_size)
+            .map_err(|_| zstd_error(ZSTD_ERROR_FRAME_PARAMETER_UNSUPPORTED))?;
+        let decompressed_size = self.frame_decompressed_size as u32;
+        let checksum = self.hasher.digest() as u32;
+        self.seek_table
+            .push_frame(compressed_size, decompressed_size, checksum);
+
+        if self.store_incompressible {
+            if self.storing {
+                self.stored_in_a_row += 1;
+                self.storing = (self.stored_in_a_row + 1) % STORE_PROBE_INTERVAL != 0;
+            } else if decompressed_size > 0 {
+                self.stored_in_a_row = 0;
+                self.storing = u64::from(compressed_size) * 100
+                    > u64::from(decompressed_size) * STORE_ABOVE_PERCENT;
+            }
+        }
+        self.frame_compressed_size = 0;
+        self.frame_decompressed_size = 0;
+        self.ending_frame = false;
+        self.header_queued = false;
+        self.hasher.reset(0);
+        instrument::frame();
+        Ok((out_pos, true))
+    }
+
+    // Like end_frame but doesn't write out empty frames: if nothing went
+    // into the current frame, there's nothing to do.
+    pub(crate) fn flush_frame(&mut self, output: &mut [u8]) -> Result<(usize, bool), Error> {
+        if self.frame_decompressed_size == 0 && !self.ending_frame {
+            return Ok((0, true));
+        }
+        self.end_frame(output)
+    }
+
+    // Adds a skippable frame of `len` bytes, which the caller writes out
+    // itself, to the seek table as a frame with no data. Must be called
+    // between frames.
+    pub(crate) fn push_skippable_frame(&mut self, len: u32) {
+        self.seek_table
+            .push_frame(len, 0, Xxh64::new(0).digest() as u32);
+    }
+
+    // Makes the given, finished, frame take up `extra` more bytes in the
+    // seek table, for when the caller adds to it after compression.
+    pub(crate) fn grow_frame(&mut self, frame: usize, extra: u64) {
+        self.seek_table.grow_frame(frame, extra);
+    }
+
+    // Ends the last frame and writes out the seek table. Returns how much
+    // output was written: keep calling until this returns 0.
+    pub(crate) fn end_stream(&mut self, output: &mut [u8]) -> Result<usize, Error> {
+        let mut out_pos = 0;
+        if self.seek_table_out.is_none() {
+            let (frame_out_pos, done) = self.end_frame(output)?;
+            out_pos = frame_out_pos;
+            if !done {
+                return Ok(out_pos);
+            }
+        }
+        Ok(out_pos + self.write_seek_table(&mut output[out_pos..]))
+    }
+
+    // Writes out the seek table for the frames finished so far, without
+    // touching the current frame. Like end_stream, keep calling until this
+    // returns 0.
+    pub(crate) fn write_seek_table(&mut self, output: &mut [u8]) -> usize {
+        let seek_table = &self.seek_table;
+        let (seek_table, written) = self
+            .seek_table_out
+            .get_or_insert_with(|| (seek_table.to_bytes(), 0));
+        let n = output.len().min(seek_table.len() - *written);
+        output[..n].copy_from_slice(&seek_table[*written..*written + n]);
+        *written += n;
+        n
+    }
+}
@@ -1,18 +1,46 @@
-use std::{convert::TryFrom, fmt::Display, num::TryFromIntError};
-use zstd_seekable::Seekable;
+use crate::{
+    cstream::{zstd_error, ZSTD_ERROR_CORRUPTION_DETECTED},
+    metadata::{is_metadata_frame, parse_metadata_frame},
+    totals::{parse_totals_frame, TOTALS_FRAME_LEN},
+    trailing_index::trailing_index_len,
+    FrameCache, FrameMeta, SeekTable, WorkerBudget, SEEK_TABLE_FOOTER_LEN,
+};
+use bytes::{Bytes, BytesMut};
+use parking_lot::Mutex;
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    io::{Read, Seek, SeekFrom},
+    num::TryFromIntError,
+    sync::{mpsc, Arc},
+};
+use zstd_seekable::{DStream, Seekable};
 
 // The seek/read methods on this object will read/seek uncompress an underlying
 // object and read/seek within it.
 pub struct SeekableDecompress<'a, A> {
-    seekable: Seekable<'a, A>,
+    seekable: Seekable<'a, SharedReader<A>>,
+    // The same compressed object the decompressor reads from, for when we
+    // want to get at the compressed frames ourselves.
+    compressed: Arc<Mutex<A>>,
     // We use this across read invocations to make sure we don't run off the end
     // of stream so just compute it once ahead of time.
     decompressed_size: u64,
     // Seek position in the decompressed data.
     decompressed_position: u64,
+    // Length the data has to end at for reads to succeed, if any.
+    length_check: Option<u64>,
+    // Frames read_range decompressed before, along with the seek table to
+    // find them by once we needed it.
+    frame_cache: Option<FrameCache>,
+    seek_table: Option<SeekTable>,
+    // Shared limit on how many frames the parallel methods decompress at
+    // once.
+    worker_budget: Option<WorkerBudget>,
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     NoFrames,
     // Frame size was too big for u64.
@@ -20,6 +48,20 @@ pub enum Error {
     // End of data was past u64.
     DataTooLarge,
     ZstdSeekable(zstd_seekable::Error),
+    // Reading the compressed data ourselves failed.
+    Io(std::io::Error),
+    // The seek table says the frame decompresses to more than the limit set
+    // with max_frame_decompressed_size.
+    FrameOverLimit { frame: usize, declared: u64 },
+    // The object starts with a metadata frame we can't make sense of.
+    BadMetadata,
+    // The data ended somewhere other than where it should have.
+    LengthMismatch { expected: u64, actual: u64 },
+    // The buffer given to decompress_all_into can't hold all the data.
+    BufferTooSmall { needed: u64, len: usize },
+    // The seek table says the data decompresses to more than the limit set
+    // with SeekableBytes::max_len.
+    DataOverLimit { declared: u64, limit: u64 },
 }
 
 impl Display for Error {
@@ -29,23 +71,78 @@ impl Display for Error {
                 f,
                 "No frames found in the stream. Use regular decompression."
             ),
-            Error::FrameTooLarge(e) => {
-                write!(f, "Encountered a frame larger than we can work with: {}", e)
+            Error::FrameTooLarge(_) => {
+                write!(f, "Encountered a frame larger than we can work with.")
             }
             Error::DataTooLarge => write!(f, "Data larger than we can work with."),
             Error::ZstdSeekable(e) => write!(f, "{}", e),
+            Error::Io(_) => write!(f, "Reading compressed data failed."),
+            Error::FrameOverLimit { frame, declared } => write!(
+                f,
+                "Frame {} decompresses to {} bytes, more than we're allowed.",
+                frame, declared
+            ),
+            Error::BadMetadata => write!(f, "Metadata at the start of the object is corrupt."),
+            Error::LengthMismatch { expected, actual } => write!(
+                f,
+                "Data ended after {} bytes rather than {}.",
+                actual, expected
+            ),
+            Error::BufferTooSmall { needed, len } => write!(
+                f,
+                "Buffer of {} bytes is too small for the {} bytes of data.",
+                len, needed
+            ),
+            Error::DataOverLimit { declared, limit } => write!(
+                f,
+                "Data decompresses to {} bytes, more than the {} we're allowed.",
+                declared, limit
+            ),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::FrameTooLarge(e) => Some(e),
+            Error::ZstdSeekable(e) => e.source(),
+            Error::Io(e) => Some(e),
+            Error::NoFrames
+            | Error::DataTooLarge
+            | Error::FrameOverLimit { .. }
+            | Error::BadMetadata
+            | Error::LengthMismatch { .. }
+            | Error::BufferTooSmall { .. }
+            | Error::DataOverLimit { .. } => None,
+        }
+    }
+}
+
+// Lets the decompressor and us both get at the compressed object. zstd only
+// ever reads from one place at a time so the lock is never contended.
+struct SharedReader<A>(Arc<Mutex<A>>);
+
+impl<A: Read> Read for SharedReader<A> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().read(buf)
+    }
+}
+
+impl<A: Seek> Seek for SharedReader<A> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.lock().seek(pos)
+    }
+}
 
 impl<'a, A> SeekableDecompress<'a, A>
 where
     A: std::io::Read + std::io::Seek,
 {
     pub fn new(compressed: A) -> Result<Self, Error> {
-        let seekable = Seekable::init(Box::new(compressed)).map_err(Error::ZstdSeekable)?;
+        let compressed = Arc::new(Mutex::new(compressed));
+        let seekable = Seekable::init(Box::new(SharedReader(compressed.clone())))
+            .map_err(Error::ZstdSeekable)?;
 
         let decompressed_size = {
             let num_frames = seekable.get_num_frames();
@@ -66,10 +163,856 @@ where
 
         Ok(SeekableDecompress {
             seekable,
+            compressed,
             decompressed_size,
             decompressed_position: 0,
+            length_check: None,
+            frame_cache: None,
+            seek_table: None,
+            worker_budget: None,
+        })
+    }
+
+    /// Refuses objects with any frame the seek table says decompresses to
+    /// more than `limit` bytes. Frames are decompressed whole in some places,
+    /// [`decompress_all_parallel`](Self::decompress_all_parallel) and
+    /// [`verify_all`](Self::verify_all) for example, so set this when
+    /// decompressing untrusted data to keep it from declaring huge frames to
+    /// run us out of memory. By default the only limit is the format's own,
+    /// 2GiB.
+    ///
+    /// This checks every frame up front, before anything is decompressed.
+    pub fn max_frame_decompressed_size(self, limit: u64) -> Result<Self, Error> {
+        for frame in 0..self.seekable.get_num_frames() {
+            let declared = self.seekable.get_frame_decompressed_size(frame) as u64;
+            if declared > limit {
+                return Err(Error::FrameOverLimit { frame, declared });
+            }
+        }
+        Ok(self)
+    }
+
+    /// Make reads fail with [`Error::LengthMismatch`] once the data ends
+    /// anywhere but at `expected` bytes, a length kept apart from the
+    /// object, say in a manifest or the uploader's logs. That catches
+    /// objects that were cut short, seek table and all, rather than ending
+    /// early as if that was all there is.
+    ///
+    /// Only where the data ends is checked, so this works the same after
+    /// seeking around.
+    pub fn with_length_check(mut self, expected: u64) -> Self {
+        self.length_check = Some(expected);
+        self
+    }
+
+    /// Length of the decompressed data, going by the seek table.
+    pub fn decompressed_len(&self) -> u64 {
+        self.decompressed_size
+    }
+
+    /// Reads `len` bytes of decompressed data at `offset`, regardless of the
+    /// current position, which this leaves alone. Like a read, this comes up
+    /// short if the range goes past the end of the data, right down to
+    /// nothing at all if it starts past it.
+    ///
+    /// With a [frame cache](Self::set_frame_cache), ranges are read whole
+    /// frames at a time through the cache, and those within a single frame
+    /// that's in there come straight out of it without a copy.
+    pub fn read_range(&mut self, offset: u64, len: usize) -> Result<Bytes, Error> {
+        if let Some(cache) = self.frame_cache.clone() {
+            return self.read_range_cached(&cache, offset, len);
+        }
+        let available = self.decompressed_size.saturating_sub(offset);
+        let len = usize::try_from(available).map_or(len, |available| available.min(len));
+        let mut out = vec![0; len];
+        let mut filled = 0;
+        while filled < len {
+            let n = self
+                .seekable
+                .decompress(&mut out[filled..], offset + filled as u64)
+                .map_err(Error::ZstdSeekable)?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        out.truncate(filled);
+        Ok(Bytes::from(out))
+    }
+
+    /// Decompresses the whole object into `buf`, which has to hold at least
+    /// [`decompressed_len`](Self::decompressed_len) bytes, say a memory
+    /// mapped file, and gives how many bytes that took. Nothing past that in
+    /// `buf` is touched and nothing is allocated for the data on the way.
+    ///
+    /// Fails before reading anything if `buf` is too small, and if the data
+    /// ends before the length the seek table gives.
+    pub fn decompress_all_into(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = match usize::try_from(self.decompressed_size) {
+            Ok(len) if len <= buf.len() => len,
+            _ => {
+                return Err(Error::BufferTooSmall {
+                    needed: self.decompressed_size,
+                    len: buf.len(),
+                })
+            }
+        };
+        let mut filled = 0;
+        while filled < len {
+            let n = self
+                .seekable
+                .decompress(&mut buf[filled..len], filled as u64)
+                .map_err(Error::ZstdSeekable)?;
+            if n == 0 {
+                return Err(Error::LengthMismatch {
+                    expected: self.decompressed_size,
+                    actual: filled as u64,
+                });
+            }
+            filled += n;
+        }
+        Ok(filled)
+    }
+
+    /// Shares `frame_cache` between this and whatever other readers it was
+    /// given to, for [`read_range`](Self::read_range). Frames are checked
+    /// against their checksums on the way in. Set to None to stop caching.
+    pub fn set_frame_cache(&mut self, frame_cache: Option<FrameCache>) {
+        self.frame_cache = frame_cache;
+    }
+
+    /// Has [`decompress_all_parallel`](Self::decompress_all_parallel) and
+    /// [`verify_all`](Self::verify_all) share `worker_budget` with every
+    /// other reader it was given to rather than each taking on as many
+    /// frames as its `concurrency` says. Set to None to go back to that.
+    pub fn set_worker_budget(&mut self, worker_budget: Option<WorkerBudget>) {
+        self.worker_budget = worker_budget;
+    }
+
+    fn read_range_cached(
+        &mut self,
+        cache: &FrameCache,
+        offset: u64,
+        len: usize,
+    ) -> Result<Bytes, Error> {
+        let table = match self.seek_table.take() {
+            Some(table) => table,
+            None => self.with_compressed(read_seek_table)?,
+        };
+        let result = table.assemble_range(offset, len as u64, |frame| {
+            self.cached_frame(cache, &table, frame)
+        });
+        self.seek_table = Some(table);
+        result
+    }
+
+    // Frame `frame` out of the cache, reading and decompressing it first if
+    // it's not in there.
+    fn cached_frame(
+        &mut self,
+        cache: &FrameCache,
+        table: &SeekTable,
+        frame: usize,
+    ) -> Result<Bytes, Error> {
+        if let Some(data) = cache
+            .frame_id(table, frame, None)
+            .and_then(|id| cache.get(id))
+        {
+            return Ok(data);
+        }
+        let compressed = self.with_compressed(|compressed| {
+            let mut input = vec![0; table.frame_compressed_size(frame) as usize];
+            compressed
+                .seek(SeekFrom::Start(table.frame_compressed_offset(frame)))
+                .and_then(|_| compressed.read_exact(&mut input))
+                .map_err(Error::Io)?;
+            Ok(input)
+        })?;
+        cache
+            .decompress_frame(table, frame, &compressed)
+            .map_err(Error::Io)
+    }
+
+    /// Decompresses frame `frame` whole and, if the seek table has
+    /// checksums, checks the data against the frame's checksum. Reads and
+    /// seeks don't check checksums, this is for callers that want to know
+    /// the data they got is what was written. Panics if there's no such
+    /// frame.
+    ///
+    /// This reads the seek table from the end of the object every time, so
+    /// use [`verify_all`](Self::verify_all) to check the whole object.
+    pub fn read_frame(&mut self, frame: usize) -> Result<Bytes, Error> {
+        self.with_compressed(|compressed| {
+            let table = read_seek_table(compressed)?;
+            let mut dstream = DStream::new().map_err(Error::ZstdSeekable)?;
+            decompress_table_frame(compressed, &table, frame, &mut dstream)
+        })
+    }
+
+    /// Decompresses just the frames `predicate` picks, going by where they
+    /// are and how big they are, in order. Frames it turns down aren't read,
+    /// let alone decompressed, so for queries that only need part of the
+    /// data this only costs as much as that part. Frames without any data,
+    /// such as the one holding [`metadata`](Self::metadata), are always
+    /// left out. Data kept on the side about each frame, such as the range
+    /// of keys in it, can be looked up by the frame's decompressed offset.
+    ///
+    /// Frames are checked against their checksums, if the seek table has
+    /// them, as with [`read_frame`](Self::read_frame). The first error ends
+    /// the iteration.
+    pub fn decompress_matching<P>(&mut self, predicate: P) -> MatchingFrames<'_, 'a, A, P>
+    where
+        P: Fn(&FrameMeta) -> bool,
+    {
+        MatchingFrames {
+            decompress: self,
+            predicate,
+            table: None,
+            frame: 0,
+            dstream: None,
+            done: false,
+        }
+    }
+
+    /// Decompresses every frame from the last one to the first, for
+    /// consumers going newest first such as through logs, without having to
+    /// read everything to reverse it. Only the frame order is reversed: the
+    /// data within each frame comes the way it was written. Frames without
+    /// any data are left out.
+    ///
+    /// This goes by the seek table, so only one frame is held in memory at
+    /// a time. Frames are checked against their checksums, if the seek
+    /// table has them, and the first error ends the iteration, as with
+    /// [`decompress_matching`](Self::decompress_matching).
+    pub fn frames_rev(&mut self) -> FramesRev<'_, 'a, A> {
+        FramesRev {
+            decompress: self,
+            table: None,
+            frames_left: None,
+            dstream: None,
+            done: false,
+        }
+    }
+
+    /// Walks the decompressed data in windows of `window_size` bytes, each
+    /// with its offset, whatever the frames look like. The last window may
+    /// be short. Panics if `window_size` is 0.
+    ///
+    /// Only the window being read and the frame it's decompressed from are
+    /// held in memory.
+    pub fn windows(&mut self, window_size: usize) -> Windows<'_, 'a, A> {
+        assert!(window_size != 0, "window size must be non-zero");
+        Windows {
+            decompress: self,
+            window_size,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Walks the decompressed data line by line, for text such as logs. Lines
+    /// are split on `\n`, which is left off, and come out whole wherever
+    /// the frames end. A last line without a `\n` at the end still comes
+    /// out, but there's no empty line after a trailing `\n`. Any `\r` in
+    /// front of it is left in.
+    ///
+    /// The data is decompressed a chunk at a time, holding on to the
+    /// unfinished line in between, so a single very long line takes up as
+    /// much memory as it's long.
+    pub fn lines(&mut self) -> Lines<'_, 'a, A> {
+        Lines {
+            decompress: self,
+            offset: 0,
+            buf: BytesMut::new(),
+            searched: 0,
+            done: false,
+        }
+    }
+
+    /// The key/value pairs given to
+    /// [`Compress::metadata`](crate::Compress::metadata), in order. Empty if
+    /// there are none.
+    pub fn metadata(&mut self) -> Result<Vec<(String, String)>, Error> {
+        let seekable = &self.seekable;
+        if seekable.get_num_frames() == 0 || seekable.get_frame_decompressed_size(0) != 0 {
+            return Ok(Vec::new());
+        }
+        let offset = seekable.get_frame_compressed_offset(0);
+        let len = seekable.get_frame_compressed_size(0);
+        // Skippable frames are never shorter than their header.
+        if len < 8 {
+            return Ok(Vec::new());
+        }
+        // Check it's metadata before reading all of it: a skippable frame
+        // of someone else's could be any size. Ours say how long they are,
+        // which has to match the seek table before we take either's word.
+        let frame = self.with_compressed(|compressed| {
+            let mut frame = vec![0; 8];
+            compressed
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| compressed.read_exact(&mut frame))
+                .map_err(Error::Io)?;
+            if !is_metadata_frame(&frame) {
+                return Ok(None);
+            }
+            let declared = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+            if declared as usize + 8 != len {
+                return Err(Error::BadMetadata);
+            }
+            frame.resize(len, 0);
+            compressed.read_exact(&mut frame[8..]).map_err(Error::Io)?;
+            Ok(Some(frame))
+        })?;
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return Ok(Vec::new()),
+        };
+        parse_metadata_frame(&frame).ok_or(Error::BadMetadata)
+    }
+
+    /// The index given to
+    /// [`Compress::trailing_index`](crate::Compress::trailing_index), None
+    /// if there isn't one.
+    pub fn trailing_index(&mut self) -> Result<Option<Bytes>, Error> {
+        // The index comes last, unless there's a totals frame after it.
+        let mut frames = self.seekable.get_num_frames();
+        if self.quick_totals()?.is_some() {
+            frames -= 1;
+        }
+        let seekable = &self.seekable;
+        if frames == 0 || seekable.get_frame_decompressed_size(frames - 1) != 0 {
+            return Ok(None);
+        }
+        let offset = seekable.get_frame_compressed_offset(frames - 1);
+        let frame_len = seekable.get_frame_compressed_size(frames - 1);
+        // As with the metadata, check it's ours before reading all of it.
+        self.with_compressed(|compressed| {
+            let mut header = [0; 8];
+            compressed
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| compressed.read_exact(&mut header))
+                .map_err(Error::Io)?;
+            let len = match trailing_index_len(&header, frame_len) {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            let mut index = vec![0; len];
+            compressed.read_exact(&mut index).map_err(Error::Io)?;
+            Ok(Some(Bytes::from(index)))
+        })
+    }
+
+    /// The decompressed length and the number of frames, from the frame
+    /// written by [`Compress::totals_frame`](crate::Compress::totals_frame),
+    /// None if there isn't one. This reads nothing but that frame. For
+    /// readers that only have the end of the object, such as a ranged GET
+    /// of it, see [`quick_totals_range`](crate::quick_totals_range).
+    pub fn quick_totals(&mut self) -> Result<Option<(u64, u64)>, Error> {
+        let seekable = &self.seekable;
+        let frames = seekable.get_num_frames();
+        if frames == 0
+            || seekable.get_frame_decompressed_size(frames - 1) != 0
+            || seekable.get_frame_compressed_size(frames - 1) != TOTALS_FRAME_LEN
+        {
+            return Ok(None);
+        }
+        let offset = seekable.get_frame_compressed_offset(frames - 1);
+        let frame = self.with_compressed(|compressed| {
+            let mut frame = [0; TOTALS_FRAME_LEN];
+            compressed
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| compressed.read_exact(&mut frame))
+                .map_err(Error::Io)?;
+            Ok(frame)
+        })?;
+        Ok(parse_totals_frame(&frame).filter(|&(_, n)| n == frames as u64))
+    }
+
+    /// Frame layout of the underlying object. This walks every frame so hold
+    /// on to the result rather than calling this repeatedly.
+    pub fn seek_table(&self) -> Result<SeekTable, Error> {
+        SeekTable::from_seekable(&self.seekable).map_err(|_e| Error::DataTooLarge)
+    }
+
+    /// Decompresses the whole object, spreading the frames over `concurrency`
+    /// threads. This gives exactly what reading the object from start to
+    /// finish does, only faster on multi-core machines for objects with many
+    /// frames.
+    ///
+    /// Compressed frames are read in order on the calling thread, with at
+    /// most around twice `concurrency` of them held at once, and
+    /// decompressed straight into the output. Frame checksums, if present,
+    /// are verified. With a [worker budget](Self::set_worker_budget),
+    /// decompression also waits on that.
+    pub fn decompress_all_parallel(&mut self, concurrency: usize) -> Result<Bytes, Error> {
+        let budget = self.worker_budget.clone();
+        self.with_compressed(|compressed| {
+            let table = read_seek_table(compressed)?;
+            let len =
+                usize::try_from(table.decompressed_len()).map_err(|_e| Error::DataTooLarge)?;
+            let mut out = vec![0; len];
+
+            // Carve the output up into one slice per frame for the workers
+            // to fill in.
+            let mut frames_out = Vec::with_capacity(table.num_frames());
+            let mut rest = &mut out[..];
+            for frame in 0..table.num_frames() {
+                let (frame_out, r) =
+                    rest.split_at_mut(table.frame_decompressed_size(frame) as usize);
+                frames_out.push(frame_out);
+                rest = r;
+            }
+
+            let errors =
+                for_each_frame(compressed, &table, concurrency, budget.as_ref(), frames_out)?;
+            match errors.into_iter().next() {
+                Some(FrameError { error, .. }) => Err(error),
+                None => Ok(Bytes::from(out)),
+            }
+        })
+    }
+
+    /// Checks every frame of the object decompresses to the size the seek
+    /// table says it should and, if the table has checksums, that the data
+    /// matches them. The decompressed data is thrown away as we go, so this
+    /// only ever holds a few frames at a time, decompressing `concurrency`
+    /// of them at once.
+    ///
+    /// Problems with individual frames end up in the report. Failing to read
+    /// the object at all is an error.
+    pub fn verify_all(&mut self, concurrency: usize) -> Result<VerifyReport, Error> {
+        let budget = self.worker_budget.clone();
+        self.with_compressed(|compressed| {
+            let table = read_seek_table(compressed)?;
+            // Each frame gets decompressed into its own scratch buffer.
+            let scratch = (0..table.num_frames())
+                .map(|frame| vec![0; table.frame_decompressed_size(frame) as usize]);
+            let errors = for_each_frame(compressed, &table, concurrency, budget.as_ref(), scratch)?;
+            Ok(VerifyReport {
+                frames: table.num_frames(),
+                decompressed_len: table.decompressed_len(),
+                checksums: table.has_checksums(),
+                errors,
+            })
         })
     }
+
+    // Gives direct access to the compressed object, putting it back where
+    // the decompressor expects it afterwards.
+    fn with_compressed<T>(
+        &mut self,
+        f: impl FnOnce(&mut A) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut compressed = self.compressed.lock();
+        let position = compressed.stream_position().map_err(Error::Io)?;
+        let result = f(&mut *compressed);
+        compressed
+            .seek(SeekFrom::Start(position))
+            .map_err(Error::Io)?;
+        result
+    }
+}
+
+/// Windows of the decompressed data, see [`SeekableDecompress::windows`].
+pub struct Windows<'d, 'a, A> {
+    decompress: &'d mut SeekableDecompress<'a, A>,
+    window_size: usize,
+    offset: u64,
+    done: bool,
+}
+
+impl<'d, 'a, A> Iterator for Windows<'d, 'a, A>
+where
+    A: Read + Seek,
+{
+    type Item = Result<(u64, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.decompress.read_range(self.offset, self.window_size) {
+            Ok(window) if window.is_empty() => {
+                self.done = true;
+                None
+            }
+            Ok(window) => {
+                let offset = self.offset;
+                self.offset += window.len() as u64;
+                Some(Ok((offset, window)))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// How much to decompress at a time for [`Lines`].
+const LINES_CHUNK: usize = 64 * 1024;
+
+/// Lines of the decompressed data, see [`SeekableDecompress::lines`].
+pub struct Lines<'d, 'a, A> {
+    decompress: &'d mut SeekableDecompress<'a, A>,
+    // Where to decompress the next chunk from.
+    offset: u64,
+    // Data we haven't given out yet, and how much of it we know holds no
+    // newline.
+    buf: BytesMut,
+    searched: usize,
+    done: bool,
+}
+
+impl<'d, 'a, A> Iterator for Lines<'d, 'a, A>
+where
+    A: Read + Seek,
+{
+    type Item = Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(newline) = self.buf[self.searched..].iter().position(|&b| b == b'\n') {
+                let mut line = self.buf.split_to(self.searched + newline + 1);
+                line.truncate(line.len() - 1);
+                self.searched = 0;
+                return Some(Ok(line.freeze()));
+            }
+            self.searched = self.buf.len();
+            match self.decompress.read_range(self.offset, LINES_CHUNK) {
+                Ok(chunk) if chunk.is_empty() => {
+                    self.done = true;
+                    if !self.buf.is_empty() {
+                        return Some(Ok(self.buf.split().freeze()));
+                    }
+                }
+                Ok(chunk) => {
+                    self.offset += chunk.len() as u64;
+                    self.buf.extend_from_slice(&chunk);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Frames picked by a predicate, see
+/// [`SeekableDecompress::decompress_matching`].
+pub struct MatchingFrames<'d, 'a, A, P> {
+    decompress: &'d mut SeekableDecompress<'a, A>,
+    predicate: P,
+    // Read on the first call to next.
+    table: Option<SeekTable>,
+    // Next frame to look at.
+    frame: usize,
+    dstream: Option<DStream>,
+    done: bool,
+}
+
+impl<'d, 'a, A, P> Iterator for MatchingFrames<'d, 'a, A, P>
+where
+    A: Read + Seek,
+    P: Fn(&FrameMeta) -> bool,
+{
+    type Item = Result<(FrameMeta, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let MatchingFrames {
+            decompress,
+            predicate,
+            table,
+            frame,
+            dstream,
+            done,
+        } = self;
+        let result = decompress.with_compressed(|compressed| {
+            let table = match table {
+                Some(table) => table,
+                None => table.insert(read_seek_table(compressed)?),
+            };
+            while *frame < table.num_frames() {
+                let meta = FrameMeta::new(table, *frame);
+                *frame += 1;
+                if meta.decompressed_size == 0 || !predicate(&meta) {
+                    continue;
+                }
+                let mut d = match dstream.take() {
+                    Some(d) => d,
+                    None => DStream::new().map_err(Error::ZstdSeekable)?,
+                };
+                let data = decompress_table_frame(compressed, table, *frame - 1, &mut d)?;
+                *dstream = Some(d);
+                return Ok(Some((meta, data)));
+            }
+            Ok(None)
+        });
+        match result {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                *done = true;
+                None
+            }
+            Err(e) => {
+                *done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Frames from last to first, see [`SeekableDecompress::frames_rev`].
+pub struct FramesRev<'d, 'a, A> {
+    decompress: &'d mut SeekableDecompress<'a, A>,
+    // Read on the first call to next, along with how many frames are left
+    // to look at.
+    table: Option<SeekTable>,
+    frames_left: Option<usize>,
+    dstream: Option<DStream>,
+    done: bool,
+}
+
+impl<'d, 'a, A> Iterator for FramesRev<'d, 'a, A>
+where
+    A: Read + Seek,
+{
+    type Item = Result<(FrameMeta, Bytes), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let FramesRev {
+            decompress,
+            table,
+            frames_left,
+            dstream,
+            done,
+        } = self;
+        let result = decompress.with_compressed(|compressed| {
+            let table = match table {
+                Some(table) => table,
+                None => table.insert(read_seek_table(compressed)?),
+            };
+            let frames_left = frames_left.get_or_insert(table.num_frames());
+            while *frames_left > 0 {
+                *frames_left -= 1;
+                let meta = FrameMeta::new(table, *frames_left);
+                if meta.decompressed_size == 0 {
+                    continue;
+                }
+                let mut d = match dstream.take() {
+                    Some(d) => d,
+                    None => DStream::new().map_err(Error::ZstdSeekable)?,
+                };
+                let data = decompress_table_frame(compressed, table, *frames_left, &mut d)?;
+                *dstream = Some(d);
+                return Ok(Some((meta, data)));
+            }
+            Ok(None)
+        });
+        match result {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                *done = true;
+                None
+            }
+            Err(e) => {
+                *done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// What [`SeekableDecompress::verify_all`] found.
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub frames: usize,
+    pub decompressed_len: u64,
+    // Whether there were checksums to verify the data against.
+    pub checksums: bool,
+    // Every frame that failed to verify, in order.
+    pub errors: Vec<FrameError>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameError {
+    pub frame: usize,
+    pub error: Error,
+}
+
+// Reads the seek table from the end of the object, checksums and all.
+pub(crate) fn read_seek_table<A: Read + Seek>(compressed: &mut A) -> Result<SeekTable, Error> {
+    let mut footer = [0; SEEK_TABLE_FOOTER_LEN];
+    compressed
+        .seek(SeekFrom::End(-(SEEK_TABLE_FOOTER_LEN as i64)))
+        .and_then(|_| compressed.read_exact(&mut footer))
+        .map_err(Error::Io)?;
+    let table_len = SeekTable::len_from_footer(&footer).map_err(|_e| Error::DataTooLarge)?;
+    let mut table = vec![0; table_len];
+    let table_start = compressed
+        .seek(SeekFrom::End(-(table_len as i64)))
+        .map_err(Error::Io)?;
+    compressed.read_exact(&mut table).map_err(Error::Io)?;
+    let table = SeekTable::parse(&table).map_err(|_e| Error::DataTooLarge)?;
+    // Frames running into the table can't be right, and believing them has
+    // us allocate whatever a corrupt table says a frame takes up.
+    if table.compressed_len() > table_start {
+        return Err(Error::ZstdSeekable(zstd_error(
+            ZSTD_ERROR_CORRUPTION_DETECTED,
+        )));
+    }
+    Ok(table)
+}
+
+// Reads each frame in order and hands it off to one of `concurrency` threads
+// to decompress into the matching output and check against its checksum,
+// each frame waiting for a worker of `budget` if there is one. Returns the
+// frames that failed, in order.
+fn for_each_frame<A, O>(
+    compressed: &mut A,
+    table: &SeekTable,
+    concurrency: usize,
+    budget: Option<&WorkerBudget>,
+    outputs: impl IntoIterator<Item = O>,
+) -> Result<Vec<FrameError>, Error>
+where
+    A: Read + Seek,
+    O: AsMut<[u8]> + Send,
+{
+    // No point in having more threads than the budget lets work at once.
+    let concurrency = concurrency
+        .min(budget.map_or(usize::MAX, WorkerBudget::workers))
+        .max(1);
+    let (send, recv) = mpsc::sync_channel::<(usize, Vec<u8>, O)>(concurrency);
+    let recv = Mutex::new(recv);
+    let mut errors = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut errors = Vec::new();
+                    let mut dstream = None;
+                    // Once the channel closes, we're done.
+                    while let Ok((frame, input, mut output)) = recv.lock().recv() {
+                        // A bad frame can leave the stream in any state so
+                        // we start again with a new one after that.
+                        let result = match dstream.take() {
+                            Some(d) => Ok(d),
+                            None => DStream::new().map_err(Error::ZstdSeekable),
+                        }
+                        .and_then(|mut d| {
+                            let _permit = budget.map(WorkerBudget::acquire);
+                            verify_frame(&mut d, table, frame, &input, output.as_mut())?;
+                            Ok(d)
+                        });
+                        match result {
+                            Ok(d) => dstream = Some(d),
+                            Err(error) => errors.push(FrameError { frame, error }),
+                        }
+                    }
+                    errors
+                })
+            })
+            .collect();
+
+        let mut read_result = Ok(());
+        for (frame, output) in outputs.into_iter().enumerate() {
+            let mut input = vec![0; table.frame_compressed_size(frame) as usize];
+            if let Err(e) = compressed
+                .seek(SeekFrom::Start(table.frame_compressed_offset(frame)))
+                .and_then(|_| compressed.read_exact(&mut input))
+            {
+                read_result = Err(Error::Io(e));
+                break;
+            }
+            if send.send((frame, input, output)).is_err() {
+                break;
+            }
+        }
+        drop(send);
+
+        let errors: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("decompression worker panicked"))
+            .collect();
+        read_result.map(|_| errors)
+    })?;
+    // The channel may borrow the outputs, get rid of it before handing those
+    // back.
+    drop(recv);
+    errors.sort_by_key(|e| e.frame);
+    Ok(errors)
+}
+
+// Reads frame `frame` of the table out of the object and decompresses it,
+// checking it against its checksum.
+fn decompress_table_frame<A: Read + Seek>(
+    compressed: &mut A,
+    table: &SeekTable,
+    frame: usize,
+    dstream: &mut DStream,
+) -> Result<Bytes, Error> {
+    let mut input = vec![0; table.frame_compressed_size(frame) as usize];
+    compressed
+        .seek(SeekFrom::Start(table.frame_compressed_offset(frame)))
+        .and_then(|_| compressed.read_exact(&mut input))
+        .map_err(Error::Io)?;
+    let mut out = vec![0; table.frame_decompressed_size(frame) as usize];
+    verify_frame(dstream, table, frame, &input, &mut out)?;
+    Ok(Bytes::from(out))
+}
+
+fn verify_frame(
+    dstream: &mut DStream,
+    table: &SeekTable,
+    frame: usize,
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<(), Error> {
+    decompress_frame(dstream, input, output)?;
+    match table.frame_checksum(frame) {
+        Some(checksum) if xxhash_rust::xxh64::xxh64(output, 0) as u32 != checksum => Err(
+            Error::ZstdSeekable(zstd_error(ZSTD_ERROR_CORRUPTION_DETECTED)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Decompresses a single whole frame, which must fill the output exactly.
+fn decompress_frame(
+    dstream: &mut DStream,
+    mut input: &[u8],
+    output: &mut [u8],
+) -> Result<(), Error> {
+    let mut written = 0;
+    while !input.is_empty() || written < output.len() {
+        let (out_pos, in_pos) = dstream
+            .decompress(&mut output[written..], input)
+            .map_err(Error::ZstdSeekable)?;
+        // DStream doesn't tell us about errors: the best we can do is notice
+        // it's stuck.
+        if out_pos == 0 && in_pos == 0 {
+            return Err(Error::ZstdSeekable(zstd_error(
+                ZSTD_ERROR_CORRUPTION_DETECTED,
+            )));
+        }
+        written += out_pos;
+        input = &input[in_pos..];
+    }
+    Ok(())
 }
 
 impl<'a, A> std::io::Seek for SeekableDecompress<'a, A> {
@@ -103,6 +1046,22 @@ impl<'a, A> std::io::Seek for SeekableDecompress<'a, A> {
     }
 }
 
+impl<'a, A> SeekableDecompress<'a, A> {
+    // What a read at the end of the data gives: nothing, unless it ended
+    // somewhere other than the length check expected. Reads past the end
+    // have the data end where the seek table says.
+    fn end_of_data(&self) -> std::io::Result<usize> {
+        let actual = self.decompressed_position.min(self.decompressed_size);
+        match self.length_check {
+            Some(expected) if expected != actual => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                Error::LengthMismatch { expected, actual },
+            )),
+            _ => Ok(0),
+        }
+    }
+}
+
 impl<'a, A> std::io::Read for SeekableDecompress<'a, A> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let data_left = match self
@@ -112,7 +1071,7 @@ impl<'a, A> std::io::Read for SeekableDecompress<'a, A> {
             Some(data_left) if data_left > 0 => data_left,
             // We're at the end of data or past it. Just return straight away
             // with no bytes read.
-            _ => return Ok(0),
+            _ => return self.end_of_data(),
         };
 
         // We know at this point we have some data remaining. The seekable
@@ -146,6 +1105,9 @@ impl<'a, A> std::io::Read for SeekableDecompress<'a, A> {
             .seekable
             .decompress(buf, self.decompressed_position)
             .map_err(zstd_error)?;
+        if decompressed_bytes == 0 {
+            return self.end_of_data();
+        }
 
         // Bump the position by however many bytes we have managed to read in.
         {
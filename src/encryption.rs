@@ -0,0 +1,241 @@
+use crate::{decompress::read_seek_table, Error, SeekTable};
+use std::{
+    convert::TryFrom,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+};
+
+/// Errors from user-supplied ciphers.
+pub type CipherError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An encrypted frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ciphertext {
+    /// The encrypted frame, which must be exactly as long as the plaintext.
+    pub data: Vec<u8>,
+    /// Anything else needed to decrypt the frame, such as the nonce and the
+    /// authentication tag. Stored in a skippable frame after the ciphertext.
+    pub metadata: Vec<u8>,
+}
+
+/// Client-side encryption of frames.
+///
+/// With [`Compress::encrypt_frames`](crate::Compress::encrypt_frames), every
+/// compressed frame is encrypted before it's yielded. The
+/// ciphertext replaces the frame, followed by a skippable frame holding
+/// whatever else the cipher needs to decrypt it, typically the nonce and the
+/// authentication tag. The seek table counts both as part of the frame so its
+/// offsets stay correct. To read the object back, wrap the reader in a
+/// [`DecryptingReader`] and decompress from that as usual.
+///
+/// We don't ship any ciphers: bring your own, for example AES-GCM or
+/// ChaCha20-Poly1305 in detached mode.
+///
+/// # Security caveats
+///
+/// Only the frames themselves are encrypted. Anyone with the object can
+/// still see:
+///
+/// - The seek table: how many frames there are and the compressed and
+///   decompressed size of each. Compressed sizes leak how compressible each
+///   frame is, which is enough to recover secrets in some settings (think
+///   CRIME and BREACH) if attackers control part of the data.
+/// - The frame checksums, which are XXH64 of the *plaintext*. These are not
+///   cryptographic and let anyone confirm a guess of a frame's contents.
+///   Don't encrypt low-entropy data this way.
+///
+/// Nothing authenticates the seek table or ties frames to their position, so
+/// frames can be dropped, repeated or reordered by rewriting the table. Bind
+/// the frame index into the authenticated data (it's passed to both sides
+/// for that reason) and check the frame count out of band if that matters.
+///
+/// Nonces must never repeat under the same key, across objects too. Deriving
+/// them from the frame index alone is only safe with a key per object.
+pub trait Encryptor {
+    /// Encrypts the compressed frame with the given index.
+    fn encrypt(&mut self, frame_index: usize, plaintext: &[u8]) -> Result<Ciphertext, CipherError>;
+}
+
+pub trait Decryptor {
+    /// Decrypts the frame with the given index, given what the
+    /// [`Encryptor`] produced for it. Fails if the frame doesn't
+    /// authenticate.
+    fn decrypt(
+        &mut self,
+        frame_index: usize,
+        ciphertext: &[u8],
+        metadata: &[u8],
+    ) -> Result<Vec<u8>, CipherError>;
+}
+
+// Magic of the skippable frame holding the metadata, one off from the seek
+// table's.
+const METADATA_MAGIC: u32 = 0x184D_2A5D;
+// Skippable frame header in front of the metadata and its length after it.
+const METADATA_OVERHEAD: usize = 12;
+
+// Encrypts a frame, giving the ciphertext followed by the metadata frame.
+// Having the metadata length at the end lets us find where the ciphertext
+// ends from the end of the frame.
+pub(crate) fn encrypt_frame(
+    encryptor: &mut (dyn Encryptor + Send),
+    frame_index: usize,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CipherError> {
+    let Ciphertext { data, metadata } = encryptor.encrypt(frame_index, plaintext)?;
+    if data.len() != plaintext.len() {
+        return Err(format!(
+            "Ciphertext of frame {} is {} bytes long rather than {}.",
+            frame_index,
+            data.len(),
+            plaintext.len()
+        )
+        .into());
+    }
+    let metadata_len = u32::try_from(metadata.len())
+        .ok()
+        .filter(|&len| len <= u32::MAX - 4)
+        .ok_or("Frame metadata too large.")?;
+    let mut frame = data;
+    frame.reserve(metadata.len() + METADATA_OVERHEAD);
+    frame.extend_from_slice(&METADATA_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&(metadata_len + 4).to_le_bytes());
+    frame.extend_from_slice(&metadata);
+    frame.extend_from_slice(&metadata_len.to_le_bytes());
+    Ok(frame)
+}
+
+// Splits an encrypted frame back into the ciphertext and the metadata frame.
+fn split_frame(frame: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len_at = frame.len().checked_sub(4)?;
+    let mut len = [0; 4];
+    len.copy_from_slice(&frame[len_at..]);
+    let metadata_len = u32::from_le_bytes(len) as usize;
+    let metadata_frame_len = metadata_len.checked_add(METADATA_OVERHEAD - 4)?;
+    let (ciphertext, metadata_frame) = frame.split_at(len_at.checked_sub(metadata_frame_len)?);
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&metadata_frame[..4]);
+    (u32::from_le_bytes(magic) == METADATA_MAGIC).then_some((ciphertext, metadata_frame))
+}
+
+/// Decrypts frames written with
+/// [`Compress::encrypt_frames`](crate::Compress::encrypt_frames) as they're
+/// read, for [`SeekableDecompress`](crate::SeekableDecompress) to read
+/// from.
+///
+/// Frames are read and decrypted whole, the last one is kept around.
+pub struct DecryptingReader<R, D> {
+    inner: R,
+    decryptor: D,
+    seek_table: SeekTable,
+    length: u64,
+    position: u64,
+    // The last frame we decrypted: its index and the decrypted frame along
+    // with the metadata frame.
+    frame: Option<(usize, Vec<u8>)>,
+}
+
+impl<R, D> DecryptingReader<R, D>
+where
+    R: Read + Seek,
+    D: Decryptor,
+{
+    pub fn new(mut inner: R, decryptor: D) -> Result<Self, Error> {
+        let seek_table = read_seek_table(&mut inner)?;
+        let length = inner.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+        Ok(DecryptingReader {
+            inner,
+            decryptor,
+            seek_table,
+            length,
+            position: 0,
+            frame: None,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn load_frame(&mut self, frame: usize) -> std::io::Result<&[u8]> {
+        if !matches!(self.frame, Some((f, _)) if f == frame) {
+            self.frame = None;
+            let mut encrypted = vec![0; self.seek_table.frame_compressed_size(frame) as usize];
+            self.inner.seek(SeekFrom::Start(
+                self.seek_table.frame_compressed_offset(frame),
+            ))?;
+            self.inner.read_exact(&mut encrypted)?;
+
+            let invalid = |e: CipherError| std::io::Error::new(ErrorKind::InvalidData, e);
+            let (ciphertext, metadata_frame) = split_frame(&encrypted)
+                .ok_or_else(|| invalid(format!("Frame {} has no valid metadata.", frame).into()))?;
+            let metadata = &metadata_frame[8..metadata_frame.len() - 4];
+            let mut decrypted = self
+                .decryptor
+                .decrypt(frame, ciphertext, metadata)
+                .map_err(invalid)?;
+            if decrypted.len() != ciphertext.len() {
+                return Err(invalid(
+                    format!("Frame {} decrypted to the wrong length.", frame).into(),
+                ));
+            }
+            // zstd skips over the metadata frame, so leave it in place.
+            decrypted.extend_from_slice(metadata_frame);
+            self.frame = Some((frame, decrypted));
+        }
+        Ok(self.frame.as_ref().map(|(_, f)| &f[..]).unwrap_or_default())
+    }
+}
+
+impl<R, D> Read for DecryptingReader<R, D>
+where
+    R: Read + Seek,
+    D: Decryptor,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = match self.seek_table.frame_for_compressed_offset(self.position) {
+            Some(frame) => {
+                let from =
+                    (self.position - self.seek_table.frame_compressed_offset(frame)) as usize;
+                let decrypted = &self.load_frame(frame)?[from..];
+                let n = buf.len().min(decrypted.len());
+                buf[..n].copy_from_slice(&decrypted[..n]);
+                n
+            }
+            // The seek table isn't encrypted.
+            None => {
+                self.inner.seek(SeekFrom::Start(self.position))?;
+                self.inner.read(buf)?
+            }
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R, D> Seek for DecryptingReader<R, D> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.position = pos;
+                return Ok(pos);
+            }
+            SeekFrom::End(pos) => (self.length, pos),
+            SeekFrom::Current(pos) => (self.position, pos),
+        };
+        let new_pos = if offset >= 0 {
+            base_pos.checked_add(offset as u64)
+        } else {
+            base_pos.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match new_pos {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
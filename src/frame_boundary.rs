@@ -0,0 +1,293 @@
+use crate::cstream::{config_error, MAX_FRAME_SIZE};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Where frames end.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum FrameBoundary {
+    /// Every frame holds `frame_size` bytes, as given to
+    /// [`compress`](crate::StreamCompress::compress). This is the default.
+    #[default]
+    Fixed,
+    /// Frames end at content-defined chunking points: wherever a rolling hash
+    /// of the last few dozen bytes hits a magic value. As boundaries depend
+    /// only on nearby content, inserting or removing bytes only changes the
+    /// frames around the edit rather than shifting every frame after it,
+    /// which makes the output much friendlier to deduplication.
+    ///
+    /// No frame is shorter than `min` (save for the last) or longer than
+    /// `max` bytes, and on random-ish data frames average around `avg`
+    /// bytes. `max` replaces the `frame_size` given to `compress`.
+    ///
+    /// The rolling hash is a gear hash as used by FastCDC: a shift, an add
+    /// and a table lookup per byte, with the first `min` bytes of every frame
+    /// skipped altogether. That's cheap next to compression itself but isn't
+    /// free: count on a few percent more CPU time at fast compression levels.
+    ContentDefined { min: usize, avg: usize, max: usize },
+    /// Frames end at the first `delimiter` once they hold at least `target`
+    /// bytes, the delimiter included, so that records such as lines of logs
+    /// are never split across frames. A frame with no delimiter in it after
+    /// `max` bytes is ended there anyway, splitting the record. `max`
+    /// replaces the `frame_size` given to
+    /// [`compress`](crate::StreamCompress::compress).
+    Delimiter {
+        delimiter: u8,
+        target: usize,
+        max: usize,
+    },
+    /// Frames end wherever the callback says, see
+    /// [`FrameBoundary::callback`].
+    Callback { callback: FrameCallback, max: usize },
+}
+
+impl FrameBoundary {
+    /// Frames end wherever `callback` says. As input comes in, the callback
+    /// is given everything in the current frame so far and returns where in
+    /// there the frame should end, or `None` to keep going. If it picks a
+    /// point it was already shown before, the frame ends before the new
+    /// input instead, as the rest was compressed already. Frames never hold
+    /// more than `max` bytes however long the callback holds out, and
+    /// `max` replaces the `frame_size` given to
+    /// [`compress`](crate::StreamCompress::compress).
+    ///
+    /// The current frame is kept around to show to the callback, so this
+    /// costs up to `max` bytes of memory, and the callback sees the same
+    /// bytes again with every new upstream item.
+    pub fn callback(
+        max: usize,
+        callback: impl FnMut(&[u8]) -> Option<usize> + Send + 'static,
+    ) -> Self {
+        FrameBoundary::Callback {
+            callback: FrameCallback(Arc::new(Mutex::new(callback))),
+            max,
+        }
+    }
+}
+
+/// A callback deciding where frames end, for [`FrameBoundary::callback`].
+/// Clones share the same callback and compare equal only to each other.
+#[derive(Clone)]
+pub struct FrameCallback(Arc<Mutex<BoundaryFn>>);
+
+type BoundaryFn = dyn FnMut(&[u8]) -> Option<usize> + Send;
+
+impl std::fmt::Debug for FrameCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameCallback").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for FrameCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for FrameCallback {}
+
+// Random values for the gear hash, one per byte value.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    // splitmix64 so we don't have to paste in a table of magic numbers.
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+// Decides where frames end as the input comes in.
+#[derive(Debug)]
+pub(crate) struct Chunker {
+    boundary: FrameBoundary,
+    // Bytes in the current frame so far.
+    frame_len: usize,
+    hash: u64,
+    // A boundary is wherever the top bits of the hash are all zero.
+    mask: u64,
+    // The current frame so far, for callbacks to look at.
+    frame: Vec<u8>,
+}
+
+impl Chunker {
+    pub(crate) fn new(boundary: FrameBoundary) -> Result<Self, zstd_seekable::Error> {
+        let mask = match boundary {
+            FrameBoundary::Fixed => 0,
+            FrameBoundary::Callback { max, .. } => {
+                if max == 0 || max > MAX_FRAME_SIZE {
+                    return Err(config_error(
+                        "max",
+                        max,
+                        &format!("from 1 to {}", MAX_FRAME_SIZE),
+                    ));
+                }
+                0
+            }
+            FrameBoundary::Delimiter { target, max, .. } => {
+                if target == 0 || target > max {
+                    return Err(config_error(
+                        "target",
+                        target,
+                        &format!("from 1 to max ({})", max),
+                    ));
+                }
+                if max > MAX_FRAME_SIZE {
+                    return Err(config_error(
+                        "max",
+                        max,
+                        &format!("from target ({}) to {}", target, MAX_FRAME_SIZE),
+                    ));
+                }
+                0
+            }
+            FrameBoundary::ContentDefined { min, avg, max } => {
+                if min == 0 || min > avg {
+                    return Err(config_error(
+                        "min",
+                        min,
+                        &format!("from 1 to avg ({})", avg),
+                    ));
+                }
+                if avg > max {
+                    return Err(config_error(
+                        "avg",
+                        avg,
+                        &format!("from min ({}) to max ({})", min, max),
+                    ));
+                }
+                if max > MAX_FRAME_SIZE {
+                    return Err(config_error(
+                        "max",
+                        max,
+                        &format!("from avg ({}) to {}", avg, MAX_FRAME_SIZE),
+                    ));
+                }
+                // We start looking for boundaries after min bytes so aim for
+                // hits every avg - min bytes after that.
+                let bits = (avg - min + 1).next_power_of_two().trailing_zeros();
+                if bits == 0 {
+                    0
+                } else {
+                    !0 << (64 - bits)
+                }
+            }
+        };
+        Ok(Chunker {
+            boundary,
+            frame_len: 0,
+            hash: 0,
+            mask,
+            frame: Vec::new(),
+        })
+    }
+
+    // The largest frame we will produce, if we decide on it.
+    pub(crate) fn max_frame_size(&self) -> Option<usize> {
+        match self.boundary {
+            FrameBoundary::Fixed => None,
+            FrameBoundary::ContentDefined { max, .. }
+            | FrameBoundary::Delimiter { max, .. }
+            | FrameBoundary::Callback { max, .. } => Some(max),
+        }
+    }
+
+    // Starts over on a new frame, for when the frame ended other than at a
+    // boundary we found.
+    pub(crate) fn reset(&mut self) {
+        self.frame_len = 0;
+        self.hash = 0;
+        self.frame.clear();
+    }
+
+    // Looks for the end of the current frame in the input. If there is one,
+    // returns how many bytes of input belong to the current frame and starts
+    // a new one. Otherwise all of the input goes in the current frame.
+    pub(crate) fn find_boundary(&mut self, input: &[u8]) -> Option<usize> {
+        let (min, max) = match &self.boundary {
+            FrameBoundary::Fixed => return None,
+            FrameBoundary::ContentDefined { min, max, .. } => (*min, *max),
+            FrameBoundary::Delimiter {
+                delimiter,
+                target,
+                max,
+            } => return self.find_delimiter(*delimiter, *target, *max, input),
+            FrameBoundary::Callback { callback, max } => {
+                return Self::ask_callback(&mut self.frame, callback, *max, input)
+            }
+        };
+        let mut pos = 0;
+        // Skip over the minimum frame size: no boundaries in there.
+        if self.frame_len < min {
+            let skip = (min - self.frame_len).min(input.len());
+            self.frame_len += skip;
+            pos = skip;
+        }
+        let mut hit = false;
+        while self.frame_len < max && pos < input.len() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[input[pos] as usize]);
+            self.frame_len += 1;
+            pos += 1;
+            if self.hash & self.mask == 0 {
+                hit = true;
+                break;
+            }
+        }
+        if hit || self.frame_len >= max {
+            self.frame_len = 0;
+            self.hash = 0;
+            return Some(pos);
+        }
+        None
+    }
+
+    fn find_delimiter(
+        &mut self,
+        delimiter: u8,
+        target: usize,
+        max: usize,
+        input: &[u8],
+    ) -> Option<usize> {
+        // A delimiter only ends the frame if it takes the frame to at least
+        // target bytes, so skip the ones before that.
+        let skip = (target - 1).saturating_sub(self.frame_len).min(input.len());
+        let room = input.len().min(max - self.frame_len);
+        let end = match input[skip..room].iter().position(|&b| b == delimiter) {
+            Some(pos) => skip + pos + 1,
+            None if self.frame_len + room == max => room,
+            None => {
+                self.frame_len += input.len();
+                return None;
+            }
+        };
+        self.frame_len = 0;
+        Some(end)
+    }
+
+    fn ask_callback(
+        frame: &mut Vec<u8>,
+        callback: &FrameCallback,
+        max: usize,
+        input: &[u8],
+    ) -> Option<usize> {
+        let seen = frame.len();
+        let taken = input.len().min(max - seen);
+        frame.extend_from_slice(&input[..taken]);
+        let boundary = match (callback.0.lock())(frame) {
+            // Can't end the frame in what we compressed already, nor end it
+            // empty.
+            Some(boundary) => Some(boundary.max(seen).max(1).min(frame.len())),
+            None if frame.len() == max => Some(max),
+            None => None,
+        };
+        boundary.map(|boundary| {
+            frame.clear();
+            boundary - seen
+        })
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{instrument, reframe::decompress_frame, SeekTable};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+use xxhash_rust::xxh64::{xxh64, Xxh64};
+use zstd_seekable::{CStream, DStream};
+
+/// What identifies a frame in a [`FrameCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCacheKey {
+    /// The frame's checksum in the seek table, along with its compressed and
+    /// decompressed size. Hits don't even need the frame to be fetched, but
+    /// the checksum is only 32 bits: among millions of frames of different
+    /// content, sooner or later two will share a key and one will be served
+    /// as the other. Only use this for data you wrote yourself with
+    /// checksums, and not too much of it.
+    ///
+    /// Frames without a checksum aren't cached.
+    Checksum,
+    /// An XXH64 hash of the compressed frame, along with its size. The frame
+    /// has to be fetched to be looked up, so hits only save decompressing
+    /// it. XXH64 isn't cryptographic, don't share a cache between objects
+    /// from sources that don't trust each other.
+    CompressedHash,
+}
+
+/// Decompressed frames, shared by position-independent key so that
+/// identical frames are decompressed once however many objects and places
+/// they show up in. That pays off for deduplicated data, such as objects
+/// written with
+/// [`FrameBoundary::ContentDefined`](crate::FrameBoundary::ContentDefined).
+///
+/// Clones share the same cache, hand one to every reader with
+/// [`SeekableS3Object::set_frame_cache`](crate::SeekableS3Object::set_frame_cache)
+/// or [`SeekableDecompress::set_frame_cache`](crate::SeekableDecompress::set_frame_cache).
+/// Holds up to about `capacity` bytes of decompressed frames, dropping the
+/// least recently used ones to make room.
+#[derive(Clone)]
+pub struct FrameCache {
+    key: FrameCacheKey,
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FrameId {
+    // The checksum or the hash, depending on the key.
+    hash: u64,
+    compressed_size: u64,
+    decompressed_size: u64,
+}
+
+struct CacheInner {
+    capacity: usize,
+    size: usize,
+    // Frames along with when they were last used.
+    frames: HashMap<FrameId, (Bytes, u64)>,
+    // What was used when, oldest first.
+    uses: BTreeMap<u64, FrameId>,
+    clock: u64,
+}
+
+impl std::fmt::Debug for FrameCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("FrameCache")
+            .field("key", &self.key)
+            .field("capacity", &inner.capacity)
+            .field("size", &inner.size)
+            .field("frames", &inner.frames.len())
+            .finish()
+    }
+}
+
+impl FrameCache {
+    pub fn new(capacity: usize, key: FrameCacheKey) -> Self {
+        FrameCache {
+            key,
+            inner: Arc::new(Mutex::new(CacheInner {
+                capacity,
+                size: 0,
+                frames: HashMap::new(),
+                uses: BTreeMap::new(),
+                clock: 0,
+            })),
+        }
+    }
+
+    pub fn key(&self) -> FrameCacheKey {
+        self.key
+    }
+
+    /// Number of frames in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Most bytes of decompressed frames the cache holds.
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().capacity
+    }
+
+    /// Bytes of decompressed frames in the cache.
+    pub fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+
+    /// Decompresses `compressed`, frame `frame` of `seek_table`, unless the
+    /// cache has it already. Frames are checked against their checksum, if
+    /// they have one, before they go in the cache.
+    pub fn decompress_frame(
+        &self,
+        seek_table: &SeekTable,
+        frame: usize,
+        compressed: &[u8],
+    ) -> std::io::Result<Bytes> {
+        let id = self.frame_id(seek_table, frame, Some(compressed));
+        if let Some(data) = id.and_then(|id| self.get(id)) {
+            return Ok(data);
+        }
+        let data = decompress_checked(seek_table, frame, compressed)?;
+        if let Some(id) = id {
+            self.insert(id, data.clone());
+        }
+        Ok(data)
+    }
+
+    // The key of the frame, if we can work it out from what we have.
+    pub(crate) fn frame_id(
+        &self,
+        seek_table: &SeekTable,
+        frame: usize,
+        compressed: Option<&[u8]>,
+    ) -> Option<FrameId> {
+        let hash = match self.key {
+            FrameCacheKey::Checksum => u64::from(seek_table.frame_checksum(frame)?),
+            FrameCacheKey::CompressedHash => xxh64(compressed?, 0),
+        };
+        Some(FrameId {
+            hash,
+            compressed_size: seek_table.frame_compressed_size(frame),
+            decompressed_size: seek_table.frame_decompressed_size(frame),
+        })
+    }
+
+    pub(crate) fn get(&self, id: FrameId) -> Option<Bytes> {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        inner.clock += 1;
+        let (data, used) = inner.frames.get_mut(&id)?;
+        inner.uses.remove(used);
+        *used = inner.clock;
+        inner.uses.insert(inner.clock, id);
+        instrument::cache_hit();
+        Some(data.clone())
+    }
+
+    fn insert(&self, id: FrameId, data: Bytes) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        if data.len() > inner.capacity || inner.frames.contains_key(&id) {
+            return;
+        }
+        while inner.size + data.len() > inner.capacity {
+            let (_, oldest) = match inner.uses.pop_first() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some((evicted, _)) = inner.frames.remove(&oldest) {
+                inner.size -= evicted.len();
+            }
+        }
+        inner.clock += 1;
+        inner.size += data.len();
+        inner.uses.insert(inner.clock, id);
+        inner.frames.insert(id, (data, inner.clock));
+    }
+}
+
+// Decompresses a frame, checking it against the seek table.
+pub(crate) fn decompress_checked(
+    seek_table: &SeekTable,
+    frame: usize,
+    compressed: &[u8],
+) -> std::io::Result<Bytes> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let mut dstream = DStream::new().map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+    let mut buf = vec![0; CStream::out_size()];
+    let mut data = Vec::with_capacity(seek_table.frame_decompressed_size(frame) as usize);
+    let mut hasher = Xxh64::new(0);
+    decompress_frame(&mut dstream, compressed, &mut buf, |out| {
+        hasher.update(out);
+        data.extend_from_slice(out);
+    })
+    .map_err(|e| invalid(format!("Frame {} failed to decompress: {}", frame, e)))?;
+    if data.len() as u64 != seek_table.frame_decompressed_size(frame) {
+        return Err(invalid(format!(
+            "Frame {} decompressed to {} bytes rather than {}.",
+            frame,
+            data.len(),
+            seek_table.frame_decompressed_size(frame)
+        )));
+    }
+    if let Some(checksum) = seek_table.frame_checksum(frame) {
+        if hasher.digest() as u32 != checksum {
+            return Err(invalid(format!("Frame {} failed its checksum.", frame)));
+        }
+    }
+    Ok(Bytes::from(data))
+}
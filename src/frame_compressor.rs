@@ -0,0 +1,87 @@
+use crate::cstream::{check, config_error, MAX_COMPRESSION_LEVEL, MIN_COMPRESSION_LEVEL};
+use zstd_seekable::{CStream, EndDirective, Error};
+
+/// What compresses the data of each frame for
+/// [`Compress`](crate::Compress), which takes care of everything else:
+/// where frames end, checksums, the seek table and what goes out when.
+/// [`ZstdFrameCompressor`] is the one used unless
+/// [`Compress::frame_compressor`](crate::Compress::frame_compressor) says
+/// otherwise, which is mostly useful for testing code built on `Compress`
+/// with a compressor that's cheap and predictable.
+///
+/// Every frame has to be a zstd frame of its own for the output to be
+/// readable as a seekable object. Anything else only gives the right
+/// layout: the same frames and seek table, going by their sizes.
+///
+/// A compressor of your own is also the way to set zstd parameters beyond
+/// the level, such as long distance matching, with bindings that expose
+/// them: [`ZstdFrameCompressor`] can't, as zstd_seekable only takes a level.
+/// Frames are compressed independently, so matches never reach past the
+/// start of a frame and long distance matching only pays off with large
+/// frames.
+pub trait FrameCompressor {
+    /// Takes as much of `input` as it likes into the current frame, starting
+    /// one if there's none, and writes as much output as it has and fits.
+    /// Gives `(out_pos, in_pos)`, how much was written and taken. Output may
+    /// be held back until the frame ends.
+    fn compress(&mut self, output: &mut [u8], input: &[u8]) -> Result<(usize, usize), Error>;
+
+    /// Ends the current frame, writing out what's left of it. Gives
+    /// `(out_pos, remaining)`: how much was written and how much more there
+    /// is to write, 0 once the frame is done. Until then, this is called
+    /// again with more room. Frames may be empty.
+    fn end_frame(&mut self, output: &mut [u8]) -> Result<(usize, usize), Error>;
+}
+
+/// Compresses frames with zstd, see [`FrameCompressor`].
+pub struct ZstdFrameCompressor {
+    cstream: CStream,
+}
+
+// CStream is just an owned pointer to the zstd context, same as
+// SeekableCStream which zstd_seekable does mark as Send. We never share it.
+unsafe impl Send for ZstdFrameCompressor {}
+
+impl std::fmt::Debug for ZstdFrameCompressor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZstdFrameCompressor")
+            .finish_non_exhaustive()
+    }
+}
+
+impl ZstdFrameCompressor {
+    /// Fails if the level is out of range, see
+    /// [`StreamCompress::compress`](crate::StreamCompress::compress).
+    pub fn new(compression_level: i32) -> Result<Self, Error> {
+        if !(MIN_COMPRESSION_LEVEL..=MAX_COMPRESSION_LEVEL).contains(&compression_level) {
+            return Err(config_error(
+                "compression_level",
+                compression_level,
+                &format!(
+                    "from {} to {} or 0 for zstd's default",
+                    MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL
+                ),
+            ));
+        }
+        Ok(ZstdFrameCompressor {
+            // zstd_seekable takes the level as a usize only to hand it to zstd
+            // as an int, so negative levels make it through the round trip.
+            cstream: CStream::new(compression_level as usize)?,
+        })
+    }
+}
+
+impl FrameCompressor for ZstdFrameCompressor {
+    fn compress(&mut self, output: &mut [u8], input: &[u8]) -> Result<(usize, usize), Error> {
+        let (out_pos, in_pos, code) =
+            self.cstream
+                .compress2(output, input, EndDirective::Continue)?;
+        check(code)?;
+        Ok((out_pos, in_pos))
+    }
+
+    fn end_frame(&mut self, output: &mut [u8]) -> Result<(usize, usize), Error> {
+        let (out_pos, _, remaining) = self.cstream.compress2(output, &[], EndDirective::End)?;
+        Ok((out_pos, check(remaining)?))
+    }
+}
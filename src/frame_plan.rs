@@ -0,0 +1,135 @@
+use crate::{
+    cstream::{compress_bound, MAX_FRAME_SIZE},
+    SEEK_TABLE_FOOTER_LEN,
+};
+use std::convert::TryFrom;
+
+// The frame plan written by Compress::frame_plan: a skippable frame holding
+// the frame size and the total length of the data, both as little endian
+// u64s.
+
+// Three off from the seek table's magic, one off from the metadata's.
+const FRAME_PLAN_MAGIC: u32 = 0x184D_2A5B;
+const FRAME_PLAN_CONTENT_LEN: u32 = 16;
+// Any skippable frame has a magic number from here to +15.
+const SKIPPABLE_MAGIC_MIN: u32 = 0x184D_2A50;
+
+/// How the data of an object written with
+/// [`Compress::frame_plan`](crate::Compress::frame_plan) is split into
+/// frames, known from its first few bytes. Frames are numbered from the
+/// first one holding data, leaving out the skippable frames in front, and
+/// all hold `frame_size` bytes but the last.
+///
+/// Only decompressed offsets can be known up front: how big each frame gets
+/// compressed isn't known until it's compressed. Frames start right after
+/// each other though, so a reader going through the object in order gets
+/// each one as it arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramePlan {
+    pub frame_size: u64,
+    pub total_len: u64,
+}
+
+impl FramePlan {
+    /// Finds the plan in the skippable frames at the start of an object,
+    /// given the start of it. None if there's no plan in there, or not
+    /// enough of the object to get to it.
+    pub fn read(mut prefix: &[u8]) -> Option<FramePlan> {
+        loop {
+            let magic = read_u32(prefix, 0)?;
+            let len = read_u32(prefix, 4)?;
+            if magic == FRAME_PLAN_MAGIC && len == FRAME_PLAN_CONTENT_LEN {
+                let frame_size = read_u64(prefix, 8)?;
+                let total_len = read_u64(prefix, 16)?;
+                return (frame_size > 0).then_some(FramePlan {
+                    frame_size,
+                    total_len,
+                });
+            }
+            if magic & 0xFFFF_FFF0 != SKIPPABLE_MAGIC_MIN {
+                return None;
+            }
+            prefix = prefix.get(8 + len as usize..)?;
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        ((self.total_len + self.frame_size - 1) / self.frame_size) as usize
+    }
+
+    pub fn frame_decompressed_offset(&self, frame: usize) -> u64 {
+        frame as u64 * self.frame_size
+    }
+
+    pub fn frame_decompressed_size(&self, frame: usize) -> u64 {
+        self.total_len
+            .saturating_sub(self.frame_decompressed_offset(frame))
+            .min(self.frame_size)
+    }
+
+    /// The frame holding the byte at the given decompressed offset, if any.
+    pub fn frame_for_offset(&self, offset: u64) -> Option<usize> {
+        (offset < self.total_len).then(|| (offset / self.frame_size) as usize)
+    }
+
+    pub(crate) fn to_frame(self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(8 + FRAME_PLAN_CONTENT_LEN as usize);
+        frame.extend_from_slice(&FRAME_PLAN_MAGIC.to_le_bytes());
+        frame.extend_from_slice(&FRAME_PLAN_CONTENT_LEN.to_le_bytes());
+        frame.extend_from_slice(&self.frame_size.to_le_bytes());
+        frame.extend_from_slice(&self.total_len.to_le_bytes());
+        frame
+    }
+}
+
+/// How compressing some input would turn out, as far as that's known
+/// without compressing it, see [`plan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionPlan {
+    pub frame_count: u64,
+    /// Length of the seek table at the end.
+    pub footer_len: u64,
+    /// Most the whole object can take up, seek table included, however badly
+    /// the data compresses.
+    pub estimated_max_output: u64,
+}
+
+/// Lays out `input_len` bytes of input the way
+/// [`StreamCompress::compress`](crate::StreamCompress::compress) does with
+/// frames of `frame_size` bytes, 0 for the largest ones, without compressing
+/// anything, for sizing storage and picking settings up front. This is only
+/// arithmetic: the frame count and seek table length are exact for plain
+/// compression with frame checksums, the default, while the output is a
+/// bound. Input that ends on a frame boundary gets an empty last frame, as
+/// it does when compressed.
+///
+/// [`Compress::checksum_frames`](crate::Compress::checksum_frames) off takes
+/// 4 bytes a frame off the seek table. Settings that add frames, such as
+/// [`Compress::metadata`](crate::Compress::metadata), or end them early,
+/// such as [`Compress::frame_boundary`](crate::Compress::frame_boundary),
+/// aren't accounted for.
+pub fn plan(input_len: u64, frame_size: usize) -> CompressionPlan {
+    let frame_size = match frame_size {
+        0 => MAX_FRAME_SIZE,
+        frame_size => frame_size.min(MAX_FRAME_SIZE),
+    } as u64;
+    let frame_count = input_len / frame_size + 1;
+    // The skippable frame header, an entry with a checksum for every frame
+    // and the footer.
+    let footer_len = 8 + frame_count * 12 + SEEK_TABLE_FOOTER_LEN as u64;
+    CompressionPlan {
+        frame_count,
+        footer_len,
+        estimated_max_output: compress_bound(input_len, frame_count) + footer_len,
+    }
+}
+
+fn read_u32(input: &[u8], at: usize) -> Option<u32> {
+    let bytes = input.get(at..at + 4)?;
+    Some(u32::from_le_bytes(<[u8; 4]>::try_from(bytes).ok()?))
+}
+
+fn read_u64(input: &[u8], at: usize) -> Option<u64> {
+    let bytes = input.get(at..at + 8)?;
+    Some(u64::from_le_bytes(<[u8; 8]>::try_from(bytes).ok()?))
+}
@@ -0,0 +1,168 @@
+use crate::{Error, SeekableDecompress};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io::{Read, Seek};
+
+// Turns HTTP Range headers into reads of the decompressed data, following
+// RFC 9110 section 14.
+
+/// More ranges than this in one request and we don't bother, serving the
+/// whole object.
+pub const MAX_HTTP_RANGES: usize = 16;
+
+/// What to answer a request with a `Range` header with, see [`serve_range`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeResponse {
+    /// The header isn't one we can make sense of, or asks for more than
+    /// [`MAX_HTTP_RANGES`] ranges. Serve the whole object with 200 as if
+    /// there was no header at all, which is what the RFC says to do.
+    Ignored,
+    /// Send the part with 206, along with its `Content-Range` and
+    /// `Content-Length`.
+    Single(RangePart),
+    /// Send the parts with 206 as `multipart/byteranges`, see
+    /// [`multipart_byteranges`].
+    Multiple(Vec<RangePart>),
+    /// None of the ranges overlap the data. Send 416 with this as the
+    /// `Content-Range`.
+    Unsatisfiable { content_range: String },
+}
+
+impl RangeResponse {
+    /// The HTTP status this goes with.
+    pub fn status(&self) -> u16 {
+        match self {
+            RangeResponse::Ignored => 200,
+            RangeResponse::Single(_) | RangeResponse::Multiple(_) => 206,
+            RangeResponse::Unsatisfiable { .. } => 416,
+        }
+    }
+}
+
+/// One of the ranges asked for, with the data in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangePart {
+    /// Where the data starts in the decompressed object.
+    pub offset: u64,
+    pub data: Bytes,
+    /// Such as `bytes 0-499/1234`.
+    pub content_range: String,
+}
+
+impl RangePart {
+    pub fn content_length(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+/// Answers a request carrying `range`, the value of its `Range` header, out
+/// of the decompressed data. Only the frames holding the ranges are
+/// decompressed. Ranges are served in the order they're asked for, but those
+/// past the end of the data are left out and those running past it cut
+/// short.
+///
+/// Every range is read into memory, and a request can ask for the same data
+/// over and over, up to [`MAX_HTTP_RANGES`] times. Put a limit on how much
+/// you're willing to serve in one response in front of this if that matters.
+/// Errors are those of reading the object.
+pub fn serve_range<A: Read + Seek>(
+    decompress: &mut SeekableDecompress<'_, A>,
+    range: &str,
+) -> Result<RangeResponse, Error> {
+    let len = decompress.decompressed_len();
+    let ranges = match parse_ranges(range) {
+        Some(ranges) if ranges.len() <= MAX_HTTP_RANGES => ranges,
+        _ => return Ok(RangeResponse::Ignored),
+    };
+    let mut parts = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let (first, last) = match range.resolve(len) {
+            Some(resolved) => resolved,
+            None => continue,
+        };
+        let data = decompress.read_range(first, (last - first + 1) as usize)?;
+        parts.push(RangePart {
+            offset: first,
+            data,
+            content_range: format!("bytes {}-{}/{}", first, last, len),
+        });
+    }
+    Ok(match parts.len() {
+        0 => RangeResponse::Unsatisfiable {
+            content_range: format!("bytes */{}", len),
+        },
+        1 => RangeResponse::Single(parts.remove(0)),
+        _ => RangeResponse::Multiple(parts),
+    })
+}
+
+/// The body of a `multipart/byteranges` response holding `parts`, each of
+/// them labelled with `content_type`, the type of the whole object. Send it
+/// with a `Content-Type` of `multipart/byteranges; boundary=` followed by
+/// `boundary`, which mustn't show up anywhere in the data.
+pub fn multipart_byteranges(parts: &[RangePart], content_type: &str, boundary: &str) -> Bytes {
+    let mut body = BytesMut::new();
+    for part in parts {
+        let header = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary, content_type, part.content_range
+        );
+        body.put_slice(header.as_bytes());
+        body.put_slice(&part.data);
+    }
+    body.put_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body.freeze()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    // From the first byte to the last one, if given, or the end.
+    FromTo(u64, Option<u64>),
+    // The last so many bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    // The first and last byte of the range in data of `len` bytes, None if
+    // the range is past the end.
+    fn resolve(self, len: u64) -> Option<(u64, u64)> {
+        let (first, last) = match self {
+            ByteRange::FromTo(first, last) => (first, last.unwrap_or(u64::MAX)),
+            ByteRange::Suffix(suffix) => (len.saturating_sub(suffix), u64::MAX),
+        };
+        let last = last.min(len.checked_sub(1)?);
+        (first <= last).then_some((first, last))
+    }
+}
+
+// Parses `bytes=` followed by ranges separated by commas. None if anything
+// about it is off, including a range ending before it starts.
+fn parse_ranges(header: &str) -> Option<Vec<ByteRange>> {
+    let (unit, ranges) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let ranges = ranges
+        .split(',')
+        .map(str::trim)
+        // The grammar allows empty elements in lists.
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let (first, last) = range.split_once('-')?;
+            let number = |n: &str| {
+                if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                n.parse::<u64>().ok()
+            };
+            match (first.is_empty(), last.is_empty()) {
+                (true, _) => number(last).map(ByteRange::Suffix),
+                (false, true) => Some(ByteRange::FromTo(number(first)?, None)),
+                (false, false) => {
+                    let (first, last) = (number(first)?, number(last)?);
+                    (first <= last).then_some(ByteRange::FromTo(first, Some(last)))
+                }
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!ranges.is_empty()).then_some(ranges)
+}
@@ -0,0 +1,89 @@
+// Counters and histograms exported through the metrics crate with the
+// `metrics` feature. Without it, all of this compiles down to nothing.
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+#[inline]
+pub(crate) fn bytes_in(_n: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.bytes_in").increment(_n as u64);
+}
+
+#[inline]
+pub(crate) fn bytes_out(_n: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.bytes_out").increment(_n as u64);
+}
+
+#[inline]
+pub(crate) fn frame() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.frames").increment(1);
+}
+
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn part_uploaded() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.parts_uploaded").increment(1);
+}
+
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn ranged_get() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.ranged_gets").increment(1);
+}
+
+#[inline]
+pub(crate) fn cache_hit() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.cache_hits").increment(1);
+}
+
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn cache_miss() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.cache_misses").increment(1);
+}
+
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn bytes_fetched(_n: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("zstd_seekable.bytes_fetched").increment(_n as u64);
+}
+
+// How long decompressing a frame took.
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn frame_decompressed(_elapsed: std::time::Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("zstd_seekable.frame_decompress_duration").record(_elapsed.as_secs_f64());
+}
+
+// Records how long compression took, in seconds, when dropped.
+pub(crate) struct CompressTimer {
+    #[cfg(feature = "metrics")]
+    start: Instant,
+}
+
+impl CompressTimer {
+    #[inline]
+    pub(crate) fn start() -> Self {
+        CompressTimer {
+            #[cfg(feature = "metrics")]
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for CompressTimer {
+    fn drop(&mut self) {
+        ::metrics::histogram!("zstd_seekable.compress_duration")
+            .record(self.start.elapsed().as_secs_f64());
+    }
+}
@@ -1,9 +1,91 @@
+#[cfg(feature = "http-body")]
+mod body;
+mod bundle;
 mod compress;
+#[cfg(feature = "s3")]
+mod compress_to_s3;
+mod cstream;
 mod decompress;
+mod encryption;
+mod frame_boundary;
+mod frame_cache;
+mod frame_compressor;
+mod frame_plan;
+mod http_range;
+mod instrument;
+mod manifest;
+mod metadata;
+#[cfg(feature = "s3")]
+mod presign;
+#[cfg(feature = "tokio")]
+mod range_fetch;
+mod reframe;
+#[cfg(feature = "tokio")]
+mod ring;
+mod roll_over;
+mod seek_table;
+mod seekable_bytes;
+#[cfg(feature = "s3")]
 mod seekable_s3;
+#[cfg(feature = "s3")]
+mod sharded_s3;
+mod skip_header;
+mod source_retry;
+#[cfg(feature = "s3")]
+mod spill;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+#[cfg(feature = "tokio")]
+mod throttle;
+#[cfg(feature = "tokio")]
+mod to_writer;
+mod totals;
+mod trailing_index;
+mod transcode;
+mod truncate;
+#[cfg(feature = "s3")]
 mod upload_s3;
+mod worker_budget;
 
+#[cfg(feature = "http-body")]
+pub use body::*;
+pub use bundle::*;
 pub use compress::*;
+#[cfg(feature = "s3")]
+pub use compress_to_s3::*;
 pub use decompress::*;
+pub use encryption::*;
+pub use frame_boundary::*;
+pub use frame_cache::*;
+pub use frame_compressor::*;
+pub use frame_plan::*;
+pub use http_range::*;
+pub use manifest::*;
+#[cfg(feature = "s3")]
+pub use presign::*;
+#[cfg(feature = "tokio")]
+pub use range_fetch::*;
+pub use reframe::*;
+#[cfg(feature = "tokio")]
+pub use ring::*;
+pub use roll_over::*;
+pub use seek_table::*;
+pub use seekable_bytes::*;
+#[cfg(feature = "s3")]
 pub use seekable_s3::*;
+#[cfg(feature = "s3")]
+pub use sharded_s3::*;
+pub use skip_header::*;
+pub use source_retry::*;
+#[cfg(feature = "s3")]
+pub use spill::*;
+#[cfg(feature = "tokio")]
+pub use throttle::*;
+#[cfg(feature = "tokio")]
+pub use to_writer::*;
+pub use totals::*;
+pub use transcode::*;
+pub use truncate::*;
+#[cfg(feature = "s3")]
 pub use upload_s3::*;
+pub use worker_budget::*;
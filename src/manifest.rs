@@ -0,0 +1,237 @@
+use crate::{Compress, SeekTable, StreamCompress};
+use futures::{channel::oneshot, ready, stream::FusedStream, Stream};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use xxhash_rust::xxh64::Xxh64;
+
+/// Hashes the decompressed content for a [`Manifest`].
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    /// The hash of everything passed to [`update`](Self::update).
+    fn finish(&mut self) -> Vec<u8>;
+}
+
+/// XXH64 with seed 0, the hash the seek table checksums come from. The hash
+/// is big endian, as printed by `xxhsum`.
+#[derive(Clone)]
+pub struct Xxh64Hasher(Xxh64);
+
+impl Default for Xxh64Hasher {
+    fn default() -> Self {
+        Xxh64Hasher(Xxh64::new(0))
+    }
+}
+
+impl ContentHasher for Xxh64Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.0.digest().to_be_bytes().to_vec()
+    }
+}
+
+/// SHA-256, for naming objects by their hash.
+#[cfg(feature = "sha2")]
+#[derive(Clone, Default)]
+pub struct Sha256Hasher(sha2::Sha256);
+
+#[cfg(feature = "sha2")]
+impl ContentHasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.0, data);
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        sha2::Digest::finalize_reset(&mut self.0).to_vec()
+    }
+}
+
+/// Where one frame ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameMeta {
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+    pub decompressed_offset: u64,
+    pub decompressed_size: u64,
+    pub checksum: Option<u32>,
+}
+
+impl FrameMeta {
+    pub(crate) fn new(seek_table: &SeekTable, frame: usize) -> Self {
+        FrameMeta {
+            compressed_offset: seek_table.frame_compressed_offset(frame),
+            compressed_size: seek_table.frame_compressed_size(frame),
+            decompressed_offset: seek_table.frame_decompressed_offset(frame),
+            decompressed_size: seek_table.frame_decompressed_size(frame),
+            checksum: seek_table.frame_checksum(frame),
+        }
+    }
+}
+
+/// Describes a compressed object, to be stored alongside it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub frames: Vec<FrameMeta>,
+    /// Hash of the decompressed content.
+    pub content_hash: Vec<u8>,
+    /// Length of the whole object, seek table included.
+    pub compressed_len: u64,
+}
+
+impl Manifest {
+    fn new(seek_table: &SeekTable, content_hash: Vec<u8>, compressed_len: u64) -> Self {
+        let frames = (0..seek_table.num_frames())
+            .map(|frame| FrameMeta::new(seek_table, frame))
+            .collect();
+        Manifest {
+            frames,
+            content_hash,
+            compressed_len,
+        }
+    }
+}
+
+/// Resolves to the [`Manifest`] once the compressed stream yields its seek
+/// table, see [`Compress::manifest`]. Fails with [`oneshot::Canceled`] if
+/// the stream is dropped or fails before that.
+#[derive(Debug)]
+pub struct ManifestFuture {
+    receiver: oneshot::Receiver<Manifest>,
+}
+
+impl Future for ManifestFuture {
+    type Output = Result<Manifest, oneshot::Canceled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx)
+    }
+}
+
+// What Compress needs to build the manifest as it goes.
+pub(crate) struct ManifestBuilder {
+    hasher: Box<dyn ContentHasher + Send>,
+    sender: Option<oneshot::Sender<Manifest>>,
+}
+
+impl ManifestBuilder {
+    pub(crate) fn new(hasher: impl ContentHasher + Send + 'static) -> (Self, ManifestFuture) {
+        let (sender, receiver) = oneshot::channel();
+        let builder = ManifestBuilder {
+            hasher: Box::new(hasher),
+            sender: Some(sender),
+        };
+        (builder, ManifestFuture { receiver })
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    // Hands the manifest to the future, the first time only.
+    pub(crate) fn finish(&mut self, seek_table: &SeekTable, compressed_len: u64) {
+        if let Some(sender) = self.sender.take() {
+            let manifest = Manifest::new(seek_table, self.hasher.finish(), compressed_len);
+            // Nobody's waiting for it if the future's gone.
+            let _ = sender.send(manifest);
+        }
+    }
+}
+
+/// Compresses the stream like [`StreamCompress::compress`] while building
+/// its [`Manifest`], hashing the content with [`Xxh64Hasher`]. Use
+/// [`Compress::manifest`] for other hashes.
+pub fn compress_with_manifest<S, I, E>(
+    stream: S,
+    compression_level: i32,
+    frame_size: usize,
+) -> Result<(Compress<S, E>, ManifestFuture), zstd_seekable::Error>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    Ok(stream
+        .compress(compression_level, frame_size)?
+        .manifest(Xxh64Hasher::default()))
+}
+
+pin_project! {
+    /// Output of [`Compress::hash_stream`].
+    pub struct HashStream<S> {
+        #[pin]
+        stream: S,
+        hasher: Box<dyn ContentHasher + Send>,
+        sender: Option<oneshot::Sender<Vec<u8>>>,
+    }
+}
+
+impl<S> HashStream<S> {
+    pub(crate) fn new(
+        stream: S,
+        hasher: impl ContentHasher + Send + 'static,
+    ) -> (Self, HashFuture) {
+        let (sender, receiver) = oneshot::channel();
+        let stream = HashStream {
+            stream,
+            hasher: Box::new(hasher),
+            sender: Some(sender),
+        };
+        (stream, HashFuture { receiver })
+    }
+}
+
+impl<S, T, E> Stream for HashStream<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: AsRef<[u8]>,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = ready!(this.stream.poll_next(cx));
+        match &item {
+            Some(Ok(bytes)) => this.hasher.update(bytes.as_ref()),
+            // The output is incomplete, so there's no hash to give.
+            Some(Err(_)) => drop(this.sender.take()),
+            None => {
+                if let Some(sender) = this.sender.take() {
+                    // Nobody's waiting for it if the future's gone.
+                    let _ = sender.send(this.hasher.finish());
+                }
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S, T, E> FusedStream for HashStream<S>
+where
+    S: FusedStream<Item = Result<T, E>>,
+    T: AsRef<[u8]>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+/// Resolves to the hash of the whole output once the stream ends, see
+/// [`Compress::hash_stream`]. Fails with [`oneshot::Canceled`] if the
+/// stream is dropped or fails before that.
+#[derive(Debug)]
+pub struct HashFuture {
+    receiver: oneshot::Receiver<Vec<u8>>,
+}
+
+impl Future for HashFuture {
+    type Output = Result<Vec<u8>, oneshot::Canceled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx)
+    }
+}
@@ -0,0 +1,66 @@
+use std::convert::TryFrom;
+
+// Key/value metadata stored in a skippable frame at the start of an object,
+// see Compress::metadata. After the skippable frame header, the frame holds
+// the number of pairs and then every key and value, each as its length
+// followed by its bytes. All numbers are little endian u32s.
+
+// Two off from the seek table's magic, one off from the encryption trailer's.
+const METADATA_MAGIC: u32 = 0x184D_2A5C;
+
+// Encodes the pairs, as long as they fit in a skippable frame.
+pub(crate) fn metadata_frame(pairs: &[(String, String)]) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    content.extend_from_slice(&u32::try_from(pairs.len()).ok()?.to_le_bytes());
+    for (key, value) in pairs {
+        for s in [key, value].iter() {
+            content.extend_from_slice(&u32::try_from(s.len()).ok()?.to_le_bytes());
+            content.extend_from_slice(s.as_bytes());
+        }
+    }
+    let mut frame = Vec::with_capacity(content.len() + 8);
+    frame.extend_from_slice(&METADATA_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&u32::try_from(content.len()).ok()?.to_le_bytes());
+    frame.extend_from_slice(&content);
+    Some(frame)
+}
+
+// Reads a little endian u32 off the front of the input.
+fn take_u32(input: &mut &[u8]) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(input.get(..4)?);
+    *input = &input[4..];
+    Some(u32::from_le_bytes(word))
+}
+
+fn take_str(input: &mut &[u8]) -> Option<String> {
+    let len = take_u32(input)? as usize;
+    let s = std::str::from_utf8(input.get(..len)?).ok()?.to_owned();
+    *input = &input[len..];
+    Some(s)
+}
+
+// Whether the frame is a metadata frame at all, going by the magic.
+pub(crate) fn is_metadata_frame(mut frame: &[u8]) -> bool {
+    take_u32(&mut frame) == Some(METADATA_MAGIC)
+}
+
+// Decodes a metadata frame, None if it's not valid. Anything after the frame
+// is ignored.
+pub(crate) fn parse_metadata_frame(mut frame: &[u8]) -> Option<Vec<(String, String)>> {
+    if take_u32(&mut frame)? != METADATA_MAGIC {
+        return None;
+    }
+    let len = take_u32(&mut frame)? as usize;
+    let mut content = frame.get(..len)?;
+    let count = take_u32(&mut content)? as usize;
+    // Every pair takes at least 8 bytes, don't let the count make us
+    // allocate more than that.
+    let mut pairs = Vec::with_capacity(count.min(content.len() / 8));
+    for _ in 0..count {
+        let key = take_str(&mut content)?;
+        let value = take_str(&mut content)?;
+        pairs.push((key, value));
+    }
+    content.is_empty().then_some(pairs)
+}
@@ -0,0 +1,79 @@
+use crate::{FrameMeta, SeekTable};
+use rusoto_core::{credential::AwsCredentials, Region};
+use rusoto_s3::{
+    util::{PreSignedRequest, PreSignedRequestOption},
+    GetObjectRequest,
+};
+
+/// A presigned GET of one compressed frame, along with what the client needs
+/// to get the requested data out of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresignedFrame {
+    pub frame: usize,
+    /// Where the frame sits in the compressed object and in the data.
+    pub meta: FrameMeta,
+    pub url: String,
+    /// The `Range` header to send with the GET, such as `bytes=0-1023`. It's
+    /// part of the signature, so requests without it, or with any other
+    /// range, are rejected.
+    pub range: String,
+    /// Where the requested data starts in the decompressed frame.
+    pub skip: u64,
+    /// How much of the decompressed frame, from `skip`, was asked for.
+    pub len: u64,
+}
+
+/// Presigns a GET for each frame holding part of the `len` bytes of
+/// decompressed data at `offset`, so clients without credentials can fetch
+/// and decompress just those frames themselves. Each frame is a complete zstd
+/// frame, decompress it with any zstd decoder and keep `len` bytes from
+/// `skip`. The range is cut short at the end of the data, as with
+/// [`SeekTable::split_range`].
+///
+/// `request` names the object, and anything else set on it, such as the
+/// version or the SSE-C headers, is signed into every URL. Its `range` is
+/// replaced.
+///
+/// # Expiry
+///
+/// The URLs stop working `option.expires_in` after this is called, S3 answers
+/// 403 from then on. Nothing is checked here, but S3 doesn't accept more than
+/// seven days, and URLs signed with temporary credentials stop working when
+/// those expire, whichever comes first. Presign close to when the frames will
+/// be fetched, and have clients ask again for URLs on a 403 rather than
+/// holding onto them. The seek table can be kept for as long as the object
+/// doesn't change.
+pub fn presign_frames(
+    seek_table: &SeekTable,
+    request: &GetObjectRequest,
+    region: &Region,
+    credentials: &AwsCredentials,
+    option: &PreSignedRequestOption,
+    offset: u64,
+    len: u64,
+) -> Vec<PresignedFrame> {
+    seek_table
+        .split_range(offset, len)
+        .into_iter()
+        .map(|(frame, skip, len)| {
+            let meta = FrameMeta::new(seek_table, frame);
+            let range = format!(
+                "bytes={}-{}",
+                meta.compressed_offset,
+                meta.compressed_offset + meta.compressed_size - 1
+            );
+            let request = GetObjectRequest {
+                range: Some(range.clone()),
+                ..request.clone()
+            };
+            PresignedFrame {
+                frame,
+                meta,
+                url: request.get_presigned_url(region, credentials, option),
+                range,
+                skip,
+                len,
+            }
+        })
+        .collect()
+}
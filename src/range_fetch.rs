@@ -0,0 +1,288 @@
+use bytes::Bytes;
+#[cfg(feature = "s3")]
+use futures::TryFutureExt;
+#[cfg(feature = "s3")]
+use rusoto_s3::{GetObjectOutput, GetObjectRequest, HeadObjectRequest, S3};
+#[cfg(feature = "s3")]
+use std::convert::TryFrom;
+use std::{
+    future::Future,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Random access to some store of bytes, such as an object store or a file,
+/// that [`RangeReader`] turns into the [`Read`] and [`Seek`]
+/// [`SeekableDecompress`](crate::SeekableDecompress) wants.
+///
+/// To support another store, implement both methods with whatever ranged
+/// reads it offers, as `async fn`s if you like. The futures have to be
+/// [`Send`]. For example, over some HTTP client:
+///
+/// ```ignore
+/// impl RangeFetch for HttpObject {
+///     async fn size(&mut self) -> std::io::Result<u64> {
+///         self.client.head(&self.url).await?.content_length()
+///     }
+///
+///     async fn fetch_range(&mut self, offset: u64, len: usize) -> std::io::Result<Bytes> {
+///         let range = format!("bytes={}-{}", offset, offset + len as u64 - 1);
+///         self.client.get(&self.url).header("Range", range).await?.bytes().await
+///     }
+/// }
+/// ```
+pub trait RangeFetch {
+    /// Size of the whole thing.
+    fn size(&mut self) -> impl Future<Output = std::io::Result<u64>> + Send + '_;
+
+    /// Fetches `len` bytes at `offset`. Only ever called with ranges within
+    /// the [size](Self::size) and `len` of at least 1, but giving back less than
+    /// asked for is fine.
+    fn fetch_range(
+        &mut self,
+        offset: u64,
+        len: usize,
+    ) -> impl Future<Output = std::io::Result<Bytes>> + Send + '_;
+}
+
+/// [`RangeFetch`] from a local file.
+#[derive(Debug)]
+pub struct FileRangeFetch {
+    file: tokio::fs::File,
+}
+
+impl FileRangeFetch {
+    pub fn new(file: tokio::fs::File) -> Self {
+        FileRangeFetch { file }
+    }
+
+    pub fn into_inner(self) -> tokio::fs::File {
+        self.file
+    }
+}
+
+impl RangeFetch for FileRangeFetch {
+    async fn size(&mut self) -> std::io::Result<u64> {
+        Ok(self.file.metadata().await?.len())
+    }
+
+    async fn fetch_range(&mut self, offset: u64, len: usize) -> std::io::Result<Bytes> {
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(len);
+        (&mut self.file)
+            .take(len as u64)
+            .read_to_end(&mut data)
+            .await?;
+        Ok(Bytes::from(data))
+    }
+}
+
+/// [`RangeFetch`] from an S3 object, one request per fetch. This is what
+/// [`SeekableS3Object`](crate::SeekableS3Object) fetches with too, keeping
+/// the body of a request around to stream subsequent reads from and the
+/// tail of the object on top.
+#[cfg(feature = "s3")]
+#[derive(Debug)]
+pub struct S3RangeFetch<A> {
+    client: A,
+    req: GetObjectRequest,
+}
+
+#[cfg(feature = "s3")]
+impl<A> S3RangeFetch<A> {
+    /// Fetches the object `req` points at, with whatever range it has set
+    /// replaced by ours.
+    pub fn new(client: A, mut req: GetObjectRequest) -> Self {
+        req.range = None;
+        S3RangeFetch { client, req }
+    }
+}
+
+#[cfg(feature = "s3")]
+impl<A: S3> S3RangeFetch<A> {
+    // GETs the object, or just `range` of it, an HTTP Range header, without
+    // reading the body. This only needs a shared borrow, so several can be
+    // in flight at once.
+    pub(crate) fn get_object(
+        &self,
+        range: Option<String>,
+    ) -> impl Future<Output = std::io::Result<GetObjectOutput>> + Send + '_ {
+        let req = GetObjectRequest {
+            range,
+            ..self.req.to_owned()
+        };
+        self.client
+            .get_object(req)
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+    }
+}
+
+#[cfg(feature = "s3")]
+impl<A: S3> RangeFetch for S3RangeFetch<A> {
+    fn size(&mut self) -> impl Future<Output = std::io::Result<u64>> + Send + '_ {
+        let req = HeadObjectRequest {
+            bucket: self.req.bucket.to_owned(),
+            expected_bucket_owner: self.req.expected_bucket_owner.to_owned(),
+            key: self.req.key.to_owned(),
+            request_payer: self.req.request_payer.to_owned(),
+            sse_customer_algorithm: self.req.sse_customer_algorithm.to_owned(),
+            sse_customer_key: self.req.sse_customer_key.to_owned(),
+            sse_customer_key_md5: self.req.sse_customer_key_md5.to_owned(),
+            version_id: self.req.version_id.to_owned(),
+            ..Default::default()
+        };
+        let head = self.client.head_object(req);
+        async move {
+            let head = head.map_err(|e| Error::new(ErrorKind::Other, e)).await?;
+            let length = head.content_length.ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Content length not set in response.",
+                )
+            })?;
+            u64::try_from(length).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        }
+    }
+
+    fn fetch_range(
+        &mut self,
+        offset: u64,
+        len: usize,
+    ) -> impl Future<Output = std::io::Result<Bytes>> + Send + '_ {
+        let get_object = self.get_object(Some(format!(
+            "bytes={}-{}",
+            offset,
+            offset + len as u64 - 1
+        )));
+        async move {
+            let object = get_object.await?;
+            let mut data = Vec::with_capacity(len);
+            if let Some(body) = object.body {
+                body.into_async_read().read_to_end(&mut data).await?;
+            }
+            Ok(Bytes::from(data))
+        }
+    }
+}
+
+/// Default for [`RangeReader::set_fetch_size`].
+pub const DEFAULT_FETCH_SIZE: usize = 256 * 1024;
+
+/// Blocking [`Read`] and [`Seek`] over a [`RangeFetch`], running the fetches
+/// on the given runtime. As with
+/// [`SeekableS3Object`](crate::SeekableS3Object), don't use this from within
+/// the runtime itself.
+///
+/// Every fetch is at least the [fetch size](Self::set_fetch_size) and the
+/// last one is kept around to serve reads from.
+pub struct RangeReader<F> {
+    fetch: F,
+    handle: tokio::runtime::Handle,
+    position: u64,
+    length: u64,
+    fetch_size: usize,
+    // The last fetch: where it starts and the data.
+    buffer: Option<(u64, Bytes)>,
+}
+
+impl<F: std::fmt::Debug> std::fmt::Debug for RangeReader<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RangeReader")
+            .field("fetch", &self.fetch)
+            .field("handle", &self.handle)
+            .field("position", &self.position)
+            .field("length", &self.length)
+            .field("fetch_size", &self.fetch_size)
+            .field(
+                "buffer",
+                &self.buffer.as_ref().map(|(start, b)| (start, b.len())),
+            )
+            .finish()
+    }
+}
+
+impl<F: RangeFetch> RangeReader<F> {
+    pub fn new(mut fetch: F, handle: tokio::runtime::Handle) -> std::io::Result<Self> {
+        let length = handle.block_on(fetch.size())?;
+        Ok(RangeReader {
+            fetch,
+            handle,
+            position: 0,
+            length,
+            fetch_size: DEFAULT_FETCH_SIZE,
+            buffer: None,
+        })
+    }
+
+    /// Fetch at least this many bytes at a time.
+    pub fn set_fetch_size(&mut self, fetch_size: usize) {
+        self.fetch_size = fetch_size;
+    }
+
+    pub fn into_inner(self) -> F {
+        self.fetch
+    }
+}
+
+impl<F: RangeFetch> Read for RangeReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.length || buf.is_empty() {
+            return Ok(0);
+        }
+        let buffered = match &self.buffer {
+            Some((start, data))
+                if self.position >= *start && self.position < start + data.len() as u64 =>
+            {
+                &data[(self.position - start) as usize..]
+            }
+            _ => {
+                let len = buf
+                    .len()
+                    .max(self.fetch_size)
+                    .min((self.length - self.position) as usize);
+                let data = self
+                    .handle
+                    .block_on(self.fetch.fetch_range(self.position, len))?;
+                if data.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Fetched nothing before the end.",
+                    ));
+                }
+                &self.buffer.insert((self.position, data)).1[..]
+            }
+        };
+        let n = buf.len().min(buffered.len());
+        buf[..n].copy_from_slice(&buffered[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F> Seek for RangeReader<F> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base_pos, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.position = pos;
+                return Ok(pos);
+            }
+            SeekFrom::End(pos) => (self.length, pos),
+            SeekFrom::Current(pos) => (self.position, pos),
+        };
+        let new_pos = if offset >= 0 {
+            base_pos.checked_add(offset as u64)
+        } else {
+            base_pos.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match new_pos {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
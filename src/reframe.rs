@@ -0,0 +1,227 @@
+use crate::{
+    compress_blocking_into,
+    cstream::{zstd_error, MAX_FRAMES, MAX_FRAME_SIZE, ZSTD_ERROR_CORRUPTION_DETECTED},
+    SeekTable,
+};
+use std::{convert::TryFrom, fmt::Display};
+use xxhash_rust::xxh64::Xxh64;
+use zstd_seekable::{CStream, DStream};
+
+// Turns plain zstd data into seekable zstd data.
+
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+// Skippable frames have any magic number from here to +15.
+const SKIPPABLE_MAGIC_MIN: u32 = 0x184D_2A50;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReframeError {
+    // The input isn't valid zstd: the structure of the frames is broken at
+    // the given offset.
+    Malformed(usize),
+    // A frame didn't decompress.
+    Corrupt(usize),
+    ZstdSeekable(zstd_seekable::Error),
+}
+
+impl Display for ReframeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReframeError::Malformed(offset) => {
+                write!(f, "Input is not valid zstd at offset {}.", offset)
+            }
+            ReframeError::Corrupt(offset) => {
+                write!(f, "Frame at offset {} failed to decompress.", offset)
+            }
+            ReframeError::ZstdSeekable(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReframeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReframeError::Malformed(_) | ReframeError::Corrupt(_) => None,
+            ReframeError::ZstdSeekable(e) => e.source(),
+        }
+    }
+}
+
+/// Makes plain zstd data, such as what the `zstd` command line tool writes,
+/// seekable.
+///
+/// The frames of the input become the frames of the output: if there are
+/// several, each is decompressed once to work out its size and checksum and
+/// the output is the input with a seek table added. Nothing gets
+/// recompressed.
+///
+/// Data in a single frame can't be seeked in without decompressing all of
+/// it, so in that case, and if any frame is too large for the seekable
+/// format, the whole input is decompressed into memory and compressed again
+/// at `compression_level` into frames of `frame_size`, as with
+/// [`compress_blocking_into`]. That costs as much as compressing from
+/// scratch.
+pub fn reframe(
+    input: &[u8],
+    compression_level: i32,
+    frame_size: usize,
+) -> Result<Vec<u8>, ReframeError> {
+    let frames = find_frames(input)?;
+    let mut dstream = DStream::new().map_err(ReframeError::ZstdSeekable)?;
+    let mut buf = vec![0; CStream::out_size()];
+
+    let data_frames = frames.iter().filter(|frame| frame.data).count();
+    if data_frames > 1 && frames.len() <= MAX_FRAMES {
+        let mut seek_table = SeekTable::new(true);
+        let mut sizes_fit = true;
+        for frame in &frames {
+            let compressed = &input[frame.offset..frame.offset + frame.len];
+            let (decompressed_size, checksum) = if frame.data {
+                let mut hasher = Xxh64::new(0);
+                let size =
+                    decompress_frame(&mut dstream, compressed, &mut buf, |out| hasher.update(out))
+                        .map_err(|_e| ReframeError::Corrupt(frame.offset))?;
+                (size, hasher.digest() as u32)
+            } else {
+                // Skippable frames are frames with no data as far as the seek
+                // table is concerned.
+                (0, Xxh64::new(0).digest() as u32)
+            };
+            match (u32::try_from(frame.len), u32::try_from(decompressed_size)) {
+                (Ok(compressed_size), Ok(decompressed_size))
+                    if decompressed_size as usize <= MAX_FRAME_SIZE =>
+                {
+                    seek_table.push_frame(compressed_size, decompressed_size, checksum)
+                }
+                _ => {
+                    sizes_fit = false;
+                    break;
+                }
+            }
+        }
+        if sizes_fit {
+            let mut output = Vec::with_capacity(input.len() + seek_table.seek_table_len());
+            output.extend_from_slice(input);
+            output.extend_from_slice(&seek_table.to_bytes());
+            return Ok(output);
+        }
+    }
+
+    let mut data = Vec::new();
+    for frame in frames.iter().filter(|frame| frame.data) {
+        let compressed = &input[frame.offset..frame.offset + frame.len];
+        decompress_frame(&mut dstream, compressed, &mut buf, |out| {
+            data.extend_from_slice(out)
+        })
+        .map_err(|_e| ReframeError::Corrupt(frame.offset))?;
+    }
+    let mut output = Vec::new();
+    compress_blocking_into(&data, &mut output, compression_level, frame_size)
+        .map_err(ReframeError::ZstdSeekable)?;
+    Ok(output)
+}
+
+#[derive(Debug)]
+struct Frame {
+    offset: usize,
+    len: usize,
+    // Whether this is a zstd frame rather than a skippable one.
+    data: bool,
+}
+
+fn read_u32(input: &[u8], at: usize) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(input.get(at..at.checked_add(4)?)?);
+    Some(u32::from_le_bytes(word))
+}
+
+// Walks the frame and block headers to find where each frame starts and
+// ends, without decompressing anything.
+fn find_frames(input: &[u8]) -> Result<Vec<Frame>, ReframeError> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < input.len() {
+        let (len, data) = frame_len(&input[offset..]).ok_or(ReframeError::Malformed(offset))?;
+        frames.push(Frame { offset, len, data });
+        offset += len;
+    }
+    Ok(frames)
+}
+
+// Length of the frame at the start of the input and whether it's a zstd
+// frame, if there's a whole valid frame there.
+pub(crate) fn frame_len(input: &[u8]) -> Option<(usize, bool)> {
+    let magic = read_u32(input, 0)?;
+    if magic & 0xFFFF_FFF0 == SKIPPABLE_MAGIC_MIN {
+        let len = 8 + read_u32(input, 4)? as usize;
+        return (len <= input.len()).then_some((len, false));
+    }
+    if magic != ZSTD_MAGIC {
+        return None;
+    }
+
+    let descriptor = *input.get(4)?;
+    let content_size_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let reserved = descriptor & 0x08 != 0;
+    let checksum = descriptor & 0x04 != 0;
+    let dictionary_id_len = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+    if reserved {
+        return None;
+    }
+    let window_descriptor_len = if single_segment { 0 } else { 1 };
+    let content_size_len = match content_size_flag {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let mut pos = 5 + window_descriptor_len + dictionary_id_len + content_size_len;
+
+    loop {
+        let header = input.get(pos..pos + 3)?;
+        let header = u32::from(header[0]) | u32::from(header[1]) << 8 | u32::from(header[2]) << 16;
+        let last = header & 1 != 0;
+        let block_size = (header >> 3) as usize;
+        pos += 3 + match (header >> 1) & 0x03 {
+            // Raw and compressed blocks take up their size, RLE blocks take
+            // a single byte whatever their size.
+            0 | 2 => block_size,
+            1 => 1,
+            _ => return None,
+        };
+        if last {
+            break;
+        }
+    }
+    if checksum {
+        pos += 4;
+    }
+    (pos <= input.len()).then_some((pos, true))
+}
+
+// Decompresses a single whole frame, handing the output to `sink` as it goes.
+// Gives the decompressed size.
+pub(crate) fn decompress_frame(
+    dstream: &mut DStream,
+    mut input: &[u8],
+    buf: &mut [u8],
+    mut sink: impl FnMut(&[u8]),
+) -> Result<usize, zstd_seekable::Error> {
+    let mut size = 0;
+    loop {
+        let (out_pos, in_pos) = dstream.decompress(buf, input)?;
+        if out_pos == 0 && in_pos == 0 {
+            // DStream doesn't tell us about errors: if it's stuck with input
+            // left, the frame is bad.
+            if input.is_empty() {
+                return Ok(size);
+            }
+            return Err(zstd_error(ZSTD_ERROR_CORRUPTION_DETECTED));
+        }
+        sink(&buf[..out_pos]);
+        size += out_pos;
+        input = &input[in_pos..];
+    }
+}
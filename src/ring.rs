@@ -0,0 +1,204 @@
+use crate::{Compress, CompressError};
+use futures::{Stream, TryStreamExt};
+use parking_lot::Mutex;
+use std::{
+    io::{Error, ErrorKind},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Makes a fixed size ring buffer of `capacity` bytes, for passing
+/// compressed output from [`Compress::write_to_ring`] to a consumer reading
+/// it concurrently with a hard cap on how much is buffered in between. The
+/// cap is on buffering only: compressed output is still made a `Bytes` at a
+/// time and copied into the ring. Panics if `capacity` is 0.
+pub fn ring_buffer(capacity: usize) -> (RingWriter, RingReader) {
+    assert!(capacity != 0, "ring buffer capacity must be non-zero");
+    let ring = Arc::new(Mutex::new(Ring {
+        buf: vec![0; capacity].into_boxed_slice(),
+        start: 0,
+        len: 0,
+        writer: WriterState::Open,
+        reader_closed: false,
+        reader_waker: None,
+        writer_waker: None,
+    }));
+    (RingWriter { ring: ring.clone() }, RingReader { ring })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriterState {
+    Open,
+    // Shut down properly, everything got written.
+    Done,
+    // Dropped without shutting down, so the data is incomplete.
+    Dropped,
+}
+
+#[derive(Debug)]
+struct Ring {
+    buf: Box<[u8]>,
+    // Where the buffered data starts and how much of it there is, wrapping
+    // around the end of the buffer.
+    start: usize,
+    len: usize,
+    writer: WriterState,
+    reader_closed: bool,
+    reader_waker: Option<Waker>,
+    writer_waker: Option<Waker>,
+}
+
+impl Ring {
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.reader_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.writer_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Writing end of a [`ring_buffer`]. Writes wait for room once the ring is
+/// full. Dropping this without shutting it down makes the reader fail once
+/// it has read everything, so a consumer can tell a complete object from one
+/// that was cut short by an error.
+#[derive(Debug)]
+pub struct RingWriter {
+    ring: Arc<Mutex<Ring>>,
+}
+
+/// Reading end of a [`ring_buffer`].
+#[derive(Debug)]
+pub struct RingReader {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl AsyncWrite for RingWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut ring = self.ring.lock();
+        if ring.reader_closed {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::BrokenPipe,
+                "Ring buffer reader is gone.",
+            )));
+        }
+        let capacity = ring.buf.len();
+        let n = data.len().min(capacity - ring.len);
+        if n == 0 && !data.is_empty() {
+            ring.writer_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let end = (ring.start + ring.len) % capacity;
+        // The free space may wrap around too.
+        let first = n.min(capacity - end);
+        ring.buf[end..end + first].copy_from_slice(&data[..first]);
+        ring.buf[..n - first].copy_from_slice(&data[first..n]);
+        ring.len += n;
+        ring.wake_reader();
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut ring = self.ring.lock();
+        ring.writer = WriterState::Done;
+        ring.wake_reader();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        let mut ring = self.ring.lock();
+        if ring.writer == WriterState::Open {
+            ring.writer = WriterState::Dropped;
+        }
+        ring.wake_reader();
+    }
+}
+
+impl AsyncRead for RingReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let mut ring = self.ring.lock();
+        if ring.len == 0 {
+            return match ring.writer {
+                WriterState::Open => {
+                    ring.reader_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+                WriterState::Done => Poll::Ready(Ok(())),
+                WriterState::Dropped => Poll::Ready(Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Ring buffer writer stopped before the end.",
+                ))),
+            };
+        }
+        let capacity = ring.buf.len();
+        let n = out.remaining().min(ring.len);
+        let start = ring.start;
+        let first = n.min(capacity - start);
+        out.put_slice(&ring.buf[start..start + first]);
+        out.put_slice(&ring.buf[..n - first]);
+        ring.start = (start + n) % capacity;
+        ring.len -= n;
+        ring.wake_writer();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        let mut ring = self.ring.lock();
+        ring.reader_closed = true;
+        ring.wake_writer();
+    }
+}
+
+impl<S, I, E> Compress<S, E>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    /// Compresses everything into the ring, shutting it down at the end.
+    /// Every item is compressed into its own `Bytes` as usual, copied into
+    /// the ring and dropped once it's all in. Memory use of the whole
+    /// pipeline is then capped at the ring's capacity plus what compressing
+    /// a single upstream item takes, about as much as the item itself: keep
+    /// items small for a tight cap.
+    ///
+    /// Once the ring fills up, this waits for the reader to make room rather
+    /// than buffering further, and stops pulling from upstream until it does.
+    /// That means the reader must be draining the ring at the same time, in
+    /// another task or joined with this future. Awaiting this to completion
+    /// first and reading afterwards deadlocks as soon as the output outgrows
+    /// the ring, and so does a consumer that stalls waiting on something
+    /// that in turn waits for compression to go further.
+    ///
+    /// If compression fails or the reader goes away, this stops with the
+    /// error and the reader fails instead of seeing a clean end.
+    pub async fn write_to_ring(self, mut ring: RingWriter) -> Result<(), CompressError<E>> {
+        let mut compress = std::pin::pin!(self);
+        let broken = |e: Error| CompressError::ZstdError(zstd_seekable::Error::Io(e));
+        while let Some(bytes) = compress.try_next().await? {
+            ring.write_all(&bytes).await.map_err(broken)?;
+        }
+        ring.shutdown().await.map_err(broken)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{
+    cstream::{config_error, FrameCStream},
+    instrument, CompressError,
+};
+use bytes::Bytes;
+use futures::{
+    ready,
+    stream::{FusedStream, Stream},
+};
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use zstd_seekable::CStream;
+
+/// Compresses `stream` like [`StreamCompress::compress`](crate::StreamCompress::compress)
+/// but into a series of seekable objects rather than a single one, for
+/// sharding a large stream over several S3 objects. As soon as a frame ends
+/// with the object at `roll_over_bytes` or more, seek table included, the
+/// object gets its seek table and the next frame starts a new one. Objects
+/// only ever end at frame boundaries, so each can go over `roll_over_bytes`
+/// by up to a frame's worth of output.
+///
+/// Every chunk of output comes with the index of the object it belongs to,
+/// counting from 0. Chunks of an object come in order and an object is
+/// complete once a chunk of the next one comes, or the stream ends. Every
+/// object is a seekable object on its own, to read with
+/// [`SeekableDecompress`](crate::SeekableDecompress) and friends: to find
+/// decompressed offset `n` of the whole stream, go by the decompressed
+/// lengths of the objects in order, or have `ShardedS3Object` do it once
+/// they're on S3. Written back to back, they also make a bundle, see
+/// [`bundle_entries`](crate::bundle_entries).
+///
+/// There's always at least one object, which is empty if the input is. None
+/// of the other [`Compress`](crate::Compress) settings are available here.
+///
+/// Fails if `roll_over_bytes` is 0 or the compression settings are out of
+/// range.
+pub fn compress_with_roll_over<S, I, E>(
+    stream: S,
+    compression_level: i32,
+    frame_size: usize,
+    roll_over_bytes: usize,
+) -> Result<RollOver<S>, zstd_seekable::Error>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    if roll_over_bytes == 0 {
+        return Err(config_error(
+            "roll_over_bytes",
+            roll_over_bytes,
+            "at least 1",
+        ));
+    }
+    Ok(RollOver {
+        stream,
+        cstream: FrameCStream::new(compression_level, frame_size)?,
+        compression_level,
+        frame_size,
+        roll_over_bytes,
+        object: 0,
+        rolled: false,
+        done: false,
+        buf_out: vec![0; CStream::out_size()].into_boxed_slice(),
+        ready: VecDeque::new(),
+    })
+}
+
+pin_project! {
+    /// What [`compress_with_roll_over`] returns.
+    pub struct RollOver<S> {
+        #[pin]
+        stream: S,
+        cstream: FrameCStream,
+        compression_level: i32,
+        frame_size: usize,
+        roll_over_bytes: usize,
+        // Index of the object being written.
+        object: usize,
+        // The object's seek table went out, so more input starts a new one.
+        rolled: bool,
+        // Upstream ended or failed.
+        done: bool,
+        buf_out: Box<[u8]>,
+        // Output waiting to be yielded, with the object it belongs to.
+        ready: VecDeque<(usize, Bytes)>,
+    }
+}
+
+impl<S> RollOver<S> {
+    /// How many objects were started so far.
+    pub fn objects(&self) -> usize {
+        self.object + 1
+    }
+
+    // Compresses some input, queueing up the output and rolling over to a
+    // new object whenever the current one is big enough.
+    fn compress_input(self: Pin<&mut Self>, mut input: &[u8]) -> Result<(), zstd_seekable::Error> {
+        let this = self.project();
+        let cstream: &mut FrameCStream = this.cstream;
+        let buf_out: &mut [u8] = this.buf_out;
+        instrument::bytes_in(input.len());
+        let mut out = Vec::new();
+        while !input.is_empty() {
+            if *this.rolled {
+                *cstream = FrameCStream::new(*this.compression_level, *this.frame_size)?;
+                *this.rolled = false;
+                *this.object += 1;
+            }
+            let frames = cstream.num_frames();
+            let (out_pos, in_pos) = cstream.compress(buf_out, input)?;
+            out.extend_from_slice(&buf_out[..out_pos]);
+            input = &input[in_pos..];
+            let seek_table = cstream.seek_table();
+            let len = seek_table.compressed_len() + seek_table.seek_table_len() as u64;
+            if cstream.num_frames() > frames && len >= *this.roll_over_bytes as u64 {
+                loop {
+                    let out_pos = cstream.write_seek_table(buf_out);
+                    if out_pos == 0 {
+                        break;
+                    }
+                    out.extend_from_slice(&buf_out[..out_pos]);
+                }
+                this.ready
+                    .push_back((*this.object, Bytes::from(std::mem::take(&mut out))));
+                *this.rolled = true;
+            }
+        }
+        if !out.is_empty() {
+            this.ready.push_back((*this.object, Bytes::from(out)));
+        }
+        Ok(())
+    }
+
+    // Finishes the last object, unless it's already done.
+    fn end_stream(self: Pin<&mut Self>) -> Result<(), zstd_seekable::Error> {
+        let this = self.project();
+        if *this.rolled {
+            return Ok(());
+        }
+        let mut out = Vec::new();
+        loop {
+            let out_pos = this.cstream.end_stream(this.buf_out)?;
+            if out_pos == 0 {
+                break;
+            }
+            out.extend_from_slice(&this.buf_out[..out_pos]);
+        }
+        this.ready.push_back((*this.object, Bytes::from(out)));
+        Ok(())
+    }
+}
+
+impl<S, I, E> Stream for RollOver<S>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    type Item = Result<(usize, Bytes), CompressError<E>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some((object, bytes)) = self.as_mut().project().ready.pop_front() {
+                instrument::bytes_out(bytes.len());
+                return Poll::Ready(Some(Ok((object, bytes))));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let result = match ready!(self.as_mut().project().stream.poll_next(cx)) {
+                Some(Ok(item)) => self.as_mut().compress_input(item.borrow()),
+                Some(Err(e)) => {
+                    *self.as_mut().project().done = true;
+                    return Poll::Ready(Some(Err(CompressError::Underlying(e))));
+                }
+                None => {
+                    *self.as_mut().project().done = true;
+                    self.as_mut().end_stream()
+                }
+            };
+            if let Err(e) = result {
+                let this = self.as_mut().project();
+                *this.done = true;
+                this.ready.clear();
+                return Poll::Ready(Some(Err(CompressError::ZstdError(e))));
+            }
+        }
+    }
+}
+
+impl<S, I, E> FusedStream for RollOver<S>
+where
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    fn is_terminated(&self) -> bool {
+        self.done && self.ready.is_empty()
+    }
+}
@@ -0,0 +1,683 @@
+use crate::cstream::MAX_FRAMES;
+use bytes::Bytes;
+use std::{convert::TryFrom, fmt::Display, ops::Range};
+use zstd_seekable::Seekable;
+
+// Magic number of the skippable frame holding the seek table.
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+// Magic number at the very end of the seek table.
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+// Skippable frame header: magic and frame size.
+const SKIPPABLE_HEADER_LEN: usize = 8;
+/// Length of the fixed footer at the very end of a seekable object: frame
+/// count, descriptor byte and magic number.
+pub const SEEK_TABLE_FOOTER_LEN: usize = 9;
+/// Most frames [`SeekTable::parse`] and [`SeekTable::len_from_footer`] take
+/// a footer's word for. A table this long takes up 200MiB, enough for
+/// 16TiB of data in frames of 1MiB.
+pub const DEFAULT_MAX_FRAMES: usize = 16 * 1024 * 1024;
+
+/// The frame layout of a seekable object.
+///
+/// Rather than storing each frame's sizes, we store cumulative offsets: entry
+/// `i` is where frame `i` starts and the final entry is where the last frame
+/// ends. This costs the same per frame, gives offsets in O(1) and lets us find
+/// the frame holding some decompressed offset with a binary search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekTable {
+    compressed_offsets: Vec<u64>,
+    decompressed_offsets: Vec<u64>,
+    checksums: Option<Vec<u32>>,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SeekTableError {
+    // Not enough data to even hold the footer or the table it describes.
+    TooShort {
+        needed: usize,
+        got: usize,
+    },
+    // Seekable magic number at the end of the footer is wrong.
+    BadMagic(u32),
+    // The skippable frame header in front of the entries is wrong.
+    BadSkippableFrame,
+    // Reserved bits in the descriptor were set.
+    ReservedBitsSet(u8),
+    // Offsets didn't fit in u64, or there are more frames than the format
+    // allows.
+    DataTooLarge,
+    // Frames appended somewhere other than where the table ends.
+    AppendGap {
+        compressed_len: u64,
+        decompressed_len: u64,
+    },
+    // Only one of the tables being appended has checksums.
+    ChecksumsDiffer,
+    // The footer claims more frames than we were willing to take.
+    TooManyFrames {
+        frames: usize,
+        max: usize,
+    },
+}
+
+impl Display for SeekTableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeekTableError::TooShort { needed, got } => write!(
+                f,
+                "Seek table needs {} bytes but only {} were available.",
+                needed, got
+            ),
+            SeekTableError::BadMagic(m) => {
+                write!(f, "Seek table footer has wrong magic number {:#x}.", m)
+            }
+            SeekTableError::BadSkippableFrame => {
+                write!(f, "Seek table is not held in a valid skippable frame.")
+            }
+            SeekTableError::ReservedBitsSet(d) => {
+                write!(f, "Seek table descriptor has reserved bits set: {:#x}.", d)
+            }
+            SeekTableError::DataTooLarge => write!(f, "Data larger than we can work with."),
+            SeekTableError::AppendGap {
+                compressed_len,
+                decompressed_len,
+            } => write!(
+                f,
+                "Frames can only be appended where the seek table ends, at compressed offset {} and decompressed offset {}.",
+                compressed_len, decompressed_len
+            ),
+            SeekTableError::TooManyFrames { frames, max } => write!(
+                f,
+                "Seek table footer claims {} frames, more than the limit of {}.",
+                frames, max
+            ),
+            SeekTableError::ChecksumsDiffer => write!(
+                f,
+                "Seek tables can only be appended if both or neither have checksums."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SeekTableError {}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(word)
+}
+
+impl SeekTable {
+    // An empty table, for building up as frames get written.
+    pub(crate) fn new(checksums: bool) -> Self {
+        SeekTable {
+            compressed_offsets: vec![0],
+            decompressed_offsets: vec![0],
+            checksums: if checksums { Some(Vec::new()) } else { None },
+        }
+    }
+
+    // Records a frame written after all the existing ones. The checksum is
+    // ignored if the table doesn't store them.
+    pub(crate) fn push_frame(
+        &mut self,
+        compressed_size: u32,
+        decompressed_size: u32,
+        checksum: u32,
+    ) {
+        let compressed_end = self.compressed_len() + u64::from(compressed_size);
+        let decompressed_end = self.decompressed_len() + u64::from(decompressed_size);
+        self.compressed_offsets.push(compressed_end);
+        self.decompressed_offsets.push(decompressed_end);
+        if let Some(checksums) = &mut self.checksums {
+            checksums.push(checksum);
+        }
+    }
+
+    // Makes a frame we already recorded take up more compressed space, for
+    // when something gets added to it after compression. Only cheap for
+    // frames near the end.
+    pub(crate) fn grow_frame(&mut self, frame: usize, extra: u64) {
+        for offset in &mut self.compressed_offsets[frame + 1..] {
+            *offset += extra;
+        }
+    }
+
+    /// Encodes the table the way it's stored at the end of a seekable object.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let table_len = self.seek_table_len();
+        let mut bytes = Vec::with_capacity(table_len);
+        bytes.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        bytes.extend_from_slice(&((table_len - SKIPPABLE_HEADER_LEN) as u32).to_le_bytes());
+        for frame in 0..self.num_frames() {
+            bytes.extend_from_slice(&(self.frame_compressed_size(frame) as u32).to_le_bytes());
+            bytes.extend_from_slice(&(self.frame_decompressed_size(frame) as u32).to_le_bytes());
+            if let Some(checksums) = &self.checksums {
+                bytes.extend_from_slice(&checksums[frame].to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&(self.num_frames() as u32).to_le_bytes());
+        bytes.push(if self.checksums.is_some() { 0x80 } else { 0 });
+        bytes.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        bytes
+    }
+
+    /// How long the whole seek table is, going by the footer alone. `tail`
+    /// must end where the object ends and hold at least the
+    /// [`SEEK_TABLE_FOOTER_LEN`] bytes of the footer. Use this to find out
+    /// how much of the end of an object to fetch for [`SeekTable::parse`].
+    ///
+    /// Footers claiming more than [`DEFAULT_MAX_FRAMES`] frames are
+    /// rejected, so that an untrusted object can't have you fetch and
+    /// allocate gigabytes for its table. See
+    /// [`len_from_footer_with_max_frames`](Self::len_from_footer_with_max_frames)
+    /// for a different limit.
+    pub fn len_from_footer(tail: &[u8]) -> Result<usize, SeekTableError> {
+        Self::len_from_footer_with_max_frames(tail, DEFAULT_MAX_FRAMES)
+    }
+
+    /// Like [`len_from_footer`](Self::len_from_footer) but taking up to
+    /// `max_frames` frames.
+    pub fn len_from_footer_with_max_frames(
+        tail: &[u8],
+        max_frames: usize,
+    ) -> Result<usize, SeekTableError> {
+        Self::parse_footer(tail, max_frames).map(|(_, _, table_len)| table_len)
+    }
+
+    // Gives the number of frames, whether there are checksums and the length
+    // of the whole table.
+    pub(crate) fn parse_footer(
+        tail: &[u8],
+        max_frames: usize,
+    ) -> Result<(usize, bool, usize), SeekTableError> {
+        if tail.len() < SEEK_TABLE_FOOTER_LEN {
+            return Err(SeekTableError::TooShort {
+                needed: SEEK_TABLE_FOOTER_LEN,
+                got: tail.len(),
+            });
+        }
+        let footer = &tail[tail.len() - SEEK_TABLE_FOOTER_LEN..];
+        let num_frames = read_u32(footer, 0) as usize;
+        let descriptor = footer[4];
+        let magic = read_u32(footer, 5);
+        if magic != SEEKABLE_MAGIC {
+            return Err(SeekTableError::BadMagic(magic));
+        }
+        if descriptor & 0x7c != 0 {
+            return Err(SeekTableError::ReservedBitsSet(descriptor));
+        }
+        if num_frames > max_frames {
+            return Err(SeekTableError::TooManyFrames {
+                frames: num_frames,
+                max: max_frames,
+            });
+        }
+        let has_checksums = descriptor & 0x80 != 0;
+        let entry_len = if has_checksums { 12 } else { 8 };
+
+        let table_len = num_frames
+            .checked_mul(entry_len)
+            .and_then(|entries| entries.checked_add(SKIPPABLE_HEADER_LEN + SEEK_TABLE_FOOTER_LEN))
+            .ok_or(SeekTableError::DataTooLarge)?;
+        Ok((num_frames, has_checksums, table_len))
+    }
+
+    /// Parses the seek table out of the tail of a seekable object. `tail`
+    /// must end where the object ends and hold at least the whole seek table;
+    /// anything in front of the table is ignored. Tables of more than
+    /// [`DEFAULT_MAX_FRAMES`] frames are rejected before anything is
+    /// allocated for them.
+    pub fn parse(tail: &[u8]) -> Result<Self, SeekTableError> {
+        Self::parse_with_max_frames(tail, DEFAULT_MAX_FRAMES)
+    }
+
+    /// Like [`parse`](Self::parse) but taking up to `max_frames` frames.
+    pub fn parse_with_max_frames(tail: &[u8], max_frames: usize) -> Result<Self, SeekTableError> {
+        let (num_frames, has_checksums, table_len) = Self::parse_footer(tail, max_frames)?;
+        let entry_len = if has_checksums { 12 } else { 8 };
+        if tail.len() < table_len {
+            return Err(SeekTableError::TooShort {
+                needed: table_len,
+                got: tail.len(),
+            });
+        }
+        let table = &tail[tail.len() - table_len..];
+        if read_u32(table, 0) != SKIPPABLE_MAGIC
+            || read_u32(table, 4) as usize != table_len - SKIPPABLE_HEADER_LEN
+        {
+            return Err(SeekTableError::BadSkippableFrame);
+        }
+
+        let mut compressed_offsets = Vec::with_capacity(num_frames + 1);
+        let mut decompressed_offsets = Vec::with_capacity(num_frames + 1);
+        let mut checksums = if has_checksums {
+            Some(Vec::with_capacity(num_frames))
+        } else {
+            None
+        };
+        let (mut compressed_offset, mut decompressed_offset) = (0u64, 0u64);
+        compressed_offsets.push(compressed_offset);
+        decompressed_offsets.push(decompressed_offset);
+        for entry in
+            table[SKIPPABLE_HEADER_LEN..table_len - SEEK_TABLE_FOOTER_LEN].chunks(entry_len)
+        {
+            compressed_offset = compressed_offset
+                .checked_add(u64::from(read_u32(entry, 0)))
+                .ok_or(SeekTableError::DataTooLarge)?;
+            decompressed_offset = decompressed_offset
+                .checked_add(u64::from(read_u32(entry, 4)))
+                .ok_or(SeekTableError::DataTooLarge)?;
+            compressed_offsets.push(compressed_offset);
+            decompressed_offsets.push(decompressed_offset);
+            if let Some(checksums) = &mut checksums {
+                checksums.push(read_u32(entry, 8));
+            }
+        }
+
+        Ok(SeekTable {
+            compressed_offsets,
+            decompressed_offsets,
+            checksums,
+        })
+    }
+
+    // Reads the table back out of an initialised decompressor. We don't get
+    // the checksums this way.
+    pub(crate) fn from_seekable<A>(seekable: &Seekable<'_, A>) -> Result<Self, SeekTableError> {
+        let num_frames = seekable.get_num_frames();
+        let mut compressed_offsets = Vec::with_capacity(num_frames + 1);
+        let mut decompressed_offsets = Vec::with_capacity(num_frames + 1);
+        compressed_offsets.push(0);
+        decompressed_offsets.push(0);
+        for frame in 0..num_frames {
+            let compressed_end = u64::try_from(seekable.get_frame_compressed_size(frame))
+                .ok()
+                .and_then(|size| {
+                    seekable
+                        .get_frame_compressed_offset(frame)
+                        .checked_add(size)
+                })
+                .ok_or(SeekTableError::DataTooLarge)?;
+            let decompressed_end = u64::try_from(seekable.get_frame_decompressed_size(frame))
+                .ok()
+                .and_then(|size| {
+                    seekable
+                        .get_frame_decompressed_offset(frame)
+                        .checked_add(size)
+                })
+                .ok_or(SeekTableError::DataTooLarge)?;
+            compressed_offsets.push(compressed_end);
+            decompressed_offsets.push(decompressed_end);
+        }
+        Ok(SeekTable {
+            compressed_offsets,
+            decompressed_offsets,
+            checksums: None,
+        })
+    }
+
+    /// Adds the frames of `other` after ours, moved along to start at
+    /// `compressed_base` and `decompressed_base`, for putting together the
+    /// table of objects compressed in pieces, say the two halves of some
+    /// data compressed in parallel. With the pieces' frames written one after
+    /// the other, the table comes out just as if the frames were compressed
+    /// in one go, down to the bytes of [`to_bytes`](Self::to_bytes), as long
+    /// as the frames end in the same places. Watch out for pieces that are a
+    /// whole number of frames long: they end in an extra, empty, frame.
+    ///
+    /// The table only stores the size of every frame, not where it is, so
+    /// there's no room for anything between the pieces: the bases have to be
+    /// where this table ends, [`compressed_len`](Self::compressed_len) and
+    /// [`decompressed_len`](Self::decompressed_len). They're there to catch
+    /// pieces put together in the wrong order. Fails, leaving the table as
+    /// it was, if they're anywhere else, if only one of the tables has
+    /// checksums or if there'd be more frames than the format allows.
+    pub fn append(
+        &mut self,
+        other: &SeekTable,
+        compressed_base: u64,
+        decompressed_base: u64,
+    ) -> Result<(), SeekTableError> {
+        if compressed_base != self.compressed_len() || decompressed_base != self.decompressed_len()
+        {
+            return Err(SeekTableError::AppendGap {
+                compressed_len: self.compressed_len(),
+                decompressed_len: self.decompressed_len(),
+            });
+        }
+        if self.has_checksums() != other.has_checksums() {
+            return Err(SeekTableError::ChecksumsDiffer);
+        }
+        if self.num_frames() + other.num_frames() > MAX_FRAMES
+            || compressed_base
+                .checked_add(other.compressed_len())
+                .is_none()
+            || decompressed_base
+                .checked_add(other.decompressed_len())
+                .is_none()
+        {
+            return Err(SeekTableError::DataTooLarge);
+        }
+        self.compressed_offsets.extend(
+            other.compressed_offsets[1..]
+                .iter()
+                .map(|offset| compressed_base + offset),
+        );
+        self.decompressed_offsets.extend(
+            other.decompressed_offsets[1..]
+                .iter()
+                .map(|offset| decompressed_base + offset),
+        );
+        if let (Some(checksums), Some(other)) = (&mut self.checksums, &other.checksums) {
+            checksums.extend_from_slice(other);
+        }
+        Ok(())
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.compressed_offsets.len() - 1
+    }
+
+    /// Total size of the compressed frames, not including the seek table.
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_offsets[self.num_frames()]
+    }
+
+    /// Total size of the data once decompressed.
+    pub fn decompressed_len(&self) -> u64 {
+        self.decompressed_offsets[self.num_frames()]
+    }
+
+    /// Size of the seek table itself, as written at the end of the object.
+    pub fn seek_table_len(&self) -> usize {
+        let entry_len = if self.checksums.is_some() { 12 } else { 8 };
+        SKIPPABLE_HEADER_LEN + self.num_frames() * entry_len + SEEK_TABLE_FOOTER_LEN
+    }
+
+    pub fn has_checksums(&self) -> bool {
+        self.checksums.is_some()
+    }
+
+    // All the per-frame accessors panic on out of range frame indices, just
+    // like slice indexing does.
+
+    pub fn frame_compressed_offset(&self, frame: usize) -> u64 {
+        self.compressed_offsets[frame]
+    }
+
+    pub fn frame_compressed_size(&self, frame: usize) -> u64 {
+        self.compressed_offsets[frame + 1] - self.compressed_offsets[frame]
+    }
+
+    pub fn frame_decompressed_offset(&self, frame: usize) -> u64 {
+        self.decompressed_offsets[frame]
+    }
+
+    pub fn frame_decompressed_size(&self, frame: usize) -> u64 {
+        self.decompressed_offsets[frame + 1] - self.decompressed_offsets[frame]
+    }
+
+    /// The decompressed offsets frame `frame` covers, as recorded for it,
+    /// whatever size the frames around it are.
+    pub fn frame_range(&self, frame: usize) -> Range<u64> {
+        self.decompressed_offsets[frame]..self.decompressed_offsets[frame + 1]
+    }
+
+    /// XXH64 checksum of the decompressed frame, truncated to 32 bits, if the
+    /// table has checksums.
+    pub fn frame_checksum(&self, frame: usize) -> Option<u32> {
+        self.checksums.as_ref().map(|checksums| checksums[frame])
+    }
+
+    /// Which frames ended up in which part when the object was written with
+    /// [`Compress::align_to_parts`](crate::Compress::align_to_parts) and
+    /// uploaded with the same minimum part size.
+    ///
+    /// Each range holds the frames of one part, in part order. Every part
+    /// can be fetched and decompressed independently. The last part also
+    /// holds the seek table.
+    pub fn aligned_parts(&self, minimum_part_size: usize) -> Vec<Range<usize>> {
+        let mut parts = Vec::new();
+        let mut part_start = 0;
+        // The last frame is only ended along with the stream so it always
+        // ends up in the last part.
+        for frame in 0..self.num_frames().saturating_sub(1) {
+            let part_len = self.compressed_offsets[frame + 1] - self.compressed_offsets[part_start];
+            if part_len >= minimum_part_size as u64 {
+                parts.push(part_start..frame + 1);
+                part_start = frame + 1;
+            }
+        }
+        if part_start < self.num_frames() {
+            parts.push(part_start..self.num_frames());
+        }
+        parts
+    }
+
+    /// Finds the frame holding the given decompressed offset, if any, in
+    /// O(log n).
+    pub fn frame_for_offset(&self, offset: u64) -> Option<usize> {
+        if offset >= self.decompressed_len() {
+            return None;
+        }
+        // The first frame ending past the offset holds it. Empty frames end
+        // where they start so they never match.
+        Some(self.decompressed_offsets[1..].partition_point(|&frame_end| frame_end <= offset))
+    }
+
+    /// Splits the decompressed range of `len` bytes at `offset` up by frame,
+    /// giving `(frame, offset in the frame, length)` for each frame it
+    /// touches, in order. The range is cut short at the end of the data, and
+    /// ranges entirely past it give nothing.
+    pub fn split_range(&self, offset: u64, len: u64) -> Vec<(usize, u64, u64)> {
+        let mut split = Vec::new();
+        let first_frame = match self.frame_for_offset(offset) {
+            Some(frame) => frame,
+            None => return split,
+        };
+        let end = offset.saturating_add(len).min(self.decompressed_len());
+        let mut position = offset;
+        for frame in first_frame..self.num_frames() {
+            if position >= end {
+                break;
+            }
+            let frame_end = self.decompressed_offsets[frame + 1];
+            // Empty frames don't get a say.
+            if frame_end == position {
+                continue;
+            }
+            let split_end = frame_end.min(end);
+            split.push((
+                frame,
+                position - self.decompressed_offsets[frame],
+                split_end - position,
+            ));
+            position = split_end;
+        }
+        split
+    }
+
+    // Puts the decompressed range of `len` bytes at `offset` together from
+    // the frames `frame` gives, as split up by split_range. A range within a
+    // single frame is sliced out of it without a copy.
+    pub(crate) fn assemble_range<E>(
+        &self,
+        offset: u64,
+        len: u64,
+        mut frame: impl FnMut(usize) -> Result<Bytes, E>,
+    ) -> Result<Bytes, E> {
+        match &self.split_range(offset, len)[..] {
+            [(index, from, len)] => {
+                frame(*index).map(|data| data.slice(*from as usize..(from + len) as usize))
+            }
+            split => split
+                .iter()
+                .try_fold(Vec::new(), |mut data, &(index, from, len)| {
+                    data.extend_from_slice(&frame(index)?[from as usize..(from + len) as usize]);
+                    Ok(data)
+                })
+                .map(Bytes::from),
+        }
+    }
+
+    /// The multipart upload parts, numbered from 1 as S3 does, holding the
+    /// frames needed to read the decompressed range of `len` bytes at
+    /// `offset`, for clients that fetch and cache whole parts. This assumes
+    /// every part but the last is `part_size` bytes long, as with
+    /// [`upload_parts`](crate::StreamUploadParts::upload_parts) fed from a
+    /// stream of fixed size chunks, or S3's own uploads. For parts that grew
+    /// up to frame boundaries with
+    /// [`Compress::align_to_parts`](crate::Compress::align_to_parts), use
+    /// [`aligned_parts`](Self::aligned_parts) instead.
+    ///
+    /// The range is cut short at the end of the data as with
+    /// [`split_range`](Self::split_range), and gives no parts if it's
+    /// entirely past it. Panics if `part_size` is 0.
+    pub fn parts_for_range(&self, offset: u64, len: u64, part_size: usize) -> Vec<u32> {
+        assert!(part_size != 0, "part size must be non-zero");
+        let split = self.split_range(offset, len);
+        let (first, last) = match (split.first(), split.last()) {
+            (Some(&(first, _, _)), Some(&(last, _, _))) => (first, last),
+            _ => return Vec::new(),
+        };
+        let part_size = part_size as u64;
+        let first_part = self.compressed_offsets[first] / part_size;
+        // Frames without any compressed bytes only turn up in corrupt tables,
+        // but those are no reason to panic.
+        let last_part =
+            (self.compressed_offsets[last + 1].saturating_sub(1) / part_size).max(first_part);
+        (first_part..=last_part)
+            .map(|part| part as u32 + 1)
+            .collect()
+    }
+
+    /// The compressed byte range, from the start of the first frame to the
+    /// end of the last, covering every frame holding one of the decompressed
+    /// `offsets`, for fetching them all in one GET along with whatever lies
+    /// in between. Offsets can come in any order and those past the end of
+    /// the data are left out, None if that's all of them. Compare with
+    /// [`exact_ranges`](Self::exact_ranges) to see how much of it would be
+    /// fetched for nothing.
+    pub fn coalesced_range(&self, offsets: &[u64]) -> Option<Range<u64>> {
+        let frames = offsets
+            .iter()
+            .filter_map(|&offset| self.frame_for_offset(offset));
+        let (first, last) = frames.fold(None, |bounds, frame| match bounds {
+            None => Some((frame, frame)),
+            Some((first, last)) => Some((frame.min(first), frame.max(last))),
+        })?;
+        Some(self.compressed_offsets[first]..self.compressed_offsets[last + 1])
+    }
+
+    /// The compressed byte ranges of just the frames holding the decompressed
+    /// `offsets`, in order, with neighbouring frames joined up into one
+    /// range, for fetching exactly what's needed in as few GETs as that
+    /// takes. Offsets are taken as with
+    /// [`coalesced_range`](Self::coalesced_range).
+    pub fn exact_ranges(&self, offsets: &[u64]) -> Vec<Range<u64>> {
+        let mut frames: Vec<usize> = offsets
+            .iter()
+            .filter_map(|&offset| self.frame_for_offset(offset))
+            .collect();
+        frames.sort_unstable();
+        frames.dedup();
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for frame in frames {
+            let (start, end) = (
+                self.compressed_offsets[frame],
+                self.compressed_offsets[frame + 1],
+            );
+            match ranges.last_mut() {
+                Some(range) if range.end == start => range.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// The compressed bytes of every frame in `object`, the whole seekable
+    /// object this is the table of, along with the frame index. Handy for
+    /// moving or hashing frames without decompressing them. Panics if
+    /// `object` is shorter than the frames, like slice indexing.
+    pub fn compressed_frames<'b>(
+        &'b self,
+        object: &'b [u8],
+    ) -> impl Iterator<Item = (usize, &'b [u8])> + 'b {
+        self.compressed_offsets
+            .windows(2)
+            .enumerate()
+            .map(move |(frame, ends)| (frame, &object[ends[0] as usize..ends[1] as usize]))
+    }
+
+    /// Like [`frame_for_offset`](Self::frame_for_offset) but for offsets in
+    /// the compressed data.
+    pub fn frame_for_compressed_offset(&self, offset: u64) -> Option<usize> {
+        if offset >= self.compressed_len() {
+            return None;
+        }
+        Some(self.compressed_offsets[1..].partition_point(|&frame_end| frame_end <= offset))
+    }
+
+    /// Where the frame holding compressed byte `compressed_offset` starts in
+    /// the decompressed data, for telling what's at a given position in
+    /// storage. None for offsets in the seek table or past it.
+    pub fn decompressed_offset_for_compressed(&self, compressed_offset: u64) -> Option<u64> {
+        self.frame_for_compressed_offset(compressed_offset)
+            .map(|frame| self.frame_decompressed_offset(frame))
+    }
+
+    /// Lays out the table for people to read, as with
+    /// [`describe_to`](Self::describe_to).
+    pub fn describe(&self) -> String {
+        let mut description = Vec::new();
+        self.describe_to(&mut description)
+            .expect("Writing to a Vec can't fail");
+        String::from_utf8(description).expect("The description is ASCII")
+    }
+
+    /// Writes out a line for every frame, with its index, compressed and
+    /// decompressed offset and size and checksum if there is one, followed
+    /// by the totals and the size of the seek table itself. Lines are written
+    /// one at a time, so put a [`BufWriter`](std::io::BufWriter) in front of
+    /// anything slow.
+    pub fn describe_to(&self, mut w: impl std::io::Write) -> std::io::Result<()> {
+        write!(
+            w,
+            "{:>8} {:>20} {:>16} {:>20} {:>18}",
+            "frame",
+            "compressed offset",
+            "compressed size",
+            "decompressed offset",
+            "decompressed size"
+        )?;
+        if self.has_checksums() {
+            write!(w, " {:>10}", "checksum")?;
+        }
+        writeln!(w)?;
+        for frame in 0..self.num_frames() {
+            write!(
+                w,
+                "{:>8} {:>20} {:>16} {:>20} {:>18}",
+                frame,
+                self.frame_compressed_offset(frame),
+                self.frame_compressed_size(frame),
+                self.frame_decompressed_offset(frame),
+                self.frame_decompressed_size(frame)
+            )?;
+            if let Some(checksum) = self.frame_checksum(frame) {
+                write!(w, " {:#010x}", checksum)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(
+            w,
+            "{} frames, {} bytes compressed, {} bytes decompressed, {} byte seek table",
+            self.num_frames(),
+            self.compressed_len(),
+            self.decompressed_len(),
+            self.seek_table_len()
+        )
+    }
+}
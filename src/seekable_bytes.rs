@@ -0,0 +1,113 @@
+use crate::{Error, SeekableDecompress};
+use bytes::Bytes;
+use std::{
+    convert::TryFrom,
+    io::Cursor,
+    ops::{Deref, Index},
+    slice::SliceIndex,
+    sync::OnceLock,
+};
+
+/// A seekable object held in memory, compressed, that reads like the slice
+/// of its data: on first access the whole thing is decompressed once and
+/// kept from then on. Only for objects small enough to hold decompressed,
+/// when indexing and slicing are handier than
+/// [`SeekableDecompress::read_range`].
+///
+/// Access through [`Deref`] and [`Index`] panics if the object doesn't
+/// decompress, use [`try_get`](Self::try_get) for the error instead.
+///
+/// The seek table says how much to allocate for the data, so objects it
+/// says decompress to more than [`max_len`](Self::max_len), 1GiB by
+/// default, are refused before anything is allocated.
+pub struct SeekableBytes {
+    compressed: Bytes,
+    data: OnceLock<Bytes>,
+    max_len: u64,
+}
+
+const DEFAULT_MAX_LEN: u64 = 1 << 30;
+
+impl std::fmt::Debug for SeekableBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeekableBytes")
+            .field("compressed_len", &self.compressed.len())
+            .field("decompressed_len", &self.data.get().map(Bytes::len))
+            .field("max_len", &self.max_len)
+            .finish()
+    }
+}
+
+impl SeekableBytes {
+    /// Nothing is decompressed, or even checked, until the data is needed.
+    pub fn new(compressed: impl Into<Bytes>) -> Self {
+        SeekableBytes {
+            compressed: compressed.into(),
+            data: OnceLock::new(),
+            max_len: DEFAULT_MAX_LEN,
+        }
+    }
+
+    /// Refuses data the seek table says decompresses to more than `limit`
+    /// bytes with [`Error::DataOverLimit`] rather than allocating for it.
+    pub fn max_len(mut self, limit: u64) -> Self {
+        self.max_len = limit;
+        self
+    }
+
+    pub fn compressed(&self) -> &Bytes {
+        &self.compressed
+    }
+
+    /// Whether the data was decompressed already.
+    pub fn is_decompressed(&self) -> bool {
+        self.data.get().is_some()
+    }
+
+    /// The decompressed data, decompressing it first if this is the first
+    /// time. Failures aren't kept: the next access tries again.
+    pub fn try_get(&self) -> Result<&Bytes, Error> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let mut decompress = SeekableDecompress::new(Cursor::new(self.compressed.clone()))?;
+        let declared = decompress.decompressed_len();
+        if declared > self.max_len {
+            return Err(Error::DataOverLimit {
+                declared,
+                limit: self.max_len,
+            });
+        }
+        let len = usize::try_from(declared).map_err(|_e| Error::DataTooLarge)?;
+        let mut data = vec![0; len];
+        decompress.decompress_all_into(&mut data)?;
+        // Should another thread get there first, its data is as good as ours.
+        let _ = self.data.set(Bytes::from(data));
+        Ok(self.data.get().unwrap())
+    }
+}
+
+impl Deref for SeekableBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.try_get() {
+            Ok(data) => data,
+            Err(e) => panic!("SeekableBytes failed to decompress: {}", e),
+        }
+    }
+}
+
+impl AsRef<[u8]> for SeekableBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<I: SliceIndex<[u8]>> Index<I> for SeekableBytes {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &I::Output {
+        &(**self)[index]
+    }
+}
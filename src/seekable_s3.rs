@@ -1,32 +1,239 @@
-use futures::TryFutureExt;
+use crate::{
+    frame_cache::decompress_checked, instrument, FrameCache, FrameMeta, S3RangeFetch, SeekTable,
+    SeekTableError, SEEK_TABLE_FOOTER_LEN,
+};
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
 use rusoto_core::RusotoError;
-use rusoto_s3::{GetObjectError, GetObjectRequest, S3Client, S3};
+use rusoto_s3::{GetObjectError, GetObjectOutput, GetObjectRequest, S3Client, S3};
 use std::convert::TryFrom;
+use std::fmt::Display;
+use std::future::Future;
 use std::io::{Error, ErrorKind, Read, Seek};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
+use zstd_seekable::DStream;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum S3ReadError {
+    // There's no valid seek table at the end of the object, typically
+    // because the upload didn't finish.
+    SeekTableCorrupt(SeekTableError),
+    // The seek table doesn't account for the object's content length.
+    LengthMismatch {
+        content_length: u64,
+        implied: u64,
+    },
+    // A read went past the end of the data, with OutOfRange::Reject.
+    OutOfRange {
+        offset: u64,
+        len: u64,
+        decompressed_len: u64,
+    },
+    // The frames prefetch_ranges was asked for don't fit in the frame cache.
+    PrefetchTooLarge {
+        needed: u64,
+        capacity: usize,
+    },
+    // The seek table says the frame decompresses to more than the limit set
+    // with set_max_frame_decompressed_size.
+    FrameTooLarge {
+        frame: usize,
+        declared: u64,
+    },
+    Io(std::io::Error),
+}
+
+/// What [`SeekableS3Object::read_decompressed`] does with ranges going past
+/// the end of the data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// Cut the read short at the end of the data, like reads do. This is the
+    /// default.
+    #[default]
+    Truncate,
+    /// Fail with [`S3ReadError::OutOfRange`].
+    Reject,
+}
+
+impl Display for S3ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            S3ReadError::SeekTableCorrupt(_) => write!(
+                f,
+                "No valid seek table at the end of the object, it may be truncated."
+            ),
+            S3ReadError::LengthMismatch {
+                content_length,
+                implied,
+            } => write!(
+                f,
+                "Object is {} bytes long but its seek table says it should be {}, it may be truncated or corrupt.",
+                content_length, implied
+            ),
+            S3ReadError::OutOfRange {
+                offset,
+                len,
+                decompressed_len,
+            } => write!(
+                f,
+                "Can't read {} bytes at offset {}, there are only {} bytes of data.",
+                len, offset, decompressed_len
+            ),
+            S3ReadError::PrefetchTooLarge { needed, capacity } => write!(
+                f,
+                "Prefetching takes {} bytes of frames but the frame cache only holds {}.",
+                needed, capacity
+            ),
+            S3ReadError::FrameTooLarge { frame, declared } => write!(
+                f,
+                "Frame {} decompresses to {} bytes, more than we're allowed.",
+                frame, declared
+            ),
+            S3ReadError::Io(_) => write!(f, "Reading the object failed."),
+        }
+    }
+}
+
+impl std::error::Error for S3ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            S3ReadError::SeekTableCorrupt(e) => Some(e),
+            S3ReadError::LengthMismatch { .. }
+            | S3ReadError::OutOfRange { .. }
+            | S3ReadError::PrefetchTooLarge { .. }
+            | S3ReadError::FrameTooLarge { .. } => None,
+            S3ReadError::Io(e) => Some(e),
+        }
+    }
+}
 
 pub struct SeekableS3Object<A> {
-    client: A,
-    req: GetObjectRequest,
+    fetch: S3RangeFetch<A>,
     position: u64,
-    // Updated when we first read the object.
+    // Updated when we first read the object. Positions and the length leave
+    // out the header, requests add it back.
     length: u64,
+    header_len: u64,
     body: Option<Pin<Box<dyn AsyncRead + Send>>>,
     handle: tokio::runtime::Handle,
     // Limit reads to this amount of time.
     read_timeout: Option<std::time::Duration>,
+    // How much of the end of the object to fetch in one go when reading
+    // there, and how far we're willing to grow that to get the whole seek
+    // table.
+    tail_fetch_size: usize,
+    max_tail_fetch_size: usize,
+    // The end of the object, once fetched: where it starts and the data.
+    tail: Option<(u64, Vec<u8>)>,
+    frame_cache: Option<FrameCache>,
+    // The checked seek table, once someone needed it.
+    seek_table: Option<SeekTable>,
+    out_of_range: OutOfRange,
+    // Most a frame may decompress to, going by the seek table.
+    max_frame_decompressed_size: Option<u64>,
+    stats: ReadCounters,
+}
+
+/// Buckets in [`ReadStats::decompress_micros`].
+pub const DECOMPRESS_TIME_BUCKETS: usize = 24;
+
+/// How much reading a [`SeekableS3Object`] took so far, from
+/// [`SeekableS3Object::read_stats`], for tuning prefetching and cache sizes
+/// against real workloads. With the `metrics` feature, the same goes out
+/// through the metrics crate too, summed over every object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadStats {
+    /// GETs of a range of the object, for reads, frames and the seek table.
+    pub ranged_gets: u64,
+    /// Bytes of the object those GETs and the first one gave.
+    pub bytes_fetched: u64,
+    /// Frames [`decompress_frame`](SeekableS3Object::decompress_frame) found
+    /// in the [frame cache](SeekableS3Object::set_frame_cache), and those
+    /// it had to fetch with a cache set.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// How long decompressing each frame took: bucket `i` counts frames that
+    /// took less than 2<sup>`i`</sup> microseconds but at least half that,
+    /// so bucket 0 is under a microsecond, and the last bucket takes in
+    /// everything slower too.
+    pub decompress_micros: [u64; DECOMPRESS_TIME_BUCKETS],
 }
 
+#[derive(Debug, Default)]
+struct ReadCounters {
+    ranged_gets: AtomicU64,
+    bytes_fetched: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    decompress_micros: [AtomicU64; DECOMPRESS_TIME_BUCKETS],
+}
+
+impl ReadCounters {
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn frame_decompressed(&self, elapsed: std::time::Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (64 - micros.leading_zeros() as usize).min(DECOMPRESS_TIME_BUCKETS - 1);
+        Self::add(&self.decompress_micros[bucket], 1);
+        instrument::frame_decompressed(elapsed);
+    }
+
+    fn snapshot(&self) -> ReadStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut decompress_micros = [0; DECOMPRESS_TIME_BUCKETS];
+        for (bucket, counter) in decompress_micros.iter_mut().zip(&self.decompress_micros) {
+            *bucket = load(counter);
+        }
+        ReadStats {
+            ranged_gets: load(&self.ranged_gets),
+            bytes_fetched: load(&self.bytes_fetched),
+            cache_hits: load(&self.cache_hits),
+            cache_misses: load(&self.cache_misses),
+            decompress_micros,
+        }
+    }
+}
+
+/// Default for [`SeekableS3Object::set_tail_fetch_size`]: enough for the seek
+/// table of a few thousand frames.
+pub const DEFAULT_TAIL_FETCH_SIZE: usize = 64 * 1024;
+/// Default for [`SeekableS3Object::set_max_tail_fetch_size`].
+pub const DEFAULT_MAX_TAIL_FETCH_SIZE: usize = 16 * 1024 * 1024;
+
+// How much of the start of the object SeekableS3Object::peek_prefix fetches
+// at first, doubling every time that's not enough.
+const PEEK_FETCH_SIZE: u64 = 16 * 1024;
+
 impl<A: std::fmt::Debug> std::fmt::Debug for SeekableS3Object<A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SeekableS3Object")
-            .field("client", &self.client)
-            .field("req", &self.req)
+            .field("fetch", &self.fetch)
             .field("position", &self.position)
             .field("length", &self.length)
+            .field("header_len", &self.header_len)
             .field("handle", &self.handle)
+            .field("read_timeout", &self.read_timeout)
+            .field("tail_fetch_size", &self.tail_fetch_size)
+            .field("max_tail_fetch_size", &self.max_tail_fetch_size)
+            .field("tail", &self.tail.as_ref().map(|(start, _)| start))
+            .field("frame_cache", &self.frame_cache)
+            .field(
+                "seek_table",
+                &self.seek_table.as_ref().map(SeekTable::num_frames),
+            )
+            .field("out_of_range", &self.out_of_range)
+            .field(
+                "max_frame_decompressed_size",
+                &self.max_frame_decompressed_size,
+            )
+            .field("stats", &self.stats)
             .finish()
     }
 }
@@ -88,16 +295,53 @@ impl<A> SeekableS3Object<A> {
         };
 
         Ok(Ok(SeekableS3Object {
-            client,
-            req,
+            fetch: S3RangeFetch::new(client, req),
             position: 0,
             length,
+            header_len: 0,
             body,
             handle,
             read_timeout,
+            tail_fetch_size: DEFAULT_TAIL_FETCH_SIZE,
+            max_tail_fetch_size: DEFAULT_MAX_TAIL_FETCH_SIZE,
+            tail: None,
+            frame_cache: None,
+            seek_table: None,
+            out_of_range: OutOfRange::default(),
+            max_frame_decompressed_size: None,
+            stats: ReadCounters::default(),
         }))
     }
 
+    /// Like [`new`](Self::new), for when the seek table was kept somewhere
+    /// else, such as in a manifest written alongside the object: it's used as
+    /// is and never fetched from the object, so
+    /// [`read_decompressed`](Self::read_decompressed) and friends go straight
+    /// to the frames. It's checked as with
+    /// [`read_seek_table`](Self::read_seek_table), failing with
+    /// [`S3ReadError::LengthMismatch`] unless the object's content length is
+    /// what the table implies. Timeouts and errors opening the object come
+    /// as [`S3ReadError::Io`], of kind [`ErrorKind::TimedOut`] and
+    /// [`ErrorKind::Other`] holding the [`RusotoError`].
+    pub fn with_seek_table(
+        client: A,
+        handle: tokio::runtime::Handle,
+        read_timeout: Option<std::time::Duration>,
+        req: GetObjectRequest,
+        seek_table: SeekTable,
+    ) -> Result<Self, S3ReadError>
+    where
+        A: S3,
+    {
+        let mut object = Self::new(client, handle, read_timeout, req)
+            .map_err(|e| S3ReadError::Io(Error::new(ErrorKind::TimedOut, e)))?
+            .map_err(|e| S3ReadError::Io(Error::new(ErrorKind::Other, e)))?;
+        object.check_length(&seek_table)?;
+        object.check_frame_sizes(&seek_table)?;
+        object.seek_table = Some(seek_table);
+        Ok(object)
+    }
+
     // Sets current position. If the position actually changes, invalidates the
     // current object body.
     //
@@ -114,20 +358,11 @@ impl<A> SeekableS3Object<A> {
     // Reads some data from the body while remebering to update the position.
     fn read_body(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         if let Some(body) = &mut self.body {
-            let bytes_read = match self.read_timeout {
-                Some(timeout) => {
-                    let _executor = self.handle.enter();
-                    match self
-                        .handle
-                        .block_on(tokio::time::timeout(timeout, body.read(buf)))
-                    {
-                        Ok(r) => r,
-                        Err(timeout_err) => Err(Error::new(ErrorKind::TimedOut, timeout_err)),
-                    }
-                }
-
-                None => self.handle.block_on(body.read(buf)),
-            }?;
+            let bytes_read = self
+                .handle
+                .block_on(with_timeout(self.read_timeout, body.read(buf)))?;
+            ReadCounters::add(&self.stats.bytes_fetched, bytes_read as u64);
+            instrument::bytes_fetched(bytes_read);
             // If we managed to read something, make sure to update position.
             // This saves us work if we something calls seek into the new
             // position.
@@ -139,11 +374,694 @@ impl<A> SeekableS3Object<A> {
         }
     }
 
+    /// Skips a header of `header_len` bytes in front of the object, such as
+    /// one written with [`Compress::header`](crate::Compress::header): from
+    /// then on the object is read as though the header weren't there, like
+    /// with [`SkipHeader`](crate::SkipHeader). Positions, the length and the
+    /// seek table's offsets all start after it. Set this before reading
+    /// anything, it goes back to the start. Fails if the object is shorter
+    /// than the header.
+    pub fn set_header_len(&mut self, header_len: u64) -> std::io::Result<()> {
+        let content_length = self.length + self.header_len;
+        if header_len > content_length {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Header of {} bytes is longer than the object, {} bytes.",
+                    header_len, content_length
+                ),
+            ));
+        }
+        self.length = content_length - header_len;
+        self.header_len = header_len;
+        self.position = 0;
+        self.body = None;
+        self.tail = None;
+        self.seek_table = None;
+        Ok(())
+    }
+
     /// Set the read timeout to the given duration. Set to None to disable
     /// time-out.
     pub fn set_read_timeout(&mut self, read_timeout: Option<std::time::Duration>) {
         self.read_timeout = read_timeout;
     }
+
+    /// Reads near the end of the object, which is where the seek table lives,
+    /// are served from a single fetch of this many bytes at the end of the
+    /// object. That way finding the seek table costs one request rather than
+    /// one for the footer and another for the table. If the footer says the
+    /// seek table is bigger than that, we fetch it whole, up to the
+    /// [maximum](Self::set_max_tail_fetch_size). Set to 0 to just fetch
+    /// ranges as they're read.
+    pub fn set_tail_fetch_size(&mut self, tail_fetch_size: usize) {
+        self.tail_fetch_size = tail_fetch_size;
+        self.tail = None;
+    }
+
+    /// Caps how far we grow the tail fetch to get the whole seek table. Past
+    /// this, the seek table is read with regular ranged reads without caching
+    /// it.
+    pub fn set_max_tail_fetch_size(&mut self, max_tail_fetch_size: usize) {
+        self.max_tail_fetch_size = max_tail_fetch_size;
+    }
+
+    // Issues a GET for the object from `start` to `end`, or to the end of
+    // the object without one.
+    fn get_range(&mut self, start: u64, end: Option<u64>) -> std::io::Result<GetObjectOutput>
+    where
+        A: S3,
+    {
+        self.handle.block_on(self.get_range_async(start, end))
+    }
+
+    // get_range, for fetching several ranges at once. The read timeout
+    // applies to the request, and reading the body is up to the caller.
+    async fn get_range_async(
+        &self,
+        start: u64,
+        end: Option<u64>,
+    ) -> std::io::Result<GetObjectOutput>
+    where
+        A: S3,
+    {
+        ReadCounters::add(&self.stats.ranged_gets, 1);
+        instrument::ranged_get();
+        let range = self.http_range(start, end);
+        with_timeout(self.read_timeout, self.fetch.get_object(Some(range))).await
+    }
+
+    // The Range header for `start` to `end`, past the header.
+    fn http_range(&self, start: u64, end: Option<u64>) -> String {
+        let start = start + self.header_len;
+        match end {
+            Some(end) => format!("bytes={}-{}", start, end + self.header_len - 1),
+            None => format!("bytes={}-", start),
+        }
+    }
+
+    /// Fetches `len` compressed bytes of the object at `offset` in one request,
+    /// regardless of the current position, which this leaves alone. Like a
+    /// read, this comes up short if the range goes past the end of the
+    /// object, right down to nothing at all (without a request) if it starts
+    /// past it.
+    pub fn read_compressed_range(&mut self, offset: u64, len: usize) -> std::io::Result<Bytes>
+    where
+        A: S3,
+    {
+        if offset >= self.length || len == 0 {
+            return Ok(Bytes::new());
+        }
+        let end = offset.saturating_add(len as u64).min(self.length);
+        self.fetch(offset, end).map(Bytes::from)
+    }
+
+    /// Fetches the compressed bytes of every frame `seek_table` lists, one
+    /// request per frame, along with the frame index. See
+    /// [`SeekTable::compressed_frames`] for data already in memory.
+    pub fn compressed_frames<'s>(
+        &'s mut self,
+        seek_table: &'s SeekTable,
+    ) -> impl Iterator<Item = std::io::Result<(usize, Bytes)>> + 's
+    where
+        A: S3,
+    {
+        (0..seek_table.num_frames())
+            .map(move |frame| Ok((frame, self.fetch_frame(seek_table, frame)?)))
+    }
+
+    // Fetches the compressed bytes of a frame.
+    fn fetch_frame(&mut self, seek_table: &SeekTable, frame: usize) -> std::io::Result<Bytes>
+    where
+        A: S3,
+    {
+        self.handle
+            .block_on(self.fetch_frame_async(seek_table, frame))
+    }
+
+    async fn fetch_frame_async(
+        &self,
+        seek_table: &SeekTable,
+        frame: usize,
+    ) -> std::io::Result<Bytes>
+    where
+        A: S3,
+    {
+        let offset = seek_table.frame_compressed_offset(frame);
+        let len = seek_table.frame_compressed_size(frame);
+        let end = offset.saturating_add(len).min(self.length);
+        let data = if offset < end {
+            self.fetch_async(offset, end).await?
+        } else {
+            Vec::new()
+        };
+        if data.len() as u64 != len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Frame {} goes past the end of the object.", frame),
+            ));
+        }
+        Ok(Bytes::from(data))
+    }
+
+    /// Fetches and decompresses every frame, from the last one to the first,
+    /// with a ranged GET each, as
+    /// [`SeekableDecompress::frames_rev`](crate::SeekableDecompress::frames_rev)
+    /// does. Frames without any data are left out. This is for going newest
+    /// first, such as through logs, without fetching everything to reverse
+    /// it: only one frame is held at a time. Only the frame order is
+    /// reversed, the data within each frame comes the way it was written.
+    ///
+    /// The seek table is fetched on the first call to `next`, as with
+    /// [`decompressed_len`](Self::decompressed_len), and frames go through
+    /// [`decompress_frame`](Self::decompress_frame), so they're checked and
+    /// cached the same way. The first error ends the iteration.
+    pub fn frames_rev(&mut self) -> S3FramesRev<'_, A> {
+        S3FramesRev {
+            object: self,
+            frames_left: None,
+            done: false,
+        }
+    }
+
+    /// What reading this took so far, see [`ReadStats`].
+    pub fn read_stats(&self) -> ReadStats {
+        self.stats.snapshot()
+    }
+
+    /// Shares `frame_cache` between this and whatever other readers it was
+    /// given to, for [`decompress_frame`](Self::decompress_frame). Set to
+    /// None to stop caching.
+    pub fn set_frame_cache(&mut self, frame_cache: Option<FrameCache>) {
+        self.frame_cache = frame_cache;
+    }
+
+    /// Refuses objects with any frame the seek table says decompresses to
+    /// more than `limit` bytes, as
+    /// [`SeekableDecompress::max_frame_decompressed_size`](crate::SeekableDecompress::max_frame_decompressed_size)
+    /// does: frames are decompressed whole, so set this when reading
+    /// untrusted objects to keep them from declaring huge frames to run us
+    /// out of memory. None, the default, leaves only the format's own limit
+    /// of 2GiB.
+    ///
+    /// Every frame is checked once the seek table is read, before anything
+    /// is decompressed, failing with [`S3ReadError::FrameTooLarge`]. If it
+    /// was read already, or given to
+    /// [`with_seek_table`](Self::with_seek_table), it's checked here.
+    pub fn set_max_frame_decompressed_size(
+        &mut self,
+        limit: Option<u64>,
+    ) -> Result<(), S3ReadError> {
+        self.max_frame_decompressed_size = limit;
+        match &self.seek_table {
+            Some(seek_table) => self.check_frame_sizes(seek_table),
+            None => Ok(()),
+        }
+    }
+
+    fn check_frame_sizes(&self, seek_table: &SeekTable) -> Result<(), S3ReadError> {
+        let limit = match self.max_frame_decompressed_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        for frame in 0..seek_table.num_frames() {
+            let declared = seek_table.frame_decompressed_size(frame);
+            if declared > limit {
+                return Err(S3ReadError::FrameTooLarge { frame, declared });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches and decompresses frame `frame` of `seek_table`, which has to
+    /// be the table of this object, such as from
+    /// [`read_seek_table`](Self::read_seek_table). The frame is checked
+    /// against its size and checksum and the position is left alone. Frames
+    /// over the [limit](Self::set_max_frame_decompressed_size) fail with
+    /// [`ErrorKind::InvalidData`] holding [`S3ReadError::FrameTooLarge`].
+    ///
+    /// With a [frame cache](Self::set_frame_cache), frames already in there,
+    /// from this object or any other, aren't decompressed again. With
+    /// [`FrameCacheKey::Checksum`](crate::FrameCacheKey::Checksum) they
+    /// aren't even fetched.
+    pub fn decompress_frame(
+        &mut self,
+        seek_table: &SeekTable,
+        frame: usize,
+    ) -> std::io::Result<Bytes>
+    where
+        A: S3,
+    {
+        self.decompress_checked_frame(seek_table, frame)
+            .map_err(|e| match e {
+                S3ReadError::Io(e) => e,
+                e => Error::new(ErrorKind::InvalidData, e),
+            })
+    }
+
+    // decompress_frame, with S3ReadError::FrameTooLarge as is.
+    fn decompress_checked_frame(
+        &mut self,
+        seek_table: &SeekTable,
+        frame: usize,
+    ) -> Result<Bytes, S3ReadError>
+    where
+        A: S3,
+    {
+        let declared = seek_table.frame_decompressed_size(frame);
+        if self
+            .max_frame_decompressed_size
+            .map_or(false, |limit| declared > limit)
+        {
+            return Err(S3ReadError::FrameTooLarge { frame, declared });
+        }
+        if let Some(cache) = &self.frame_cache {
+            let id = cache.frame_id(seek_table, frame, None);
+            if let Some(data) = id.and_then(|id| cache.get(id)) {
+                ReadCounters::add(&self.stats.cache_hits, 1);
+                return Ok(data);
+            }
+            ReadCounters::add(&self.stats.cache_misses, 1);
+            instrument::cache_miss();
+        }
+        let compressed = self
+            .fetch_frame(seek_table, frame)
+            .map_err(S3ReadError::Io)?;
+        let start = Instant::now();
+        let data = match &self.frame_cache {
+            Some(cache) => cache.decompress_frame(seek_table, frame, &compressed),
+            None => decompress_checked(seek_table, frame, &compressed),
+        };
+        self.stats.frame_decompressed(start.elapsed());
+        data.map_err(S3ReadError::Io)
+    }
+
+    /// Fetches and decompresses every frame holding some of the decompressed
+    /// `(offset, len)` ranges into the [frame cache](Self::set_frame_cache)
+    /// ahead of time, say for the reads a query plan is about to make, so
+    /// that [`read_decompressed`](Self::read_decompressed) and
+    /// [`decompress_frame`](Self::decompress_frame) then find them there.
+    /// Up to `concurrency` frames are fetched at once, each with a ranged GET
+    /// of its own, and decompressed on as many threads. Frames already in
+    /// the cache are left alone. Gives how many frames went in.
+    ///
+    /// The frames have to fit in the cache together, or the first ones
+    /// would be evicted to make room for the last: if they don't, this
+    /// fails with [`S3ReadError::PrefetchTooLarge`] before fetching anything,
+    /// as it does for anything at all without a cache. Other readers sharing
+    /// the cache can still push frames out before they're read.
+    pub fn prefetch_ranges(
+        &mut self,
+        ranges: &[(u64, usize)],
+        concurrency: usize,
+    ) -> Result<usize, S3ReadError>
+    where
+        A: S3,
+    {
+        // Take the table out while we fetch frames so we can borrow both.
+        self.cached_seek_table()?;
+        let seek_table = self.seek_table.take().unwrap();
+        let result = self.prefetch_frames(&seek_table, ranges, concurrency);
+        self.seek_table = Some(seek_table);
+        result
+    }
+
+    fn prefetch_frames(
+        &mut self,
+        seek_table: &SeekTable,
+        ranges: &[(u64, usize)],
+        concurrency: usize,
+    ) -> Result<usize, S3ReadError>
+    where
+        A: S3,
+    {
+        let mut frames: Vec<usize> = ranges
+            .iter()
+            .flat_map(|&(offset, len)| seek_table.split_range(offset, len as u64))
+            .map(|(frame, _, _)| frame)
+            .collect();
+        frames.sort_unstable();
+        frames.dedup();
+        let cache = match &self.frame_cache {
+            Some(cache) => cache.clone(),
+            None if frames.is_empty() => return Ok(0),
+            None => {
+                return Err(S3ReadError::PrefetchTooLarge {
+                    needed: frames
+                        .iter()
+                        .map(|&frame| seek_table.frame_decompressed_size(frame))
+                        .sum(),
+                    capacity: 0,
+                })
+            }
+        };
+        // With checksums for keys, there's no need to fetch frames to find
+        // out we have them.
+        frames.retain(|&frame| {
+            cache
+                .frame_id(seek_table, frame, None)
+                .and_then(|id| cache.get(id))
+                .is_none()
+        });
+        let needed = frames
+            .iter()
+            .map(|&frame| seek_table.frame_decompressed_size(frame))
+            .sum();
+        if needed > cache.capacity() as u64 {
+            return Err(S3ReadError::PrefetchTooLarge {
+                needed,
+                capacity: cache.capacity(),
+            });
+        }
+
+        let concurrency = concurrency.max(1);
+        let this = &*self;
+        let fetches = frames.iter().map(|&frame| async move {
+            Ok::<_, Error>((frame, this.fetch_frame_async(seek_table, frame).await?))
+        });
+        let fetched: Vec<(usize, Bytes)> = self
+            .handle
+            .block_on(
+                futures::stream::iter(fetches)
+                    .buffer_unordered(concurrency)
+                    .try_collect(),
+            )
+            .map_err(S3ReadError::Io)?;
+
+        let stats = &self.stats;
+        let fetched = parking_lot::Mutex::new(fetched);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..concurrency.min(frames.len()))
+                .map(|_| {
+                    scope.spawn(|| loop {
+                        let (frame, compressed) = match fetched.lock().pop() {
+                            Some(next) => next,
+                            None => break Ok(()),
+                        };
+                        ReadCounters::add(&stats.cache_misses, 1);
+                        instrument::cache_miss();
+                        let start = Instant::now();
+                        cache.decompress_frame(seek_table, frame, &compressed)?;
+                        stats.frame_decompressed(start.elapsed());
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("decompression worker panicked"))
+        })
+        .map_err(S3ReadError::Io)?;
+        Ok(frames.len())
+    }
+
+    /// Gives the first `len` bytes of decompressed data, or all of it if
+    /// there's less, without touching the seek table or the rest of the
+    /// object: handy for sniffing the content type. Fetches the start of the
+    /// object in small, growing ranges until enough of it decompresses, so
+    /// for a few hundred bytes that's usually a single request.
+    ///
+    /// This assumes the object starts with a frame, which is how we and
+    /// zstd write them, and leaves the position alone.
+    pub fn peek_prefix(&mut self, len: usize) -> std::io::Result<Bytes>
+    where
+        A: S3,
+    {
+        let mut dstream =
+            DStream::new().map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        let mut prefix = vec![0; len];
+        let mut written = 0;
+        let mut fetched = 0;
+        let mut fetch_size = PEEK_FETCH_SIZE;
+        while written < len && fetched < self.length {
+            let end = fetched.saturating_add(fetch_size).min(self.length);
+            let compressed = self.fetch(fetched, end)?;
+            fetched = end;
+            fetch_size *= 2;
+
+            let mut input = &compressed[..];
+            while written < len && !input.is_empty() {
+                let (out_pos, in_pos) = dstream
+                    .decompress(&mut prefix[written..], input)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                // DStream doesn't tell us about errors: if it's stuck with
+                // input left, the data is bad.
+                if out_pos == 0 && in_pos == 0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Object doesn't start with valid zstd data.",
+                    ));
+                }
+                written += out_pos;
+                input = &input[in_pos..];
+            }
+        }
+        prefix.truncate(written);
+        Ok(Bytes::from(prefix))
+    }
+
+    /// Fetches and checks the seek table, which catches objects that were cut
+    /// short or otherwise mangled before decompressing anything: the table
+    /// has to be valid and, together with the frames it lists, account for
+    /// exactly the object's content length. Whatever gets fetched is kept
+    /// for reads of the end of the object, so decompressing from here
+    /// costs no extra requests for the seek table.
+    ///
+    /// Reading the end of the object, as [`SeekableDecompress`](crate::SeekableDecompress)
+    /// does first thing, checks the same, unless the [tail fetch](Self::set_tail_fetch_size)
+    /// is off or the table doesn't fit in it: such reads fail with
+    /// [`ErrorKind::InvalidData`] holding the [`S3ReadError`].
+    pub fn read_seek_table(&mut self) -> Result<SeekTable, S3ReadError>
+    where
+        A: S3,
+    {
+        let corrupt = S3ReadError::SeekTableCorrupt;
+        if self.length < SEEK_TABLE_FOOTER_LEN as u64 {
+            return Err(corrupt(SeekTableError::TooShort {
+                needed: SEEK_TABLE_FOOTER_LEN,
+                got: self.length as usize,
+            }));
+        }
+        let tail = match self.tail.take() {
+            Some(tail) => tail,
+            None => self
+                .fetch_seek_table_tail(self.tail_fetch_size.max(SEEK_TABLE_FOOTER_LEN) as u64)
+                .map_err(S3ReadError::Io)?,
+        };
+        let table_len = SeekTable::len_from_footer(&tail.1).map_err(corrupt)? as u64;
+        let seek_table = if table_len > tail.1.len() as u64 && table_len <= self.length {
+            // Too big to keep around, past max_tail_fetch_size.
+            match self.fetch(self.length - table_len, self.length) {
+                Ok(table) => self.parse_seek_table(&table),
+                Err(e) => Err(S3ReadError::Io(e)),
+            }
+        } else {
+            self.parse_seek_table(&tail.1)
+        };
+        self.tail = Some(tail);
+        let seek_table = seek_table?;
+        self.check_frame_sizes(&seek_table)?;
+        Ok(seek_table)
+    }
+
+    // Parses the seek table at the end of `tail`, which has to be the end of
+    // the object, and checks it accounts for the object's content length.
+    fn parse_seek_table(&self, tail: &[u8]) -> Result<SeekTable, S3ReadError> {
+        let seek_table = SeekTable::parse(tail).map_err(S3ReadError::SeekTableCorrupt)?;
+        self.check_length(&seek_table)?;
+        Ok(seek_table)
+    }
+
+    fn check_length(&self, seek_table: &SeekTable) -> Result<(), S3ReadError> {
+        let implied = seek_table.compressed_len() + seek_table.seek_table_len() as u64;
+        if implied != self.length {
+            return Err(S3ReadError::LengthMismatch {
+                content_length: self.length,
+                implied,
+            });
+        }
+        Ok(())
+    }
+
+    // The seek table, fetched and checked the first time.
+    fn cached_seek_table(&mut self) -> Result<&SeekTable, S3ReadError>
+    where
+        A: S3,
+    {
+        if self.seek_table.is_none() {
+            self.seek_table = Some(self.read_seek_table()?);
+        }
+        Ok(self.seek_table.as_ref().unwrap())
+    }
+
+    /// Length of the decompressed data, going by the seek table. That's
+    /// fetched and checked as with [`read_seek_table`](Self::read_seek_table)
+    /// the first time it's needed, by this or the other methods reading
+    /// decompressed data, and kept from then on: nothing gets decompressed.
+    pub fn decompressed_len(&mut self) -> Result<u64, S3ReadError>
+    where
+        A: S3,
+    {
+        Ok(self.cached_seek_table()?.decompressed_len())
+    }
+
+    /// Length of the frames in the object, going by the seek table, which
+    /// is fetched as with [`decompressed_len`](Self::decompressed_len). The
+    /// seek table comes on top.
+    pub fn compressed_len(&mut self) -> Result<u64, S3ReadError>
+    where
+        A: S3,
+    {
+        Ok(self.cached_seek_table()?.compressed_len())
+    }
+
+    /// What [`read_decompressed`](Self::read_decompressed) does with reads
+    /// going past the end of the data.
+    pub fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        self.out_of_range = out_of_range;
+    }
+
+    /// Reads `len` bytes of decompressed data at `offset`, fetching and
+    /// decompressing just the frames holding them with
+    /// [`decompress_frame`](Self::decompress_frame), through the frame cache
+    /// if there is one. The seek table is fetched the first time, as with
+    /// [`decompressed_len`](Self::decompressed_len).
+    ///
+    /// Ranges going past the end of the data are dealt with before fetching
+    /// any frames, as [set](Self::set_out_of_range): by default they're cut
+    /// short, down to nothing at all if they start past the end.
+    pub fn read_decompressed(&mut self, offset: u64, len: u64) -> Result<Bytes, S3ReadError>
+    where
+        A: S3,
+    {
+        let decompressed_len = self.decompressed_len()?;
+        if self.out_of_range == OutOfRange::Reject
+            && offset
+                .checked_add(len)
+                .map_or(true, |end| end > decompressed_len)
+        {
+            return Err(S3ReadError::OutOfRange {
+                offset,
+                len,
+                decompressed_len,
+            });
+        }
+        // Take the table out while we fetch frames so we can borrow both.
+        let seek_table = self.seek_table.take().unwrap();
+        let result = seek_table.assemble_range(offset, len, |frame| {
+            self.decompress_checked_frame(&seek_table, frame)
+        });
+        self.seek_table = Some(seek_table);
+        result
+    }
+
+    // Fetches everything from the given offset to the end of the object.
+    fn fetch_tail(&mut self, start: u64) -> std::io::Result<Vec<u8>>
+    where
+        A: S3,
+    {
+        self.fetch(start, self.length)
+    }
+
+    // Fetches the given range of the object, which has to be in bounds.
+    fn fetch(&mut self, start: u64, end: u64) -> std::io::Result<Vec<u8>>
+    where
+        A: S3,
+    {
+        self.handle.block_on(self.fetch_async(start, end))
+    }
+
+    async fn fetch_async(&self, start: u64, end: u64) -> std::io::Result<Vec<u8>>
+    where
+        A: S3,
+    {
+        let object = self.get_range_async(start, Some(end)).await?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        if let Some(body) = object.body {
+            let mut body = body.into_async_read();
+            with_timeout(self.read_timeout, body.read_to_end(&mut data)).await?;
+        }
+        ReadCounters::add(&self.stats.bytes_fetched, data.len() as u64);
+        instrument::bytes_fetched(data.len());
+        Ok(data)
+    }
+
+    // Fetches the last `fetch_size` bytes of the object, or more to get the
+    // whole seek table if the footer says it's bigger, as long as that's no
+    // more than max_tail_fetch_size. Gives where the fetch starts and the
+    // data.
+    fn fetch_seek_table_tail(&mut self, fetch_size: u64) -> std::io::Result<(u64, Vec<u8>)>
+    where
+        A: S3,
+    {
+        let start = self.length.saturating_sub(fetch_size);
+        let tail = self.fetch_tail(start)?;
+        match SeekTable::len_from_footer(&tail) {
+            Ok(table_len)
+                if table_len as u64 > tail.len() as u64
+                    && table_len as u64 <= self.length
+                    && table_len <= self.max_tail_fetch_size =>
+            {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    fetched = tail.len(),
+                    table_len,
+                    "seek table larger than the tail fetch, fetching a bigger tail"
+                );
+                let start = self.length - table_len as u64;
+                Ok((start, self.fetch_tail(start)?))
+            }
+            _ => Ok((start, tail)),
+        }
+    }
+
+    // Serves the read from the end of the object if the position is in
+    // there, fetching it first if need be.
+    fn read_tail(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>>
+    where
+        A: S3,
+    {
+        let fetched = self.tail.is_none();
+        if fetched {
+            if self.tail_fetch_size == 0 {
+                return Ok(None);
+            }
+            let start = self.length.saturating_sub(self.tail_fetch_size as u64);
+            if self.position < start {
+                return Ok(None);
+            }
+            let tail = self.fetch_seek_table_tail(self.tail_fetch_size as u64)?;
+            // This is how the seek table is usually found, so it's where a
+            // truncated object gets caught, rather than by zstd failing to
+            // make sense of whatever's there. Tables too big for the tail are
+            // left to zstd.
+            let checked = match SeekTable::len_from_footer(&tail.1) {
+                Ok(table_len)
+                    if table_len as u64 > tail.1.len() as u64
+                        && table_len as u64 <= self.length =>
+                {
+                    Ok(())
+                }
+                Ok(_) => self.parse_seek_table(&tail.1).map(drop),
+                Err(e) => Err(S3ReadError::SeekTableCorrupt(e)),
+            };
+            self.tail = Some(tail);
+            checked.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        }
+
+        match &self.tail {
+            Some((start, tail)) if self.position >= *start => {
+                if !fetched {
+                    instrument::cache_hit();
+                }
+                let from = (self.position - start) as usize;
+                let n = buf.len().min(tail.len().saturating_sub(from));
+                buf[..n].copy_from_slice(&tail[from..from + n]);
+                self.set_position(self.position + n as u64);
+                Ok(Some(n))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 impl<A> Read for SeekableS3Object<A>
@@ -164,29 +1082,16 @@ where
             return self.read_body(buf);
         }
 
+        // Reads at the end of the object, for the seek table, get served from
+        // a single fetch of it.
+        if let Some(n) = self.read_tail(buf)? {
+            return Ok(n);
+        }
+
         // We didn't have existing body to read from: probably we have done a
         // seek. Get the body at the new position, read some data and store the
         // new body for the future.
-        self.req.range = Some(format!("bytes={}-", self.position));
-
-        let get_object = self
-            .client
-            .get_object(self.req.to_owned())
-            .map_err(|e| Error::new(ErrorKind::Other, e));
-
-        let object = match self.read_timeout {
-            Some(timeout) => {
-                let _executor = self.handle.enter();
-                match self
-                    .handle
-                    .block_on(tokio::time::timeout(timeout, get_object))
-                {
-                    Ok(r) => r,
-                    Err(timeout_err) => Err(Error::new(ErrorKind::TimedOut, timeout_err)),
-                }
-            }
-            None => self.handle.block_on(get_object),
-        }?;
+        let object = self.get_range(self.position, None)?;
 
         self.body = object
             .body
@@ -226,6 +1131,86 @@ impl<A> Seek for SeekableS3Object<A> {
     }
 }
 
+/// Frames from last to first, see [`SeekableS3Object::frames_rev`].
+pub struct S3FramesRev<'o, A> {
+    object: &'o mut SeekableS3Object<A>,
+    // How many frames are left to look at, once we have the seek table.
+    frames_left: Option<usize>,
+    done: bool,
+}
+
+impl<'o, A: std::fmt::Debug> std::fmt::Debug for S3FramesRev<'o, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3FramesRev")
+            .field("object", &self.object)
+            .field("frames_left", &self.frames_left)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl<'o, A: S3> S3FramesRev<'o, A> {
+    fn next_frame(&mut self) -> Result<Option<(FrameMeta, Bytes)>, S3ReadError> {
+        let object = &mut *self.object;
+        let frames_left = match &mut self.frames_left {
+            Some(frames_left) => frames_left,
+            None => self
+                .frames_left
+                .insert(object.cached_seek_table()?.num_frames()),
+        };
+        // Take the table out while we fetch frames so we can borrow both.
+        let seek_table = object.seek_table.take().unwrap();
+        let mut result = Ok(None);
+        while *frames_left > 0 {
+            *frames_left -= 1;
+            let meta = FrameMeta::new(&seek_table, *frames_left);
+            if meta.decompressed_size == 0 {
+                continue;
+            }
+            result = object
+                .decompress_checked_frame(&seek_table, *frames_left)
+                .map(|data| Some((meta, data)));
+            break;
+        }
+        object.seek_table = Some(seek_table);
+        result
+    }
+}
+
+impl<'o, A: S3> Iterator for S3FramesRev<'o, A> {
+    type Item = Result<(FrameMeta, Bytes), S3ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_frame() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Runs `future`, bailing out if it takes longer than `timeout`.
+async fn with_timeout<F, T>(timeout: Option<std::time::Duration>, future: F) -> std::io::Result<T>
+where
+    F: Future<Output = std::io::Result<T>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|e| Error::new(ErrorKind::TimedOut, e))?,
+        None => future.await,
+    }
+}
+
 // Allows to simply say `s3.get_seekable_object` to be consistent with rest of
 // rusoto API.
 pub trait GetSeekableObject: Sized {
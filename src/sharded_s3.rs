@@ -0,0 +1,161 @@
+use crate::{FrameCache, S3ReadError, SeekTable, SeekableS3Object};
+use bytes::Bytes;
+use rusoto_s3::{GetObjectRequest, S3};
+
+/// The objects [`compress_with_roll_over`](crate::compress_with_roll_over)
+/// sharded a stream over, read as the one stream they hold. Offsets are
+/// offsets in the whole stream: each read is mapped to the shards and frames
+/// holding it and only those frames are fetched, with a ranged GET of the
+/// right object each.
+///
+/// This goes by the seek tables of the shards, which have to be known up
+/// front, say from a manifest written along with them: reading them from
+/// every shard would cost a request per shard before anything else could be
+/// done. A shard is only opened, as with
+/// [`SeekableS3Object::with_seek_table`], the first time a read needs it.
+pub struct ShardedS3Object<A> {
+    client: A,
+    handle: tokio::runtime::Handle,
+    read_timeout: Option<std::time::Duration>,
+    // Requests for each of the shards along with their seek tables, and the
+    // shards we opened so far.
+    requests: Vec<GetObjectRequest>,
+    seek_tables: Vec<SeekTable>,
+    shards: Vec<Option<SeekableS3Object<A>>>,
+    // Where the data of each shard starts in the stream, with where the last
+    // one ends at the end.
+    offsets: Vec<u64>,
+    frame_cache: Option<FrameCache>,
+}
+
+impl<A> std::fmt::Debug for ShardedS3Object<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardedS3Object")
+            .field("handle", &self.handle)
+            .field("read_timeout", &self.read_timeout)
+            .field("requests", &self.requests)
+            .field("offsets", &self.offsets)
+            .field(
+                "open_shards",
+                &self.shards.iter().filter(|shard| shard.is_some()).count(),
+            )
+            .field("frame_cache", &self.frame_cache)
+            .finish()
+    }
+}
+
+impl<A> ShardedS3Object<A> {
+    /// Reads the shards with the seek tables in `seek_tables`, in order.
+    /// Shard `n` is the object at key `shard_key(n)`, with everything else
+    /// about the GETs, such as the bucket, taken from `req`, for example
+    /// `|shard| format!("object.zst.{}", shard)`.
+    pub fn new(
+        client: A,
+        handle: tokio::runtime::Handle,
+        read_timeout: Option<std::time::Duration>,
+        req: GetObjectRequest,
+        shard_key: impl Fn(usize) -> String,
+        seek_tables: Vec<SeekTable>,
+    ) -> Self {
+        let requests = (0..seek_tables.len())
+            .map(|shard| GetObjectRequest {
+                key: shard_key(shard),
+                range: None,
+                ..req.clone()
+            })
+            .collect();
+        let mut offsets = vec![0];
+        for seek_table in &seek_tables {
+            let end = offsets[offsets.len() - 1] + seek_table.decompressed_len();
+            offsets.push(end);
+        }
+        ShardedS3Object {
+            client,
+            handle,
+            read_timeout,
+            shards: seek_tables.iter().map(|_| None).collect(),
+            requests,
+            seek_tables,
+            offsets,
+            frame_cache: None,
+        }
+    }
+
+    /// How many shards the stream is over, one per seek table given to
+    /// [`new`](Self::new), empty ones included.
+    pub fn num_shards(&self) -> usize {
+        self.seek_tables.len()
+    }
+
+    /// Length of the whole stream, going by the seek tables.
+    pub fn decompressed_len(&self) -> u64 {
+        self.offsets[self.offsets.len() - 1]
+    }
+
+    /// The shard holding `offset` of the stream, along with where that is in
+    /// the shard's own data, None if it's past the end.
+    pub fn shard_for_offset(&self, offset: u64) -> Option<(usize, u64)> {
+        if offset >= self.decompressed_len() {
+            return None;
+        }
+        // As with frames, the first shard ending past the offset holds it,
+        // which leaves out empty ones.
+        let shard = self.offsets[1..].partition_point(|&shard_end| shard_end <= offset);
+        Some((shard, offset - self.offsets[shard]))
+    }
+
+    /// Shares `frame_cache` between every shard and whatever other readers
+    /// it was given to. Set to None to stop caching. Shards already opened
+    /// keep what they had.
+    pub fn set_frame_cache(&mut self, frame_cache: Option<FrameCache>) {
+        self.frame_cache = frame_cache;
+    }
+
+    /// Reads `len` bytes of the stream at `offset`, across as many shards as
+    /// that takes, with
+    /// [`SeekableS3Object::read_decompressed`] on each of them. Reads going
+    /// past the end of the stream are cut short, down to nothing at all if
+    /// they start past it.
+    pub fn read_range(&mut self, offset: u64, len: u64) -> Result<Bytes, S3ReadError>
+    where
+        A: S3 + Clone,
+    {
+        let end = offset.saturating_add(len).min(self.decompressed_len());
+        let mut position = offset;
+        let mut pieces = Vec::new();
+        while position < end {
+            let (shard, from) = match self.shard_for_offset(position) {
+                Some(found) => found,
+                None => break,
+            };
+            let n = (self.offsets[shard + 1] - position).min(end - position);
+            pieces.push(self.shard(shard)?.read_decompressed(from, n)?);
+            position += n;
+        }
+        // No need to copy anything if it's all in one shard.
+        Ok(match pieces.len() {
+            0 => Bytes::new(),
+            1 => pieces.remove(0),
+            _ => Bytes::from(pieces.concat()),
+        })
+    }
+
+    // Shard `shard`, opening it if we haven't yet.
+    fn shard(&mut self, shard: usize) -> Result<&mut SeekableS3Object<A>, S3ReadError>
+    where
+        A: S3 + Clone,
+    {
+        if self.shards[shard].is_none() {
+            let mut object = SeekableS3Object::with_seek_table(
+                self.client.clone(),
+                self.handle.clone(),
+                self.read_timeout,
+                self.requests[shard].clone(),
+                self.seek_tables[shard].clone(),
+            )?;
+            object.set_frame_cache(self.frame_cache.clone());
+            self.shards[shard] = Some(object);
+        }
+        Ok(self.shards[shard].as_mut().unwrap())
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+
+/// Reads an object with a header of `header_len` bytes in front, such as one
+/// written with [`Compress::header`](crate::Compress::header), as though the
+/// header weren't there: offset 0 is the first byte after it. Hand it to
+/// [`SeekableDecompress::new`](crate::SeekableDecompress::new) and the seek
+/// table's offsets work out as they would without a header.
+///
+/// Seeking into the header fails, leaving the position where it was.
+#[derive(Debug)]
+pub struct SkipHeader<A> {
+    inner: A,
+    header_len: u64,
+}
+
+impl<A: Seek> SkipHeader<A> {
+    /// Seeks `inner` to the end of the header. Fails if that does.
+    pub fn new(mut inner: A, header_len: u64) -> std::io::Result<Self> {
+        inner.seek(SeekFrom::Start(header_len))?;
+        Ok(SkipHeader { inner, header_len })
+    }
+}
+
+impl<A> SkipHeader<A> {
+    pub fn header_len(&self) -> u64 {
+        self.header_len
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A: Read> Read for SkipHeader<A> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<A: Seek> Seek for SkipHeader<A> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let current = self.inner.stream_position()?;
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(
+                offset
+                    .checked_add(self.header_len)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Seek past u64."))?,
+            ),
+            pos => pos,
+        };
+        let new = self.inner.seek(pos)?;
+        match new.checked_sub(self.header_len) {
+            Some(offset) => Ok(offset),
+            None => {
+                self.inner.seek(SeekFrom::Start(current))?;
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Seek to before the end of the header.",
+                ))
+            }
+        }
+    }
+}
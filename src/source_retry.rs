@@ -0,0 +1,131 @@
+use crate::{cstream::config_error, Compress, CompressError, StreamCompress};
+use bytes::Bytes;
+use futures::{
+    ready,
+    stream::{FusedStream, Stream},
+};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Compresses the stream made by `make_stream` like
+/// [`StreamCompress::compress`], starting over with a fresh stream from
+/// `make_stream` when the source fails, up to `max_attempts` times in all.
+/// This is for sources whose errors are transient and which give the same
+/// data every time, such as a download that can be started again.
+///
+/// Only errors that come before any output can be retried: once compressed
+/// bytes have gone out they can't be taken back, and starting over would
+/// yield them again. Source errors after that, and the last error when the
+/// attempts run out, are passed on as [`CompressError::Underlying`] as
+/// usual. Other errors are never retried.
+///
+/// Fails if `max_attempts` is 0 or the compression settings are out of
+/// range.
+pub fn compress_with_source_retry<F, S, I, E>(
+    make_stream: F,
+    compression_level: i32,
+    frame_size: usize,
+    max_attempts: usize,
+) -> Result<SourceRetry<F, S, E>, zstd_seekable::Error>
+where
+    F: Fn() -> S,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    if max_attempts == 0 {
+        return Err(config_error("max_attempts", max_attempts, "at least 1"));
+    }
+    let compress = make_stream().compress(compression_level, frame_size)?;
+    Ok(SourceRetry {
+        make_stream,
+        compression_level,
+        frame_size,
+        attempts: 1,
+        max_attempts,
+        emitted: false,
+        compress: Some(Box::pin(compress)),
+    })
+}
+
+/// What [`compress_with_source_retry`] returns.
+pub struct SourceRetry<F, S, E> {
+    make_stream: F,
+    compression_level: i32,
+    frame_size: usize,
+    attempts: usize,
+    max_attempts: usize,
+    // Whether any compressed bytes went out, after which we can't retry.
+    emitted: bool,
+    // Gone if we couldn't start compressing again.
+    compress: Option<Pin<Box<Compress<S, E>>>>,
+}
+
+impl<F, S, E> SourceRetry<F, S, E> {
+    /// How many times compression was started, the first time included.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+}
+
+// Nothing is pinned but the compression, which is boxed.
+impl<F, S, E> Unpin for SourceRetry<F, S, E> {}
+
+impl<F, S, I, E> Stream for SourceRetry<F, S, E>
+where
+    F: Fn() -> S,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    type Item = Result<Bytes, CompressError<E>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let compress = match &mut this.compress {
+                Some(compress) => compress,
+                None => return Poll::Ready(None),
+            };
+            match ready!(compress.as_mut().poll_next(cx)) {
+                Some(Err(CompressError::Underlying(_)))
+                    if !this.emitted && this.attempts < this.max_attempts =>
+                {
+                    this.attempts += 1;
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        attempt = this.attempts,
+                        max_attempts = this.max_attempts,
+                        "Source failed before any output, starting compression over"
+                    );
+                    match (this.make_stream)().compress(this.compression_level, this.frame_size) {
+                        Ok(compress) => this.compress = Some(Box::pin(compress)),
+                        Err(e) => {
+                            this.compress = None;
+                            return Poll::Ready(Some(Err(CompressError::ZstdError(e))));
+                        }
+                    }
+                }
+                item => {
+                    if matches!(&item, Some(Ok(bytes)) if !bytes.is_empty()) {
+                        this.emitted = true;
+                    }
+                    return Poll::Ready(item);
+                }
+            }
+        }
+    }
+}
+
+impl<F, S, I, E> FusedStream for SourceRetry<F, S, E>
+where
+    F: Fn() -> S,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    fn is_terminated(&self) -> bool {
+        self.compress
+            .as_ref()
+            .map_or(true, |compress| compress.is_terminated())
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    compress_to_s3::{create_upload, spill_to, truncate_spill, upload_and_complete},
+    reframe::{decompress_frame, frame_len},
+    CompressProgress, CompressToS3Config, CompressToS3Error, SeekTable, StreamCompress,
+    StreamUploadParts, UploadReport,
+};
+use bytes::Bytes;
+use futures::{future::Either, stream, Stream, StreamExt, TryStreamExt};
+use rusoto_s3::{
+    CompletedPart, ListMultipartUploadsRequest, ListPartsRequest, MultipartUpload,
+    UploadPartRequest, S3,
+};
+use std::{
+    convert::TryFrom,
+    io::{self, ErrorKind, Read, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::Mutex,
+};
+use xxhash_rust::xxh64::Xxh64;
+use zstd_seekable::{CStream, DStream};
+
+/// What a [`compress_to_s3`](crate::compress_to_s3()) upload with a
+/// [`spill`](CompressToS3Config::spill) got through before the process died,
+/// going by the spill. Hand it to [`resume_compress_to_s3`] to finish the
+/// upload.
+#[derive(Debug, Clone)]
+pub struct SpillRecovery {
+    path: PathBuf,
+    // Every whole frame in the spill, which is all of the object if it
+    // ended with its seek table.
+    seek_table: SeekTable,
+    len: u64,
+    finished: bool,
+}
+
+impl SpillRecovery {
+    /// Reads the spill at `path`, decompressing every frame in it to rebuild
+    /// the seek table, so this takes a while for a large spill. Whatever
+    /// comes after the last whole frame is dropped.
+    ///
+    /// Fails if the spill can't be read or a frame in it doesn't decompress.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut file = std::fs::File::open(&path)?;
+        let mut dstream = DStream::new().map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+        let mut buf_out = vec![0; CStream::out_size()];
+        let mut seek_table = SeekTable::new(true);
+        let mut finished = false;
+
+        // What we read of the spill and haven't gone through yet, starting
+        // with the frame after the last whole one.
+        let mut input = Vec::new();
+        let mut len = 0;
+        let mut eof = false;
+        loop {
+            let frame = frame_len(&input);
+            // Only a skippable frame that ends the spill can be the seek
+            // table, so there's no telling what it is before the end.
+            let need_more = match frame {
+                None => true,
+                Some((frame_len, data)) => !data && frame_len == input.len(),
+            };
+            if need_more && !eof {
+                let mut chunk = [0; 64 * 1024];
+                let n = file.read(&mut chunk)?;
+                eof = n == 0;
+                input.extend_from_slice(&chunk[..n]);
+                continue;
+            }
+            let (frame_len, data) = match frame {
+                Some(frame) => frame,
+                None => break,
+            };
+            let compressed = &input[..frame_len];
+            let compressed_size =
+                u32::try_from(frame_len).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            if data {
+                let mut hasher = Xxh64::new(0);
+                let size = decompress_frame(&mut dstream, compressed, &mut buf_out, |out| {
+                    hasher.update(out)
+                })
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                let size =
+                    u32::try_from(size).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                seek_table.push_frame(compressed_size, size, hasher.digest() as u32);
+            } else {
+                match SeekTable::parse(compressed) {
+                    Ok(table) if frame_len == input.len() && table == seek_table => {
+                        finished = true;
+                    }
+                    _ => seek_table.push_frame(compressed_size, 0, Xxh64::new(0).digest() as u32),
+                }
+            }
+            len += frame_len as u64;
+            input.drain(..frame_len);
+            if finished {
+                break;
+            }
+        }
+
+        Ok(SpillRecovery {
+            path,
+            seek_table,
+            len,
+            finished,
+        })
+    }
+
+    /// Where to start the source again for [`resume_compress_to_s3`]: how
+    /// much of the data made it into whole frames in the spill.
+    pub fn decompressed_len(&self) -> u64 {
+        self.seek_table.decompressed_len()
+    }
+
+    /// The whole frames in the spill.
+    pub fn seek_table(&self) -> &SeekTable {
+        &self.seek_table
+    }
+
+    /// Whether the spill holds the whole object, seek table and all, so
+    /// there's nothing left to compress.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Finishes a [`compress_to_s3`](crate::compress_to_s3()) upload with a
+/// [`spill`](CompressToS3Config::spill) that was cut short by a crash, given
+/// what [`SpillRecovery::open`] found in the spill and `source` starting
+/// again at [`decompressed_len`](SpillRecovery::decompressed_len).
+///
+/// To recover from a crash:
+///
+/// 1. Open the spill with [`SpillRecovery::open`].
+/// 2. Start the source again from `decompressed_len` bytes in, say by
+///    seeking in the file being compressed.
+/// 3. Call this with the `bucket`, `key` and `config` the upload had.
+///
+/// This carries on with the latest multipart upload to `key` still in
+/// progress, or starts a new one if there's none. Parts S3 already has are
+/// kept as long as the spill covers them in whole frames, everything else
+/// in the spill is uploaded again from the spill, and the rest of `source`
+/// is compressed after it, into the spill as well, so this can be resumed
+/// again in turn. With the same settings, the object comes out the same as
+/// if nothing had happened. `source` isn't read at all if the spill holds
+/// the whole object already. The spill is truncated once the upload is
+/// complete, and the upload is left in place if this fails.
+///
+/// The spill is flushed before each chunk of it goes out, so it survives
+/// the process dying but not necessarily the machine going down.
+pub async fn resume_compress_to_s3<A, S, I, E>(
+    recovery: SpillRecovery,
+    source: S,
+    client: &A,
+    bucket: String,
+    key: String,
+    config: CompressToS3Config,
+) -> Result<UploadReport, CompressToS3Error<E>>
+where
+    A: S3,
+    S: Stream<Item = Result<I, E>>,
+    I: std::borrow::Borrow<[u8]>,
+{
+    let compress = if recovery.finished {
+        None
+    } else {
+        Some(
+            source
+                .compress(config.compression_level, config.frame_size)
+                .map_err(|e| CompressToS3Error::Compress(e.into()))?
+                .continue_after(recovery.seek_table.clone()),
+        )
+    };
+    let compress_progress = compress
+        .as_ref()
+        .map(|compress| compress.progress())
+        .unwrap_or_else(CompressProgress::default);
+
+    let upload_id = match latest_upload(client, &bucket, &key).await? {
+        Some(upload_id) => upload_id,
+        None => create_upload(client, &bucket, &key).await?,
+    };
+    let (completed, uploaded) =
+        uploaded_parts(client, &bucket, &key, &upload_id, recovery.len).await?;
+
+    let spill = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(&recovery.path)
+        .await
+        .map_err(CompressToS3Error::Spill)?;
+    // Anything after the last whole frame has to go, new frames follow on
+    // from there.
+    if !recovery.finished {
+        spill
+            .set_len(recovery.len)
+            .await
+            .map_err(CompressToS3Error::Spill)?;
+    }
+    let spill = Arc::new(Mutex::new(spill));
+
+    // What S3 doesn't have of the spill goes out again a part's worth at a
+    // time, all of it before anything new is written to the spill.
+    let rest = stream::try_unfold(uploaded, {
+        let spill = spill.clone();
+        let (end, chunk_len) = (recovery.len, config.part_size.max(1) as u64);
+        move |offset| {
+            let spill = spill.clone();
+            async move {
+                if offset >= end {
+                    return Ok::<_, io::Error>(None);
+                }
+                let mut chunk = vec![0; chunk_len.min(end - offset) as usize];
+                let mut spill = spill.lock().await;
+                spill.seek(SeekFrom::Start(offset)).await?;
+                spill.read_exact(&mut chunk).await?;
+                let next = offset + chunk.len() as u64;
+                Ok(Some((Bytes::from(chunk), next)))
+            }
+        }
+    })
+    .map_err(CompressToS3Error::Spill);
+    let data = match compress {
+        Some(compress) => Either::Left(rest.chain(spill_to(
+            compress.map_err(CompressToS3Error::Compress),
+            Some(spill.clone()),
+        ))),
+        None => Either::Right(rest),
+    };
+    let part_template = UploadPartRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        upload_id: upload_id.to_owned(),
+        ..Default::default()
+    };
+    let parts = data
+        .upload_parts(part_template, config.part_size)
+        .verify_part_md5(config.verify_part_md5)
+        .starting_at_part(completed.len() as i64 + 1);
+    let progress = parts.progress();
+    let e_tag = upload_and_complete(
+        parts,
+        client,
+        &bucket,
+        &key,
+        &upload_id,
+        config.concurrency,
+        completed,
+        uploaded,
+        &mut |_| {},
+    )
+    .await?;
+    truncate_spill(&spill).await?;
+
+    let mut report = progress.report(&compress_progress, e_tag);
+    report.original_len += recovery.decompressed_len();
+    report.compressed_len += uploaded;
+    if recovery.finished {
+        report.frames = recovery.seek_table.num_frames() as u64;
+    }
+    Ok(report)
+}
+
+// The ID of the multipart upload to `key` started last, if there's any.
+async fn latest_upload<A: S3, E>(
+    client: &A,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<String>, CompressToS3Error<E>> {
+    let mut latest: Option<MultipartUpload> = None;
+    let (mut key_marker, mut upload_id_marker) = (None, None);
+    loop {
+        let out = client
+            .list_multipart_uploads(ListMultipartUploadsRequest {
+                bucket: bucket.to_owned(),
+                prefix: Some(key.to_owned()),
+                key_marker,
+                upload_id_marker,
+                ..Default::default()
+            })
+            .await
+            .map_err(CompressToS3Error::ListUploads)?;
+        for upload in out.uploads.unwrap_or_default() {
+            // The prefix takes in longer keys too. Times are all in the same
+            // ISO 8601 format, so they compare as strings.
+            if upload.key.as_deref() == Some(key)
+                && latest
+                    .as_ref()
+                    .map_or(true, |latest| upload.initiated >= latest.initiated)
+            {
+                latest = Some(upload);
+            }
+        }
+        key_marker = out.next_key_marker;
+        upload_id_marker = out.next_upload_id_marker;
+        if out.is_truncated != Some(true) || (key_marker.is_none() && upload_id_marker.is_none()) {
+            break;
+        }
+    }
+    Ok(latest.and_then(|upload| upload.upload_id))
+}
+
+// The parts of the upload to keep, which are those numbered from 1 on with
+// no gaps that lie within the first `len` bytes, along with how many bytes
+// they hold.
+async fn uploaded_parts<A: S3, E>(
+    client: &A,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    len: u64,
+) -> Result<(Vec<CompletedPart>, u64), CompressToS3Error<E>> {
+    let mut parts = Vec::new();
+    let mut part_number_marker = None;
+    loop {
+        let out = client
+            .list_parts(ListPartsRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                upload_id: upload_id.to_owned(),
+                part_number_marker,
+                ..Default::default()
+            })
+            .await
+            .map_err(CompressToS3Error::ListParts)?;
+        parts.extend(out.parts.unwrap_or_default());
+        part_number_marker = out.next_part_number_marker;
+        if out.is_truncated != Some(true) || part_number_marker.is_none() {
+            break;
+        }
+    }
+    parts.sort_by_key(|part| part.part_number);
+
+    let mut completed = Vec::new();
+    let mut uploaded = 0;
+    for part in parts {
+        let size = part.size.unwrap_or_default() as u64;
+        // Parts past the end get uploaded again with new data, and anything
+        // that doesn't end up in the completed upload is dropped by S3.
+        if part.part_number != Some(completed.len() as i64 + 1) || uploaded + size > len {
+            break;
+        }
+        uploaded += size;
+        completed.push(CompletedPart {
+            e_tag: part.e_tag,
+            part_number: part.part_number,
+        });
+    }
+    Ok((completed, uploaded))
+}
@@ -0,0 +1,119 @@
+//! Helpers for checking that data survives a trip through this crate, for
+//! use in the tests of downstream crates.
+
+use crate::{Error, SeekableDecompress, StreamCompress};
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    io::{Cursor, Read, Seek, SeekFrom},
+};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RoundtripError {
+    Compress(zstd_seekable::Error),
+    Decompress(Error),
+    Read(std::io::Error),
+    // Reading at the offset didn't give back the original data.
+    Mismatch { offset: u64, len: usize },
+}
+
+impl Display for RoundtripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoundtripError::Compress(_) => write!(f, "Compression failed."),
+            RoundtripError::Decompress(_) => write!(f, "Decompression failed."),
+            RoundtripError::Read(_) => write!(f, "Read failed."),
+            RoundtripError::Mismatch { offset, len } => write!(
+                f,
+                "Reading {} bytes at offset {} didn't match the original data.",
+                len, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RoundtripError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RoundtripError::Compress(e) => Some(e),
+            RoundtripError::Decompress(e) => Some(e),
+            RoundtripError::Read(e) => Some(e),
+            RoundtripError::Mismatch { .. } => None,
+        }
+    }
+}
+
+/// Compresses `data` and reads it back at a bunch of offsets through
+/// [`SeekableDecompress`], checking everything matches the original. Ranges
+/// are picked pseudo-randomly but deterministically, so failures reproduce.
+/// Along with those we always read the whole thing, the first and last byte
+/// and past the end.
+pub fn roundtrip(
+    data: &[u8],
+    compression_level: i32,
+    frame_size: usize,
+) -> Result<(), RoundtripError> {
+    let compressed = compress(data, compression_level, frame_size)?;
+    let mut decompress =
+        SeekableDecompress::new(Cursor::new(compressed)).map_err(RoundtripError::Decompress)?;
+
+    let len = data.len() as u64;
+    let mut ranges = vec![
+        (0, data.len()),
+        (0, 1),
+        (len.saturating_sub(1), 1),
+        (len, 1),
+        (len + 1, 10),
+    ];
+    let mut state = len ^ 0x5DEE_CE66;
+    let mut random = |bound: u64| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) % bound.max(1)
+    };
+    for _ in 0..32 {
+        let offset = random(len);
+        let range_len = random(frame_size.max(1) as u64 * 3 + 1) as usize;
+        ranges.push((offset, range_len));
+    }
+
+    for (offset, range_len) in ranges {
+        decompress
+            .seek(SeekFrom::Start(offset))
+            .map_err(RoundtripError::Read)?;
+        let mut read = Vec::with_capacity(range_len);
+        (&mut decompress)
+            .take(range_len as u64)
+            .read_to_end(&mut read)
+            .map_err(RoundtripError::Read)?;
+        let start = (offset.min(len)) as usize;
+        let end = (start + range_len).min(data.len());
+        if read != data[start..end] {
+            return Err(RoundtripError::Mismatch {
+                offset,
+                len: range_len,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn compress(
+    data: &[u8],
+    compression_level: i32,
+    frame_size: usize,
+) -> Result<Vec<u8>, RoundtripError> {
+    let input = stream::iter(data.chunks(64 * 1024).map(Ok::<_, Infallible>));
+    let compress = input
+        .compress(compression_level, frame_size)
+        .map_err(RoundtripError::Compress)?;
+    let mut compressed = Vec::new();
+    for bytes in block_on_stream(Box::pin(compress)) {
+        let bytes = bytes.map_err(|e| RoundtripError::Compress(e.into()))?;
+        compressed.extend_from_slice(&bytes);
+    }
+    Ok(compressed)
+}
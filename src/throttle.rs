@@ -0,0 +1,117 @@
+use bytes::Bytes;
+use futures::{ready, stream::FusedStream, Stream};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+
+// Limits how fast a stream of bytes gets yielded.
+
+pub trait StreamThrottle {
+    /// Paces the stream so that on average it yields at most
+    /// `bytes_per_sec` bytes a second, using a token bucket. Up to a second's
+    /// worth can go out at once after a quiet spell, see
+    /// [`Throttle::burst`] to change that. Errors go out as soon as they
+    /// come.
+    ///
+    /// Waiting uses the tokio timer, so this needs to run on a tokio runtime
+    /// with time enabled.
+    fn throttle<E>(self, bytes_per_sec: u64) -> Throttle<Self>
+    where
+        Self: Stream<Item = Result<Bytes, E>> + Sized;
+}
+
+impl<S> StreamThrottle for S {
+    fn throttle<E>(self, bytes_per_sec: u64) -> Throttle<Self>
+    where
+        Self: Stream<Item = Result<Bytes, E>> + Sized,
+    {
+        Throttle {
+            stream: self,
+            bytes_per_sec: bytes_per_sec.max(1),
+            burst: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            refilled: Instant::now(),
+            delayed: None,
+        }
+    }
+}
+
+pin_project! {
+    #[derive(Debug)]
+    pub struct Throttle<S: Stream> {
+        #[pin]
+        stream: S,
+        bytes_per_sec: u64,
+        // Most tokens the bucket holds.
+        burst: f64,
+        // Bytes we can yield right now. Goes negative when we yield something
+        // bigger than what's in the bucket, which we then wait out.
+        tokens: f64,
+        refilled: Instant,
+        // Item we are holding back until the timer fires.
+        delayed: Option<(S::Item, Pin<Box<Sleep>>)>,
+    }
+}
+
+impl<S: Stream> Throttle<S> {
+    /// Lets up to `burst` bytes go out at once when the stream has been
+    /// quiet for a while.
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst as f64;
+        self.tokens = self.tokens.min(self.burst);
+        self
+    }
+}
+
+impl<S, E> Stream for Throttle<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if let Some((_, sleep)) = this.delayed {
+            ready!(sleep.as_mut().poll(cx));
+            return Poll::Ready(this.delayed.take().map(|(item, _)| item));
+        }
+
+        let item = match ready!(this.stream.poll_next(cx)) {
+            Some(Ok(bytes)) => bytes,
+            other => return Poll::Ready(other),
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(*this.refilled).as_secs_f64() * *this.bytes_per_sec as f64;
+        *this.tokens = (*this.tokens + refill).min(*this.burst);
+        *this.refilled = now;
+        *this.tokens -= item.len() as f64;
+        if *this.tokens >= 0.0 {
+            return Poll::Ready(Some(Ok(item)));
+        }
+
+        // We're in debt: hold on to the item until we've paid it off.
+        let wait = Duration::from_secs_f64(-*this.tokens / *this.bytes_per_sec as f64);
+        let mut sleep = Box::pin(tokio::time::sleep(wait));
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Ok(item))),
+            Poll::Pending => {
+                *this.delayed = Some((Ok(item), sleep));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S, E> FusedStream for Throttle<S>
+where
+    S: FusedStream<Item = Result<Bytes, E>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.delayed.is_none() && self.stream.is_terminated()
+    }
+}
@@ -0,0 +1,61 @@
+use futures::{Stream, TryStreamExt};
+use std::fmt::Display;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// What [`decompress_to_writer`] fails with.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WriteOutError<E> {
+    /// Getting the decompressed data failed.
+    Decompress(E),
+    /// Writing it out failed, or flushing at the end did.
+    Write(std::io::Error),
+}
+
+impl<E> Display for WriteOutError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteOutError::Decompress(_) => write!(f, "Decompression error."),
+            WriteOutError::Write(_) => write!(f, "Write error."),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for WriteOutError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteOutError::Decompress(e) => Some(e),
+            WriteOutError::Write(e) => Some(e),
+        }
+    }
+}
+
+/// Writes all of `stream`, chunks of decompressed data, into `writer` and
+/// flushes it, for the usual "download and write to disk" case. Each chunk is written in full before the next one is
+/// asked for, so a slow writer slows down decompression rather than having
+/// data pile up in memory.
+///
+/// Returns how many bytes were written. The writer isn't shut down, and on
+/// error it may have taken some of the data already.
+pub async fn decompress_to_writer<S, B, E, W>(
+    stream: S,
+    writer: &mut W,
+) -> Result<u64, WriteOutError<E>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    futures::pin_mut!(stream);
+    let mut written = 0;
+    while let Some(chunk) = stream.try_next().await.map_err(WriteOutError::Decompress)? {
+        let chunk = chunk.as_ref();
+        writer
+            .write_all(chunk)
+            .await
+            .map_err(WriteOutError::Write)?;
+        written += chunk.len() as u64;
+    }
+    writer.flush().await.map_err(WriteOutError::Write)?;
+    Ok(written)
+}
@@ -0,0 +1,63 @@
+use crate::{SeekTable, SeekTableError, DEFAULT_MAX_FRAMES};
+use std::{convert::TryFrom, ops::Range};
+
+// The decompressed length and frame count of an object in a skippable frame
+// right in front of the seek table, see Compress::totals_frame. After the
+// skippable frame header, the frame holds both as little endian u64s. The
+// frame count is the seek table's, this frame included.
+
+// Five off from the seek table's magic, one off from the trailing index's.
+const TOTALS_MAGIC: u32 = 0x184D_2A59;
+
+// Length of the whole frame, header included.
+pub(crate) const TOTALS_FRAME_LEN: usize = 24;
+
+pub(crate) fn totals_frame(decompressed_len: u64, frames: u64) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(TOTALS_FRAME_LEN);
+    frame.extend_from_slice(&TOTALS_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&16u32.to_le_bytes());
+    frame.extend_from_slice(&decompressed_len.to_le_bytes());
+    frame.extend_from_slice(&frames.to_le_bytes());
+    frame
+}
+
+// The decompressed length and frame count in the frame, None if it's not a
+// totals frame.
+pub(crate) fn parse_totals_frame(frame: &[u8]) -> Option<(u64, u64)> {
+    if frame.len() != TOTALS_FRAME_LEN || frame[..8] != totals_frame(0, 0)[..8] {
+        return None;
+    }
+    let decompressed_len = u64::from_le_bytes(<[u8; 8]>::try_from(&frame[8..16]).ok()?);
+    let frames = u64::from_le_bytes(<[u8; 8]>::try_from(&frame[16..]).ok()?);
+    Some((decompressed_len, frames))
+}
+
+/// Where the frame written by
+/// [`Compress::totals_frame`](crate::Compress::totals_frame) would be in an
+/// object of `object_len` bytes, going by its footer, the last
+/// [`SEEK_TABLE_FOOTER_LEN`](crate::SEEK_TABLE_FOOTER_LEN) bytes of it or
+/// more: the 24 bytes right in front of the seek table. Fetch those, say
+/// with a ranged GET, for [`quick_totals_from_frame`]. Fails as
+/// [`SeekTable::len_from_footer`] does, or if the object is too short to
+/// hold them and the seek table.
+pub fn quick_totals_range(footer: &[u8], object_len: u64) -> Result<Range<u64>, SeekTableError> {
+    let table_len = SeekTable::len_from_footer(footer)?;
+    let needed = table_len + TOTALS_FRAME_LEN;
+    match object_len.checked_sub(needed as u64) {
+        Some(start) => Ok(start..start + TOTALS_FRAME_LEN as u64),
+        None => Err(SeekTableError::TooShort {
+            needed,
+            got: usize::try_from(object_len).unwrap_or(usize::MAX),
+        }),
+    }
+}
+
+/// The decompressed length and the number of frames of an object from the
+/// bytes at [`quick_totals_range`], along with the object's `footer`. None if
+/// they're not a totals frame.
+pub fn quick_totals_from_frame(frame: &[u8], footer: &[u8]) -> Option<(u64, u64)> {
+    let (frames, _, _) = SeekTable::parse_footer(footer, DEFAULT_MAX_FRAMES).ok()?;
+    // Data that happens to look like a totals frame won't have the frame
+    // count right too.
+    parse_totals_frame(frame).filter(|&(_, n)| n == frames as u64)
+}
@@ -0,0 +1,25 @@
+use std::convert::TryFrom;
+
+// An application's index stored in a skippable frame after the data, see
+// Compress::trailing_index. The frame holds the index as given, we don't
+// look inside it.
+
+// Four off from the seek table's magic, one off from the metadata's.
+const TRAILING_INDEX_MAGIC: u32 = 0x184D_2A5A;
+
+// Wraps the index in a skippable frame, as long as it fits in one.
+pub(crate) fn trailing_index_frame(index: &[u8]) -> Option<Vec<u8>> {
+    let mut frame = Vec::with_capacity(index.len() + 8);
+    frame.extend_from_slice(&TRAILING_INDEX_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&u32::try_from(index.len()).ok()?.to_le_bytes());
+    frame.extend_from_slice(index);
+    Some(frame)
+}
+
+// The length of the index held in the frame, going by its 8 byte header,
+// None if it's not a trailing index frame of `frame_len` bytes.
+pub(crate) fn trailing_index_len(header: &[u8], frame_len: usize) -> Option<usize> {
+    let magic = u32::from_le_bytes(<[u8; 4]>::try_from(header.get(..4)?).ok()?);
+    let len = u32::from_le_bytes(<[u8; 4]>::try_from(header.get(4..8)?).ok()?) as usize;
+    (magic == TRAILING_INDEX_MAGIC && len + 8 == frame_len).then_some(len)
+}
@@ -0,0 +1,111 @@
+use crate::{Compress, CompressError, StreamCompress};
+use bytes::Bytes;
+use futures::{
+    executor::{block_on_stream, BlockingStream},
+    stream::{self, Iter},
+};
+use std::{
+    io::{ErrorKind, Read},
+    pin::Pin,
+};
+use zstd_seekable::CStream;
+
+/// Recompresses whatever `reader` decompresses to, typically a
+/// [`SeekableDecompress`](crate::SeekableDecompress), into a fresh seekable
+/// object with frames of `new_frame_size` compressed at `new_level`. Use it
+/// to change the layout of an object once the way it's read changes.
+///
+/// Data is streamed through, so only about a frame of it is held in memory
+/// whatever the size of the object. The new object comes as an iterator
+/// that reads from `reader` as it goes and blocks, so keep it off async
+/// runtimes' worker threads, and off the runtime
+/// [`SeekableS3Object`](crate::SeekableS3Object) runs its requests on in
+/// particular.
+pub fn transcode<R: Read>(
+    reader: R,
+    new_frame_size: usize,
+    new_level: i32,
+) -> Result<Transcode<R>, zstd_seekable::Error> {
+    let chunks = stream::iter(ReadChunks::new(reader));
+    Ok(Transcode {
+        compress: block_on_stream(Box::pin(chunks.compress(new_level, new_frame_size)?)),
+    })
+}
+
+/// The recompressed object, see [`transcode`].
+pub struct Transcode<R: Read> {
+    // Only ever polled here, where blocking is what we want.
+    compress: BlockingStream<Pin<Box<ReadCompress<R>>>>,
+}
+
+type ReadCompress<R> = Compress<Iter<ReadChunks<R>>, std::io::Error>;
+
+impl<R: Read> std::fmt::Debug for Transcode<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transcode").finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> Iterator for Transcode<R> {
+    type Item = Result<Bytes, CompressError<std::io::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.compress.next()
+    }
+}
+
+// What a reader reads, in chunks of the size zstd likes its input in.
+struct ReadChunks<R> {
+    reader: R,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl<R> ReadChunks<R> {
+    fn new(reader: R) -> Self {
+        ReadChunks {
+            reader,
+            chunk_size: CStream::in_size(),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> ReadChunks<R> {
+    // Reads a whole chunk unless the reader runs out first.
+    fn read_chunk(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut chunk = vec![0; self.chunk_size];
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match self.reader.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        chunk.truncate(filled);
+        Ok(chunk)
+    }
+}
+
+impl<R: Read> Iterator for ReadChunks<R> {
+    type Item = std::io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_chunk() {
+            Ok(chunk) if chunk.is_empty() => {
+                self.done = true;
+                None
+            }
+            Ok(chunk) => Some(Ok(Bytes::from(chunk))),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    decompress::read_seek_table, Error, FrameCompressor, SeekTable, SeekableDecompress,
+    ZstdFrameCompressor,
+};
+use bytes::Bytes;
+use std::{convert::TryFrom, io::Cursor};
+use xxhash_rust::xxh64::Xxh64;
+use zstd_seekable::CStream;
+
+/// Cuts the seekable object `input` down to the first `len` bytes of its
+/// data, for keeping the start of a log and dropping the rest. Like
+/// [`Vec::truncate`], this does nothing if the data is no longer than that.
+///
+/// Frames that end by `len` are copied over as they are, and the seek table
+/// is rewritten for them. Only the frame `len` falls in the middle of, if
+/// there is one, gets decompressed and what's kept of it compressed again at
+/// `compression_level`, into a single frame. That costs about as much as
+/// compressing a frame from scratch, so with large frames it's most of the
+/// work, but it's the same for any size of object. Frames without data past
+/// `len`, such as a trailing index or totals, are dropped: they'd be out of
+/// date. Metadata at the start is kept. Cutting to nothing leaves a single
+/// empty frame, as compressing nothing does.
+///
+/// Fails if `input` isn't a seekable object, the boundary frame doesn't
+/// decompress or doesn't match its checksum, or the level is out of range.
+pub fn truncate_seekable(input: &[u8], len: u64, compression_level: i32) -> Result<Vec<u8>, Error> {
+    let mut decompress = SeekableDecompress::new(Cursor::new(input))?;
+    // Read ourselves rather than through zstd_seekable, which leaves the
+    // checksums out.
+    let table = read_seek_table(&mut Cursor::new(input))?;
+    if len >= table.decompressed_len() {
+        return Ok(input.to_vec());
+    }
+
+    let mut new_table = SeekTable::new(table.has_checksums());
+    let mut frame = 0;
+    // Frames ending by `len`, along with frames without data before the
+    // first one with some.
+    while frame < table.num_frames() {
+        let range = table.frame_range(frame);
+        if range.end > len || (range.start == len && range.end != 0) {
+            break;
+        }
+        new_table.push_frame(
+            table.frame_compressed_size(frame) as u32,
+            (range.end - range.start) as u32,
+            table.frame_checksum(frame).unwrap_or(0),
+        );
+        frame += 1;
+    }
+    let kept = table.frame_compressed_offset(frame) as usize;
+    let mut output = Vec::with_capacity(kept + new_table.seek_table_len());
+    output.extend_from_slice(&input[..kept]);
+
+    let start = table.frame_decompressed_offset(frame);
+    // With no data kept, there's still an empty frame, as when compressing
+    // nothing at all.
+    if start < len || len == 0 {
+        let data = if start < len {
+            decompress
+                .read_frame(frame)?
+                .slice(..(len - start) as usize)
+        } else {
+            Bytes::new()
+        };
+        let data = &data[..];
+        let frame_start = output.len();
+        compress_frame(data, compression_level, &mut output).map_err(Error::ZstdSeekable)?;
+        let compressed_size =
+            u32::try_from(output.len() - frame_start).map_err(Error::FrameTooLarge)?;
+        let mut hasher = Xxh64::new(0);
+        hasher.update(data);
+        new_table.push_frame(compressed_size, data.len() as u32, hasher.digest() as u32);
+    }
+
+    output.extend_from_slice(&new_table.to_bytes());
+    Ok(output)
+}
+
+// Compresses `data` into a single frame at the end of `output`.
+fn compress_frame(
+    mut data: &[u8],
+    compression_level: i32,
+    output: &mut Vec<u8>,
+) -> Result<(), zstd_seekable::Error> {
+    let mut compressor = ZstdFrameCompressor::new(compression_level)?;
+    let mut buf = vec![0; CStream::out_size()];
+    while !data.is_empty() {
+        let (out_pos, in_pos) = compressor.compress(&mut buf, data)?;
+        output.extend_from_slice(&buf[..out_pos]);
+        data = &data[in_pos..];
+    }
+    loop {
+        let (out_pos, remaining) = compressor.end_frame(&mut buf)?;
+        output.extend_from_slice(&buf[..out_pos]);
+        if remaining == 0 {
+            return Ok(());
+        }
+    }
+}
@@ -1,13 +1,24 @@
-use std::{marker::PhantomData, pin::Pin};
+use crate::CompressProgress;
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use bytes::{BufMut, BytesMut};
 use futures::{
     ready,
     stream::{FusedStream, Stream},
 };
+use md5::Digest;
 use pin_project_lite::pin_project;
 use rusoto_core::ByteStream;
 use rusoto_s3::UploadPartRequest;
+use tokio::sync::watch;
 
 // Uploads a stream of data.
 
@@ -36,6 +47,112 @@ impl<S> StreamUploadParts for S {
     }
 }
 
+/// How many parts and bytes were cut for upload so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PartsProgress {
+    pub parts: u64,
+    pub bytes: u64,
+}
+
+/// Handle for observing an [`UploadParts`] stream from elsewhere, for example
+/// from the producer feeding it, which can then slow down or shed load.
+///
+/// This only sees what the stream itself holds on to. Parts that were
+/// yielded but are still being uploaded are in the hands of whatever does the
+/// uploading: with a `buffered`/`buffer_unordered` upload stage, that's at
+/// most its concurrency limit worth of parts.
+#[derive(Debug, Clone)]
+pub struct UploadProgress {
+    inner: Arc<ProgressInner>,
+}
+
+#[derive(Debug)]
+struct ProgressInner {
+    buffered_bytes: AtomicU64,
+    parts: watch::Sender<PartsProgress>,
+    started: Instant,
+}
+
+/// Summary of an upload, see [`UploadProgress::report`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadReport {
+    /// Size of the data before compression.
+    pub original_len: u64,
+    /// Size of the uploaded object.
+    pub compressed_len: u64,
+    pub frames: u64,
+    /// Time since the upload stream was made.
+    pub elapsed: Duration,
+    /// Whatever completing the multipart upload gave back.
+    pub e_tag: Option<String>,
+}
+
+impl UploadReport {
+    /// How many times smaller the data got, 0 if it was empty.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_len == 0 {
+            0.0
+        } else {
+            self.original_len as f64 / self.compressed_len as f64
+        }
+    }
+}
+
+impl UploadProgress {
+    fn new() -> Self {
+        UploadProgress {
+            inner: Arc::new(ProgressInner {
+                buffered_bytes: AtomicU64::new(0),
+                parts: watch::channel(PartsProgress::default()).0,
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    /// Compressed bytes taken from upstream and buffered until there's enough
+    /// of them for a part. Only this buffer is counted: parts already cut are
+    /// in [`parts`](Self::parts) whether or not they're uploaded yet.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.inner.buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Parts cut so far.
+    pub fn parts(&self) -> PartsProgress {
+        *self.inner.parts.borrow()
+    }
+
+    /// Get notified every time a part is cut.
+    pub fn subscribe(&self) -> watch::Receiver<PartsProgress> {
+        self.inner.parts.subscribe()
+    }
+
+    /// Sums up the upload for once it's done, given the progress of the
+    /// [`Compress`](crate::Compress) stream feeding it. Pass in the ETag
+    /// `CompleteMultipartUpload` gave back if you want it in there.
+    pub fn report(&self, compress: &CompressProgress, e_tag: Option<String>) -> UploadReport {
+        UploadReport {
+            original_len: compress.bytes_in(),
+            compressed_len: self.parts().bytes + self.buffered_bytes(),
+            frames: compress.frames(),
+            elapsed: self.inner.started.elapsed(),
+            e_tag,
+        }
+    }
+
+    fn set_buffered_bytes(&self, buffered_bytes: usize) {
+        self.inner
+            .buffered_bytes
+            .store(buffered_bytes as u64, Ordering::Relaxed);
+    }
+
+    fn part_cut(&self, part_len: usize) {
+        let mut progress = self.parts();
+        progress.parts += 1;
+        progress.bytes += part_len as u64;
+        self.inner.parts.send_replace(progress);
+    }
+}
+
 // Chunk into parts for upload.
 pin_project! {
     pub struct UploadParts<S, E> {
@@ -46,6 +163,8 @@ pin_project! {
         finished: bool,
         part_template: UploadPartRequest,
         minimum_part_size: usize,
+        verify_part_md5: bool,
+        progress: UploadProgress,
         error_type: PhantomData<E>,
     }
 }
@@ -62,10 +181,34 @@ impl<S, E> UploadParts<S, E> {
             finished: false,
             part_template,
             minimum_part_size,
+            verify_part_md5: false,
+            progress: UploadProgress::new(),
             error_type: PhantomData,
         }
     }
 
+    // Numbers parts from `part_number` on, for carrying on an upload that
+    // has parts already.
+    pub(crate) fn starting_at_part(mut self, part_number: i64) -> Self {
+        self.next_part_number = part_number;
+        self
+    }
+
+    /// Sends the MD5 of every part along with it as its Content-MD5, so that
+    /// S3 turns down parts that got mangled on the way rather than putting
+    /// them in the object. Off by default as it takes hashing every part,
+    /// in which case parts get whatever Content-MD5 the part template has.
+    pub fn verify_part_md5(mut self, verify_part_md5: bool) -> Self {
+        self.verify_part_md5 = verify_part_md5;
+        self
+    }
+
+    /// A handle to observe how far along we are. Grab it before handing the
+    /// stream off.
+    pub fn progress(&self) -> UploadProgress {
+        self.progress.clone()
+    }
+
     fn next_input(
         self: &mut Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -84,13 +227,18 @@ impl<S, E> UploadParts<S, E> {
         let this = self.as_mut().project();
         let buffer: &mut BytesMut = this.input;
         let part_template: &UploadPartRequest = this.part_template;
+        let content_md5 = if *this.verify_part_md5 {
+            Some(base64::encode(md5::Md5::digest(&buffer[..])))
+        } else {
+            part_template.content_md5.to_owned()
+        };
         let req = UploadPartRequest {
             body: Some(ByteStream::from(Vec::from(&buffer[..]))),
             bucket: part_template.bucket.to_owned(),
-            // As we're going through Vec in body, the size hint is set and
-            // rusoto fills in content_length by itself.
-            content_length: None,
-            content_md5: part_template.content_md5.to_owned(),
+            // rusoto would fill this in by itself from the body, but then
+            // there'd be no telling how big the part is once it's yielded.
+            content_length: Some(buffer.len() as i64),
+            content_md5,
             expected_bucket_owner: part_template.expected_bucket_owner.to_owned(),
             key: part_template.key.to_owned(),
             part_number: *this.next_part_number,
@@ -102,6 +250,8 @@ impl<S, E> UploadParts<S, E> {
         };
         // Next part we make should have new number.
         *this.next_part_number += 1;
+        this.progress.part_cut(buffer.len());
+        this.progress.set_buffered_bytes(0);
         // Clear the buffer, we copied the data we wanted now and future inputs
         // should fill it from the start.
         buffer.clear();
@@ -112,6 +262,7 @@ impl<S, E> UploadParts<S, E> {
         let this = self.as_mut().project();
         let buffer: &mut BytesMut = this.input;
         buffer.put(input);
+        this.progress.set_buffered_bytes(buffer.len());
         // After combining whatever we had with new input, if we have enough for a part, yield one.
         if buffer.len() >= *this.minimum_part_size {
             Some(self.part_from_buffer())
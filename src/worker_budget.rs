@@ -0,0 +1,77 @@
+use parking_lot::{Condvar, Mutex};
+use std::sync::Arc;
+
+/// A limit on how many frames get decompressed at once, shared between every
+/// reader it's given to with
+/// [`SeekableDecompress::set_worker_budget`](crate::SeekableDecompress::set_worker_budget),
+/// for services running many readers at a time that shouldn't have each of
+/// them take on `concurrency` frames of its own.
+///
+/// Clones share the same budget. With a budget, each call to
+/// [`decompress_all_parallel`](crate::SeekableDecompress::decompress_all_parallel)
+/// or [`verify_all`](crate::SeekableDecompress::verify_all) still uses up
+/// to `concurrency` threads, but no more than the budget allows, and those
+/// wait their turn for every frame they decompress. Without one, as by
+/// default, each call goes by its own `concurrency`.
+#[derive(Clone)]
+pub struct WorkerBudget {
+    inner: Arc<BudgetInner>,
+}
+
+struct BudgetInner {
+    workers: usize,
+    busy: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl std::fmt::Debug for WorkerBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerBudget")
+            .field("workers", &self.inner.workers)
+            .field("busy", &self.busy())
+            .finish()
+    }
+}
+
+impl WorkerBudget {
+    /// A budget of `workers` frames at a time, at least 1.
+    pub fn new(workers: usize) -> Self {
+        WorkerBudget {
+            inner: Arc::new(BudgetInner {
+                workers: workers.max(1),
+                busy: Mutex::new(0),
+                freed: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.inner.workers
+    }
+
+    /// How many frames are being decompressed under the budget right now.
+    pub fn busy(&self) -> usize {
+        *self.inner.busy.lock()
+    }
+
+    // Waits for a worker to be free, taking it until the permit is dropped.
+    pub(crate) fn acquire(&self) -> WorkerPermit<'_> {
+        let mut busy = self.inner.busy.lock();
+        while *busy >= self.inner.workers {
+            self.inner.freed.wait(&mut busy);
+        }
+        *busy += 1;
+        WorkerPermit { budget: self }
+    }
+}
+
+pub(crate) struct WorkerPermit<'b> {
+    budget: &'b WorkerBudget,
+}
+
+impl Drop for WorkerPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.inner.busy.lock() -= 1;
+        self.budget.inner.freed.notify_one();
+    }
+}
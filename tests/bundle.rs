@@ -0,0 +1,47 @@
+mod common;
+
+use common::{compress, decompress_all, lines, noise};
+use std::io::{Cursor, Read};
+use zstd_seekable_s3::{bundle_entries, BundleError, SeekableDecompress};
+
+#[test]
+fn bundle_splits_into_objects() {
+    let contents = vec![lines(3000), noise(20_000, 1), Vec::new(), lines(10)];
+    let objects: Vec<_> = contents
+        .iter()
+        .map(|data| compress(data, 1, 4096))
+        .collect();
+    let bundle = objects.concat();
+
+    let entries = bundle_entries(&mut Cursor::new(&bundle)).unwrap();
+    assert_eq!(entries.len(), contents.len());
+    let mut offset = 0;
+    for ((entry, object), data) in entries.iter().zip(&objects).zip(&contents) {
+        assert_eq!(entry.offset, offset);
+        assert_eq!(entry.len, object.len() as u64);
+        assert_eq!(entry.seek_table.decompressed_len(), data.len() as u64);
+        offset += entry.len;
+
+        let mut decompress = SeekableDecompress::new(entry.reader(Cursor::new(&bundle))).unwrap();
+        let mut decompressed = Vec::new();
+        decompress.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(&decompressed, data);
+    }
+
+    // A single object is a bundle of one.
+    let entries = bundle_entries(&mut Cursor::new(&objects[0])).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(decompress_all(objects[0].clone()), contents[0]);
+}
+
+#[test]
+fn bundle_rejects_stray_bytes() {
+    let object = compress(&lines(100), 1, 4096);
+    let mut bundle = b"not an object".to_vec();
+    bundle.extend_from_slice(&object);
+    bundle.extend_from_slice(&object);
+    match bundle_entries(&mut Cursor::new(&bundle)) {
+        Err(BundleError::NoSeekTable { end, .. }) => assert_eq!(end, 13),
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,357 @@
+// An S3 of our own, in memory, for testing S3 code without a server. It
+// only knows the requests the crate makes and doesn't check much of them:
+// there's a single bucket, parts can be any size, and a GET with a Range
+// gives just that range.
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use rusoto_core::{
+    request::{DispatchSignedRequestFuture, HttpResponse},
+    signature::{SignedRequest, SignedRequestPayload},
+    ByteStream, DispatchSignedRequest, HttpDispatchError, Region,
+};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::S3Client;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Clone, Default)]
+pub struct FakeS3 {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    objects: HashMap<String, Bytes>,
+    // Parts of every multipart upload in progress by upload ID, with the key
+    // they're for.
+    uploads: BTreeMap<String, (String, BTreeMap<i64, Bytes>)>,
+    next_upload: usize,
+    // Method, key and query of every request, in order.
+    requests: Vec<(String, String, BTreeMap<String, Option<String>>)>,
+    // UploadParts of this part number fail.
+    failing_part: Option<i64>,
+    // How long UploadParts of each part number take, and how many took
+    // their time at once so far, at most.
+    part_delay: Option<fn(i64) -> Duration>,
+    parts_in_flight: usize,
+    max_parts_in_flight: usize,
+}
+
+impl FakeS3 {
+    pub fn client(&self) -> S3Client {
+        S3Client::new_with(
+            self.clone(),
+            StaticProvider::new_minimal("key".to_owned(), "secret".to_owned()),
+            Region::Custom {
+                name: "us-east-1".to_owned(),
+                endpoint: "http://fake-s3".to_owned(),
+            },
+        )
+    }
+
+    pub fn put_object(&self, key: &str, data: impl Into<Bytes>) {
+        let mut state = self.state.lock().unwrap();
+        state.objects.insert(key.to_owned(), data.into());
+    }
+
+    pub fn object(&self, key: &str) -> Option<Bytes> {
+        self.state.lock().unwrap().objects.get(key).cloned()
+    }
+
+    // Starts a multipart upload to `key` with the given parts already in,
+    // giving the upload ID.
+    pub fn start_upload(&self, key: &str, parts: &[(i64, &[u8])]) -> String {
+        let mut state = self.state.lock().unwrap();
+        let upload_id = format!("upload-{}", state.next_upload);
+        state.next_upload += 1;
+        let parts = parts
+            .iter()
+            .map(|&(number, data)| (number, Bytes::copy_from_slice(data)))
+            .collect();
+        state
+            .uploads
+            .insert(upload_id.clone(), (key.to_owned(), parts));
+        upload_id
+    }
+
+    pub fn uploads_in_progress(&self) -> usize {
+        self.state.lock().unwrap().uploads.len()
+    }
+
+    // Part numbers of every UploadPart so far, in order.
+    pub fn uploaded_part_numbers(&self) -> Vec<i64> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|(method, _, params)| method == "PUT" && params.contains_key("partNumber"))
+            .map(|(_, _, params)| param(params, "partNumber").parse().unwrap())
+            .collect()
+    }
+
+    // Makes every UploadPart of part `number` fail.
+    pub fn fail_part(&self, number: i64) {
+        self.state.lock().unwrap().failing_part = Some(number);
+    }
+
+    // Makes UploadParts take `delay(part_number)` before they're answered.
+    pub fn delay_parts(&self, delay: fn(i64) -> Duration) {
+        self.state.lock().unwrap().part_delay = Some(delay);
+    }
+
+    // Most UploadParts that were being delayed at once.
+    pub fn max_parts_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_parts_in_flight
+    }
+
+    // GETs with a Range so far.
+    pub fn ranged_gets(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|(method, _, params)| method == "GET" && params.contains_key("range"))
+            .count()
+    }
+
+    // Ranges of every GET with one so far, in order, as (start, end) with
+    // the end exclusive and as asked for, whatever the object's length.
+    pub fn ranges(&self) -> Vec<(u64, u64)> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|(method, _, _)| method == "GET")
+            .filter_map(|(_, _, params)| params.get("range").cloned().flatten())
+            .map(|range| {
+                let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+                let end = end.parse::<u64>().map_or(u64::MAX, |end| end + 1);
+                (start.parse().unwrap(), end)
+            })
+            .collect()
+    }
+
+    // Keys of every GET of an object so far, in order.
+    pub fn got_keys(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|(method, _, params)| {
+                method == "GET"
+                    && !params.contains_key("uploads")
+                    && !params.contains_key("uploadId")
+            })
+            .map(|(_, key, _)| key.clone())
+            .collect()
+    }
+
+    fn handle(
+        &self,
+        request: &SignedRequest,
+        body: Bytes,
+    ) -> (u16, Vec<(&'static str, String)>, Bytes) {
+        let mut state = self.state.lock().unwrap();
+        let mut params = request.params.clone();
+        let range = request
+            .headers
+            .get("range")
+            .map(|values| String::from_utf8(values[0].clone()).unwrap());
+        if let Some(range) = &range {
+            params.insert("range".to_owned(), Some(range.clone()));
+        }
+        // Paths are /bucket/key.
+        let key = request.path.splitn(3, '/').nth(2).unwrap_or("").to_owned();
+        state
+            .requests
+            .push((request.method.clone(), key.clone(), params.clone()));
+        let upload_id = params.get("uploadId").cloned().flatten();
+
+        match (request.method.as_str(), upload_id) {
+            ("GET", None) if params.contains_key("uploads") => {
+                let prefix = param(&params, "prefix");
+                let mut uploads = String::new();
+                for (id, (upload_key, _)) in &state.uploads {
+                    if upload_key.starts_with(&prefix) {
+                        uploads.push_str(&format!(
+                            "<Upload><Key>{}</Key><UploadId>{}</UploadId>\
+                             <Initiated>2026-01-01T00:00:00.000Z</Initiated></Upload>",
+                            upload_key, id
+                        ));
+                    }
+                }
+                xml(format!(
+                    "<ListMultipartUploadsResult><IsTruncated>false</IsTruncated>{}\
+                     </ListMultipartUploadsResult>",
+                    uploads
+                ))
+            }
+            ("GET", Some(upload_id)) => {
+                let mut parts = String::new();
+                for (number, data) in &state.uploads[&upload_id].1 {
+                    parts.push_str(&format!(
+                        "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag>\
+                         <Size>{}</Size></Part>",
+                        number,
+                        number,
+                        data.len()
+                    ));
+                }
+                xml(format!(
+                    "<ListPartsResult><IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+                    parts
+                ))
+            }
+            ("GET", None) => match state.objects.get(&key) {
+                Some(object) => {
+                    let data = match range {
+                        Some(range) => {
+                            let (start, end) =
+                                range.trim_start_matches("bytes=").split_once('-').unwrap();
+                            let start: usize = start.parse().unwrap();
+                            let end = end
+                                .parse::<usize>()
+                                .map_or(object.len() - 1, |end| end.min(object.len() - 1));
+                            object.slice(start..end + 1)
+                        }
+                        None => object.clone(),
+                    };
+                    (200, vec![("Content-Length", data.len().to_string())], data)
+                }
+                None => (
+                    404,
+                    vec![],
+                    Bytes::from_static(b"<Error><Code>NoSuchKey</Code></Error>"),
+                ),
+            },
+            ("HEAD", None) => match state.objects.get(&key) {
+                Some(object) => (
+                    200,
+                    vec![("Content-Length", object.len().to_string())],
+                    Bytes::new(),
+                ),
+                None => (404, vec![], Bytes::new()),
+            },
+            ("PUT", None) => {
+                state.objects.insert(key, body);
+                (200, vec![("ETag", "\"object\"".to_owned())], Bytes::new())
+            }
+            ("PUT", Some(upload_id)) => {
+                let number = param(&params, "partNumber").parse().unwrap();
+                if state.failing_part == Some(number) {
+                    return (
+                        500,
+                        vec![],
+                        Bytes::from_static(b"<Error><Code>InternalError</Code></Error>"),
+                    );
+                }
+                state
+                    .uploads
+                    .get_mut(&upload_id)
+                    .unwrap()
+                    .1
+                    .insert(number, body);
+                (200, vec![("ETag", format!("\"{}\"", number))], Bytes::new())
+            }
+            ("POST", None) => {
+                let upload_id = format!("upload-{}", state.next_upload);
+                state.next_upload += 1;
+                state
+                    .uploads
+                    .insert(upload_id.clone(), (key.clone(), BTreeMap::new()));
+                xml(format!(
+                    "<InitiateMultipartUploadResult><Key>{}</Key><UploadId>{}</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                    key, upload_id
+                ))
+            }
+            ("POST", Some(upload_id)) => {
+                // Only the listed parts make it into the object, in order.
+                let (_, mut parts) = state.uploads.remove(&upload_id).unwrap();
+                let listed = String::from_utf8(body.to_vec()).unwrap();
+                let mut object = Vec::new();
+                for number in listed.split("<PartNumber>").skip(1) {
+                    let number: i64 = number.split('<').next().unwrap().parse().unwrap();
+                    object.extend_from_slice(&parts.remove(&number).unwrap());
+                }
+                state.objects.insert(key, Bytes::from(object));
+                xml("<CompleteMultipartUploadResult><ETag>\"complete\"</ETag>\
+                     </CompleteMultipartUploadResult>"
+                    .to_owned())
+            }
+            ("DELETE", Some(upload_id)) => {
+                state.uploads.remove(&upload_id);
+                (204, vec![], Bytes::new())
+            }
+            ("DELETE", None) => {
+                state.objects.remove(&key);
+                (204, vec![], Bytes::new())
+            }
+            (method, _) => panic!("FakeS3 doesn't know {} {}", method, request.path),
+        }
+    }
+}
+
+fn param(params: &BTreeMap<String, Option<String>>, name: &str) -> String {
+    params.get(name).cloned().flatten().unwrap_or_default()
+}
+
+fn xml(body: String) -> (u16, Vec<(&'static str, String)>, Bytes) {
+    (200, vec![], Bytes::from(body))
+}
+
+impl DispatchSignedRequest for FakeS3 {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let fake = self.clone();
+        Box::pin(async move {
+            let mut request = request;
+            let body = match request.payload.take() {
+                None => Bytes::new(),
+                Some(SignedRequestPayload::Buffer(bytes)) => bytes,
+                Some(SignedRequestPayload::Stream(stream)) => {
+                    let chunks: Vec<Bytes> = stream
+                        .try_collect()
+                        .await
+                        .map_err(|e| HttpDispatchError::new(e.to_string()))?;
+                    Bytes::from(chunks.concat())
+                }
+            };
+            let part_number = request
+                .params
+                .get("partNumber")
+                .cloned()
+                .flatten()
+                .map(|number| number.parse().unwrap());
+            let delay = fake.state.lock().unwrap().part_delay;
+            if let (Some(number), Some(delay)) = (part_number, delay) {
+                {
+                    let mut state = fake.state.lock().unwrap();
+                    state.parts_in_flight += 1;
+                    state.max_parts_in_flight =
+                        state.max_parts_in_flight.max(state.parts_in_flight);
+                }
+                tokio::time::sleep(delay(number)).await;
+                fake.state.lock().unwrap().parts_in_flight -= 1;
+            }
+            let (status, headers, body) = fake.handle(&request, body);
+            let mut header_map = http::HeaderMap::<String>::default();
+            for (name, value) in headers {
+                header_map.insert(name, value);
+            }
+            Ok(HttpResponse {
+                status: http::StatusCode::from_u16(status).unwrap(),
+                body: ByteStream::from(body.to_vec()),
+                headers: header_map,
+            })
+        })
+    }
+}
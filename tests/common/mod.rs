@@ -0,0 +1,70 @@
+#![allow(dead_code)]
+
+pub mod fake_s3;
+
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    io::{Cursor, Read},
+};
+use zstd_seekable_s3::{SeekableDecompress, StreamCompress};
+
+// Some easily compressible line-based data.
+pub fn lines(num_lines: usize) -> Vec<u8> {
+    (0..num_lines)
+        .flat_map(|i| format!("This is line {}.\n", i).into_bytes())
+        .collect()
+}
+
+// Compresses data fed in as chunks of the given size.
+pub fn compress_chunked(
+    data: &[u8],
+    chunk_size: usize,
+    compression_level: i32,
+    frame_size: usize,
+) -> Vec<u8> {
+    let chunks = stream::iter(data.chunks(chunk_size).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(compression_level, frame_size).unwrap();
+    block_on_stream(Box::pin(compress))
+        .map(|bytes| bytes.map_err(zstd_seekable::Error::from).unwrap())
+        .fold(Vec::new(), |mut compressed, bytes| {
+            compressed.extend_from_slice(&bytes);
+            compressed
+        })
+}
+
+pub fn compress(data: &[u8], compression_level: i32, frame_size: usize) -> Vec<u8> {
+    compress_chunked(data, 100, compression_level, frame_size)
+}
+
+pub fn decompress_all(compressed: Vec<u8>) -> Vec<u8> {
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    let mut out = Vec::new();
+    decompress.read_to_end(&mut out).unwrap();
+    out
+}
+
+// Data that doesn't repeat, so content-defined boundaries don't either.
+pub fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            // Keep it to a few letters so it still compresses a bit.
+            b'a' + ((state >> 59) as u8 % 8)
+        })
+        .collect()
+}
+
+// Compressed bytes of every frame. The seek table is left out.
+pub fn frames(compressed: &[u8]) -> Vec<&[u8]> {
+    let table = zstd_seekable_s3::SeekTable::parse(compressed).unwrap();
+    (0..table.num_frames())
+        .map(|frame| {
+            let start = table.frame_compressed_offset(frame) as usize;
+            &compressed[start..start + table.frame_compressed_size(frame) as usize]
+        })
+        .collect()
+}
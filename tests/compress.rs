@@ -0,0 +1,915 @@
+mod common;
+
+use common::{compress, decompress_all, lines};
+use futures::{
+    executor::{block_on, block_on_stream},
+    stream, StreamExt, TryStreamExt,
+};
+use rusoto_s3::UploadPartRequest;
+use std::{convert::Infallible, io::Cursor, time::Duration};
+use zstd_seekable_s3::{
+    compress_blocking_into, BlockingCompress, CompressError, SeekTable, SeekableDecompress,
+    StreamCompress, StreamUploadParts, DEFAULT_POLL_BUDGET,
+};
+
+// Yields the input in small chunks and then errors out half way through.
+fn failing_upstream(data: &[u8]) -> impl futures::Stream<Item = Result<Vec<u8>, String>> + '_ {
+    let (good, _) = data.split_at(data.len() / 2);
+    stream::iter(
+        good.chunks(100)
+            .map(|c| Ok(c.to_vec()))
+            .chain(std::iter::once(Err("upstream went away".to_owned()))),
+    )
+}
+
+#[test]
+fn upstream_error_passes_through() {
+    let data = lines(2000);
+    let compress = failing_upstream(&data).compress(1, 1024).unwrap();
+    let mut items = block_on_stream(Box::pin(compress));
+
+    // Without finalize_on_error, what we have up to the error has no seek
+    // table.
+    let mut compressed = Vec::new();
+    let error = loop {
+        match items.next() {
+            Some(Ok(bytes)) => compressed.extend_from_slice(&bytes),
+            Some(Err(CompressError::Underlying(e))) => break e,
+            Some(Err(e)) => panic!("unexpected error: {}", e),
+            None => panic!("stream ended without passing on the upstream error"),
+        }
+    };
+    assert_eq!(error, "upstream went away");
+    assert!(SeekableDecompress::new(Cursor::new(compressed)).is_err());
+}
+
+#[test]
+fn upstream_error_finalizes_seek_table() {
+    let data = lines(2000);
+    let compress = failing_upstream(&data)
+        .compress(1, 1024)
+        .unwrap()
+        .finalize_on_error(true);
+    let mut items = block_on_stream(Box::pin(compress));
+
+    // Everything up to the error should form a valid seekable object.
+    let mut compressed = Vec::new();
+    let error = loop {
+        match items.next() {
+            Some(Ok(bytes)) => compressed.extend_from_slice(&bytes),
+            Some(Err(CompressError::Underlying(e))) => break e,
+            Some(Err(e)) => panic!("unexpected error: {}", e),
+            None => panic!("stream ended without passing on the upstream error"),
+        }
+    };
+    assert_eq!(error, "upstream went away");
+    assert!(items.next().is_none());
+
+    assert_eq!(decompress_all(compressed), &data[..data.len() / 2]);
+}
+
+#[test]
+fn parts_hold_whole_frames() {
+    let data = lines(50_000);
+    let part_size = 16 * 1024;
+    let part_template = UploadPartRequest::default();
+    let parts = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .align_to_parts(part_size)
+        .upload_parts(part_template, part_size);
+    let parts: Vec<Vec<u8>> = block_on_stream(Box::pin(parts))
+        .map(|part| {
+            let body = part.unwrap().body.unwrap();
+            block_on(body.map_ok(|bytes| bytes.to_vec()).try_concat()).unwrap()
+        })
+        .collect();
+
+    let compressed = parts.concat();
+    let table = SeekTable::parse(&compressed).unwrap();
+    let aligned_parts = table.aligned_parts(part_size);
+    assert!(parts.len() > 2);
+    assert_eq!(aligned_parts.len(), parts.len());
+
+    let mut part_start = 0;
+    for (part, frames) in parts.iter().zip(aligned_parts) {
+        assert_eq!(table.frame_compressed_offset(frames.start), part_start);
+        let frames_len = table.frame_compressed_offset(frames.end - 1)
+            + table.frame_compressed_size(frames.end - 1)
+            - part_start;
+        if frames.end == table.num_frames() {
+            // Last part carries the seek table too.
+            assert_eq!(
+                frames_len + table.seek_table_len() as u64,
+                part.len() as u64
+            );
+        } else {
+            assert!(part.len() >= part_size);
+            assert_eq!(frames_len, part.len() as u64);
+        }
+        part_start += part.len() as u64;
+    }
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn compress_infallible_matches_compress() {
+    let data = lines(2000);
+    let compressed: Vec<u8> = block_on_stream(Box::pin(
+        stream::iter(data.chunks(100))
+            .compress_infallible(1, 1024)
+            .unwrap(),
+    ))
+    .map(|bytes| bytes.unwrap())
+    .flat_map(|bytes| bytes.to_vec())
+    .collect();
+    assert_eq!(compressed, common::compress(&data, 1, 1024));
+}
+
+#[test]
+fn unpin_upstream_gives_unpin_compress() {
+    fn assert_unpin<T: Unpin>(_: &T) {}
+
+    let data = lines(2000);
+    let mut compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap();
+    assert_unpin(&compress);
+
+    // So no pinning is needed to poll it.
+    let mut compressed = Vec::new();
+    while let Some(bytes) = block_on(compress.try_next()).unwrap() {
+        compressed.extend_from_slice(&bytes);
+    }
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn frame_per_item_ends_frames_at_items() {
+    let data = lines(2000);
+    let mut items = Vec::new();
+    let mut rest = &data[..];
+    for len in [10, 500, 0, 3000, 1, 2500].iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let (item, r) = rest.split_at((*len).min(rest.len()));
+        items.push(item);
+        rest = r;
+    }
+    let compress = stream::iter(items.iter().copied().map(Ok::<_, Infallible>))
+        .compress(1, 2048)
+        .unwrap()
+        .frame_per_item(true);
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+
+    // Empty items get no frame and big ones get split at the frame size.
+    let expected: Vec<u64> = items
+        .iter()
+        .flat_map(|item| item.chunks(2048).map(|c| c.len() as u64))
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+    let sizes: Vec<u64> = (0..table.num_frames())
+        .map(|frame| table.frame_decompressed_size(frame))
+        .collect();
+    assert_eq!(sizes, expected);
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn compress_blocking_into_appends() {
+    let data = lines(5000);
+    let mut out = b"prefix".to_vec();
+    let appended = compress_blocking_into(&data, &mut out, 1, 1024).unwrap();
+    assert_eq!(appended, out.len() - 6);
+    assert_eq!(&out[..6], b"prefix");
+    assert_eq!(out[6..], common::compress(&data, 1, 1024)[..]);
+
+    // Empty input still makes a valid object.
+    let mut out = Vec::new();
+    compress_blocking_into(&[], &mut out, 1, 1024).unwrap();
+    assert_eq!(out, common::compress(&[], 1, 1024));
+}
+
+#[test]
+fn blocking_compress_closes_frames_by_hand() {
+    let data = lines(5000);
+    let mut compress = BlockingCompress::new(1, 1 << 20).unwrap();
+    let mut compressed = Vec::new();
+    for (i, chunk) in data.chunks(1000).enumerate() {
+        compress.write(chunk).unwrap();
+        if i % 3 == 2 {
+            let frame = compress.close_frame().unwrap();
+            assert!(!frame.is_empty());
+            compressed.extend_from_slice(&frame);
+            // Closing again right away gives nothing and adds no frame.
+            let frames = compress.frames();
+            assert!(compress.close_frame().unwrap().is_empty());
+            assert_eq!(compress.frames(), frames);
+        }
+    }
+    compress.write(b"tail").unwrap();
+    compressed.extend_from_slice(&compress.finish().unwrap());
+
+    let table = SeekTable::parse(&compressed).unwrap();
+    let sizes: Vec<_> = (0..table.num_frames())
+        .map(|frame| table.frame_decompressed_size(frame))
+        .collect();
+    let chunks = (data.len() + 999) / 1000;
+    let mut expected = vec![3000; chunks / 3];
+    expected.push(data.len() as u64 - 3000 * (chunks / 3) as u64 + 4);
+    assert_eq!(sizes, expected);
+    let mut with_tail = data.clone();
+    with_tail.extend_from_slice(b"tail");
+    assert_eq!(decompress_all(compressed), with_tail);
+}
+
+#[test]
+fn without_seek_table_is_plain_zstd() {
+    let data = lines(5000);
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .seek_table(false);
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    assert!(SeekTable::parse(&compressed).is_err());
+
+    // Same frames as with the seek table, just without it.
+    let seekable = common::compress(&data, 1, 1024);
+    let table = SeekTable::parse(&seekable).unwrap();
+    assert_eq!(compressed, seekable[..table.compressed_len() as usize]);
+
+    let mut dstream = zstd_seekable::DStream::new().unwrap();
+    let mut decompressed = vec![0; data.len()];
+    let (mut written, mut read) = (0, 0);
+    while read < compressed.len() {
+        let (out_pos, in_pos) = dstream
+            .decompress(&mut decompressed[written..], &compressed[read..])
+            .unwrap();
+        written += out_pos;
+        read += in_pos;
+    }
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn by_frame_yields_whole_frames() {
+    let data = lines(5000);
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .by_frame();
+    let items: Vec<_> = block_on_stream(compress)
+        .map(|bytes| bytes.unwrap())
+        .collect();
+
+    // Same output as usual, cut at the frame boundaries.
+    let seekable = common::compress(&data, 1, 1024);
+    let table = SeekTable::parse(&seekable).unwrap();
+    assert_eq!(items.len(), table.num_frames() + 1);
+    for (frame, item) in items[..table.num_frames()].iter().enumerate() {
+        let start = table.frame_compressed_offset(frame) as usize;
+        let end = start + table.frame_compressed_size(frame) as usize;
+        assert_eq!(item[..], seekable[start..end]);
+    }
+    assert_eq!(
+        items.last().unwrap()[..],
+        seekable[table.compressed_len() as usize..]
+    );
+}
+
+#[test]
+fn negative_levels_roundtrip() {
+    let data = lines(20_000);
+    let fast = common::compress(&data, -5, 4096);
+    assert_eq!(decompress_all(fast.clone()), data);
+    // Faster, so not as small.
+    assert!(fast.len() > common::compress(&data, 3, 4096).len());
+}
+
+// Compresses `data` in items of `chunk_size` with at least `min_frames`
+// frames of up to `frame_size`.
+fn compress_min_frames(
+    data: &[u8],
+    chunk_size: usize,
+    frame_size: usize,
+    min_frames: usize,
+) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(chunk_size).map(Ok::<_, Infallible>))
+        .compress(1, frame_size)
+        .unwrap()
+        .min_frames(min_frames);
+    block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn min_frames_shrinks_frames_of_small_inputs() {
+    let data = lines(500);
+    let compressed = compress_min_frames(&data, 1000, 1024 * 1024, 8);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert!(table.num_frames() >= 8, "{}", table.num_frames());
+    let size = table.frame_decompressed_size(0);
+    assert_eq!(size, (data.len() as u64 + 7) / 8);
+    assert_eq!(decompress_all(compressed.clone()), data);
+    // However it comes in.
+    assert_eq!(compress_min_frames(&data, 7, 1024 * 1024, 8), compressed);
+
+    // Knowing the length up front gives the same frames.
+    let planned = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+        .compress(1, 1024 * 1024)
+        .unwrap()
+        .frame_plan(data.len() as u64)
+        .min_frames(8);
+    let planned: Vec<u8> = block_on_stream(planned)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let planned_table = SeekTable::parse(&planned).unwrap();
+    // One more frame for the plan itself.
+    assert_eq!(planned_table.num_frames(), table.num_frames() + 1);
+    assert_eq!(planned_table.frame_decompressed_size(1), size);
+}
+
+#[test]
+fn min_frames_leaves_large_inputs_alone() {
+    let data = lines(20_000);
+    assert_eq!(
+        compress_min_frames(&data, 1000, 4096, 8),
+        common::compress(&data, 1, 4096)
+    );
+    // Nor does it mind a frame per item, as long as it's not asked for.
+    let compress = stream::iter(vec![Ok::<_, Infallible>(&b"data"[..])])
+        .compress(1, 1024)
+        .unwrap()
+        .frame_per_item(true)
+        .min_frames(1);
+    assert!(block_on(compress.try_collect::<Vec<_>>()).is_ok());
+    let compress = stream::iter(vec![Ok::<_, Infallible>(&b"data"[..])])
+        .compress(1, 1024)
+        .unwrap()
+        .frame_per_item(true)
+        .min_frames(2);
+    assert!(block_on(compress.try_collect::<Vec<_>>()).is_err());
+}
+
+#[test]
+fn min_frames_compresses_held_input_before_upstream_error() {
+    let data = lines(2000);
+    let compress = failing_upstream(&data)
+        .compress(1, 1024 * 1024)
+        .unwrap()
+        .min_frames(4)
+        .finalize_on_error(true);
+    let mut items = block_on_stream(Box::pin(compress));
+    let mut compressed = Vec::new();
+    let error = loop {
+        match items.next() {
+            Some(Ok(bytes)) => compressed.extend_from_slice(&bytes),
+            Some(Err(CompressError::Underlying(e))) => break e,
+            Some(Err(e)) => panic!("unexpected error: {}", e),
+            None => panic!("stream ended without passing on the upstream error"),
+        }
+    };
+    assert_eq!(error, "upstream went away");
+    assert!(SeekTable::parse(&compressed).unwrap().num_frames() >= 4);
+    assert_eq!(decompress_all(compressed), &data[..data.len() / 2]);
+}
+
+#[test]
+fn out_of_range_settings_are_named() {
+    let compress = |level, frame_size| {
+        stream::iter(vec![Ok::<_, Infallible>(&b"data"[..])]).compress(level, frame_size)
+    };
+    let message = compress(1, 0x8000_0001).unwrap_err().to_string();
+    assert!(message.contains("frame_size of 2147483649"), "{}", message);
+    let message = compress(23, 1024).unwrap_err().to_string();
+    assert!(message.contains("compression_level of 23"), "{}", message);
+
+    let message = compress(-(1 << 17) - 1, 1024).unwrap_err().to_string();
+    assert!(
+        message.contains("compression_level of -131073"),
+        "{}",
+        message
+    );
+
+    // Both ends of the valid ranges are fine.
+    assert!(compress(22, 0x8000_0000).is_ok());
+    assert!(compress(-(1 << 17), 1024).is_ok());
+    assert!(compress(0, 0).is_ok());
+    assert!(compress(1, 1).is_ok());
+}
+
+#[test]
+fn out_buffer_len_roundtrips() {
+    let data = lines(20_000);
+    for len in [1, 13, 4096, 1 << 20] {
+        let compress = stream::iter(data.chunks(999).map(Ok::<_, Infallible>))
+            .compress(3, 7000)
+            .unwrap()
+            .with_out_buffer_len(len)
+            .unwrap();
+        assert_eq!(compress.out_buffer_len(), len);
+        let compressed: Vec<u8> = block_on_stream(Box::pin(compress))
+            .flat_map(|bytes| bytes.unwrap().to_vec())
+            .collect();
+        assert_eq!(
+            decompress_all(compressed),
+            data,
+            "out buffer of {} bytes",
+            len
+        );
+    }
+
+    let compress = stream::iter(vec![Ok::<_, Infallible>(&b"data"[..])])
+        .compress(1, 1024)
+        .unwrap();
+    let message = compress.with_out_buffer_len(0).unwrap_err().to_string();
+    assert!(message.contains("out_buffer_len of 0"), "{}", message);
+}
+
+#[test]
+fn poll_budget_splits_work() {
+    let data = lines(20_000);
+    let compress = |budget: Option<usize>| {
+        let items = vec![Ok::<_, Infallible>(&data[..100_000]), Ok(&data[100_000..])];
+        let compress = stream::iter(items)
+            .compress(1, 1 << 20)
+            .unwrap()
+            .frame_per_item(true);
+        let compress = match budget {
+            Some(budget) => compress.poll_budget(budget),
+            None => compress,
+        };
+        block_on_stream(Box::pin(compress))
+            .map(|bytes| bytes.unwrap())
+            .collect::<Vec<_>>()
+    };
+    let unbudgeted = compress(None);
+    let budgeted = compress(Some(1000));
+    assert!(budgeted.len() > unbudgeted.len());
+    // Items still get a frame each, the same as without a budget.
+    assert_eq!(budgeted.concat(), unbudgeted.concat());
+    let table = SeekTable::parse(&budgeted.concat()).unwrap();
+    assert_eq!(table.num_frames(), 2);
+    assert_eq!(decompress_all(budgeted.concat()), data);
+}
+
+#[test]
+fn slow_consumers_hold_back_upstream() {
+    const ITEM: usize = 4 << 20;
+    let items: Vec<_> = (0..4).map(|seed| common::noise(ITEM, seed)).collect();
+    let pulled = std::cell::Cell::new(0);
+    let upstream = || {
+        stream::iter(&items).map(|item| {
+            pulled.set(pulled.get() + 1);
+            Ok::<_, Infallible>(&item[..])
+        })
+    };
+
+    // Nothing is pulled ahead of the consumer: one chunk out takes one item
+    // in, and at most that item's worth of output is buffered.
+    let mut chunks = block_on_stream(Box::pin(upstream().compress(1, 64 << 10).unwrap()));
+    for taken in 1..=items.len() {
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!(pulled.get(), taken);
+        assert!(chunk.len() <= ITEM + ITEM / 100, "{}", chunk.len());
+    }
+
+    // With a poll budget, output comes in pieces of about the budget
+    // instead, however large the items.
+    pulled.set(0);
+    let compress = upstream()
+        .compress(1, 64 << 10)
+        .unwrap()
+        .poll_budget(DEFAULT_POLL_BUDGET);
+    let mut chunks = block_on_stream(Box::pin(compress));
+    for _ in 0..ITEM / DEFAULT_POLL_BUDGET {
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!(pulled.get(), 1);
+        assert!(chunk.len() <= 2 * DEFAULT_POLL_BUDGET, "{}", chunk.len());
+    }
+}
+
+#[test]
+fn peak_buffered_bytes_stays_in_bounds() {
+    let data = common::noise(2 << 20, 3);
+    let upstream = || stream::iter(data.chunks(64 << 10).map(Ok::<_, Infallible>));
+    let drain = |compress: zstd_seekable_s3::Compress<_, Infallible>| {
+        let progress = compress.progress();
+        for chunk in block_on_stream(compress) {
+            chunk.unwrap();
+        }
+        progress.peak_buffered_bytes()
+    };
+
+    // Output goes straight out as it's made.
+    assert_eq!(drain(upstream().compress(1, 64 << 10).unwrap()), 0);
+
+    // Up to a part short of the part size, plus the frame that fills it.
+    let peak = drain(
+        upstream()
+            .compress(1, 64 << 10)
+            .unwrap()
+            .align_to_parts(256 << 10),
+    );
+    assert!(peak > 0);
+    assert!(peak < (256 + 64) << 10, "{}", peak);
+
+    // The rest of an item that went over the budget.
+    let peak = drain(
+        upstream()
+            .compress(1, 64 << 10)
+            .unwrap()
+            .poll_budget(10_000),
+    );
+    assert!(peak > 0);
+    assert!(peak < 64 << 10, "{}", peak);
+}
+
+#[test]
+fn checksum_frames_off_leaves_them_out() {
+    let data = lines(3000);
+    let chunks = stream::iter(data.chunks(100).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(1, 4096).unwrap().checksum_frames(false);
+    let compressed: Vec<u8> = block_on(
+        Box::pin(compress)
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat(),
+    )
+    .unwrap();
+
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert!(table.num_frames() > 1);
+    assert!(!table.has_checksums());
+    assert_eq!(table.frame_checksum(0), None);
+    assert_eq!(table.seek_table_len(), 8 + table.num_frames() * 8 + 9);
+    assert_eq!(SeekTable::parse(&table.to_bytes()).unwrap(), table);
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn max_frame_age_ends_idle_frames() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let data = lines(100);
+        let (send, recv) = futures::channel::mpsc::unbounded::<Result<Vec<u8>, Infallible>>();
+        let mut compress = recv
+            .compress(1, 1 << 20)
+            .unwrap()
+            .max_frame_age(Duration::from_secs(1));
+        let progress = compress.progress();
+
+        // Far short of the frame size, but nothing else comes for a while so
+        // the frame goes out once it's a second old.
+        send.unbounded_send(Ok(data.clone())).unwrap();
+        let start = tokio::time::Instant::now();
+        let mut compressed = compress.next().await.unwrap().unwrap().to_vec();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(progress.frames(), 1);
+
+        send.unbounded_send(Ok(data.clone())).unwrap();
+        drop(send);
+        while let Some(bytes) = compress.next().await {
+            compressed.extend_from_slice(&bytes.unwrap());
+        }
+        assert_eq!(SeekTable::parse(&compressed).unwrap().num_frames(), 2);
+        assert_eq!(decompress_all(compressed), [&data[..], &data[..]].concat());
+    });
+}
+
+#[test]
+fn arc_chunks_match_bytes() {
+    let data = lines(3000);
+    let compress = || {
+        stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+            .compress(1, 4096)
+            .unwrap()
+    };
+    let bytes: Vec<_> = block_on_stream(Box::pin(compress()))
+        .map(Result::unwrap)
+        .collect();
+    let arcs: Vec<_> = block_on_stream(Box::pin(compress().arc_chunks()))
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(arcs.len(), bytes.len());
+    for (arc, bytes) in arcs.iter().zip(&bytes) {
+        assert_eq!(arc[..], bytes[..]);
+    }
+}
+
+#[test]
+fn into_body_yields_the_output() {
+    use http_body::Body;
+
+    // What hyper needs of a body's errors.
+    fn check_error<B: Body>(_body: &B)
+    where
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+    }
+
+    let data = lines(3000);
+    let mut body = stream::iter(data.chunks(100).map(Ok::<_, std::io::Error>))
+        .compress(1, 4096)
+        .unwrap()
+        .into_body();
+    check_error(&body);
+    let mut compressed = Vec::new();
+    while let Some(bytes) = block_on(body.data()) {
+        compressed.extend_from_slice(&bytes.unwrap());
+    }
+    assert!(body.is_end_stream());
+    assert_eq!(block_on(body.trailers()).unwrap(), None);
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn max_output_bytes_stops_the_stream() {
+    let data = common::noise(64 * 1024, 1);
+    let compress = || {
+        stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+            .compress(1, 4096)
+            .unwrap()
+    };
+    let full: usize = block_on_stream(Box::pin(compress()))
+        .map(|bytes| bytes.unwrap().len())
+        .sum();
+
+    let limit = full as u64 / 2;
+    let mut items = block_on_stream(Box::pin(compress().max_output_bytes(limit)));
+    let mut yielded = 0;
+    loop {
+        match items.next() {
+            Some(Ok(bytes)) => yielded += bytes.len() as u64,
+            Some(Err(CompressError::OutputTooLarge { limit: l })) => {
+                assert_eq!(l, limit);
+                break;
+            }
+            item => panic!("unexpected item: {:?}", item.map(|r| r.map(|b| b.len()))),
+        }
+    }
+    assert!(yielded <= limit);
+    assert!(items.next().is_none());
+
+    // Right at the limit is fine.
+    let out: usize = block_on_stream(Box::pin(compress().max_output_bytes(full as u64)))
+        .map(|bytes| bytes.unwrap().len())
+        .sum();
+    assert_eq!(out, full);
+}
+
+#[test]
+fn annotated_chunks_line_up_with_seek_table() {
+    let data = lines(3000);
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 4096)
+        .unwrap()
+        .metadata("name", "lines")
+        .annotated();
+    let chunks: Vec<_> = block_on_stream(Box::pin(compress))
+        .map(Result::unwrap)
+        .collect();
+    let compressed: Vec<u8> = chunks.iter().flat_map(|c| c.bytes.to_vec()).collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    let (footer, frames) = chunks.split_last().unwrap();
+    assert!(footer.is_footer);
+    assert_eq!(footer.frame_index, None);
+    assert_eq!(footer.decompressed_range, None);
+    assert_eq!(footer.bytes.len(), table.seek_table_len());
+    assert_eq!(frames.len(), table.num_frames());
+    // The metadata comes first, without any data.
+    assert_eq!(frames[0].decompressed_range, Some((0, 0)));
+    let mut end = 0;
+    for (index, chunk) in frames.iter().enumerate() {
+        assert!(!chunk.is_footer);
+        assert_eq!(chunk.frame_index, Some(index));
+        let (start, chunk_end) = chunk.decompressed_range.unwrap();
+        assert_eq!(start, end);
+        assert_eq!(start, table.frame_decompressed_offset(index));
+        assert_eq!(chunk.bytes.len() as u64, table.frame_compressed_size(index));
+        end = chunk_end;
+    }
+    assert_eq!(end, data.len() as u64);
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn empty_input_with_metadata_goes_out_a_frame_at_a_time() {
+    let compress = || {
+        stream::iter(Vec::<Result<Vec<u8>, Infallible>>::new())
+            .compress(1, 4096)
+            .unwrap()
+            .metadata("name", "empty")
+    };
+    let items: Vec<_> = block_on_stream(Box::pin(compress().by_frame()))
+        .map(Result::unwrap)
+        .collect();
+    let compressed: Vec<u8> = items.iter().flat_map(|b| b.to_vec()).collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+    // The metadata, the empty frame and the seek table.
+    assert_eq!(table.num_frames(), 2);
+    assert_eq!(items.len(), 3);
+    for (frame, item) in items[..2].iter().enumerate() {
+        assert_eq!(item.len() as u64, table.frame_compressed_size(frame));
+    }
+    assert_eq!(items[2].len(), table.seek_table_len());
+
+    let chunks: Vec<_> = block_on_stream(Box::pin(compress().annotated()))
+        .map(Result::unwrap)
+        .collect();
+    let labels: Vec<_> = chunks
+        .iter()
+        .map(|c| (c.frame_index, c.bytes.len(), c.is_footer))
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            (Some(0), items[0].len(), false),
+            (Some(1), items[1].len(), false),
+            (None, items[2].len(), true),
+        ]
+    );
+}
+
+#[test]
+fn frame_layout_ignores_chunking() {
+    use common::compress_chunked;
+    let data = common::noise(100_000, 5);
+    let whole = compress_chunked(&data, data.len(), 3, 4096);
+    let table = SeekTable::parse(&whole).unwrap();
+    for frame in 0..table.num_frames() {
+        assert_eq!(table.frame_decompressed_offset(frame), frame as u64 * 4096);
+    }
+    for &chunk_size in &[1, 7, 100, 4095, 4096, 4097, 65_536] {
+        let compressed = compress_chunked(&data, chunk_size, 3, 4096);
+        assert!(
+            compressed == whole,
+            "output differs with chunks of {}",
+            chunk_size
+        );
+    }
+}
+
+#[test]
+fn empty_items_change_nothing() {
+    let data = lines(5000);
+    // The same chunks, with a run of empty items before, between and after
+    // them.
+    let with_empties = || {
+        stream::iter(
+            data.chunks(700)
+                .flat_map(|chunk| vec![&[][..], &[][..], chunk])
+                .chain(vec![&[][..]; 10])
+                .map(Ok::<_, Infallible>),
+        )
+    };
+    let without = || stream::iter(data.chunks(700).map(Ok::<_, Infallible>));
+    fn collect<S>(compress: S) -> Vec<u8>
+    where
+        S: futures::Stream<Item = Result<bytes::Bytes, CompressError<Infallible>>> + Unpin,
+    {
+        block_on_stream(compress)
+            .flat_map(|bytes| bytes.unwrap().to_vec())
+            .collect()
+    }
+
+    let expected = collect(without().compress(1, 1024).unwrap());
+    assert_eq!(collect(with_empties().compress(1, 1024).unwrap()), expected);
+    assert_eq!(decompress_all(expected), data);
+
+    // With the settings that go by items.
+    assert_eq!(
+        collect(
+            with_empties()
+                .compress(1, 1024)
+                .unwrap()
+                .frame_per_item(true)
+        ),
+        collect(without().compress(1, 1024).unwrap().frame_per_item(true)),
+    );
+    assert_eq!(
+        collect(with_empties().compress(1, 1024).unwrap().poll_budget(500)),
+        collect(without().compress(1, 1024).unwrap().poll_budget(500)),
+    );
+    assert_eq!(
+        collect(with_empties().compress(1, 1 << 20).unwrap().min_frames(8)),
+        collect(without().compress(1, 1 << 20).unwrap().min_frames(8)),
+    );
+}
+
+// Bytes that don't compress at all.
+fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn store_incompressible_stores_frames() {
+    let frame_size = 16 * 1024;
+    let mut data = random_bytes(32 * frame_size, 9);
+    data.extend(lines(40_000).into_iter().take(16 * frame_size));
+    let compress = |out_buffer_len: usize| {
+        let compress = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+            .compress(1, frame_size)
+            .unwrap()
+            .store_incompressible(true)
+            .with_out_buffer_len(out_buffer_len)
+            .unwrap();
+        block_on_stream(compress)
+            .flat_map(|bytes| bytes.unwrap().to_vec())
+            .collect::<Vec<u8>>()
+    };
+    let compressed = compress(128 * 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    // The first frame is compressed to find out it doesn't compress, then
+    // the next seven are stored: a header and a single raw block. zstd does
+    // about as well, it just spends longer on it.
+    let frames = common::frames(&compressed);
+    let stored = |frame: &[u8]| frame[..6] == [0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x38];
+    assert!(!stored(frames[0]));
+    for frame in &frames[1..8] {
+        assert!(stored(frame));
+        assert_eq!(frame.len(), frame_size + 6 + 3);
+    }
+    assert!(!stored(frames[8]));
+    assert!(stored(frames[9]));
+    // Compressing picks up again soon after the data starts compressing.
+    let last = table.num_frames() - 1;
+    assert!(table.frame_compressed_size(last - 1) < frame_size as u64 / 2);
+    assert_eq!(decompress_all(compressed.clone()), data);
+    let mut decompress = SeekableDecompress::new(Cursor::new(&compressed)).unwrap();
+    assert_eq!(
+        decompress
+            .read_range(3 * frame_size as u64 + 10, 5000)
+            .unwrap(),
+        data[3 * frame_size + 10..3 * frame_size + 5010]
+    );
+
+    // Stored frames come out the same through the smallest buffer.
+    let small = compress(1);
+    assert_eq!(
+        small[..table.frame_compressed_offset(8) as usize],
+        compressed[..table.frame_compressed_offset(8) as usize]
+    );
+    assert_eq!(decompress_all(small), data);
+}
+
+// What zstd_seekable's own compressor makes of `data`.
+fn seekable_cstream(data: &[u8], compression_level: usize, frame_size: usize) -> Vec<u8> {
+    use zstd_seekable::SeekableCStream;
+    let mut cstream = SeekableCStream::new(compression_level, frame_size).unwrap();
+    let mut buf = vec![0; zstd_seekable::CStream::out_size()];
+    let mut out = Vec::new();
+    let mut input = data;
+    while !input.is_empty() {
+        // Named in full, StreamCompress::compress would get in the way.
+        let (out_pos, in_pos) = SeekableCStream::compress(&mut cstream, &mut buf, input).unwrap();
+        out.extend_from_slice(&buf[..out_pos]);
+        input = &input[in_pos..];
+    }
+    loop {
+        let out_pos = cstream.end_stream(&mut buf).unwrap();
+        if out_pos == 0 {
+            return out;
+        }
+        out.extend_from_slice(&buf[..out_pos]);
+    }
+}
+
+#[test]
+fn same_output_as_seekable_cstream() {
+    let data = lines(20_000);
+    for &level in &[1, 3, 9] {
+        for &frame_size in &[1000, 4096, 64 * 1024, 1 << 20] {
+            assert_eq!(
+                compress(&data, level as i32, frame_size),
+                seekable_cstream(&data, level, frame_size),
+                "level {} frame size {}",
+                level,
+                frame_size
+            );
+        }
+    }
+    // Ending right at a frame boundary.
+    assert_eq!(
+        compress(&data[..8192], 1, 4096),
+        seekable_cstream(&data[..8192], 1, 4096)
+    );
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{decompress_all, fake_s3::FakeS3, noise};
+use futures::stream;
+use std::{convert::Infallible, time::Duration};
+use zstd_seekable_s3::{
+    compress_to_s3, compress_to_s3_with_progress, CompressError, CompressToS3Config,
+    CompressToS3Error,
+};
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn config() -> CompressToS3Config {
+    CompressToS3Config {
+        compression_level: 1,
+        frame_size: 4096,
+        part_size: 8 * 1024,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn failed_parts_abort_the_upload() {
+    let s3 = FakeS3::default();
+    s3.fail_part(3);
+    let data = noise(100_000, 1);
+    let source = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>));
+    let result = runtime().block_on(compress_to_s3(
+        source,
+        &s3.client(),
+        "bucket".to_owned(),
+        "object.zst".to_owned(),
+        config(),
+    ));
+    assert!(matches!(result, Err(CompressToS3Error::UploadPart(_))));
+    assert!(s3.uploaded_part_numbers().contains(&3));
+    assert_eq!(s3.uploads_in_progress(), 0);
+    assert!(s3.object("object.zst").is_none());
+}
+
+#[test]
+fn failed_sources_abort_the_upload() {
+    let s3 = FakeS3::default();
+    let data = noise(100_000, 1);
+    let source = stream::iter(
+        data.chunks(1000)
+            .map(Ok)
+            .chain(Some(Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "upstream went away",
+            )))),
+    );
+    let result = runtime().block_on(compress_to_s3(
+        source,
+        &s3.client(),
+        "bucket".to_owned(),
+        "object.zst".to_owned(),
+        config(),
+    ));
+    match result {
+        Err(CompressToS3Error::Compress(CompressError::Underlying(e))) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe)
+        }
+        other => panic!("expected the source's error, got {:?}", other),
+    }
+    // Some parts went up before the source failed.
+    assert!(!s3.uploaded_part_numbers().is_empty());
+    assert_eq!(s3.uploads_in_progress(), 0);
+    assert!(s3.object("object.zst").is_none());
+}
+
+#[test]
+fn concurrency_limits_parts_in_flight() {
+    let s3 = FakeS3::default();
+    s3.delay_parts(|_| Duration::from_millis(20));
+    let data = noise(200_000, 1);
+    let source = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>));
+    let config = CompressToS3Config {
+        concurrency: 3,
+        ..config()
+    };
+    runtime()
+        .block_on(compress_to_s3(
+            source,
+            &s3.client(),
+            "bucket".to_owned(),
+            "object.zst".to_owned(),
+            config,
+        ))
+        .unwrap();
+    assert!(s3.uploaded_part_numbers().len() > 6);
+    assert_eq!(s3.max_parts_in_flight(), 3);
+    assert_eq!(
+        decompress_all(s3.object("object.zst").unwrap().to_vec()),
+        data
+    );
+}
+
+#[test]
+fn progress_comes_in_part_order() {
+    let s3 = FakeS3::default();
+    // Odd parts take longer, so part 2 is in before part 1.
+    s3.delay_parts(|number| Duration::from_millis(if number % 2 == 1 { 30 } else { 1 }));
+    let data = noise(200_000, 1);
+    let source = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>));
+    let config = CompressToS3Config {
+        concurrency: 4,
+        ..config()
+    };
+    let mut reported = Vec::new();
+    runtime()
+        .block_on(compress_to_s3_with_progress(
+            source,
+            &s3.client(),
+            "bucket".to_owned(),
+            "object.zst".to_owned(),
+            config.clone(),
+            |part| reported.push(part),
+        ))
+        .unwrap();
+    let object = s3.object("object.zst").unwrap();
+
+    assert!(reported.len() > 4);
+    let mut cumulative_bytes = 0;
+    for (i, part) in reported.iter().enumerate() {
+        let number = i as i64 + 1;
+        assert_eq!(part.part_number, number);
+        assert_eq!(part.e_tag, Some(format!("\"{}\"", number)));
+        assert!(part.cumulative_bytes > cumulative_bytes);
+        if i + 1 < reported.len() {
+            assert!(part.cumulative_bytes - cumulative_bytes >= config.part_size as u64);
+        }
+        cumulative_bytes = part.cumulative_bytes;
+    }
+    assert_eq!(cumulative_bytes, object.len() as u64);
+}
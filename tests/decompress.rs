@@ -0,0 +1,408 @@
+mod common;
+
+use common::{compress, decompress_all, lines};
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    io::{Cursor, Read, Seek, SeekFrom},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use zstd_seekable_s3::{
+    truncate_seekable, SeekTable, SeekableDecompress, StreamCompress, WorkerBudget,
+};
+
+#[test]
+fn parallel_matches_sequential() {
+    let data = lines(20_000);
+    let compressed = compress(&data, 1, 4096);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    for concurrency in [0, 1, 3, 16] {
+        let parallel = decompress.decompress_all_parallel(concurrency).unwrap();
+        assert_eq!(parallel, decompress_all(compressed.clone()));
+    }
+
+    // Regular reads still work afterwards, including half way through the
+    // frame being read from before.
+    decompress.seek(SeekFrom::Start(1000)).unwrap();
+    let mut some = [0; 10];
+    decompress.read_exact(&mut some).unwrap();
+    decompress.decompress_all_parallel(2).unwrap();
+    let mut rest = Vec::new();
+    decompress.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, data[1010..]);
+}
+
+#[test]
+fn readers_share_a_worker_budget() {
+    let data = lines(20_000);
+    let compressed = compress(&data, 1, 1024);
+    let budget = WorkerBudget::new(2);
+    let most_busy = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                most_busy.fetch_max(budget.busy(), Ordering::Relaxed);
+            }
+        });
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut decompress =
+                        SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+                    decompress.set_worker_budget(Some(budget.clone()));
+                    for _ in 0..5 {
+                        assert!(decompress.verify_all(8).unwrap().is_ok());
+                        assert_eq!(decompress.decompress_all_parallel(8).unwrap(), data);
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    assert!(most_busy.into_inner() <= 2);
+    assert_eq!(budget.busy(), 0);
+}
+
+#[test]
+fn parallel_detects_corruption() {
+    let data = lines(5000);
+    let mut compressed = compress(&data, 1, 4096);
+    // Flip a byte in the middle of the compressed frames.
+    let middle = compressed.len() / 2;
+    compressed[middle] ^= 0xff;
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert!(decompress.decompress_all_parallel(4).is_err());
+}
+
+#[test]
+fn verify_reports_bad_frames() {
+    let data = lines(5000);
+    let mut compressed = compress(&data, 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    let report = decompress.verify_all(3).unwrap();
+    assert!(report.is_ok());
+    assert!(report.checksums);
+    assert_eq!(report.frames, table.num_frames());
+    assert_eq!(report.decompressed_len, data.len() as u64);
+
+    // Break the checksums of a couple of frames: skippable frame header, then
+    // 12 byte entries with the checksum last.
+    let entries = compressed.len() - table.seek_table_len() + 8;
+    for frame in [2, 5] {
+        compressed[entries + frame * 12 + 8] ^= 1;
+    }
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    let report = decompress.verify_all(3).unwrap();
+    let bad: Vec<_> = report.errors.iter().map(|e| e.frame).collect();
+    assert_eq!(bad, [2, 5]);
+}
+
+#[test]
+fn verify_rejects_frames_past_the_seek_table() {
+    let mut compressed = compress(&lines(5000), 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    // Make a frame claim gigabytes, which we mustn't try to read in.
+    let entries = compressed.len() - table.seek_table_len() + 8;
+    compressed[entries + 2 * 12..entries + 2 * 12 + 4]
+        .copy_from_slice(&0xff00_0000u32.to_le_bytes());
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert!(decompress.verify_all(1).is_err());
+    assert!(decompress.read_frame(0).is_err());
+}
+
+#[test]
+fn read_frame_checks_checksum() {
+    let data = lines(5000);
+    let mut compressed = compress(&data, 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    for frame in 0..table.num_frames() {
+        let offset = table.frame_decompressed_offset(frame) as usize;
+        let len = table.frame_decompressed_size(frame) as usize;
+        assert_eq!(
+            decompress.read_frame(frame).unwrap(),
+            data[offset..offset + len]
+        );
+    }
+
+    let entries = compressed.len() - table.seek_table_len() + 8;
+    compressed[entries + 3 * 12 + 8] ^= 1;
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert!(decompress.read_frame(2).is_ok());
+    assert!(decompress.read_frame(3).is_err());
+}
+
+#[test]
+fn frames_rev_goes_last_to_first() {
+    let data = lines(5000);
+    let chunks = stream::iter(data.chunks(100).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(1, 4096).unwrap().metadata("name", "lines");
+    let compressed: Vec<u8> = block_on_stream(Box::pin(compress))
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    // Every frame with data, the metadata frame left out, each the right
+    // way round.
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    let frames: Vec<_> = decompress.frames_rev().map(Result::unwrap).collect();
+    assert_eq!(frames.len(), table.num_frames() - 1);
+    let mut end = data.len();
+    for (meta, frame_data) in frames {
+        let offset = meta.decompressed_offset as usize;
+        assert_eq!(offset + frame_data.len(), end);
+        assert_eq!(frame_data, data[offset..end]);
+        end = offset;
+    }
+    assert_eq!(end, 0);
+
+    // Reads pick up where they were.
+    let mut some = [0; 10];
+    decompress.read_exact(&mut some).unwrap();
+    assert_eq!(some, data[..10]);
+}
+
+#[test]
+fn decompress_matching_picks_frames() {
+    let data = lines(5000);
+    let chunks = stream::iter(data.chunks(100).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(1, 4096).unwrap().metadata("name", "lines");
+    let compressed: Vec<u8> = block_on_stream(Box::pin(compress))
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    // Frames overlapping 10000..20000, leaving out the metadata frame.
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    let frames: Vec<_> = decompress
+        .decompress_matching(|meta| {
+            meta.decompressed_offset < 20_000
+                && meta.decompressed_offset + meta.decompressed_size > 10_000
+        })
+        .map(Result::unwrap)
+        .collect();
+    let expected: Vec<_> = (1..table.num_frames())
+        .filter(|&frame| {
+            let offset = table.frame_decompressed_offset(frame);
+            offset < 20_000 && offset + table.frame_decompressed_size(frame) > 10_000
+        })
+        .collect();
+    assert_eq!(frames.len(), expected.len());
+    for ((meta, frame_data), frame) in frames.iter().zip(expected) {
+        assert_eq!(
+            meta.decompressed_offset,
+            table.frame_decompressed_offset(frame)
+        );
+        let offset = meta.decompressed_offset as usize;
+        assert_eq!(
+            frame_data,
+            &data[offset..offset + meta.decompressed_size as usize]
+        );
+    }
+    assert_eq!(
+        decompress.decompress_matching(|_| true).count(),
+        table.num_frames() - 1
+    );
+
+    // A bad frame ends it.
+    let mut compressed = compressed;
+    let entries = compressed.len() - table.seek_table_len() + 8;
+    compressed[entries + 2 * 12 + 8] ^= 1;
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    let results: Vec<_> = decompress.decompress_matching(|_| true).collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+}
+
+#[test]
+fn lines_split_on_newlines() {
+    let mut data = lines(5000);
+    // Empty lines, one longer than a chunk and a last one without a newline.
+    data.extend_from_slice(b"\n\n");
+    data.extend_from_slice(&vec![b'x'; 200_000]);
+    data.extend_from_slice(b"\nlast");
+    let mut decompress = SeekableDecompress::new(Cursor::new(compress(&data, 1, 1024))).unwrap();
+
+    let got: Vec<_> = decompress.lines().map(Result::unwrap).collect();
+    let expected: Vec<_> = data.split(|&b| b == b'\n').collect();
+    assert_eq!(got.len(), expected.len());
+    for (got, expected) in got.iter().zip(expected) {
+        assert_eq!(got, expected);
+    }
+
+    // No empty line after a trailing newline.
+    let data = b"one\ntwo\n";
+    let mut decompress = SeekableDecompress::new(Cursor::new(compress(data, 1, 1024))).unwrap();
+    let got: Vec<_> = decompress.lines().map(Result::unwrap).collect();
+    assert_eq!(got, [&b"one"[..], &b"two"[..]]);
+}
+
+#[test]
+fn read_range_stops_at_end() {
+    let data = lines(5000);
+    let len = data.len() as u64;
+    let mut decompress = SeekableDecompress::new(Cursor::new(compress(&data, 1, 4096))).unwrap();
+
+    assert_eq!(
+        decompress.read_range(100, 10_000).unwrap(),
+        data[100..10_100]
+    );
+    // Up to, across, at and past the end.
+    assert_eq!(
+        decompress.read_range(len - 10, 10).unwrap(),
+        data[data.len() - 10..]
+    );
+    assert_eq!(
+        decompress.read_range(len - 10, 100).unwrap(),
+        data[data.len() - 10..]
+    );
+    assert!(decompress.read_range(len, 10).unwrap().is_empty());
+    assert!(decompress.read_range(len + 1, 10).unwrap().is_empty());
+    assert!(decompress
+        .read_range(u64::MAX, usize::MAX)
+        .unwrap()
+        .is_empty());
+
+    // The position is left alone.
+    let mut first = [0; 5];
+    decompress.read_exact(&mut first).unwrap();
+    assert_eq!(first, data[..5]);
+}
+
+#[test]
+fn frame_size_limit() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 4096);
+    let decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    assert!(decompress.max_frame_decompressed_size(4096).is_ok());
+
+    let decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    match decompress.max_frame_decompressed_size(4095) {
+        Err(zstd_seekable_s3::Error::FrameOverLimit { frame, declared }) => {
+            assert_eq!((frame, declared), (0, 4096))
+        }
+        _ => panic!("expected the limit to be hit"),
+    }
+}
+
+#[test]
+fn windows_cover_data() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1000);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+
+    let windows: Vec<_> = decompress.windows(4096).map(|w| w.unwrap()).collect();
+    assert_eq!(windows.len(), (data.len() + 4095) / 4096);
+    for (offset, window) in &windows {
+        let offset = *offset as usize;
+        assert_eq!(window[..], data[offset..(offset + 4096).min(data.len())]);
+    }
+}
+
+#[test]
+fn length_check_passes_good_data() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 4096);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed))
+        .unwrap()
+        .with_length_check(data.len() as u64);
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn length_check_catches_the_wrong_length() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 4096);
+    // Cut short, seek table and all, so just going by the table the data
+    // looks complete.
+    let cut = truncate_seekable(&compressed, 10_000, 1).unwrap();
+
+    for (object, expected, actual) in [
+        (cut, data.len() as u64, 10_000),
+        (compressed, 10_000, data.len() as u64),
+    ] {
+        let mut decompress = SeekableDecompress::new(Cursor::new(object))
+            .unwrap()
+            .with_length_check(expected);
+        let e = decompress.read_to_end(&mut Vec::new()).unwrap_err();
+        match e
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<zstd_seekable_s3::Error>()
+        {
+            Some(&zstd_seekable_s3::Error::LengthMismatch {
+                expected: e,
+                actual: a,
+            }) => assert_eq!((e, a), (expected, actual)),
+            other => panic!("expected a length mismatch, got {:?}", other),
+        }
+
+        // Reading past the end still has the data end in the same place.
+        decompress.seek(SeekFrom::Start(actual + 100)).unwrap();
+        assert!(decompress.read(&mut [0; 10]).is_err());
+    }
+}
+
+#[test]
+fn decompress_all_into_fills_the_buffer() {
+    let data = lines(5000);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compress(&data, 1, 4096))).unwrap();
+
+    let mut buf = vec![0xAA; data.len() + 10];
+    assert_eq!(
+        decompress.decompress_all_into(&mut buf).unwrap(),
+        data.len()
+    );
+    assert_eq!(buf[..data.len()], data[..]);
+    assert!(buf[data.len()..].iter().all(|&b| b == 0xAA));
+
+    match decompress.decompress_all_into(&mut buf[..data.len() - 1]) {
+        Err(zstd_seekable_s3::Error::BufferTooSmall { needed, len }) => {
+            assert_eq!(needed, data.len() as u64);
+            assert_eq!(len, data.len() - 1);
+        }
+        other => panic!("expected BufferTooSmall, got {:?}", other),
+    }
+}
+
+#[test]
+fn reads_go_by_the_seek_table_whatever_the_frame_size() {
+    let data = lines(2000);
+    let len = data.len();
+    // Single bytes, odd sizes, and a frame bigger than all of the data.
+    for &frame_size in &[1, 7, 1000, 4096, 64 * 1024, 4 * len] {
+        let compressed = compress(&data, 1, frame_size);
+        let table = SeekTable::parse(&compressed).unwrap();
+        assert_eq!(table.decompressed_len(), len as u64, "{}", frame_size);
+
+        assert_eq!(decompress_all(compressed.clone()), data, "{}", frame_size);
+        let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(decompress.decompressed_len(), len as u64);
+        for &(offset, n) in &[(0, 1), (3, 5000), (len - 1, 10), (len / 2, len)] {
+            assert_eq!(
+                decompress.read_range(offset as u64, n).unwrap(),
+                data[offset..(offset + n).min(len)],
+                "{} at {}",
+                frame_size,
+                offset
+            );
+        }
+        decompress.seek(SeekFrom::Start(len as u64 - 100)).unwrap();
+        let mut tail = Vec::new();
+        decompress.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[len - 100..]);
+
+        assert_eq!(decompress.decompress_all_parallel(3).unwrap(), data);
+        assert!(decompress.verify_all(3).unwrap().is_ok());
+    }
+}
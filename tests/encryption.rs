@@ -0,0 +1,105 @@
+mod common;
+
+use common::lines;
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    io::{Cursor, Read, Seek, SeekFrom},
+};
+use zstd_seekable_s3::{
+    CipherError, Ciphertext, DecryptingReader, Decryptor, Encryptor, SeekTable, SeekableDecompress,
+    StreamCompress,
+};
+
+// Not remotely secure: XORs with a keystream derived from the frame index and
+// appends a checksum as the "tag".
+struct Toy;
+
+fn keystream(frame_index: usize, data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(i, b)| b ^ (i.wrapping_mul(31) ^ frame_index.wrapping_mul(7)) as u8)
+        .collect()
+}
+
+fn tag(data: &[u8]) -> Vec<u8> {
+    let sum = data
+        .iter()
+        .fold(0u32, |sum, &b| sum.wrapping_mul(33) ^ u32::from(b));
+    sum.to_le_bytes().to_vec()
+}
+
+impl Encryptor for Toy {
+    fn encrypt(&mut self, frame_index: usize, plaintext: &[u8]) -> Result<Ciphertext, CipherError> {
+        Ok(Ciphertext {
+            data: keystream(frame_index, plaintext),
+            metadata: tag(plaintext),
+        })
+    }
+}
+
+impl Decryptor for Toy {
+    fn decrypt(
+        &mut self,
+        frame_index: usize,
+        ciphertext: &[u8],
+        metadata: &[u8],
+    ) -> Result<Vec<u8>, CipherError> {
+        let plaintext = keystream(frame_index, ciphertext);
+        if tag(&plaintext) != metadata {
+            return Err("tag mismatch".into());
+        }
+        Ok(plaintext)
+    }
+}
+
+fn compress_encrypted(data: &[u8], frame_size: usize) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, frame_size)
+        .unwrap()
+        .encrypt_frames(Toy);
+    block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn encrypted_roundtrip() {
+    let data = lines(5000);
+    let compressed = compress_encrypted(&data, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(
+        table.compressed_len() as usize + table.seek_table_len(),
+        compressed.len()
+    );
+
+    // The frames aren't readable without decrypting them.
+    assert!(SeekableDecompress::new(Cursor::new(compressed.clone()))
+        .and_then(|mut d| d
+            .read_to_end(&mut Vec::new())
+            .map_err(zstd_seekable_s3::Error::Io))
+        .is_err());
+
+    let reader = DecryptingReader::new(Cursor::new(compressed), Toy).unwrap();
+    let mut decompress = SeekableDecompress::new(reader).unwrap();
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+
+    decompress.seek(SeekFrom::Start(10_000)).unwrap();
+    let mut some = vec![0; 5000];
+    decompress.read_exact(&mut some).unwrap();
+    assert_eq!(some, data[10_000..15_000]);
+}
+
+#[test]
+fn tampered_frame_fails() {
+    let data = lines(5000);
+    let mut compressed = compress_encrypted(&data, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    compressed[table.frame_compressed_offset(1) as usize + 3] ^= 1;
+
+    let reader = DecryptingReader::new(Cursor::new(compressed), Toy).unwrap();
+    let mut decompress = SeekableDecompress::new(reader).unwrap();
+    assert!(decompress.read_to_end(&mut Vec::new()).is_err());
+}
@@ -0,0 +1,77 @@
+use futures::{executor::block_on_stream, stream};
+use std::{error::Error as StdError, io::Cursor};
+use zstd_seekable_s3::{
+    CompressError, CompressToS3Error, Error, S3ReadError, SeekTable, SeekTableError,
+    SeekableDecompress, StreamCompress, WriteOutError,
+};
+
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+// Our errors go into the boxed errors anyhow and friends build on with `?`.
+fn compress_failing() -> Result<(), BoxError> {
+    let upstream = stream::iter(vec![Err(std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "upstream went away",
+    ))]);
+    for bytes in block_on_stream(Box::pin(upstream.compress::<&[u8], _>(1, 1024)?)) {
+        bytes?;
+    }
+    Ok(())
+}
+
+#[test]
+fn errors_box_and_chain() {
+    let e = compress_failing().unwrap_err();
+    let compress_error = e.downcast_ref::<CompressError<std::io::Error>>().unwrap();
+    assert!(matches!(compress_error, CompressError::Underlying(_)));
+    let source = e
+        .source()
+        .unwrap()
+        .downcast_ref::<std::io::Error>()
+        .unwrap();
+    assert_eq!(source.kind(), std::io::ErrorKind::BrokenPipe);
+
+    let open = || -> Result<(), BoxError> {
+        SeekableDecompress::new(Cursor::new(vec![0; 100]))?;
+        Ok(())
+    };
+    // zstd's errors are passed along as they are.
+    let e = open().unwrap_err();
+    match e.downcast_ref::<Error>() {
+        Some(Error::ZstdSeekable(zstd)) => assert_eq!(e.to_string(), zstd.to_string()),
+        other => panic!("expected a zstd error, got {:?}", other),
+    }
+
+    let parse = || -> Result<SeekTable, BoxError> { Ok(SeekTable::parse(b"short")?) };
+    let e = parse().unwrap_err();
+    assert!(e.downcast_ref::<SeekTableError>().is_some());
+
+    let read_error: BoxError =
+        S3ReadError::SeekTableCorrupt(SeekTableError::BadSkippableFrame).into();
+    assert!(read_error.source().unwrap().is::<SeekTableError>());
+}
+
+// Messages leave out the error they wrap, that's for source(), so walking
+// the chain says everything once.
+#[test]
+fn messages_leave_out_the_source() {
+    let io = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "upstream went away");
+    let errors: Vec<BoxError> = vec![
+        Box::new(CompressError::Underlying(io())),
+        Box::new(Error::Io(io())),
+        Box::new(S3ReadError::Io(io())),
+        Box::new(S3ReadError::SeekTableCorrupt(
+            SeekTableError::BadSkippableFrame,
+        )),
+        Box::new(CompressToS3Error::<std::io::Error>::Spill(io())),
+        Box::new(WriteOutError::<std::io::Error>::Write(io())),
+    ];
+    for e in errors {
+        let source = e.source().unwrap().to_string();
+        assert!(!e.to_string().contains(&source), "{}", e);
+    }
+
+    let wrapped = CompressToS3Error::Compress(CompressError::Underlying(io()));
+    assert_eq!(wrapped.to_string(), "The input stream failed.");
+    assert_eq!(wrapped.source().unwrap().to_string(), "upstream went away");
+}
@@ -0,0 +1,240 @@
+mod common;
+
+use common::{decompress_all, frames, lines, noise};
+use futures::{executor::block_on_stream, stream};
+use std::{collections::HashSet, convert::Infallible};
+use zstd_seekable_s3::{FrameBoundary, SeekTable, StreamCompress};
+
+const CONTENT_DEFINED: FrameBoundary = FrameBoundary::ContentDefined {
+    min: 1024,
+    avg: 4096,
+    max: 16384,
+};
+
+fn compress_with(data: &[u8], frame_boundary: FrameBoundary) -> Vec<u8> {
+    compress_chunked_with(data, 999, frame_boundary)
+}
+
+fn compress_chunked_with(data: &[u8], chunk_size: usize, frame_boundary: FrameBoundary) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(chunk_size).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .frame_boundary(frame_boundary)
+        .unwrap();
+    block_on_stream(Box::pin(compress))
+        .map(|bytes| bytes.map_err(zstd_seekable::Error::from).unwrap())
+        .fold(Vec::new(), |mut compressed, bytes| {
+            compressed.extend_from_slice(&bytes);
+            compressed
+        })
+}
+
+#[test]
+fn content_defined_roundtrip() {
+    let data = noise(500_000, 1);
+    let compressed = compress_with(&data, CONTENT_DEFINED);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert!(table.num_frames() > 50);
+    for frame in 0..table.num_frames() - 1 {
+        let size = table.frame_decompressed_size(frame);
+        assert!((1024..=16384).contains(&size), "frame of {} bytes", size);
+    }
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn content_defined_ignores_chunking() {
+    let data = noise(200_000, 4);
+    let whole = compress_chunked_with(&data, data.len(), CONTENT_DEFINED);
+    for &chunk_size in &[1, 100, 1023, 1024, 16385] {
+        let compressed = compress_chunked_with(&data, chunk_size, CONTENT_DEFINED);
+        assert!(
+            compressed == whole,
+            "output differs with chunks of {}",
+            chunk_size
+        );
+    }
+}
+
+#[test]
+fn content_defined_survives_insertions() {
+    let data = noise(500_000, 2);
+    let mut edited = data.clone();
+    edited.splice(250_000..250_000, b"some inserted bytes".iter().copied());
+
+    let original = compress_with(&data, CONTENT_DEFINED);
+    let edited = compress_with(&edited, CONTENT_DEFINED);
+    let original_frames: HashSet<&[u8]> = frames(&original).into_iter().collect();
+    let edited_frames = frames(&edited);
+    let shared = edited_frames
+        .iter()
+        .filter(|frame| original_frames.contains(*frame))
+        .count();
+    // Only the frames around the edit should have changed.
+    assert!(
+        shared + 3 >= edited_frames.len(),
+        "only {} of {} frames shared",
+        shared,
+        edited_frames.len()
+    );
+}
+
+#[test]
+fn content_defined_rejects_bad_sizes() {
+    let compress = stream::iter(Vec::<Result<Vec<u8>, Infallible>>::new())
+        .compress(1, 1024)
+        .unwrap();
+    let bad = FrameBoundary::ContentDefined {
+        min: 4096,
+        avg: 1024,
+        max: 16384,
+    };
+    assert!(compress.frame_boundary(bad).is_err());
+}
+
+#[test]
+fn callback_ends_frames_where_asked() {
+    // End frames after every 50 lines. Chunks of 999 bytes don't line up
+    // with lines, so frames end mid-chunk.
+    let data = lines(5010);
+    let every_50_lines = FrameBoundary::callback(1 << 20, |frame| {
+        frame
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'\n')
+            .nth(49)
+            .map(|(i, _)| i + 1)
+    });
+    let compressed = compress_with(&data, every_50_lines);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(table.num_frames(), 101);
+    let decompressed = decompress_all(compressed);
+    for frame in 0..100 {
+        let start = table.frame_decompressed_offset(frame) as usize;
+        let end = start + table.frame_decompressed_size(frame) as usize;
+        let newlines = decompressed[start..end].iter().filter(|&&b| b == b'\n');
+        assert_eq!(newlines.count(), 50);
+        assert_eq!(decompressed[end - 1], b'\n');
+    }
+    assert_eq!(decompressed, data);
+
+    // A callback that never ends frames still gets them ended at max.
+    let never = FrameBoundary::callback(3000, |_| None);
+    let table = SeekTable::parse(&compress_with(&data, never)).unwrap();
+    for frame in 0..table.num_frames() - 1 {
+        assert_eq!(table.frame_decompressed_size(frame), 3000);
+    }
+}
+
+#[test]
+fn delimiter_keeps_lines_whole() {
+    let data = lines(5000);
+    let newlines = FrameBoundary::Delimiter {
+        delimiter: b'\n',
+        target: 2000,
+        max: 1 << 20,
+    };
+    let compressed = compress_with(&data, newlines);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert!(table.num_frames() > 20);
+    let decompressed = decompress_all(compressed);
+    for frame in 0..table.num_frames() {
+        let start = table.frame_decompressed_offset(frame) as usize;
+        let size = table.frame_decompressed_size(frame) as usize;
+        let frame_data = &decompressed[start..start + size];
+        assert_eq!(frame_data.last(), Some(&b'\n'));
+        if frame < table.num_frames() - 1 {
+            // The frame ends at the first newline past the target.
+            assert!(size >= 2000, "frame of {} bytes", size);
+            assert!(!frame_data[1999..size - 1].contains(&b'\n'));
+        }
+    }
+    assert_eq!(decompressed, data);
+
+    // Without delimiters, frames end at max.
+    let data = vec![b'x'; 10_000];
+    let newlines = FrameBoundary::Delimiter {
+        delimiter: b'\n',
+        target: 100,
+        max: 3000,
+    };
+    let table = SeekTable::parse(&compress_with(&data, newlines)).unwrap();
+    let sizes: Vec<_> = (0..table.num_frames())
+        .map(|frame| table.frame_decompressed_size(frame))
+        .collect();
+    assert_eq!(sizes, [3000, 3000, 3000, 1000]);
+}
+
+#[test]
+fn frames_ended_otherwise_start_the_boundary_over() {
+    // The first item ends its frame short of the target, the second has to
+    // start a frame of its own rather than carry on where the first left off.
+    let items: Vec<&[u8]> = vec![b"hi\n", b"hello world hello\n"];
+    let newlines = FrameBoundary::Delimiter {
+        delimiter: b'\n',
+        target: 10,
+        max: 20,
+    };
+    let compress = stream::iter(items.into_iter().map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .frame_boundary(newlines)
+        .unwrap()
+        .frame_per_item(true);
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+    let sizes: Vec<_> = (0..table.num_frames())
+        .map(|frame| table.frame_decompressed_size(frame))
+        .collect();
+    assert_eq!(sizes, [3, 18]);
+}
+
+#[test]
+fn uneven_frames_map_offsets() {
+    // One frame per segment, each ended by a delimiter, of wildly different
+    // sizes.
+    let sizes = [2, 5000, 17, 70_000, 3, 1200, 2, 33_333];
+    let mut data = Vec::new();
+    for (i, &size) in sizes.iter().enumerate() {
+        data.extend((0..size - 1).map(|j| b'a' + ((i + j) % 26) as u8));
+        data.push(b'|');
+    }
+    let segments = FrameBoundary::Delimiter {
+        delimiter: b'|',
+        target: 1,
+        max: 1 << 20,
+    };
+    let compressed = compress_with(&data, segments);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let frame_sizes: Vec<u64> = (0..table.num_frames())
+        .map(|frame| table.frame_decompressed_size(frame))
+        .filter(|&size| size > 0)
+        .collect();
+    assert_eq!(
+        frame_sizes,
+        sizes.iter().map(|&s| s as u64).collect::<Vec<_>>()
+    );
+
+    let mut decompress =
+        zstd_seekable_s3::SeekableDecompress::new(std::io::Cursor::new(compressed)).unwrap();
+    for frame in 0..table.num_frames() {
+        let range = table.frame_range(frame);
+        assert_eq!(
+            range.end - range.start,
+            table.frame_decompressed_size(frame)
+        );
+        if range.is_empty() {
+            continue;
+        }
+        // First and last byte of every frame, and a read across into the
+        // next one.
+        assert_eq!(table.frame_for_offset(range.start), Some(frame));
+        assert_eq!(table.frame_for_offset(range.end - 1), Some(frame));
+        let read = decompress.read_range(range.end - 1, 2).unwrap();
+        let end = (range.end as usize + 1).min(data.len());
+        assert_eq!(read, data[range.end as usize - 1..end]);
+    }
+    assert_eq!(table.frame_for_offset(data.len() as u64), None);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{compress, frames, lines};
+use futures::{executor::block_on_stream, stream};
+use std::{convert::Infallible, io::Cursor};
+use zstd_seekable_s3::{
+    FrameBoundary, FrameCache, FrameCacheKey, SeekTable, SeekableDecompress, StreamCompress,
+};
+
+fn compress_with_lines(data: &[u8]) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(999).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .frame_boundary(FrameBoundary::Delimiter {
+            delimiter: b'\n',
+            target: 1000,
+            max: 1 << 20,
+        })
+        .unwrap();
+    block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect()
+}
+
+// Decompresses every frame of the object through the cache.
+fn decompress_through(cache: &FrameCache, compressed: &[u8]) -> Vec<u8> {
+    let table = SeekTable::parse(compressed).unwrap();
+    frames(compressed)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(frame, data)| cache.decompress_frame(&table, frame, data).unwrap())
+        .collect()
+}
+
+#[test]
+fn shared_frames_are_cached_once() {
+    for key in [FrameCacheKey::Checksum, FrameCacheKey::CompressedHash] {
+        // Both objects start with the same frames.
+        let first = lines(2000);
+        let mut second = first.clone();
+        second.extend_from_slice(b"one more line\n");
+        let first_compressed = compress_with_lines(&first);
+        let second_compressed = compress_with_lines(&second);
+
+        let cache = FrameCache::new(1 << 20, key);
+        assert_eq!(decompress_through(&cache, &first_compressed), first);
+        let cached = cache.len();
+        assert_eq!(
+            cached,
+            SeekTable::parse(&first_compressed).unwrap().num_frames()
+        );
+        assert_eq!(decompress_through(&cache, &second_compressed), second);
+        // Only the last frame differs.
+        assert_eq!(cache.len(), cached + 1);
+    }
+}
+
+#[test]
+fn cache_stays_under_capacity() {
+    let data = lines(20_000);
+    let compressed = compress_with_lines(&data);
+    let cache = FrameCache::new(10_000, FrameCacheKey::CompressedHash);
+    assert_eq!(decompress_through(&cache, &compressed), data);
+    assert!(cache.size() <= 10_000);
+    assert!(!cache.is_empty());
+}
+
+#[test]
+fn bad_frames_are_not_cached() {
+    let data = lines(2000);
+    let compressed = compress_with_lines(&data);
+    let table = SeekTable::parse(&compressed).unwrap();
+    // The second frame passed off as the first doesn't check out.
+    let frames = frames(&compressed);
+    let cache = FrameCache::new(1 << 20, FrameCacheKey::CompressedHash);
+    assert!(cache.decompress_frame(&table, 0, frames[1]).is_err());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn cached_reads_share_frames() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 4096);
+    for key in [FrameCacheKey::Checksum, FrameCacheKey::CompressedHash] {
+        let cache = FrameCache::new(1 << 20, key);
+        let mut decompress = SeekableDecompress::new(Cursor::new(&compressed)).unwrap();
+        decompress.set_frame_cache(Some(cache.clone()));
+
+        let first = decompress.read_range(5000, 100).unwrap();
+        assert_eq!(first, data[5000..5100]);
+        assert_eq!(cache.len(), 1);
+        // Same frame, so this is a slice of what's in the cache.
+        let second = decompress.read_range(4500, 1000).unwrap();
+        assert_eq!(second, data[4500..5500]);
+        assert_eq!(second.as_ptr(), first[..].as_ptr().wrapping_sub(500));
+
+        // Ranges over several frames and past the end still come out right.
+        let across = decompress.read_range(3000, 20_000).unwrap();
+        assert_eq!(across, data[3000..23_000]);
+        let end = decompress.read_range(data.len() as u64 - 10, 100).unwrap();
+        assert_eq!(end, data[data.len() - 10..]);
+        assert!(decompress
+            .read_range(data.len() as u64, 100)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::lines;
+use futures::{executor::block_on_stream, stream};
+use std::convert::Infallible;
+use zstd_seekable_s3::{CompressError, FrameCompressor, SeekTable, StreamCompress};
+
+// Copies input through as it is and ends every frame with a marker, failing
+// once it's been given `fail_after` bytes.
+struct Copy {
+    taken: usize,
+    fail_after: usize,
+}
+
+impl FrameCompressor for Copy {
+    fn compress(
+        &mut self,
+        output: &mut [u8],
+        input: &[u8],
+    ) -> Result<(usize, usize), zstd_seekable::Error> {
+        if self.taken >= self.fail_after {
+            return Err(zstd_seekable::Error::Null);
+        }
+        let n = output.len().min(input.len());
+        output[..n].copy_from_slice(&input[..n]);
+        self.taken += n;
+        Ok((n, n))
+    }
+
+    fn end_frame(&mut self, output: &mut [u8]) -> Result<(usize, usize), zstd_seekable::Error> {
+        match output.first_mut() {
+            Some(byte) => {
+                *byte = b'|';
+                Ok((1, 0))
+            }
+            None => Ok((0, 1)),
+        }
+    }
+}
+
+fn copying(fail_after: usize) -> Copy {
+    Copy {
+        taken: 0,
+        fail_after,
+    }
+}
+
+#[test]
+fn layout_follows_the_frame_compressor() {
+    let data = lines(1000);
+    let compress = stream::iter(data.chunks(700).map(Ok::<_, Infallible>))
+        .compress(1, 4096)
+        .unwrap()
+        .frame_compressor(copying(usize::MAX));
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+
+    // Every frame is its data and the marker, as the seek table says.
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(table.num_frames(), (data.len() + 4095) / 4096);
+    for (frame, bytes) in table.compressed_frames(&compressed) {
+        let start = table.frame_decompressed_offset(frame) as usize;
+        let end = start + table.frame_decompressed_size(frame) as usize;
+        assert_eq!(bytes[..bytes.len() - 1], data[start..end]);
+        assert_eq!(bytes[bytes.len() - 1], b'|');
+    }
+    assert_eq!(table.decompressed_len(), data.len() as u64);
+}
+
+#[test]
+fn frame_compressor_errors_pass_through() {
+    let data = lines(1000);
+    let compress = stream::iter(data.chunks(700).map(Ok::<_, Infallible>))
+        .compress(1, 4096)
+        .unwrap()
+        .frame_compressor(copying(5000));
+    let mut items = block_on_stream(compress);
+    let mut out = 0;
+    let error = loop {
+        match items.next().unwrap() {
+            Ok(bytes) => out += bytes.len(),
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(
+        error,
+        CompressError::ZstdError(zstd_seekable::Error::Null)
+    ));
+    // Items went out until the one after the compressor had taken 5000
+    // bytes, along with the marker of the frame that ended on the way.
+    assert_eq!(out, 8 * 700 + 1);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{decompress_all, lines, noise};
+use futures::{executor::block_on_stream, stream};
+use std::convert::Infallible;
+use zstd_seekable_s3::{
+    plan, Compress, CompressError, FrameBoundary, FramePlan, SeekTable, StreamCompress,
+};
+
+type Input<'a> = stream::Iter<std::vec::IntoIter<Result<&'a [u8], Infallible>>>;
+
+fn compressor(data: &[u8]) -> Compress<Input<'_>, Infallible> {
+    let chunks: Vec<_> = data.chunks(999).map(Ok).collect();
+    stream::iter(chunks).compress(1, 1024).unwrap()
+}
+
+fn run(compress: Compress<Input<'_>, Infallible>) -> Result<Vec<u8>, CompressError<Infallible>> {
+    let mut compressed = Vec::new();
+    for bytes in block_on_stream(compress) {
+        compressed.extend_from_slice(&bytes?);
+    }
+    Ok(compressed)
+}
+
+#[test]
+fn plan_matches_seek_table() {
+    let data = lines(2000);
+    let compressed = run(compressor(&data)
+        .metadata("name", "lines.txt")
+        .frame_plan(data.len() as u64))
+    .unwrap();
+
+    // The plan is there from the first few bytes on.
+    let plan = FramePlan::read(&compressed[..64]).unwrap();
+    assert_eq!(
+        plan,
+        FramePlan {
+            frame_size: 1024,
+            total_len: data.len() as u64
+        }
+    );
+    let table = SeekTable::parse(&compressed).unwrap();
+    // The metadata and the plan come before the data frames.
+    assert_eq!(table.num_frames(), plan.num_frames() + 2);
+    for frame in 0..plan.num_frames() {
+        assert_eq!(
+            plan.frame_decompressed_offset(frame),
+            table.frame_decompressed_offset(frame + 2)
+        );
+        assert_eq!(
+            plan.frame_decompressed_size(frame),
+            table.frame_decompressed_size(frame + 2)
+        );
+    }
+    assert_eq!(plan.frame_for_offset(1024), Some(1));
+    assert_eq!(plan.frame_for_offset(data.len() as u64), None);
+    assert_eq!(decompress_all(compressed), data);
+}
+
+#[test]
+fn plan_checks_input() {
+    let data = lines(2000);
+    let len = data.len() as u64;
+    for wrong_len in [len - 1, len + 1] {
+        let result = run(compressor(&data).frame_plan(wrong_len));
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("frame plan said"), "{}", message);
+    }
+    assert!(run(compressor(&data).frame_per_item(true).frame_plan(len)).is_err());
+    let lines = FrameBoundary::Delimiter {
+        delimiter: b'\n',
+        target: 1000,
+        max: 4000,
+    };
+    let compress = compressor(&data).frame_boundary(lines).unwrap();
+    assert!(run(compress.frame_plan(len)).is_err());
+
+    assert!(run(compressor(&[]).frame_plan(0)).is_ok());
+}
+
+#[test]
+fn plan_matches_compression() {
+    for &len in &[0, 1, 1000, 4096, 4097, 100_000] {
+        let data = noise(len, len as u64);
+        let compressed = common::compress(&data, 1, 4096);
+        let table = SeekTable::parse(&compressed).unwrap();
+        let plan = plan(len as u64, 4096);
+        assert_eq!(plan.frame_count, table.num_frames() as u64, "{}", len);
+        assert_eq!(plan.footer_len, table.seek_table_len() as u64, "{}", len);
+        assert!(
+            compressed.len() as u64 <= plan.estimated_max_output,
+            "{}: {} > {}",
+            len,
+            compressed.len(),
+            plan.estimated_max_output
+        );
+    }
+    assert_eq!(plan(10 << 30, 0).frame_count, 6);
+}
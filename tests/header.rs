@@ -0,0 +1,69 @@
+mod common;
+
+use bytes::Bytes;
+use common::{compress, lines};
+use futures::{executor::block_on_stream, stream, Stream};
+use std::{
+    borrow::Borrow,
+    convert::Infallible,
+    io::{Cursor, Read, Seek, SeekFrom},
+};
+use zstd_seekable_s3::{Compress, SeekableDecompress, SkipHeader, StreamCompress};
+
+const HEADER: &[u8] = b"ENVL\x00\x01";
+
+fn start(
+    data: &[u8],
+) -> Compress<impl Stream<Item = Result<&[u8], Infallible>> + Unpin, Infallible> {
+    stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+}
+
+fn items<S, I>(compress: Compress<S, Infallible>) -> Vec<Bytes>
+where
+    S: Stream<Item = Result<I, Infallible>> + Unpin,
+    I: Borrow<[u8]>,
+{
+    block_on_stream(compress).map(Result::unwrap).collect()
+}
+
+#[test]
+fn header_goes_in_front() {
+    let data = lines(5000);
+    let items = items(start(&data).header(HEADER));
+    assert_eq!(items[0], HEADER);
+    let compressed = items.concat();
+    assert_eq!(compressed[HEADER.len()..], compress(&data, 1, 1024)[..]);
+
+    let reader = SkipHeader::new(Cursor::new(compressed), HEADER.len() as u64).unwrap();
+    let mut decompress = SeekableDecompress::new(reader).unwrap();
+    assert_eq!(
+        decompress.read_range(20_000, 1000).unwrap(),
+        data[20_000..21_000]
+    );
+    let mut out = Vec::new();
+    decompress.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+}
+
+#[test]
+fn header_starts_the_first_part() {
+    let data = lines(5000);
+    let plain = items(start(&data).align_to_parts(10_000));
+    let with_header = items(start(&data).align_to_parts(10_000).header(HEADER));
+    assert_eq!(with_header.len(), plain.len());
+    assert_eq!(with_header[0], [HEADER, &plain[0]].concat());
+    assert_eq!(with_header[1..], plain[1..]);
+}
+
+#[test]
+fn no_seeking_into_the_header() {
+    let mut reader = SkipHeader::new(Cursor::new(b"HEADERdata".to_vec()), 6).unwrap();
+    assert_eq!(reader.seek(SeekFrom::Start(2)).unwrap(), 2);
+    assert!(reader.seek(SeekFrom::Current(-3)).is_err());
+    assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap(), 0);
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "data");
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{compress, lines};
+use std::io::Cursor;
+use zstd_seekable_s3::{
+    multipart_byteranges, serve_range, RangePart, RangeResponse, SeekableDecompress,
+    MAX_HTTP_RANGES,
+};
+
+fn decompress(data: &[u8]) -> SeekableDecompress<'static, Cursor<Vec<u8>>> {
+    SeekableDecompress::new(Cursor::new(compress(data, 1, 1024))).unwrap()
+}
+
+fn single(decompress: &mut SeekableDecompress<'_, Cursor<Vec<u8>>>, range: &str) -> RangePart {
+    match serve_range(decompress, range).unwrap() {
+        RangeResponse::Single(part) => part,
+        response => panic!("unexpected response to {}: {:?}", range, response),
+    }
+}
+
+#[test]
+fn single_ranges() {
+    let data = lines(1000);
+    let len = data.len();
+    let mut decompress = decompress(&data);
+
+    let part = single(&mut decompress, "bytes=100-1999");
+    assert_eq!(part.offset, 100);
+    assert_eq!(part.data, data[100..2000]);
+    assert_eq!(part.content_length(), 1900);
+    assert_eq!(part.content_range, format!("bytes 100-1999/{}", len));
+
+    // Open ended, suffix and past the end, with the unit in any case.
+    let part = single(&mut decompress, "Bytes=5000-");
+    assert_eq!(part.data, data[5000..]);
+    let part = single(&mut decompress, "bytes=-300");
+    assert_eq!(part.data, data[len - 300..]);
+    assert_eq!(
+        part.content_range,
+        format!("bytes {}-{}/{}", len - 300, len - 1, len)
+    );
+    let part = single(
+        &mut decompress,
+        &format!("bytes={}-{}", len - 10, len + 100),
+    );
+    assert_eq!(part.data, data[len - 10..]);
+    let part = single(&mut decompress, &format!("bytes=-{}", len * 2));
+    assert_eq!(part.data, data[..]);
+}
+
+#[test]
+fn multiple_ranges() {
+    let data = lines(1000);
+    let mut decompress = decompress(&data);
+
+    // The range past the end is left out.
+    let response = serve_range(&mut decompress, "bytes=0-9, 5000-5009,, 99999999-").unwrap();
+    assert_eq!(response.status(), 206);
+    let parts = match response {
+        RangeResponse::Multiple(parts) => parts,
+        response => panic!("unexpected response: {:?}", response),
+    };
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].data, data[..10]);
+    assert_eq!(parts[1].data, data[5000..5010]);
+
+    let body = multipart_byteranges(&parts, "text/plain", "BOUNDARY");
+    let expected = [
+        "\r\n--BOUNDARY\r\nContent-Type: text/plain\r\n",
+        &format!("Content-Range: {}\r\n\r\n", parts[0].content_range),
+        std::str::from_utf8(&data[..10]).unwrap(),
+        "\r\n--BOUNDARY\r\nContent-Type: text/plain\r\n",
+        &format!("Content-Range: {}\r\n\r\n", parts[1].content_range),
+        std::str::from_utf8(&data[5000..5010]).unwrap(),
+        "\r\n--BOUNDARY--\r\n",
+    ]
+    .concat();
+    assert_eq!(body, expected.as_bytes());
+}
+
+#[test]
+fn unsatisfiable_and_ignored() {
+    let data = lines(100);
+    let mut decompress = decompress(&data);
+
+    let response = serve_range(&mut decompress, &format!("bytes={}-", data.len())).unwrap();
+    assert_eq!(response.status(), 416);
+    assert_eq!(
+        response,
+        RangeResponse::Unsatisfiable {
+            content_range: format!("bytes */{}", data.len())
+        }
+    );
+    assert_eq!(
+        serve_range(&mut decompress, "bytes=-0").unwrap().status(),
+        416
+    );
+
+    let many = vec!["0-0"; MAX_HTTP_RANGES + 1].join(",");
+    for range in [
+        "",
+        "bytes=",
+        "bytes=10-5",
+        "bytes=a-b",
+        "bytes=1-2-3",
+        "bytes=+1-2",
+        "items=0-10",
+        &format!("bytes={}", many),
+    ] {
+        let response = serve_range(&mut decompress, range).unwrap();
+        assert_eq!(response, RangeResponse::Ignored, "{}", range);
+        assert_eq!(response.status(), 200);
+    }
+}
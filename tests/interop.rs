@@ -0,0 +1,93 @@
+mod common;
+
+// Checks our output against the reference zstd command line tool and the
+// other way around. Skipped when the tool isn't around: set ZSTD_CLI to
+// point at it if it's not on the path as `zstd`, and ZSTD_CLI_REQUIRED to
+// fail rather than skip without it.
+
+use common::{compress, decompress_all, lines, noise};
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    io::Write,
+    process::{Command, Stdio},
+};
+use zstd_seekable_s3::{reframe, SeekTable, StreamCompress};
+
+// Runs the tool with the input on stdin, giving its output. None if there's
+// no tool to run.
+fn zstd_cli(args: &[&str], input: &[u8]) -> Option<Vec<u8>> {
+    let cli = std::env::var("ZSTD_CLI").unwrap_or_else(|_| "zstd".to_owned());
+    let child = Command::new(&cli)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if std::env::var_os("ZSTD_CLI_REQUIRED").is_none() => {
+            eprintln!("Skipping, can't run {}: {}", cli, e);
+            return None;
+        }
+        Err(e) => panic!("Can't run {}: {}", cli, e),
+    };
+    // Write from another thread so a full stdout pipe can't block us.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "{} {:?} failed", cli, args);
+    Some(output.stdout)
+}
+
+#[test]
+fn cli_decompresses_our_output() {
+    let data = lines(20_000);
+    let compressed = compress(&data, 3, 4096);
+    if let Some(decompressed) = zstd_cli(&["-d", "-c"], &compressed) {
+        assert_eq!(decompressed, data);
+    }
+}
+
+#[test]
+fn cli_decompresses_stored_frames() {
+    // Already compressed data doesn't compress again, so gets stored.
+    let data = compress(&noise(1_000_000, 1), 3, 0);
+    let compress = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+        .compress(1, 32 * 1024)
+        .unwrap()
+        .store_incompressible(true);
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    assert!(SeekTable::parse(&compressed).unwrap().num_frames() > 2);
+    if let Some(decompressed) = zstd_cli(&["-d", "-c"], &compressed) {
+        assert_eq!(decompressed, data);
+    }
+}
+
+#[test]
+fn we_read_cli_output() {
+    let data = lines(20_000);
+    // Separate runs of the tool give separate frames, which is as close to
+    // seekable as its output gets.
+    let mut concatenated = Vec::new();
+    for chunk in data.chunks(8192) {
+        match zstd_cli(&["-3", "-c", "--no-progress"], chunk) {
+            Some(frame) => concatenated.extend_from_slice(&frame),
+            None => return,
+        }
+    }
+    let seekable = reframe(&concatenated, 3, 8192).unwrap();
+    // The frames are kept as they are, only the seek table gets added.
+    assert_eq!(seekable[..concatenated.len()], concatenated[..]);
+    let table = SeekTable::parse(&seekable).unwrap();
+    assert_eq!(table.num_frames(), (data.len() + 8191) / 8192);
+    assert_eq!(decompress_all(seekable.clone()), data);
+
+    // And the tool reads it back with the seek table in place.
+    if let Some(decompressed) = zstd_cli(&["-d", "-c"], &seekable) {
+        assert_eq!(decompressed, data);
+    }
+}
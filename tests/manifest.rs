@@ -0,0 +1,102 @@
+mod common;
+
+use common::lines;
+use futures::{
+    executor::{block_on, block_on_stream},
+    stream,
+};
+use std::convert::Infallible;
+use zstd_seekable_s3::{
+    compress_with_manifest, ContentHasher, SeekTable, StreamCompress, Xxh64Hasher,
+};
+
+#[test]
+fn manifest_matches_seek_table() {
+    let data = lines(5000);
+    let (compress, manifest) = compress_with_manifest(
+        stream::iter(data.chunks(100).map(Ok::<_, Infallible>)),
+        1,
+        1024,
+    )
+    .unwrap();
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let manifest = block_on(manifest).unwrap();
+
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(manifest.compressed_len, compressed.len() as u64);
+    assert_eq!(manifest.frames.len(), table.num_frames());
+    for (frame, meta) in manifest.frames.iter().enumerate() {
+        assert_eq!(meta.compressed_offset, table.frame_compressed_offset(frame));
+        assert_eq!(meta.compressed_size, table.frame_compressed_size(frame));
+        assert_eq!(
+            meta.decompressed_offset,
+            table.frame_decompressed_offset(frame)
+        );
+        assert_eq!(meta.decompressed_size, table.frame_decompressed_size(frame));
+        assert_eq!(meta.checksum, table.frame_checksum(frame));
+    }
+    assert_eq!(
+        manifest.content_hash,
+        xxhash_rust::xxh64::xxh64(&data, 0).to_be_bytes()
+    );
+}
+
+// Just counts the bytes.
+struct Length(u64);
+
+impl ContentHasher for Length {
+    fn update(&mut self, data: &[u8]) {
+        self.0 += data.len() as u64;
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+}
+
+#[test]
+fn manifest_with_own_hasher() {
+    let data = lines(5000);
+    let (compress, mut manifest) = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .manifest(Length(0));
+    let mut compress = block_on_stream(compress);
+    // Not done until the seek table is out.
+    compress.next().unwrap().unwrap();
+    assert!(futures::FutureExt::now_or_never(&mut manifest).is_none());
+    compress.for_each(|bytes| drop(bytes.unwrap()));
+    let manifest = block_on(manifest).unwrap();
+    assert_eq!(manifest.content_hash, (data.len() as u64).to_le_bytes());
+}
+
+#[test]
+fn manifest_canceled_when_dropped() {
+    let (compress, manifest) = compress_with_manifest(
+        stream::iter(vec![Ok::<_, Infallible>(&b"data"[..])]),
+        1,
+        1024,
+    )
+    .unwrap();
+    drop(compress);
+    assert!(block_on(manifest).is_err());
+}
+
+#[test]
+fn hash_stream_covers_output() {
+    let data = lines(5000);
+    let (compress, hash) = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .hash_stream(Xxh64Hasher::default());
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    assert_eq!(
+        block_on(hash).unwrap(),
+        xxhash_rust::xxh64::xxh64(&compressed, 0).to_be_bytes()
+    );
+    SeekTable::parse(&compressed).unwrap();
+}
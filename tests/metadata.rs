@@ -0,0 +1,194 @@
+mod common;
+
+use common::{compress, lines};
+use futures::{executor::block_on_stream, stream};
+use std::{
+    convert::Infallible,
+    io::{Cursor, Read},
+};
+use zstd_seekable_s3::{
+    quick_totals_from_frame, quick_totals_range, Error, SeekTable, SeekableDecompress,
+    StreamCompress, SEEK_TABLE_FOOTER_LEN,
+};
+
+fn compress_with_metadata(data: &[u8]) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .metadata("path", "logs/app.log")
+        .metadata("mtime", "1700000000");
+    block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn metadata_roundtrip() {
+    let data = lines(5000);
+    let compressed = compress_with_metadata(&data);
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(table.frame_decompressed_size(0), 0);
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(
+        decompress.metadata().unwrap(),
+        vec![
+            ("path".to_owned(), "logs/app.log".to_owned()),
+            ("mtime".to_owned(), "1700000000".to_owned())
+        ]
+    );
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+    assert_eq!(decompress.decompress_all_parallel(4).unwrap(), data);
+    assert!(decompress.verify_all(4).unwrap().is_ok());
+}
+
+#[test]
+fn metadata_of_empty_data() {
+    let compressed = compress_with_metadata(&[]);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(decompress.metadata().unwrap().len(), 2);
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed.is_empty());
+}
+
+#[test]
+fn no_metadata() {
+    let compressed = compress(&lines(100), 1, 1024);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+    assert!(decompress.metadata().unwrap().is_empty());
+}
+
+#[test]
+fn trailing_index_roundtrip() {
+    let data = lines(5000);
+    let compress_indexed = || {
+        stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+            .compress(1, 1024)
+            .unwrap()
+            .trailing_index(|table: &SeekTable| {
+                format!("{} frames", table.num_frames()).into_bytes()
+            })
+    };
+    let compressed: Vec<u8> = block_on_stream(compress_indexed())
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+    let frames = table.num_frames();
+    assert_eq!(table.frame_decompressed_size(frames - 1), 0);
+    assert_eq!(table.decompressed_len(), data.len() as u64);
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    let index = format!("{} frames", frames - 1);
+    assert_eq!(
+        decompress.trailing_index().unwrap().unwrap(),
+        index.as_bytes()
+    );
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+    assert!(decompress.verify_all(4).unwrap().is_ok());
+
+    // Frame by frame, the index goes out on its own.
+    let chunks: Vec<_> = block_on_stream(compress_indexed().by_frame())
+        .map(|bytes| bytes.unwrap())
+        .collect();
+    assert_eq!(chunks.len(), frames + 1);
+    assert_eq!(chunks[frames - 1][8..], *index.as_bytes());
+    assert_eq!(chunks.concat(), compressed);
+
+    let plain = compress(&data, 1, 1024);
+    let mut decompress = SeekableDecompress::new(Cursor::new(plain)).unwrap();
+    assert_eq!(decompress.trailing_index().unwrap(), None);
+}
+
+#[test]
+fn totals_frame_gives_totals() {
+    let data = lines(5000);
+    let compress_with_totals = || {
+        stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+            .compress(1, 1024)
+            .unwrap()
+            .totals_frame(true)
+            .trailing_index(|_: &SeekTable| b"index".to_vec())
+    };
+    let compressed: Vec<u8> = block_on_stream(compress_with_totals())
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&compressed).unwrap();
+    let totals = (data.len() as u64, table.num_frames() as u64);
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(compressed.clone())).unwrap();
+    assert_eq!(decompress.quick_totals().unwrap(), Some(totals));
+    // The index is still found in front of it, and the data is untouched.
+    assert_eq!(&decompress.trailing_index().unwrap().unwrap()[..], b"index");
+    let mut decompressed = Vec::new();
+    decompress.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+
+    // From the footer and the frame alone, in two small reads.
+    let footer = &compressed[compressed.len() - SEEK_TABLE_FOOTER_LEN..];
+    let range = quick_totals_range(footer, compressed.len() as u64).unwrap();
+    assert_eq!(
+        range.end,
+        (compressed.len() - table.seek_table_len()) as u64
+    );
+    let frame = &compressed[range.start as usize..range.end as usize];
+    assert_eq!(frame.len(), 24);
+    assert_eq!(quick_totals_from_frame(frame, footer), Some(totals));
+    assert_eq!(quick_totals_from_frame(&frame[1..], footer), None);
+    assert!(quick_totals_range(footer, 30).is_err());
+
+    // Frame by frame, it goes out on its own.
+    let chunks: Vec<_> = block_on_stream(compress_with_totals().by_frame())
+        .map(|bytes| bytes.unwrap())
+        .collect();
+    assert_eq!(chunks.len(), table.num_frames() + 1);
+    assert_eq!(chunks.concat(), compressed);
+
+    let plain = compress(&data, 1, 1024);
+    let mut decompress = SeekableDecompress::new(Cursor::new(plain.clone())).unwrap();
+    assert_eq!(decompress.quick_totals().unwrap(), None);
+    let footer = &plain[plain.len() - SEEK_TABLE_FOOTER_LEN..];
+    let range = quick_totals_range(footer, plain.len() as u64).unwrap();
+    let frame = &plain[range.start as usize..range.end as usize];
+    assert_eq!(quick_totals_from_frame(frame, footer), None);
+}
+
+// An object of the given frames, with a seek table without checksums saying
+// the first decompresses to nothing and the rest to `data_len` each.
+fn object_of(frames: &[&[u8]], data_len: u32) -> Vec<u8> {
+    let mut object: Vec<u8> = frames.concat();
+    object.extend_from_slice(&0x184D_2A5Eu32.to_le_bytes());
+    object.extend_from_slice(&(frames.len() as u32 * 8 + 9).to_le_bytes());
+    for (i, frame) in frames.iter().enumerate() {
+        object.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        let decompressed = if i == 0 { 0 } else { data_len };
+        object.extend_from_slice(&decompressed.to_le_bytes());
+    }
+    object.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    object.push(0);
+    object.extend_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+    object
+}
+
+#[test]
+fn malformed_metadata_frame() {
+    let data = lines(50);
+    let compressed = compress(&data, 1, 0);
+    let frame = &common::frames(&compressed)[0];
+    let open = |object| SeekableDecompress::new(Cursor::new(object)).unwrap();
+
+    // Shorter than a skippable frame's header, but starting like ours.
+    let short = object_of(&[&[0x5C, 0x2A, 0x4D, 0x18, 0], frame], data.len() as u32);
+    assert!(open(short).metadata().unwrap().is_empty());
+
+    // Claiming far more than the seek table gives it.
+    let mut huge = vec![0x5C, 0x2A, 0x4D, 0x18];
+    huge.extend_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+    huge.extend_from_slice(&[0; 8]);
+    let huge = object_of(&[&huge, frame], data.len() as u32);
+    assert!(matches!(open(huge).metadata(), Err(Error::BadMetadata)));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{compress, lines};
+use rusoto_core::{credential::AwsCredentials, Region};
+use rusoto_s3::{util::PreSignedRequestOption, GetObjectRequest};
+use std::time::Duration;
+use zstd_seekable_s3::{presign_frames, SeekTable};
+
+#[test]
+fn presigned_frames_cover_range() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let request = GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        key: "object.zst".to_owned(),
+        ..Default::default()
+    };
+    let credentials = AwsCredentials::new("key", "secret", None, None);
+    let option = PreSignedRequestOption {
+        expires_in: Duration::from_secs(600),
+    };
+
+    let frames = presign_frames(
+        &table,
+        &request,
+        &Region::EuWest1,
+        &credentials,
+        &option,
+        1500,
+        3000,
+    );
+    assert_eq!(frames.len(), 4);
+    let mut read = Vec::new();
+    for frame in &frames {
+        assert!(frame.url.contains("/bucket/object.zst?"));
+        assert!(frame.url.contains("X-Amz-Expires=600"));
+        assert!(frame.url.contains("range"));
+        let start = frame.meta.compressed_offset as usize;
+        let end = start + frame.meta.compressed_size as usize;
+        assert_eq!(frame.range, format!("bytes={}-{}", start, end - 1));
+
+        let decompressed = zstd_seekable::DStream::new()
+            .and_then(|mut dstream| {
+                let mut out = vec![0; frame.meta.decompressed_size as usize];
+                dstream.decompress(&mut out, &compressed[start..end])?;
+                Ok(out)
+            })
+            .unwrap();
+        read.extend_from_slice(
+            &decompressed[frame.skip as usize..(frame.skip + frame.len) as usize],
+        );
+    }
+    assert_eq!(frames[0].skip, 1500 - frames[0].meta.decompressed_offset);
+    assert_eq!(read, data[1500..4500]);
+
+    assert!(presign_frames(
+        &table,
+        &request,
+        &Region::EuWest1,
+        &credentials,
+        &option,
+        data.len() as u64,
+        10,
+    )
+    .is_empty());
+}
+
+#[test]
+fn custom_endpoints_address_buckets_by_path() {
+    let table = SeekTable::parse(&compress(&lines(100), 1, 1024)).unwrap();
+    let request = GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        key: "object.zst".to_owned(),
+        ..Default::default()
+    };
+    let region = Region::Custom {
+        name: "us-east-1".to_owned(),
+        endpoint: "http://localhost:9000".to_owned(),
+    };
+    let frames = presign_frames(
+        &table,
+        &request,
+        &region,
+        &AwsCredentials::new("key", "secret", None, None),
+        &PreSignedRequestOption {
+            expires_in: Duration::from_secs(600),
+        },
+        0,
+        10,
+    );
+    assert!(frames[0]
+        .url
+        .starts_with("http://localhost:9000/bucket/object.zst?"));
+}
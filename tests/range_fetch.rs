@@ -0,0 +1,60 @@
+mod common;
+
+use common::{compress, fake_s3::FakeS3, lines};
+use rusoto_s3::GetObjectRequest;
+use std::io::{Read, Seek, SeekFrom};
+use zstd_seekable_s3::{FileRangeFetch, RangeReader, S3RangeFetch, SeekableDecompress};
+
+fn check_reads<A: Read + Seek>(compressed: A, data: &[u8]) {
+    let mut decompress = SeekableDecompress::new(compressed).unwrap();
+
+    let offset = data.len() / 3;
+    decompress.seek(SeekFrom::Start(offset as u64)).unwrap();
+    let mut read = vec![0; 10_000];
+    decompress.read_exact(&mut read).unwrap();
+    assert_eq!(read, data[offset..offset + 10_000]);
+
+    decompress.seek(SeekFrom::Start(0)).unwrap();
+    let mut all = Vec::new();
+    decompress.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+}
+
+#[test]
+fn decompress_from_file() {
+    let data = lines(20_000);
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut file, &compress(&data, 1, 4096)).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let file = runtime
+        .block_on(tokio::fs::File::open(file.path()))
+        .unwrap();
+    let mut reader = RangeReader::new(FileRangeFetch::new(file), runtime.handle().clone()).unwrap();
+    reader.set_fetch_size(1000);
+    check_reads(reader, &data);
+}
+
+#[test]
+fn decompress_from_s3() {
+    let data = lines(20_000);
+    let s3 = FakeS3::default();
+    s3.put_object("object.zst", compress(&data, 1, 4096));
+    let req = GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        key: "object.zst".to_owned(),
+        range: Some("bytes=0-99".to_owned()),
+        ..Default::default()
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let fetch = S3RangeFetch::new(s3.client(), req);
+    let mut reader = RangeReader::new(fetch, runtime.handle().clone()).unwrap();
+    reader.set_fetch_size(1000);
+    check_reads(reader, &data);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{compress, decompress_all, lines};
+use futures::{executor::block_on_stream, stream};
+use std::convert::Infallible;
+use zstd_seekable_s3::{reframe, ReframeError, SeekTable, StreamCompress};
+
+fn plain_zstd(data: &[u8], frame_size: usize) -> Vec<u8> {
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, frame_size)
+        .unwrap()
+        .seek_table(false);
+    block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn multiple_frames_just_get_a_seek_table() {
+    let data = lines(5000);
+    let plain = plain_zstd(&data, 1024);
+    let reframed = reframe(&plain, 1, 1024).unwrap();
+    assert_eq!(reframed[..plain.len()], plain[..]);
+    // Exactly what compressing it seekably in the first place gives.
+    assert_eq!(reframed, compress(&data, 1, 1024));
+}
+
+#[test]
+fn single_frame_gets_recompressed() {
+    let data = lines(5000);
+    let plain = plain_zstd(&data, 0);
+    let reframed = reframe(&plain, 1, 1024).unwrap();
+    let table = SeekTable::parse(&reframed).unwrap();
+    assert!(table.num_frames() > 50);
+    assert_eq!(decompress_all(reframed), data);
+}
+
+#[test]
+fn skippable_frames_are_kept() {
+    let data = lines(5000);
+    let mut plain = plain_zstd(&data[..40_000], 1024);
+    // A skippable frame with 3 bytes of content.
+    plain.extend_from_slice(&[0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+    plain.extend(plain_zstd(&data[40_000..], 1024));
+    let reframed = reframe(&plain, 1, 1024).unwrap();
+    assert_eq!(reframed[..plain.len()], plain[..]);
+    assert_eq!(decompress_all(reframed), data);
+}
+
+#[test]
+fn rejects_garbage() {
+    let mut plain = plain_zstd(&lines(100), 1024);
+    plain.truncate(plain.len() - 1);
+    assert!(matches!(
+        reframe(&plain, 1, 1024),
+        Err(ReframeError::Malformed(_))
+    ));
+    assert!(matches!(
+        reframe(b"not zstd", 1, 1024),
+        Err(ReframeError::Malformed(0))
+    ));
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{compress, lines};
+use futures::{executor::block_on, stream};
+use std::convert::Infallible;
+use tokio::io::AsyncReadExt;
+use zstd_seekable_s3::{ring_buffer, StreamCompress};
+
+#[test]
+fn ring_passes_everything_through() {
+    let data = lines(20_000);
+    let (writer, mut reader) = ring_buffer(1000);
+    let write = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .write_to_ring(writer);
+
+    let mut compressed = Vec::new();
+    let (written, read) =
+        block_on(async { futures::join!(write, reader.read_to_end(&mut compressed)) });
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(compressed, compress(&data, 1, 1024));
+}
+
+#[test]
+fn reader_fails_when_writer_stops_early() {
+    let (writer, mut reader) = ring_buffer(1000);
+    let failing = stream::iter(vec![Ok(vec![0u8; 100]), Err("upstream failed")]);
+    let write = failing.compress(1, 1024).unwrap().write_to_ring(writer);
+
+    let mut compressed = Vec::new();
+    let (written, read) =
+        block_on(async { futures::join!(write, reader.read_to_end(&mut compressed)) });
+    assert!(written.is_err());
+    assert!(read.is_err());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{decompress_all, noise};
+use futures::{executor::block_on_stream, stream};
+use std::{convert::Infallible, io::Cursor};
+use zstd_seekable_s3::{bundle_entries, compress_with_roll_over, SeekTable};
+
+#[test]
+fn rolls_over_into_separate_objects() {
+    let data = noise(200_000, 3);
+    let roll_over_bytes = 30_000;
+    let roll_over = compress_with_roll_over(
+        stream::iter(data.chunks(7000).map(Ok::<_, Infallible>)),
+        1,
+        4096,
+        roll_over_bytes,
+    )
+    .unwrap();
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    for chunk in block_on_stream(roll_over) {
+        let (object, bytes) = chunk.unwrap();
+        if object == objects.len() {
+            objects.push(Vec::new());
+        }
+        assert_eq!(object, objects.len() - 1);
+        objects[object].extend_from_slice(&bytes);
+    }
+    assert!(objects.len() > 2);
+
+    let mut decompressed = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        let table = SeekTable::parse(object).unwrap();
+        let len = object.len();
+        assert_eq!(
+            table.compressed_len() + table.seek_table_len() as u64,
+            len as u64
+        );
+        if i + 1 < objects.len() {
+            assert!(len >= roll_over_bytes);
+            // Less than a frame over.
+            let last = table.num_frames() - 1;
+            assert!(len - (table.frame_compressed_size(last) as usize) < roll_over_bytes);
+        }
+        decompressed.extend(decompress_all(object.clone()));
+    }
+    assert_eq!(decompressed, data);
+
+    // Back to back they make a bundle.
+    let bundle = objects.concat();
+    let entries = bundle_entries(&mut Cursor::new(&bundle)).unwrap();
+    assert_eq!(entries.len(), objects.len());
+}
+
+#[test]
+fn roll_over_of_nothing_is_one_empty_object() {
+    let roll_over = compress_with_roll_over(
+        stream::iter(Vec::<Result<&[u8], Infallible>>::new()),
+        1,
+        4096,
+        100,
+    )
+    .unwrap();
+    let chunks: Vec<_> = block_on_stream(roll_over).map(Result::unwrap).collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].0, 0);
+    assert!(decompress_all(chunks[0].1.to_vec()).is_empty());
+}
@@ -0,0 +1,325 @@
+mod common;
+
+use common::{compress, lines, noise};
+use futures::stream;
+use rusoto_core::{HttpClient, Region};
+use rusoto_credential::EnvironmentProvider;
+use rusoto_s3::{DeleteObjectRequest, GetObjectRequest, PutObjectRequest, S3Client, S3};
+use std::convert::Infallible;
+use tokio::io::AsyncReadExt;
+use zstd_seekable_s3::{
+    compress_to_s3, compress_with_roll_over, resume_compress_to_s3, CompressToS3Config, FrameCache,
+    FrameCacheKey, S3ReadError, SeekTable, SeekableS3Object, ShardedS3Object, SpillRecovery,
+};
+
+// Uploads and reads back through an S3 compatible server such as MinIO or
+// localstack, only if one is given:
+//
+// ZSTD_SEEKABLE_S3_TEST_ENDPOINT=http://localhost:9000
+// ZSTD_SEEKABLE_S3_TEST_BUCKET=some-bucket-that-exists
+//
+// Credentials come from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY.
+// The client and bucket to test against, None if there's none.
+fn test_server() -> Option<(S3Client, String)> {
+    let (endpoint, bucket) = match (
+        std::env::var("ZSTD_SEEKABLE_S3_TEST_ENDPOINT"),
+        std::env::var("ZSTD_SEEKABLE_S3_TEST_BUCKET"),
+    ) {
+        (Ok(endpoint), Ok(bucket)) => (endpoint, bucket),
+        _ => {
+            eprintln!(
+                "ZSTD_SEEKABLE_S3_TEST_ENDPOINT and ZSTD_SEEKABLE_S3_TEST_BUCKET not set, skipping"
+            );
+            return None;
+        }
+    };
+    let region = Region::Custom {
+        name: std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_owned()),
+        endpoint,
+    };
+    let client = S3Client::new_with(
+        HttpClient::new().unwrap(),
+        EnvironmentProvider::default(),
+        region,
+    );
+    Some((client, bucket))
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn upload_and_read_range() {
+    let (client, bucket) = match test_server() {
+        Some(server) => server,
+        None => return,
+    };
+    let key = format!("zstd-seekable-s3-test-{}.zst", std::process::id());
+    let runtime = runtime();
+
+    let data = lines(200_000);
+    let config = CompressToS3Config {
+        frame_size: 64 * 1024,
+        part_size: 5 * 1024 * 1024,
+        ..Default::default()
+    };
+    let source = stream::iter(data.chunks(10_000).map(Ok::<_, Infallible>));
+    runtime
+        .block_on(compress_to_s3(
+            source,
+            &client,
+            bucket.clone(),
+            key.clone(),
+            config,
+        ))
+        .unwrap();
+
+    let req = GetObjectRequest {
+        bucket: bucket.clone(),
+        key: key.clone(),
+        ..Default::default()
+    };
+    let mut object = SeekableS3Object::new(client.clone(), runtime.handle().clone(), None, req)
+        .unwrap()
+        .unwrap();
+    assert_eq!(object.decompressed_len().unwrap(), data.len() as u64);
+    let read = object.read_decompressed(1_000_000, 300_000).unwrap();
+    assert_eq!(read, data[1_000_000..1_300_000]);
+    // That's frames 15 to 19, each with a GET of its own after the one
+    // for the seek table.
+    let stats = object.read_stats();
+    assert_eq!(stats.decompress_micros.iter().sum::<u64>(), 5);
+    assert_eq!(stats.ranged_gets, 6);
+    assert!(stats.bytes_fetched > 0);
+    assert_eq!((stats.cache_hits, stats.cache_misses), (0, 0));
+
+    // Frames 30 to 32, fetched ahead of time and then read from the cache.
+    let cache = FrameCache::new(1024 * 1024, FrameCacheKey::Checksum);
+    object.set_frame_cache(Some(cache.clone()));
+    let prefetched = object
+        .prefetch_ranges(&[(2_000_000, 100_000), (2_050_000, 10)], 4)
+        .unwrap();
+    assert_eq!(prefetched, 3);
+    assert_eq!(cache.len(), 3);
+    let read = object.read_decompressed(2_000_000, 100_000).unwrap();
+    assert_eq!(read, data[2_000_000..2_100_000]);
+    let stats = object.read_stats();
+    assert_eq!((stats.cache_hits, stats.cache_misses), (3, 3));
+    match object.prefetch_ranges(&[(0, data.len())], 4) {
+        Err(S3ReadError::PrefetchTooLarge { capacity, .. }) => {
+            assert_eq!(capacity, 1024 * 1024)
+        }
+        other => panic!("expected PrefetchTooLarge, got {:?}", other),
+    }
+    object.set_frame_cache(None);
+
+    // Newest first, frame by frame.
+    let mut end = data.len();
+    for frame in object.frames_rev() {
+        let (_, frame) = frame.unwrap();
+        assert_eq!(frame, data[end - frame.len()..end]);
+        end -= frame.len();
+    }
+    assert_eq!(end, 0);
+
+    runtime
+        .block_on(client.delete_object(DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        }))
+        .unwrap();
+}
+
+#[test]
+fn read_any_frame_size() {
+    let (client, bucket) = match test_server() {
+        Some(server) => server,
+        None => return,
+    };
+    let key = format!("zstd-seekable-s3-test-{}.zst", std::process::id());
+    let runtime = runtime();
+
+    let data = lines(2000);
+    let len = data.len();
+    // Tiny frames have a seek table too big for the first fetch of the tail.
+    for &frame_size in &[4, 1000, 4 * len] {
+        runtime
+            .block_on(client.put_object(PutObjectRequest {
+                bucket: bucket.clone(),
+                key: key.clone(),
+                body: Some(compress(&data, 1, frame_size).into()),
+                ..Default::default()
+            }))
+            .unwrap();
+        let req = GetObjectRequest {
+            bucket: bucket.clone(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let mut object = SeekableS3Object::new(client.clone(), runtime.handle().clone(), None, req)
+            .unwrap()
+            .unwrap();
+        assert_eq!(object.decompressed_len().unwrap(), len as u64);
+        for &offset in &[0, 1001, len - 50] {
+            let read = object.read_decompressed(offset as u64, 100).unwrap();
+            assert_eq!(
+                read,
+                data[offset..(offset + 100).min(len)],
+                "{}",
+                frame_size
+            );
+        }
+    }
+
+    runtime
+        .block_on(client.delete_object(DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        }))
+        .unwrap();
+}
+
+#[test]
+fn resume_from_spill() {
+    let (client, bucket) = match test_server() {
+        Some(server) => server,
+        None => return,
+    };
+    let key = format!("zstd-seekable-s3-test-spill-{}.zst", std::process::id());
+    let runtime = runtime();
+
+    // A spill cut off partway through, with nothing uploaded yet.
+    let data = lines(200_000);
+    let compressed = compress(&data, 3, 64 * 1024);
+    let spill = std::env::temp_dir().join(format!("zstd-seekable-s3-spill-{}", std::process::id()));
+    std::fs::write(&spill, &compressed[..compressed.len() / 3]).unwrap();
+    let recovery = SpillRecovery::open(&spill).unwrap();
+    let rest = &data[recovery.decompressed_len() as usize..];
+
+    let config = CompressToS3Config {
+        compression_level: 3,
+        frame_size: 64 * 1024,
+        part_size: 5 * 1024 * 1024,
+        spill: Some(spill.clone()),
+        ..Default::default()
+    };
+    let source = stream::iter(rest.chunks(10_000).map(Ok::<_, Infallible>));
+    let report = runtime
+        .block_on(resume_compress_to_s3(
+            recovery,
+            source,
+            &client,
+            bucket.clone(),
+            key.clone(),
+            config,
+        ))
+        .unwrap();
+    assert_eq!(report.original_len, data.len() as u64);
+    assert_eq!(report.compressed_len, compressed.len() as u64);
+    assert_eq!(std::fs::metadata(&spill).unwrap().len(), 0);
+
+    // Same as if it had all been compressed in one go.
+    let uploaded = runtime.block_on(async {
+        let object = client
+            .get_object(GetObjectRequest {
+                bucket: bucket.clone(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut uploaded = Vec::new();
+        object
+            .body
+            .unwrap()
+            .into_async_read()
+            .read_to_end(&mut uploaded)
+            .await
+            .unwrap();
+        uploaded
+    });
+    assert_eq!(uploaded, compressed);
+
+    std::fs::remove_file(&spill).unwrap();
+    runtime
+        .block_on(client.delete_object(DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        }))
+        .unwrap();
+}
+
+#[test]
+fn sharded_read_spans_shards() {
+    let (client, bucket) = match test_server() {
+        Some(server) => server,
+        None => return,
+    };
+    let key = |shard| format!("zstd-seekable-s3-test-{}.zst.{}", std::process::id(), shard);
+    let runtime = runtime();
+
+    let data = noise(500_000, 1);
+    let shards = compress_with_roll_over(
+        stream::iter(data.chunks(10_000).map(Ok::<_, Infallible>)),
+        1,
+        16 * 1024,
+        100_000,
+    )
+    .unwrap();
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    for item in futures::executor::block_on_stream(Box::pin(shards)) {
+        let (object, bytes) = item.unwrap();
+        if object == objects.len() {
+            objects.push(Vec::new());
+        }
+        objects[object].extend_from_slice(&bytes);
+    }
+    let seek_tables: Vec<SeekTable> = objects
+        .iter()
+        .map(|object| SeekTable::parse(object).unwrap())
+        .collect();
+    for (shard, object) in objects.into_iter().enumerate() {
+        runtime
+            .block_on(client.put_object(PutObjectRequest {
+                bucket: bucket.clone(),
+                key: key(shard),
+                body: Some(object.into()),
+                ..Default::default()
+            }))
+            .unwrap();
+    }
+
+    let req = GetObjectRequest {
+        bucket: bucket.clone(),
+        ..Default::default()
+    };
+    let shards = seek_tables.len();
+    let mut sharded = ShardedS3Object::new(
+        client.clone(),
+        runtime.handle().clone(),
+        None,
+        req,
+        key,
+        seek_tables,
+    );
+    // Starting in the first shard and ending in the last.
+    let read = sharded.read_range(1000, data.len() as u64).unwrap();
+    assert_eq!(read, data[1000..]);
+
+    for shard in 0..shards {
+        runtime
+            .block_on(client.delete_object(DeleteObjectRequest {
+                bucket: bucket.clone(),
+                key: key(shard),
+                ..Default::default()
+            }))
+            .unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+mod common;
+
+use common::{compress, lines};
+use futures::{executor::block_on_stream, stream};
+use std::{convert::Infallible, io::Cursor};
+use zstd_seekable_s3::{
+    SeekTable, SeekTableError, SeekableDecompress, StreamCompress, DEFAULT_MAX_FRAMES,
+};
+
+#[test]
+fn parse_matches_decompressor() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+
+    let parsed = SeekTable::parse(&compressed).unwrap();
+    let from_decompressor = SeekableDecompress::new(Cursor::new(compressed.clone()))
+        .unwrap()
+        .seek_table()
+        .unwrap();
+
+    assert!(parsed.num_frames() > 1);
+    assert_eq!(parsed.num_frames(), from_decompressor.num_frames());
+    for frame in 0..parsed.num_frames() {
+        assert_eq!(
+            parsed.frame_compressed_offset(frame),
+            from_decompressor.frame_compressed_offset(frame)
+        );
+        assert_eq!(
+            parsed.frame_decompressed_size(frame),
+            from_decompressor.frame_decompressed_size(frame)
+        );
+    }
+    assert_eq!(parsed.decompressed_len(), data.len() as u64);
+    assert_eq!(
+        parsed.compressed_len() + parsed.seek_table_len() as u64,
+        compressed.len() as u64
+    );
+}
+
+#[test]
+fn checksums_roundtrip() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+
+    assert!(table.has_checksums());
+    for frame in 0..table.num_frames() {
+        let offset = table.frame_decompressed_offset(frame) as usize;
+        let frame_data = &data[offset..offset + table.frame_decompressed_size(frame) as usize];
+        assert_eq!(
+            table.frame_checksum(frame),
+            Some(xxhash_rust::xxh64::xxh64(frame_data, 0) as u32)
+        );
+    }
+    let bytes = table.to_bytes();
+    assert_eq!(bytes[..], compressed[compressed.len() - bytes.len()..]);
+    assert_eq!(SeekTable::parse(&bytes).unwrap(), table);
+}
+
+#[test]
+fn append_matches_serial() {
+    let data = lines(5000);
+    let (first, second) = data.split_at(30_000);
+    // Ending a frame after every item ends one where the halves meet, just
+    // like compressing them separately.
+    let halves = stream::iter(vec![Ok::<_, Infallible>(first), Ok(second)]);
+    let serial = halves.compress(1, 1024).unwrap().frame_per_item(true);
+    let serial: Vec<u8> = block_on_stream(Box::pin(serial))
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let serial = SeekTable::parse(&serial).unwrap();
+
+    let mut table = SeekTable::parse(&compress(first, 1, 1024)).unwrap();
+    let other = SeekTable::parse(&compress(second, 1, 1024)).unwrap();
+    let (compressed_len, decompressed_len) = (table.compressed_len(), table.decompressed_len());
+    assert!(table
+        .append(&other, compressed_len + 1, decompressed_len)
+        .is_err());
+    assert!(table.append(&other, compressed_len, 0).is_err());
+    table
+        .append(&other, compressed_len, decompressed_len)
+        .unwrap();
+    assert_eq!(table, serial);
+    assert_eq!(table.to_bytes(), serial.to_bytes());
+}
+
+#[test]
+fn parse_only_needs_tail() {
+    let compressed = compress(&lines(5000), 1, 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let tail = &compressed[compressed.len() - table.seek_table_len()..];
+    assert_eq!(SeekTable::parse(tail).unwrap(), table);
+    assert!(SeekTable::parse(&tail[1..]).is_err());
+}
+
+#[test]
+fn implausible_frame_counts_rejected() {
+    // Nothing but a footer claiming 4 billion frames.
+    let mut footer = u32::MAX.to_le_bytes().to_vec();
+    footer.push(0x80);
+    footer.extend_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+    assert!(matches!(
+        SeekTable::len_from_footer(&footer),
+        Err(SeekTableError::TooManyFrames { frames, max })
+            if frames == u32::MAX as usize && max == DEFAULT_MAX_FRAMES
+    ));
+    assert!(matches!(
+        SeekTable::parse(&footer),
+        Err(SeekTableError::TooManyFrames { .. })
+    ));
+
+    let compressed = compress(&lines(5000), 1, 1024);
+    let frames = SeekTable::parse(&compressed).unwrap().num_frames();
+    assert!(SeekTable::parse_with_max_frames(&compressed, frames).is_ok());
+    assert!(matches!(
+        SeekTable::parse_with_max_frames(&compressed, frames - 1),
+        Err(SeekTableError::TooManyFrames { .. })
+    ));
+    assert!(SeekTable::len_from_footer_with_max_frames(&compressed, frames - 1).is_err());
+}
+
+#[test]
+fn frame_for_offset_matches_linear_scan() {
+    let data = lines(5000);
+    let table = SeekTable::parse(&compress(&data, 1, 1000)).unwrap();
+    let linear = |offset: u64| {
+        (0..table.num_frames()).find(|&frame| {
+            offset >= table.frame_decompressed_offset(frame)
+                && offset
+                    < table.frame_decompressed_offset(frame) + table.frame_decompressed_size(frame)
+        })
+    };
+    for offset in (0..data.len() as u64 + 10).step_by(7) {
+        assert_eq!(table.frame_for_offset(offset), linear(offset));
+    }
+}
+
+#[test]
+fn decompressed_offset_for_compressed_starts_the_frame() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1000);
+    let table = SeekTable::parse(&compressed).unwrap();
+    for frame in 0..table.num_frames() {
+        let start = table.frame_compressed_offset(frame);
+        let end = start + table.frame_compressed_size(frame);
+        for offset in [start, (start + end) / 2, end - 1] {
+            assert_eq!(
+                table.decompressed_offset_for_compressed(offset),
+                Some(table.frame_decompressed_offset(frame))
+            );
+        }
+    }
+    // Nothing in the seek table or past the end.
+    assert_eq!(
+        table.decompressed_offset_for_compressed(table.compressed_len()),
+        None
+    );
+    assert_eq!(
+        table.decompressed_offset_for_compressed(compressed.len() as u64 + 10),
+        None
+    );
+}
+
+#[test]
+fn split_range_covers_range() {
+    let data = lines(5000);
+    let table = SeekTable::parse(&compress(&data, 1, 1000)).unwrap();
+
+    // Mid-frame start and end, spanning a few frames.
+    let split = table.split_range(1500, 2800);
+    assert_eq!(
+        split,
+        [(1, 500, 500), (2, 0, 1000), (3, 0, 1000), (4, 0, 300)]
+    );
+
+    // Within one frame.
+    assert_eq!(table.split_range(2100, 10), [(2, 100, 10)]);
+
+    // Cut short at the end and nothing past it.
+    let len = data.len() as u64;
+    let last = table.num_frames() - 1;
+    assert_eq!(
+        table.split_range(len - 5, 100),
+        [(last, table.frame_decompressed_size(last) - 5, 5)]
+    );
+    assert!(table.split_range(len, 10).is_empty());
+    assert!(table.split_range(0, 0).is_empty());
+}
+
+#[test]
+fn compressed_frames_slice_object() {
+    let compressed = compress(&lines(5000), 1, 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let frames: Vec<_> = table.compressed_frames(&compressed).collect();
+    assert_eq!(frames.len(), table.num_frames());
+    let mut joined = Vec::new();
+    for (i, (frame, bytes)) in frames.into_iter().enumerate() {
+        assert_eq!(frame, i);
+        assert_eq!(bytes.len() as u64, table.frame_compressed_size(frame));
+        joined.extend_from_slice(bytes);
+    }
+    assert_eq!(joined, compressed[..table.compressed_len() as usize]);
+}
+
+#[test]
+fn parts_for_range_covers_frames() {
+    let data = lines(5000);
+    let table = SeekTable::parse(&compress(&data, 1, 1024)).unwrap();
+    let part_size = 1000;
+    for &(offset, len) in &[
+        (0, 1),
+        (0, 100_000),
+        (5000, 3000),
+        (30_000, 1),
+        (90_000, 50_000),
+    ] {
+        // Every part any byte of the frames falls in.
+        let mut expected: Vec<u32> = table
+            .split_range(offset, len)
+            .into_iter()
+            .flat_map(|(frame, _, _)| {
+                let start = table.frame_compressed_offset(frame);
+                start..start + table.frame_compressed_size(frame)
+            })
+            .map(|byte| (byte / part_size as u64) as u32 + 1)
+            .collect();
+        expected.dedup();
+        assert_eq!(table.parts_for_range(offset, len, part_size), expected);
+    }
+    assert!(table
+        .parts_for_range(data.len() as u64, 10, part_size)
+        .is_empty());
+    assert_eq!(table.parts_for_range(0, 100, 1 << 30), [1]);
+}
+
+#[test]
+fn parts_for_range_copes_with_empty_compressed_frames() {
+    // A corrupt table with a frame of no compressed bytes holding data.
+    let mut table = 0x184D_2A5Eu32.to_le_bytes().to_vec();
+    table.extend_from_slice(&17u32.to_le_bytes());
+    table.extend_from_slice(&0u32.to_le_bytes());
+    table.extend_from_slice(&100u32.to_le_bytes());
+    table.extend_from_slice(&1u32.to_le_bytes());
+    table.push(0);
+    table.extend_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+    let table = SeekTable::parse(&table).unwrap();
+    assert_eq!(table.parts_for_range(0, 10, 1000), [1]);
+}
+
+#[test]
+fn exact_ranges_fall_within_coalesced_range() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let last = data.len() as u64 - 1;
+    // Out of order, repeated, neighbouring and past the end.
+    let offsets = [40_000, 10, 10, 20_000, 20_000 + 1024, last, last + 1];
+
+    let frame = |offset| table.frame_for_offset(offset).unwrap();
+    let start = |frame| table.frame_compressed_offset(frame);
+    let end = |frame| start(frame) + table.frame_compressed_size(frame);
+    assert_eq!(
+        table.coalesced_range(&offsets),
+        Some(0..table.compressed_len())
+    );
+    assert_eq!(
+        table.exact_ranges(&offsets),
+        [
+            0..end(0),
+            start(frame(20_000))..end(frame(21_024)),
+            start(frame(40_000))..end(frame(40_000)),
+            start(frame(last))..table.compressed_len(),
+        ]
+    );
+    assert_eq!(
+        table.coalesced_range(&[20_000, 10_000]),
+        Some(start(frame(10_000))..end(frame(20_000)))
+    );
+    assert_eq!(table.coalesced_range(&[last + 1]), None);
+    assert!(table.exact_ranges(&[]).is_empty());
+}
+
+#[test]
+fn describe_lists_every_frame() {
+    let compressed = compress(&lines(3000), 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let description = table.describe();
+    let mut written = Vec::new();
+    table.describe_to(&mut written).unwrap();
+    assert_eq!(written, description.as_bytes());
+
+    let lines: Vec<_> = description.lines().collect();
+    // A header, the frames and the totals.
+    assert_eq!(lines.len(), table.num_frames() + 2);
+    assert!(lines[0].ends_with("checksum"));
+    let fields: Vec<_> = lines[2].split_whitespace().collect();
+    assert_eq!(
+        fields,
+        vec![
+            "1".to_owned(),
+            table.frame_compressed_offset(1).to_string(),
+            table.frame_compressed_size(1).to_string(),
+            table.frame_decompressed_offset(1).to_string(),
+            table.frame_decompressed_size(1).to_string(),
+            format!("{:#010x}", table.frame_checksum(1).unwrap()),
+        ]
+    );
+    assert!(lines[lines.len() - 1].contains(&format!("{} byte seek table", table.seek_table_len())));
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{compress, lines};
+use zstd_seekable_s3::{Error, SeekableBytes};
+
+#[test]
+fn seekable_bytes_decompress_once() {
+    let data = lines(5000);
+    let bytes = SeekableBytes::new(compress(&data, 1, 4096));
+    assert!(!bytes.is_decompressed());
+
+    assert_eq!(bytes[10], data[10]);
+    assert!(bytes.is_decompressed());
+    assert_eq!(&bytes[1000..2000], &data[1000..2000]);
+    assert_eq!(bytes.len(), data.len());
+    assert_eq!(*bytes, data[..]);
+    // Same data every time, not decompressed again.
+    assert_eq!(bytes.try_get().unwrap().as_ptr(), bytes.as_ptr());
+
+    let broken = SeekableBytes::new(&b"not zstd at all"[..]);
+    assert!(broken.try_get().is_err());
+    assert!(!broken.is_decompressed());
+    assert!(std::panic::catch_unwind(|| broken[0]).is_err());
+}
+
+#[test]
+fn seekable_bytes_over_max_len_are_refused() {
+    let data = lines(5000);
+    let len = data.len() as u64;
+    let compressed = compress(&data, 1, 4096);
+
+    let bytes = SeekableBytes::new(compressed.clone()).max_len(len);
+    assert_eq!(*bytes, data[..]);
+
+    let bytes = SeekableBytes::new(compressed).max_len(len - 1);
+    match bytes.try_get() {
+        Err(Error::DataOverLimit { declared, limit }) => {
+            assert_eq!((declared, limit), (len, len - 1))
+        }
+        other => panic!("expected DataOverLimit, got {:?}", other),
+    }
+    assert!(!bytes.is_decompressed());
+}
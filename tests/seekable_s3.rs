@@ -0,0 +1,493 @@
+mod common;
+
+use common::{compress, fake_s3::FakeS3, lines};
+use futures::{executor::block_on_stream, stream};
+use rusoto_s3::GetObjectRequest;
+use std::{
+    convert::Infallible,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+};
+use zstd_seekable_s3::{
+    FrameCache, FrameCacheKey, OutOfRange, S3ReadError, SeekTable, SeekableDecompress,
+    SeekableS3Object, StreamCompress,
+};
+
+const HEADER: &[u8] = b"ENVL\x00\x01";
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn request() -> GetObjectRequest {
+    GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        key: "object.zst".to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn frames_over_the_limit_are_refused() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    s3.put_object("object.zst", compress(&data, 1, 4096));
+    let runtime = runtime();
+    let new_object = || {
+        SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+            .unwrap()
+            .unwrap()
+    };
+
+    // Frames are 4096 bytes.
+    let mut object = new_object();
+    object.set_max_frame_decompressed_size(Some(4096)).unwrap();
+    assert_eq!(
+        object.read_decompressed(10_000, 100).unwrap(),
+        data[10_000..10_100]
+    );
+
+    // Checked when the seek table is read, before any frame is fetched.
+    let mut object = new_object();
+    object.set_max_frame_decompressed_size(Some(4095)).unwrap();
+    let gets = s3.ranged_gets();
+    match object.read_decompressed(10_000, 100) {
+        Err(S3ReadError::FrameTooLarge { frame, declared }) => {
+            assert_eq!((frame, declared), (0, 4096))
+        }
+        other => panic!("expected FrameTooLarge, got {:?}", other),
+    }
+    assert_eq!(s3.ranged_gets(), gets + 1);
+
+    // Or when the limit's set, if the table is in already.
+    let mut object = new_object();
+    let seek_table = object.read_seek_table().unwrap();
+    object.decompressed_len().unwrap();
+    assert!(matches!(
+        object.set_max_frame_decompressed_size(Some(1000)),
+        Err(S3ReadError::FrameTooLarge { frame: 0, .. })
+    ));
+    let e = object.decompress_frame(&seek_table, 2).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn seek_tables_past_the_tail_limit_are_fetched_on_their_own() {
+    let s3 = FakeS3::default();
+    let compressed = compress(&lines(50_000), 1, 256);
+    let len = compressed.len() as u64;
+    let table_len = SeekTable::len_from_footer(&compressed).unwrap() as u64;
+    assert!(table_len > 4096);
+    s3.put_object("object.zst", compressed);
+    let runtime = runtime();
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    object.set_tail_fetch_size(1024);
+    object.set_max_tail_fetch_size(4096);
+
+    let seek_table = object.read_seek_table().unwrap();
+    assert_eq!(seek_table.seek_table_len() as u64, table_len);
+    assert_eq!(s3.ranges(), [(len - 1024, len), (len - table_len, len)]);
+
+    // Only the tail is kept for reads, not the table.
+    let mut byte = [0; 1];
+    object.seek(SeekFrom::End(-1000)).unwrap();
+    object.read_exact(&mut byte).unwrap();
+    assert_eq!(s3.ranged_gets(), 2);
+    object.seek(SeekFrom::End(-2000)).unwrap();
+    object.read_exact(&mut byte).unwrap();
+    assert_eq!(s3.ranged_gets(), 3);
+}
+
+#[test]
+fn truncated_objects_are_caught_reading_the_end() {
+    let s3 = FakeS3::default();
+    let compressed = compress(&lines(5000), 1, 4096);
+    let runtime = runtime();
+    let read_end = |object: &mut SeekableS3Object<_>| {
+        object.seek(SeekFrom::End(-4)).unwrap();
+        let e = object.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        *e.into_inner().unwrap().downcast::<S3ReadError>().unwrap()
+    };
+
+    s3.put_object("object.zst", compressed[..compressed.len() - 100].to_vec());
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        read_end(&mut object),
+        S3ReadError::SeekTableCorrupt(_)
+    ));
+    assert!(matches!(
+        object.read_seek_table(),
+        Err(S3ReadError::SeekTableCorrupt(_))
+    ));
+
+    // Missing frames rather than the end.
+    s3.put_object("object.zst", compressed[100..].to_vec());
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    let implied = compressed.len() as u64;
+    match read_end(&mut object) {
+        S3ReadError::LengthMismatch {
+            content_length,
+            implied: got,
+        } => assert_eq!((content_length, got), (implied - 100, implied)),
+        other => panic!("expected LengthMismatch, got {:?}", other),
+    }
+    assert!(matches!(
+        object.decompressed_len(),
+        Err(S3ReadError::LengthMismatch { .. })
+    ));
+
+    // The whole object reads fine.
+    s3.put_object("object.zst", compressed.clone());
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    object.seek(SeekFrom::End(-4)).unwrap();
+    let mut end = [0; 4];
+    object.read_exact(&mut end).unwrap();
+    assert_eq!(end, compressed[compressed.len() - 4..]);
+}
+
+#[test]
+fn headers_are_skipped() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    let chunks = stream::iter(data.chunks(100).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(1, 1024).unwrap().header(HEADER);
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .concat();
+    s3.put_object("object.zst", compressed.clone());
+    let runtime = runtime();
+    let new_object = || {
+        let mut object =
+            SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+                .unwrap()
+                .unwrap();
+        object.set_header_len(HEADER.len() as u64).unwrap();
+        object
+    };
+
+    let mut object = new_object();
+    assert_eq!(object.peek_prefix(100).unwrap(), data[..100]);
+    assert_eq!(object.decompressed_len().unwrap(), data.len() as u64);
+    assert_eq!(
+        object.read_decompressed(20_000, 1000).unwrap(),
+        data[20_000..21_000]
+    );
+    assert!(s3
+        .ranges()
+        .iter()
+        .all(|&(start, _)| start >= HEADER.len() as u64));
+
+    let mut decompress = SeekableDecompress::new(new_object()).unwrap();
+    assert_eq!(
+        decompress.read_range(30_000, 1000).unwrap(),
+        data[30_000..31_000]
+    );
+    let mut object = new_object();
+    let mut read = Vec::new();
+    object.read_to_end(&mut read).unwrap();
+    assert_eq!(read, compressed[HEADER.len()..]);
+
+    assert_eq!(
+        object
+            .set_header_len(compressed.len() as u64 + 1)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn prefetched_frames_are_read_from_the_cache() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    s3.put_object("object.zst", compress(&data, 1, 4096));
+    let runtime = runtime();
+    let new_object = |cache: Option<FrameCache>| {
+        let mut object =
+            SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+                .unwrap()
+                .unwrap();
+        object.set_frame_cache(cache);
+        object
+    };
+
+    let mut object = new_object(None);
+    match object.prefetch_ranges(&[(0, 100)], 4) {
+        Err(S3ReadError::PrefetchTooLarge { needed, capacity }) => {
+            assert_eq!((needed, capacity), (4096, 0))
+        }
+        other => panic!("expected PrefetchTooLarge, got {:?}", other),
+    }
+
+    // Checked before fetching any frames.
+    let cache = FrameCache::new(2 * 4096, FrameCacheKey::Checksum);
+    let mut object = new_object(Some(cache));
+    let gets = s3.ranged_gets();
+    match object.prefetch_ranges(&[(0, 12_000)], 4) {
+        Err(S3ReadError::PrefetchTooLarge { needed, capacity }) => {
+            assert_eq!((needed, capacity), (3 * 4096, 2 * 4096))
+        }
+        other => panic!("expected PrefetchTooLarge, got {:?}", other),
+    }
+    assert_eq!(s3.ranged_gets(), gets + 1);
+
+    let cache = FrameCache::new(4 * 4096, FrameCacheKey::Checksum);
+    let mut object = new_object(Some(cache));
+    assert_eq!(
+        object.prefetch_ranges(&[(0, 100), (5000, 100)], 4).unwrap(),
+        2
+    );
+    let gets = s3.ranged_gets();
+    // Frames already in there are left alone, only frame 2 is new.
+    assert_eq!(
+        object
+            .prefetch_ranges(&[(100, 100), (9000, 100)], 4)
+            .unwrap(),
+        1
+    );
+    assert_eq!(s3.ranged_gets(), gets + 1);
+
+    let stats = object.read_stats();
+    assert_eq!(
+        object.read_decompressed(3000, 8000).unwrap(),
+        data[3000..11_000]
+    );
+    assert_eq!(s3.ranged_gets(), gets + 1);
+    assert_eq!(object.read_stats().cache_hits, stats.cache_hits + 3);
+    assert_eq!(object.read_stats().cache_misses, stats.cache_misses);
+}
+
+#[test]
+fn frames_rev_goes_last_to_first() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    let chunks = stream::iter(data.chunks(100).map(Ok::<_, Infallible>));
+    let compress = chunks.compress(1, 4096).unwrap().metadata("name", "lines");
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .concat();
+    let table = SeekTable::parse(&compressed).unwrap();
+    s3.put_object("object.zst", compressed.clone());
+    let runtime = runtime();
+    let new_object = || {
+        SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+            .unwrap()
+            .unwrap()
+    };
+
+    // Every frame with data, the metadata frame left out.
+    let mut object = new_object();
+    let frames: Vec<_> = object.frames_rev().map(Result::unwrap).collect();
+    assert_eq!(frames.len(), table.num_frames() - 1);
+    let mut end = data.len();
+    for (meta, frame_data) in frames {
+        let offset = meta.decompressed_offset as usize;
+        assert_eq!(offset + frame_data.len(), end);
+        assert_eq!(frame_data, data[offset..end]);
+        end = offset;
+    }
+    assert_eq!(end, 0);
+
+    // The first error is the last thing we get.
+    let mut corrupt = compressed;
+    let last = table.num_frames() - 1;
+    corrupt
+        [(table.frame_compressed_offset(last) + table.frame_compressed_size(last) / 2) as usize] ^=
+        0xff;
+    s3.put_object("object.zst", corrupt);
+    let mut object = new_object();
+    let mut frames = object.frames_rev();
+    assert!(matches!(frames.next(), Some(Err(S3ReadError::Io(_)))));
+    assert!(frames.next().is_none());
+
+    // As does failing to read the seek table.
+    s3.put_object("object.zst", data[..1000].to_vec());
+    let mut object = new_object();
+    let mut frames = object.frames_rev();
+    assert!(matches!(
+        frames.next(),
+        Some(Err(S3ReadError::SeekTableCorrupt(_)))
+    ));
+    assert!(frames.next().is_none());
+}
+
+#[test]
+fn read_stats_count_what_reads_took() {
+    let s3 = FakeS3::default();
+    let data = lines(20_000);
+    let compressed = compress(&data, 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    s3.put_object("object.zst", compressed.clone());
+    let runtime = runtime();
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    object.set_tail_fetch_size(1024);
+    object.set_frame_cache(Some(FrameCache::new(1 << 20, FrameCacheKey::Checksum)));
+
+    // The tail, then the whole seek table since it's bigger.
+    let table_len = table.seek_table_len() as u64;
+    assert!(table_len > 1024);
+    object.decompressed_len().unwrap();
+    let stats = object.read_stats();
+    assert_eq!(
+        (stats.ranged_gets, stats.bytes_fetched),
+        (2, 1024 + table_len)
+    );
+
+    // Frames 1 and 2, then 2 again from the cache.
+    object.read_decompressed(5000, 4000).unwrap();
+    object.read_decompressed(9000, 10).unwrap();
+    let stats = object.read_stats();
+    let frames = table.frame_compressed_size(1) + table.frame_compressed_size(2);
+    assert_eq!(stats.ranged_gets, 4);
+    assert_eq!(stats.bytes_fetched, 1024 + table_len + frames);
+    assert_eq!((stats.cache_hits, stats.cache_misses), (1, 2));
+    assert_eq!(stats.decompress_micros.iter().sum::<u64>(), 2);
+    assert_eq!(stats.ranged_gets as usize, s3.ranged_gets());
+}
+
+#[test]
+fn given_seek_tables_are_checked_and_used() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    s3.put_object("object.zst", compressed);
+    let runtime = runtime();
+    let with_seek_table = |seek_table| {
+        SeekableS3Object::with_seek_table(
+            s3.client(),
+            runtime.handle().clone(),
+            None,
+            request(),
+            seek_table,
+        )
+    };
+
+    // Straight to the frame, without fetching the seek table.
+    let mut object = with_seek_table(table.clone()).unwrap();
+    assert_eq!(
+        object.read_decompressed(5000, 100).unwrap(),
+        data[5000..5100]
+    );
+    assert_eq!(s3.ranged_gets(), 1);
+
+    let other = SeekTable::parse(&compress(&data[..1000], 1, 4096)).unwrap();
+    assert!(matches!(
+        with_seek_table(other),
+        Err(S3ReadError::LengthMismatch { .. })
+    ));
+
+    let missing = SeekableS3Object::with_seek_table(
+        s3.client(),
+        runtime.handle().clone(),
+        None,
+        GetObjectRequest {
+            key: "missing.zst".to_owned(),
+            ..request()
+        },
+        table,
+    );
+    match missing {
+        Err(S3ReadError::Io(e)) => assert_eq!(e.kind(), ErrorKind::Other),
+        Err(other) => panic!("expected Io, got {:?}", other),
+        Ok(_) => panic!("opened a missing object"),
+    }
+}
+
+#[test]
+fn peek_prefix_only_fetches_the_start() {
+    let s3 = FakeS3::default();
+    let data = lines(200_000);
+    let compressed = compress(&data, 1, 64 * 1024);
+    assert!(compressed.len() > 16 * 1024);
+    s3.put_object("object.zst", compressed);
+    let runtime = runtime();
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    assert_eq!(object.peek_prefix(100).unwrap(), data[..100]);
+    assert_eq!(s3.ranges(), [(0, 16 * 1024)]);
+
+    // Asking for more than there is gives everything.
+    let short = lines(10);
+    let compressed = compress(&short, 1, 4096);
+    let len = compressed.len() as u64;
+    s3.put_object("short.zst", compressed);
+    let req = GetObjectRequest {
+        key: "short.zst".to_owned(),
+        ..request()
+    };
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, req)
+        .unwrap()
+        .unwrap();
+    assert_eq!(object.peek_prefix(10_000).unwrap(), short);
+    assert_eq!(s3.ranges()[1..], [(0, len)]);
+}
+
+#[test]
+fn read_decompressed_fetches_the_frames_holding_the_range() {
+    let s3 = FakeS3::default();
+    let data = lines(5000);
+    let len = data.len() as u64;
+    s3.put_object("object.zst", compress(&data, 1, 4096));
+    let runtime = runtime();
+    let mut object = SeekableS3Object::new(s3.client(), runtime.handle().clone(), None, request())
+        .unwrap()
+        .unwrap();
+    object.decompressed_len().unwrap();
+
+    // Within a frame, one request.
+    let gets = s3.ranged_gets();
+    assert_eq!(
+        object.read_decompressed(5000, 100).unwrap(),
+        data[5000..5100]
+    );
+    assert_eq!(s3.ranged_gets(), gets + 1);
+
+    // Across three frames, three.
+    let gets = s3.ranged_gets();
+    assert_eq!(
+        object.read_decompressed(4000, 5000).unwrap(),
+        data[4000..9000]
+    );
+    assert_eq!(s3.ranged_gets(), gets + 3);
+
+    // Cut short at the end, down to nothing without a request.
+    assert_eq!(
+        object.read_decompressed(len - 10, 100).unwrap(),
+        data[data.len() - 10..]
+    );
+    let gets = s3.ranged_gets();
+    assert!(object.read_decompressed(len, 100).unwrap().is_empty());
+    assert_eq!(s3.ranged_gets(), gets);
+
+    object.set_out_of_range(OutOfRange::Reject);
+    match object.read_decompressed(len - 10, 100) {
+        Err(S3ReadError::OutOfRange {
+            offset,
+            len: 100,
+            decompressed_len,
+        }) => assert_eq!((offset, decompressed_len), (len - 10, len)),
+        other => panic!("expected OutOfRange, got {:?}", other),
+    }
+    assert_eq!(s3.ranged_gets(), gets);
+    assert_eq!(
+        object.read_decompressed(len - 10, 10).unwrap(),
+        data[data.len() - 10..]
+    );
+}
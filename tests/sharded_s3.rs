@@ -0,0 +1,133 @@
+mod common;
+
+use common::{fake_s3::FakeS3, noise};
+use futures::{executor::block_on_stream, stream};
+use rusoto_core::Region;
+use rusoto_s3::{GetObjectRequest, S3Client};
+use std::convert::Infallible;
+use zstd_seekable_s3::{compress_with_roll_over, SeekTable, ShardedS3Object};
+
+// Compresses `data` into shards of about 30K.
+fn shards(data: &[u8]) -> Vec<Vec<u8>> {
+    let shards = compress_with_roll_over(
+        stream::iter(data.chunks(1000).map(Ok::<_, Infallible>)),
+        1,
+        4096,
+        30_000,
+    )
+    .unwrap();
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    for item in block_on_stream(Box::pin(shards)) {
+        let (object, bytes) = item.unwrap();
+        if object == objects.len() {
+            objects.push(Vec::new());
+        }
+        objects[object].extend_from_slice(&bytes);
+    }
+    objects
+}
+
+fn shard_key(shard: usize) -> String {
+    format!("object.zst.{}", shard)
+}
+
+#[test]
+fn offsets_map_to_shards() {
+    let data = noise(200_000, 3);
+    let objects = shards(&data);
+    let seek_tables: Vec<SeekTable> = objects
+        .iter()
+        .map(|object| SeekTable::parse(object).unwrap())
+        .collect();
+    assert!(seek_tables.len() > 2);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let req = GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        ..Default::default()
+    };
+    let sharded = ShardedS3Object::new(
+        S3Client::new(Region::EuWest1),
+        runtime.handle().clone(),
+        None,
+        req,
+        shard_key,
+        seek_tables.clone(),
+    );
+    assert_eq!(sharded.num_shards(), seek_tables.len());
+    assert_eq!(sharded.decompressed_len(), data.len() as u64);
+
+    let first_len = seek_tables[0].decompressed_len();
+    assert_eq!(sharded.shard_for_offset(0), Some((0, 0)));
+    assert_eq!(
+        sharded.shard_for_offset(first_len - 1),
+        Some((0, first_len - 1))
+    );
+    assert_eq!(sharded.shard_for_offset(first_len), Some((1, 0)));
+    assert_eq!(
+        sharded.shard_for_offset(data.len() as u64 - 1),
+        Some((
+            seek_tables.len() - 1,
+            seek_tables.last().unwrap().decompressed_len() - 1
+        ))
+    );
+    assert_eq!(sharded.shard_for_offset(data.len() as u64), None);
+}
+
+#[test]
+fn reads_only_get_the_shards_they_need() {
+    let data = noise(200_000, 3);
+    let objects = shards(&data);
+    assert!(objects.len() > 2);
+    let s3 = FakeS3::default();
+    for (shard, object) in objects.iter().enumerate() {
+        s3.put_object(&shard_key(shard), object.clone());
+    }
+    let seek_tables: Vec<SeekTable> = objects
+        .iter()
+        .map(|object| SeekTable::parse(object).unwrap())
+        .collect();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let req = GetObjectRequest {
+        bucket: "bucket".to_owned(),
+        ..Default::default()
+    };
+    let mut sharded = ShardedS3Object::new(
+        s3.client(),
+        runtime.handle().clone(),
+        None,
+        req,
+        shard_key,
+        seek_tables.clone(),
+    );
+
+    // Across the end of the first shard.
+    let boundary = seek_tables[0].decompressed_len();
+    let (start, end) = (boundary - 5000, boundary + 5000);
+    assert_eq!(
+        sharded.read_range(start, end - start).unwrap(),
+        data[start as usize..end as usize]
+    );
+    let mut got = s3.got_keys();
+    got.dedup();
+    assert_eq!(got, [shard_key(0), shard_key(1)]);
+    // Only the frames holding the range.
+    let frames =
+        seek_tables[0].split_range(start, 5000).len() + seek_tables[1].split_range(0, 5000).len();
+    assert_eq!(s3.ranged_gets(), frames);
+
+    let last = objects.len() - 1;
+    let start = sharded.decompressed_len() - 100;
+    assert_eq!(
+        sharded.read_range(start, 1000).unwrap(),
+        data[start as usize..]
+    );
+    let mut got = s3.got_keys();
+    got.dedup();
+    assert_eq!(got, [shard_key(0), shard_key(1), shard_key(last)]);
+}
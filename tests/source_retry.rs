@@ -0,0 +1,72 @@
+mod common;
+
+use common::{decompress_all, lines, noise};
+use futures::{executor::block_on_stream, stream};
+use std::cell::Cell;
+use zstd_seekable_s3::{compress_with_source_retry, CompressError};
+
+// Makes a source of `data` in chunks that fails after `fail_after` chunks
+// the first `failures` times it's made.
+fn flaky_source<'a>(
+    data: &'a [u8],
+    chunk_size: usize,
+    fail_after: usize,
+    failures: &'a Cell<usize>,
+) -> impl Fn() -> stream::Iter<std::vec::IntoIter<Result<&'a [u8], &'static str>>> + 'a {
+    move || {
+        let mut items: Vec<_> = data.chunks(chunk_size).map(Ok).collect();
+        if failures.get() > 0 {
+            failures.set(failures.get() - 1);
+            items.truncate(fail_after);
+            items.push(Err("transient"));
+        }
+        stream::iter(items)
+    }
+}
+
+#[test]
+fn retries_until_source_succeeds() {
+    let data = lines(5000);
+    let failures = Cell::new(2);
+    let compress =
+        compress_with_source_retry(flaky_source(&data, 100, 3, &failures), 1, 1 << 20, 3).unwrap();
+    let mut compress = block_on_stream(compress);
+    let compressed: Vec<u8> = (&mut compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    assert_eq!(compress.into_inner().attempts(), 3);
+    assert_eq!(decompress_all(compressed), data);
+
+    // One attempt short, the last error comes through.
+    let failures = Cell::new(2);
+    let compress =
+        compress_with_source_retry(flaky_source(&data, 100, 3, &failures), 1, 1 << 20, 2).unwrap();
+    let results: Vec<_> = block_on_stream(compress).collect();
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, Err(CompressError::Underlying("transient")))));
+}
+
+#[test]
+fn no_retry_after_output() {
+    // Small frames get output going straight away.
+    let data = noise(100_000, 1);
+    let failures = Cell::new(1);
+    let compress =
+        compress_with_source_retry(flaky_source(&data, 10_000, 3, &failures), 1, 1024, 5).unwrap();
+    let mut compress = block_on_stream(compress);
+    let results: Vec<_> = (&mut compress).collect();
+    assert!(results[0].as_ref().map_or(false, |bytes| !bytes.is_empty()));
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, Err(CompressError::Underlying("transient")))));
+    assert_eq!(compress.into_inner().attempts(), 1);
+}
+
+#[test]
+fn rejects_zero_attempts() {
+    let data = lines(10);
+    let failures = Cell::new(0);
+    let source = flaky_source(&data, 100, 0, &failures);
+    assert!(compress_with_source_retry(source, 1, 1024, 0).is_err());
+}
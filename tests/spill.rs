@@ -0,0 +1,103 @@
+mod common;
+
+use common::{compress, fake_s3::FakeS3, lines};
+use futures::stream;
+use std::convert::Infallible;
+use zstd_seekable_s3::{resume_compress_to_s3, CompressToS3Config, SeekTable, SpillRecovery};
+
+#[test]
+fn spill_recovery_keeps_whole_frames() {
+    let data = lines(20_000);
+    let compressed = compress(&data, 1, 4096);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let path = std::env::temp_dir().join("zstd-seekable-s3-spill.zst");
+
+    // Cut off halfway through a frame, as a crash would.
+    let frame = table.num_frames() / 2;
+    let cut = table.frame_compressed_offset(frame) + table.frame_compressed_size(frame) / 2;
+    std::fs::write(&path, &compressed[..cut as usize]).unwrap();
+    let recovery = SpillRecovery::open(&path).unwrap();
+    assert!(!recovery.is_finished());
+    assert_eq!(recovery.seek_table().num_frames(), frame);
+    assert_eq!(
+        recovery.decompressed_len(),
+        table.frame_decompressed_offset(frame)
+    );
+    for f in 0..frame {
+        assert_eq!(
+            recovery.seek_table().frame_checksum(f),
+            table.frame_checksum(f)
+        );
+    }
+
+    // Seek table and all.
+    std::fs::write(&path, &compressed).unwrap();
+    let recovery = SpillRecovery::open(&path).unwrap();
+    assert!(recovery.is_finished());
+    assert_eq!(recovery.seek_table(), &table);
+    assert_eq!(recovery.decompressed_len(), data.len() as u64);
+
+    std::fs::write(&path, b"").unwrap();
+    assert_eq!(SpillRecovery::open(&path).unwrap().decompressed_len(), 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn resume_keeps_the_parts_the_spill_covers() {
+    let data = lines(50_000);
+    let compressed = compress(&data, 3, 16 * 1024);
+    let table = SeekTable::parse(&compressed).unwrap();
+    let path = std::env::temp_dir().join("zstd-seekable-s3-spill-resume.zst");
+    let frame = table.num_frames() * 2 / 3;
+    let cut = table.frame_compressed_offset(frame) + table.frame_compressed_size(frame) / 2;
+    std::fs::write(&path, &compressed[..cut as usize]).unwrap();
+    let recovery = SpillRecovery::open(&path).unwrap();
+    let whole = table.frame_compressed_offset(frame) as usize;
+
+    // The first two parts are in the spill's whole frames, the third runs
+    // past them so it has to go again.
+    let s3 = FakeS3::default();
+    let (a, b) = (whole / 3, whole * 2 / 3);
+    s3.start_upload(
+        "object.zst",
+        &[
+            (1, &compressed[..a]),
+            (2, &compressed[a..b]),
+            (3, &compressed[b..whole + 100]),
+        ],
+    );
+
+    let config = CompressToS3Config {
+        compression_level: 3,
+        frame_size: 16 * 1024,
+        part_size: 8 * 1024,
+        spill: Some(path.clone()),
+        ..Default::default()
+    };
+    let rest = &data[recovery.decompressed_len() as usize..];
+    let source = stream::iter(rest.chunks(10_000).map(Ok::<_, Infallible>));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let report = runtime
+        .block_on(resume_compress_to_s3(
+            recovery,
+            source,
+            &s3.client(),
+            "bucket".to_owned(),
+            "object.zst".to_owned(),
+            config,
+        ))
+        .unwrap();
+    assert_eq!(report.compressed_len, compressed.len() as u64);
+    assert_eq!(s3.object("object.zst").unwrap(), compressed);
+
+    // Everything from the third part on was uploaded, a part at a time.
+    let parts = s3.uploaded_part_numbers();
+    let expected_parts = (compressed.len() - b + 8 * 1024 - 1) / (8 * 1024);
+    assert_eq!(parts, (3..3 + expected_parts as i64).collect::<Vec<_>>());
+    assert_eq!(s3.uploads_in_progress(), 0);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    std::fs::remove_file(&path).unwrap();
+}
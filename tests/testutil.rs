@@ -0,0 +1,11 @@
+mod common;
+
+use zstd_seekable_s3::testutil::roundtrip;
+
+#[test]
+fn roundtrips() {
+    roundtrip(&[], 1, 1024).unwrap();
+    roundtrip(b"x", 1, 1024).unwrap();
+    roundtrip(&common::lines(5000), 1, 1024).unwrap();
+    roundtrip(&common::noise(100_000, 3), 3, 4096).unwrap();
+}
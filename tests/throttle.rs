@@ -0,0 +1,45 @@
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use std::{convert::Infallible, time::Duration};
+use tokio::time::Instant;
+use zstd_seekable_s3::StreamThrottle;
+
+fn paused_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap()
+}
+
+fn chunks(n: usize, len: usize) -> impl futures::Stream<Item = Result<Bytes, Infallible>> {
+    stream::iter((0..n).map(move |_| Ok(Bytes::from(vec![0; len]))))
+}
+
+#[test]
+fn throttle_paces_output() {
+    paused_runtime().block_on(async {
+        let start = Instant::now();
+        // A burst of 1000 lets the first chunk straight through, after which
+        // every chunk takes half a second.
+        let out: Vec<_> = chunks(10, 1000).throttle(2000).burst(1000).collect().await;
+        assert_eq!(out.len(), 10);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(4490) && elapsed <= Duration::from_millis(4510),
+            "took {:?}",
+            elapsed
+        );
+    });
+}
+
+#[test]
+fn throttle_allows_bursts() {
+    paused_runtime().block_on(async {
+        let start = Instant::now();
+        // Default burst is a second's worth.
+        let out: Vec<_> = chunks(4, 500).throttle(2000).collect().await;
+        assert_eq!(out.len(), 4);
+        assert!(start.elapsed() < Duration::from_millis(10));
+    });
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{compress, lines};
+use futures::{executor::block_on, stream, TryStreamExt};
+use std::{
+    io::{Cursor, Error, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::AsyncWrite;
+use zstd_seekable_s3::{decompress_to_writer, SeekableDecompress, WriteOutError};
+
+#[test]
+fn writes_all_the_windows() {
+    let data = lines(20_000);
+    let mut decompress = SeekableDecompress::new(Cursor::new(compress(&data, 1, 4096))).unwrap();
+    let windows = stream::iter(decompress.windows(10_000)).map_ok(|(_, window)| window);
+
+    let mut out = Vec::new();
+    let written = block_on(decompress_to_writer(windows, &mut out)).unwrap();
+    assert_eq!(written, data.len() as u64);
+    assert_eq!(out, data);
+}
+
+// Takes a few bytes, then fails every write.
+struct FailingWriter(usize);
+
+impl AsyncWrite for FailingWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.0 == 0 {
+            return Poll::Ready(Err(Error::new(ErrorKind::Other, "disk full")));
+        }
+        let len = buf.len().min(self.0);
+        self.0 -= len;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn errors_say_which_side_failed() {
+    let chunks = stream::iter(vec![Ok(vec![0u8; 100]), Ok(vec![0u8; 100])]);
+    match block_on(decompress_to_writer::<_, _, &str, _>(
+        chunks,
+        &mut FailingWriter(150),
+    )) {
+        Err(WriteOutError::Write(e)) => assert_eq!(e.to_string(), "disk full"),
+        other => panic!("expected a write error, got {:?}", other),
+    }
+
+    let chunks = stream::iter(vec![Ok(vec![0u8; 100]), Err("corrupt frame")]);
+    match block_on(decompress_to_writer(chunks, &mut Vec::new())) {
+        Err(WriteOutError::Decompress(e)) => assert_eq!(e, "corrupt frame"),
+        other => panic!("expected a decompression error, got {:?}", other),
+    }
+}
@@ -0,0 +1,21 @@
+mod common;
+
+use common::{compress, decompress_all, lines};
+use std::io::Cursor;
+use zstd_seekable_s3::{transcode, SeekTable, SeekableDecompress};
+
+#[test]
+fn transcode_changes_frame_size() {
+    let data = lines(20_000);
+    let compressed = compress(&data, 1, 1024);
+    let decompress = SeekableDecompress::new(Cursor::new(compressed)).unwrap();
+
+    let transcoded: Vec<u8> = transcode(decompress, 8192, 3)
+        .unwrap()
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+    let table = SeekTable::parse(&transcoded).unwrap();
+    assert_eq!(table.frame_decompressed_size(0), 8192);
+    assert_eq!(table.num_frames(), (data.len() + 8191) / 8192);
+    assert_eq!(decompress_all(transcoded), data);
+}
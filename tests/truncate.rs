@@ -0,0 +1,83 @@
+mod common;
+
+use common::{compress, decompress_all, frames, lines};
+use futures::{executor::block_on_stream, stream};
+use std::{convert::Infallible, io::Cursor};
+use zstd_seekable_s3::{truncate_seekable, SeekTable, SeekableDecompress, StreamCompress};
+
+#[test]
+fn cut_at_a_frame_boundary_keeps_the_frames() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+    let truncated = truncate_seekable(&compressed, 8 * 1024, 1).unwrap();
+    assert_eq!(frames(&truncated), frames(&compressed)[..8]);
+
+    let table = SeekTable::parse(&compressed).unwrap();
+    let new_table = SeekTable::parse(&truncated).unwrap();
+    assert_eq!(new_table.decompressed_len(), 8 * 1024);
+    for frame in 0..8 {
+        assert_eq!(new_table.frame_checksum(frame), table.frame_checksum(frame));
+    }
+}
+
+#[test]
+fn cut_mid_frame_recompresses_just_that_frame() {
+    let data = lines(5000);
+    let compressed = compress(&data, 1, 1024);
+    let truncated = truncate_seekable(&compressed, 10_000, 1).unwrap();
+
+    let table = SeekTable::parse(&truncated).unwrap();
+    assert_eq!(table.num_frames(), 10);
+    assert!(table.has_checksums());
+    assert_eq!(table.frame_range(9), 9 * 1024..10_000);
+    assert_eq!(frames(&truncated)[..9], frames(&compressed)[..9]);
+    assert_eq!(decompress_all(truncated.clone()), &data[..10_000]);
+
+    let mut decompress = SeekableDecompress::new(Cursor::new(truncated)).unwrap();
+    assert!(decompress.verify_all(2).unwrap().is_ok());
+}
+
+#[test]
+fn cut_past_the_end_changes_nothing() {
+    let data = lines(500);
+    let compressed = compress(&data, 1, 1024);
+    let len = data.len() as u64;
+    assert_eq!(truncate_seekable(&compressed, len, 1).unwrap(), compressed);
+    assert_eq!(
+        truncate_seekable(&compressed, len * 2, 1).unwrap(),
+        compressed
+    );
+}
+
+#[test]
+fn cut_to_nothing_leaves_an_empty_frame() {
+    let data = lines(500);
+    let truncated = truncate_seekable(&compress(&data, 1, 1024), 0, 1).unwrap();
+    let table = SeekTable::parse(&truncated).unwrap();
+    assert_eq!(table.num_frames(), 1);
+    assert_eq!(table.decompressed_len(), 0);
+    assert!(decompress_all(truncated).is_empty());
+}
+
+#[test]
+fn metadata_is_kept() {
+    let data = lines(500);
+    let compress = stream::iter(data.chunks(100).map(Ok::<_, Infallible>))
+        .compress(1, 1024)
+        .unwrap()
+        .metadata("path", "logs/app.log");
+    let compressed: Vec<u8> = block_on_stream(compress)
+        .flat_map(|bytes| bytes.unwrap().to_vec())
+        .collect();
+
+    for &len in &[0, 1000, 2000] {
+        let truncated = truncate_seekable(&compressed, len, 1).unwrap();
+        let table = SeekTable::parse(&truncated).unwrap();
+        assert_eq!(table.decompressed_len(), len);
+        let mut decompress = SeekableDecompress::new(Cursor::new(truncated)).unwrap();
+        assert_eq!(
+            decompress.metadata().unwrap(),
+            vec![("path".to_owned(), "logs/app.log".to_owned())]
+        );
+    }
+}
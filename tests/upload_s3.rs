@@ -0,0 +1,84 @@
+mod common;
+
+use common::lines;
+use futures::{executor::block_on_stream, stream};
+use rusoto_s3::UploadPartRequest;
+use std::{convert::Infallible, io::Read};
+use zstd_seekable_s3::{PartsProgress, SeekTable, StreamCompress, StreamUploadParts};
+
+#[test]
+fn progress_tracks_parts() {
+    let chunks = stream::iter(vec![Ok::<_, Infallible>(vec![0u8; 600]); 5]);
+    let parts = chunks.upload_parts(UploadPartRequest::default(), 1000);
+    let progress = parts.progress();
+    let mut parts = block_on_stream(Box::pin(parts));
+
+    // Two chunks make a part, leaving nothing buffered.
+    let part = parts.next().unwrap().unwrap();
+    assert_eq!(part.content_length, Some(1200));
+    assert_eq!(
+        progress.parts(),
+        PartsProgress {
+            parts: 1,
+            bytes: 1200
+        }
+    );
+    assert_eq!(progress.buffered_bytes(), 0);
+
+    parts.next().unwrap().unwrap();
+    parts.next().unwrap().unwrap();
+    assert!(parts.next().is_none());
+    assert_eq!(
+        progress.parts(),
+        PartsProgress {
+            parts: 3,
+            bytes: 3000
+        }
+    );
+}
+
+#[test]
+fn report_sums_up_upload() {
+    let data = lines(20_000);
+    let compress = stream::iter(data.chunks(1000).map(Ok::<_, Infallible>))
+        .compress(1, 4096)
+        .unwrap();
+    let compress_progress = compress.progress();
+    let parts = compress.upload_parts(UploadPartRequest::default(), 5000);
+    let progress = parts.progress();
+
+    let mut compressed = Vec::new();
+    for part in block_on_stream(parts) {
+        let mut body = part.unwrap().body.unwrap().into_blocking_read();
+        body.read_to_end(&mut compressed).unwrap();
+    }
+
+    let report = progress.report(&compress_progress, Some("etag".to_owned()));
+    let table = SeekTable::parse(&compressed).unwrap();
+    assert_eq!(report.original_len, data.len() as u64);
+    assert_eq!(report.compressed_len, compressed.len() as u64);
+    assert_eq!(report.frames, table.num_frames() as u64);
+    assert!(report.ratio() > 1.0);
+    assert_eq!(report.e_tag.as_deref(), Some("etag"));
+}
+
+#[test]
+fn verify_part_md5_sends_content_md5() {
+    let chunks = || stream::iter(vec![Ok::<_, Infallible>(vec![0u8; 600]); 5]);
+    let parts = chunks()
+        .upload_parts(UploadPartRequest::default(), 1000)
+        .verify_part_md5(true);
+    let md5s: Vec<_> = block_on_stream(Box::pin(parts))
+        .map(|part| part.unwrap().content_md5)
+        .collect();
+    // Of 1200 zero bytes twice and then 600 of them.
+    let twelve_hundred = Some("+0qon7ib+U0FkKMXTRGT/w==".to_owned());
+    let six_hundred = Some("uJyeandVZ/son/Ok4V6fWg==".to_owned());
+    assert_eq!(
+        md5s,
+        vec![twelve_hundred.clone(), twelve_hundred, six_hundred]
+    );
+
+    let parts = chunks().upload_parts(UploadPartRequest::default(), 1000);
+    assert!(block_on_stream(Box::pin(parts)).all(|part| part.unwrap().content_md5.is_none()));
+}